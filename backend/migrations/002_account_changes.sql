@@ -0,0 +1,14 @@
+-- Account credential changes (password / email) with re-authentication
+
+-- Access tokens now carry a session id claim and no longer fit in 255 chars
+ALTER TABLE user_sessions ALTER COLUMN session_token TYPE TEXT;
+
+-- Pending email change, confirmed from the new address before it is swapped in
+ALTER TABLE users
+    ADD COLUMN pending_email VARCHAR(255),
+    ADD COLUMN pending_email_token VARCHAR(255),
+    ADD COLUMN pending_email_expires TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN password_changed_at TIMESTAMP WITH TIME ZONE;
+
+CREATE UNIQUE INDEX idx_users_pending_email_token ON users (pending_email_token)
+    WHERE pending_email_token IS NOT NULL;
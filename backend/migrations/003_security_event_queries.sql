@@ -0,0 +1,9 @@
+-- Reading back the security audit trail
+
+-- Platform administrators (may query every member's security events)
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT false;
+
+-- Keyset pagination over the full event stream and risk filtering
+CREATE INDEX idx_security_events_created ON security_events (created_at DESC, id DESC);
+CREATE INDEX idx_security_events_risk ON security_events (risk_level, created_at DESC);
+CREATE INDEX idx_security_events_ip ON security_events USING gist (ip_address inet_ops);
@@ -0,0 +1,15 @@
+-- Real-time security alerts to members
+
+-- Which alerts a member wants and where to send them (no row = defaults)
+CREATE TABLE notification_preferences (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    min_risk_level INTEGER NOT NULL DEFAULT 5 CHECK (min_risk_level BETWEEN 1 AND 10),
+    email_enabled BOOLEAN NOT NULL DEFAULT true,
+    push_enabled BOOLEAN NOT NULL DEFAULT true,
+    webhook_url TEXT,
+    webhook_secret VARCHAR(64),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- New-device detection looks up earlier sessions by fingerprint
+CREATE INDEX idx_user_sessions_device ON user_sessions (user_id, device_fingerprint);
@@ -0,0 +1,26 @@
+-- Network allow/deny rules and per-organization allowlists
+
+CREATE TABLE organizations (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name VARCHAR(255) NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE users ADD COLUMN organization_id UUID REFERENCES organizations(id) ON DELETE SET NULL;
+CREATE INDEX idx_users_organization ON users (organization_id);
+
+-- Platform-wide rules have no organization; organization rules are allowlists for its Enterprise members
+CREATE TABLE ip_rules (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    network CIDR NOT NULL,
+    action VARCHAR(20) NOT NULL CHECK (action IN ('allow', 'deny', 'elevated_risk')),
+    risk_adjustment INTEGER NOT NULL DEFAULT 0 CHECK (risk_adjustment BETWEEN 0 AND 9),
+    organization_id UUID REFERENCES organizations(id) ON DELETE CASCADE,
+    description TEXT,
+    expires_at TIMESTAMP WITH TIME ZONE,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    CHECK (organization_id IS NULL OR action = 'allow')
+);
+
+CREATE INDEX idx_ip_rules_organization ON ip_rules (organization_id);
@@ -0,0 +1,18 @@
+-- Deception: canary accounts and honeytoken refresh tokens
+
+-- Decoy accounts that no legitimate user ever signs in to
+ALTER TABLE users ADD COLUMN is_canary BOOLEAN NOT NULL DEFAULT false;
+
+-- Refresh tokens were never given an expiry at login
+UPDATE user_sessions SET refresh_expires_at = expires_at WHERE refresh_expires_at IS NULL;
+
+-- Decoy refresh tokens planted where an intruder would find them; only a hash is kept
+CREATE TABLE honeytokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    label VARCHAR(255) NOT NULL,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_triggered_at TIMESTAMP WITH TIME ZONE,
+    trigger_count INTEGER NOT NULL DEFAULT 0
+);
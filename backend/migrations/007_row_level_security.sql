@@ -0,0 +1,51 @@
+-- Row level security policies for users, user_sessions and security_events
+--
+-- Every transaction says who it acts for through two settings, set with SET LOCAL
+-- (set_config(..., true)) so they never outlive the transaction:
+--   app.role     'member', 'admin' or 'system'
+--   app.user_id  the authenticated member or administrator
+-- The backend's pool (utils/db.rs, connect) starts every session as 'anonymous', which no
+-- policy admits; each request or job picks its scope per transaction with begin_scoped. Any
+-- other session, including psql, sees no rows until it runs: SET app.role = 'system';
+
+CREATE OR REPLACE FUNCTION app_current_role() RETURNS TEXT
+LANGUAGE sql STABLE AS $$
+    SELECT NULLIF(current_setting('app.role', true), '')
+$$;
+
+CREATE OR REPLACE FUNCTION app_current_user_id() RETURNS UUID
+LANGUAGE sql STABLE AS $$
+    SELECT NULLIF(current_setting('app.user_id', true), '')::uuid
+$$;
+
+-- The backend connects as the table owner, which RLS otherwise exempts
+ALTER TABLE users FORCE ROW LEVEL SECURITY;
+ALTER TABLE user_sessions FORCE ROW LEVEL SECURITY;
+ALTER TABLE security_events FORCE ROW LEVEL SECURITY;
+
+-- Privileged path: SecurityService, authentication flows and admin operations
+CREATE POLICY users_privileged ON users
+    USING (app_current_role() IN ('system', 'admin'))
+    WITH CHECK (app_current_role() IN ('system', 'admin'));
+CREATE POLICY user_sessions_privileged ON user_sessions
+    USING (app_current_role() IN ('system', 'admin'))
+    WITH CHECK (app_current_role() IN ('system', 'admin'));
+CREATE POLICY security_events_privileged ON security_events
+    USING (app_current_role() IN ('system', 'admin'))
+    WITH CHECK (app_current_role() IN ('system', 'admin'));
+
+-- Members: their own account and sessions, and read-only access to their own events
+CREATE POLICY users_member_select ON users FOR SELECT
+    USING (app_current_role() = 'member' AND id = app_current_user_id());
+CREATE POLICY users_member_update ON users FOR UPDATE
+    USING (app_current_role() = 'member' AND id = app_current_user_id())
+    WITH CHECK (id = app_current_user_id());
+
+CREATE POLICY user_sessions_member_select ON user_sessions FOR SELECT
+    USING (app_current_role() = 'member' AND user_id = app_current_user_id());
+CREATE POLICY user_sessions_member_update ON user_sessions FOR UPDATE
+    USING (app_current_role() = 'member' AND user_id = app_current_user_id())
+    WITH CHECK (user_id = app_current_user_id());
+
+CREATE POLICY security_events_member_select ON security_events FOR SELECT
+    USING (app_current_role() = 'member' AND user_id = app_current_user_id());
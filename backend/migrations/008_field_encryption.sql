@@ -0,0 +1,33 @@
+-- Field-level envelope encryption for sensitive user columns
+--
+-- Values are encrypted in the application with data keys; data keys are stored here wrapped
+-- by a master key that never reaches the database. Encrypted columns hold
+-- "enc:v1:<data key id>:<base64 nonce || ciphertext>".
+
+CREATE TABLE data_keys (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    purpose VARCHAR(32) NOT NULL CHECK (purpose IN ('field_encryption', 'blind_index')),
+    wrapped_key BYTEA NOT NULL,
+    master_key_id VARCHAR(64) NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT true,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    rotated_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE UNIQUE INDEX idx_data_keys_active ON data_keys (purpose) WHERE active;
+
+-- Ciphertext is longer than the plaintext limits
+ALTER TABLE users
+    ALTER COLUMN email TYPE TEXT,
+    ALTER COLUMN pending_email TYPE TEXT,
+    ALTER COLUMN mfa_secret TYPE TEXT,
+    ALTER COLUMN destruction_key TYPE TEXT,
+    ALTER COLUMN biometric_hash TYPE TEXT;
+
+-- Keyed hash of the normalized email; email uniqueness moves here. Existing rows are
+-- encrypted and indexed by the backend at startup.
+ALTER TABLE users ADD COLUMN email_index VARCHAR(64);
+CREATE UNIQUE INDEX idx_users_email_index ON users (email_index);
+ALTER TABLE users DROP CONSTRAINT users_email_key;
+DROP INDEX idx_users_email;
+CREATE INDEX idx_users_unencrypted ON users (id) WHERE email_index IS NULL;
@@ -0,0 +1,11 @@
+-- Per-member encryption keys (crypto-shredding)
+--
+-- Member keys live in the backend's key store (USER_KEY_DIR), never in the database, so
+-- backups hold only ciphertext. Rows still under the shared v1 data key are moved onto their
+-- member's key by the backend at startup; this index finds them.
+
+DROP INDEX idx_users_unencrypted;
+CREATE INDEX idx_users_unshreddable ON users (id) WHERE email NOT LIKE 'enc:v2:%';
+
+COMMENT ON COLUMN destruction_logs.forensic_residue_level IS
+    '0: member key shredded, nothing readable remains; 1: key shredded but some fields predate per-member keys; 2: key not shredded, data recoverable from WAL and backups';
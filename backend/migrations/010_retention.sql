@@ -0,0 +1,20 @@
+-- Retention windows and IP address minimization for security_events and user_sessions
+
+-- First address of the /24 (IPv4) or /48 (IPv6) network, kept as a host address so it still
+-- reads back as a plain IP
+CREATE OR REPLACE FUNCTION minimize_ip(ip INET) RETURNS INET
+LANGUAGE sql IMMUTABLE AS $$
+    SELECT set_masklen(
+        network(set_masklen(ip, CASE WHEN family(ip) = 4 THEN 24 ELSE 48 END))::inet,
+        CASE WHEN family(ip) = 4 THEN 32 ELSE 128 END
+    )
+$$;
+
+-- True while the row still holds a full address or a user agent
+CREATE OR REPLACE FUNCTION needs_minimizing(ip INET, user_agent TEXT) RETURNS BOOLEAN
+LANGUAGE sql IMMUTABLE AS $$
+    SELECT user_agent IS NOT NULL OR ip IS DISTINCT FROM minimize_ip(ip)
+$$;
+
+CREATE INDEX idx_security_events_created_at ON security_events (created_at);
+CREATE INDEX idx_user_sessions_created_at ON user_sessions (created_at);
@@ -0,0 +1,66 @@
+-- Roles and permissions, replacing users.is_admin
+
+CREATE TABLE permissions (
+    name VARCHAR(100) PRIMARY KEY,
+    description TEXT NOT NULL
+);
+
+CREATE TABLE roles (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name VARCHAR(50) UNIQUE NOT NULL,
+    description TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE TABLE role_permissions (
+    role_id UUID NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    permission VARCHAR(100) NOT NULL REFERENCES permissions(name) ON DELETE CASCADE,
+    PRIMARY KEY (role_id, permission)
+);
+
+CREATE TABLE user_roles (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role_id UUID NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    granted_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    granted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, role_id)
+);
+
+CREATE INDEX idx_user_roles_role ON user_roles (role_id);
+
+INSERT INTO permissions (name, description) VALUES
+('security_events:read', 'Query every member''s security events'),
+('security_events:export', 'Export security events as CSV or NDJSON'),
+('ip_rules:manage', 'Manage network rules and reload reputation lists'),
+('organizations:manage', 'Manage organizations and their members'),
+('deception:manage', 'Manage canary accounts and honeytokens'),
+('retention:manage', 'Review retention and run the purge job'),
+('users:unlock', 'Unlock accounts locked after failed logins'),
+('users:deactivate', 'Deactivate and reactivate accounts'),
+('destruction_logs:read', 'Review destruction logs'),
+('roles:manage', 'Grant and revoke roles');
+
+INSERT INTO roles (name, description) VALUES
+('admin', 'Platform administrator; receives alerts for critical events'),
+('security_analyst', 'Reviews security events and destruction logs'),
+('support', 'Helps members back into their accounts');
+
+INSERT INTO role_permissions (role_id, permission)
+SELECT r.id, p.name FROM roles r CROSS JOIN permissions p WHERE r.name = 'admin';
+
+INSERT INTO role_permissions (role_id, permission)
+SELECT r.id, p.permission FROM roles r
+JOIN (VALUES
+    ('security_analyst', 'security_events:read'),
+    ('security_analyst', 'security_events:export'),
+    ('security_analyst', 'destruction_logs:read'),
+    ('support', 'users:unlock')
+) AS p (role, permission) ON p.role = r.name;
+
+-- Existing administrators keep their access
+SET app.role = 'system';
+INSERT INTO user_roles (user_id, role_id)
+SELECT u.id, r.id FROM users u CROSS JOIN roles r WHERE u.is_admin AND r.name = 'admin';
+RESET app.role;
+
+ALTER TABLE users DROP COLUMN is_admin;
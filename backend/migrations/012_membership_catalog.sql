@@ -0,0 +1,51 @@
+-- Versioned membership catalog
+--
+-- Each row is one version of a tier's terms. Changing a tier inserts a new version and stops
+-- offering the previous one; retiring a tier stops offering its current version. Subscriptions
+-- keep pointing at the version they signed up for, so existing subscribers keep their terms.
+
+ALTER TABLE memberships DROP CONSTRAINT memberships_name_key;
+
+ALTER TABLE memberships
+    ADD COLUMN tier VARCHAR(50),
+    ADD COLUMN version INTEGER NOT NULL DEFAULT 1,
+    ADD COLUMN description TEXT NOT NULL DEFAULT '',
+    ADD COLUMN sort_order INTEGER NOT NULL DEFAULT 0,
+    -- When this version stopped being offered (superseded or retired)
+    ADD COLUMN retired_at TIMESTAMP WITH TIME ZONE;
+
+UPDATE memberships SET
+    tier = LOWER(name),
+    features = COALESCE(features, '{}'),
+    sort_order = CASE LOWER(name)
+        WHEN 'basic' THEN 10
+        WHEN 'standard' THEN 20
+        WHEN 'premium' THEN 30
+        WHEN 'enterprise' THEN 40
+        ELSE 100
+    END;
+
+ALTER TABLE memberships
+    ALTER COLUMN tier SET NOT NULL,
+    ALTER COLUMN features SET NOT NULL,
+    ALTER COLUMN features SET DEFAULT '{}',
+    ALTER COLUMN max_file_size_mb SET NOT NULL,
+    ALTER COLUMN max_storage_gb SET NOT NULL,
+    ALTER COLUMN max_conversations SET NOT NULL,
+    ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN updated_at SET NOT NULL,
+    ALTER COLUMN is_active SET NOT NULL,
+    ADD CONSTRAINT memberships_tier_version_key UNIQUE (tier, version),
+    -- -1 means unlimited
+    ADD CONSTRAINT memberships_limits_check CHECK (
+        max_file_size_mb >= -1 AND max_storage_gb >= -1 AND max_conversations >= -1
+    );
+
+-- At most one version of each tier is offered to new subscribers
+CREATE UNIQUE INDEX idx_memberships_offered ON memberships (tier) WHERE is_active;
+
+INSERT INTO permissions (name, description) VALUES
+('memberships:manage', 'Create, change and retire membership tiers');
+
+INSERT INTO role_permissions (role_id, permission)
+SELECT id, 'memberships:manage' FROM roles WHERE name = 'admin';
@@ -0,0 +1,39 @@
+-- Members' tiers come from their subscriptions
+--
+-- `users.membership_tier` was free text that registering clients could set to anything. A
+-- member's tier is now the catalog version of their live subscription, or the offered version of
+-- the default `basic` tier when they have none.
+
+ALTER TABLE subscriptions
+    ALTER COLUMN user_id SET NOT NULL,
+    ALTER COLUMN membership_id SET NOT NULL,
+    ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN updated_at SET NOT NULL;
+
+-- Statuses that entitle the member to the subscription's tier; one such subscription per member
+CREATE UNIQUE INDEX idx_subscriptions_live ON subscriptions (user_id)
+    WHERE status IN ('active', 'trialing', 'past_due');
+
+CREATE FUNCTION current_membership_id(member UUID) RETURNS UUID
+LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(
+        (SELECT membership_id FROM subscriptions
+         WHERE user_id = member AND status IN ('active', 'trialing', 'past_due')),
+        (SELECT id FROM memberships WHERE tier = 'basic' AND is_active)
+    )
+$$;
+
+-- Existing paid tiers become complimentary subscriptions (no Stripe ids) on the offered version;
+-- unrecognised values fall back to basic
+SET app.role = 'system';
+
+INSERT INTO subscriptions (user_id, membership_id, status)
+SELECT u.id, m.id, 'active'
+FROM users u
+JOIN memberships m ON m.tier = LOWER(u.membership_tier) AND m.is_active
+WHERE m.tier <> 'basic';
+
+RESET app.role;
+
+DROP INDEX idx_users_membership_tier;
+ALTER TABLE users DROP COLUMN membership_tier;
@@ -0,0 +1,23 @@
+-- Stripe billing
+--
+-- Stripe only ever learns a member's id (in metadata); Checkout collects whatever payment
+-- details it needs itself.
+
+-- One Stripe customer per member, created at their first checkout
+CREATE TABLE billing_customers (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    stripe_customer_id VARCHAR(255) NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Checkout sessions we started, with the catalog version and interval the member chose
+CREATE TABLE checkout_sessions (
+    id VARCHAR(255) PRIMARY KEY, -- Stripe's session id (cs_...)
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    membership_id UUID NOT NULL REFERENCES memberships(id),
+    billing_interval VARCHAR(10) NOT NULL CHECK (billing_interval IN ('monthly', 'yearly')),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX idx_checkout_sessions_user_id ON checkout_sessions (user_id);
@@ -0,0 +1,14 @@
+-- Stripe webhook ingestion
+
+-- Every event applied, so redeliveries are acknowledged without being applied twice
+CREATE TABLE stripe_events (
+    id VARCHAR(255) PRIMARY KEY, -- Stripe's event id (evt_...)
+    event_type VARCHAR(100) NOT NULL,
+    processed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Both NULL for subscriptions not billed through Stripe
+ALTER TABLE subscriptions
+    ADD COLUMN billing_interval VARCHAR(10) CHECK (billing_interval IN ('monthly', 'yearly')),
+    -- Creation time of the last Stripe event applied; Stripe does not deliver events in order
+    ADD COLUMN provider_updated_at TIMESTAMP WITH TIME ZONE;
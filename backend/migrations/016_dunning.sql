@@ -0,0 +1,25 @@
+-- Grace period for subscriptions whose payment failed
+--
+-- A `past_due` subscription keeps its tier until `grace_ends_at`, with reminders meanwhile. The
+-- scheduler then marks it `unpaid`, which moves the member to basic; a later payment makes it
+-- `active` again.
+
+ALTER TABLE subscriptions
+    ADD COLUMN past_due_since TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN grace_ends_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN reminders_sent INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN last_reminder_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX idx_subscriptions_grace ON subscriptions (grace_ends_at) WHERE status = 'past_due';
+
+-- A lapsed grace period stops counting before the scheduler gets to it
+CREATE OR REPLACE FUNCTION current_membership_id(member UUID) RETURNS UUID
+LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(
+        (SELECT membership_id FROM subscriptions
+         WHERE user_id = member
+           AND (status IN ('active', 'trialing')
+                OR (status = 'past_due' AND (grace_ends_at IS NULL OR grace_ends_at > NOW())))),
+        (SELECT id FROM memberships WHERE tier = 'basic' AND is_active)
+    )
+$$;
@@ -0,0 +1,34 @@
+-- Plan upgrades and downgrades for Stripe subscribers
+
+-- The Stripe product each catalog version is billed under once a plan change needs one
+-- (Checkout creates its own products from `product_data`)
+CREATE TABLE stripe_products (
+    membership_id UUID PRIMARY KEY REFERENCES memberships(id),
+    stripe_product_id VARCHAR(255) NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Every plan change a member asked for. Upgrades apply at once; downgrades stay `scheduled`
+-- until the end of the period already paid for
+CREATE TABLE plan_changes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    subscription_id UUID NOT NULL REFERENCES subscriptions(id) ON DELETE CASCADE,
+    from_membership_id UUID NOT NULL REFERENCES memberships(id),
+    from_interval VARCHAR(10) NOT NULL CHECK (from_interval IN ('monthly', 'yearly')),
+    to_membership_id UUID NOT NULL REFERENCES memberships(id),
+    to_interval VARCHAR(10) NOT NULL CHECK (to_interval IN ('monthly', 'yearly')),
+    kind VARCHAR(10) NOT NULL CHECK (kind IN ('upgrade', 'downgrade')),
+    status VARCHAR(10) NOT NULL CHECK (status IN ('scheduled', 'applied', 'cancelled')),
+    -- Prorated amount charged when the change applied; 0 for downgrades
+    amount_due NUMERIC(10,2) NOT NULL DEFAULT 0,
+    currency VARCHAR(3) NOT NULL,
+    effective_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    applied_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX idx_plan_changes_user_id ON plan_changes (user_id, created_at DESC);
+-- At most one pending downgrade per subscription
+CREATE UNIQUE INDEX idx_plan_changes_scheduled ON plan_changes (subscription_id)
+    WHERE status = 'scheduled';
@@ -0,0 +1,51 @@
+-- Prepaid access codes: membership without a card
+--
+-- Administrators issue codes in batches, each worth a number of months of one catalog version.
+-- Only SHA-256 hashes of the codes are stored. Redeeming one creates (or extends) a subscription
+-- with no Stripe ids whose period ends when the prepaid months run out.
+
+CREATE TABLE access_code_batches (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    membership_id UUID NOT NULL REFERENCES memberships(id),
+    months INTEGER NOT NULL CHECK (months BETWEEN 1 AND 36),
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    -- Codes cannot be redeemed after this; NULL never expires
+    expires_at TIMESTAMP WITH TIME ZONE,
+    note VARCHAR(255),
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE access_codes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    batch_id UUID NOT NULL REFERENCES access_code_batches(id) ON DELETE CASCADE,
+    code_hash VARCHAR(64) NOT NULL UNIQUE,
+    redeemed_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    redeemed_at TIMESTAMP WITH TIME ZONE,
+    subscription_id UUID REFERENCES subscriptions(id) ON DELETE SET NULL,
+    revoked_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX idx_access_codes_batch_id ON access_codes (batch_id);
+
+INSERT INTO permissions (name, description) VALUES
+('access_codes:manage', 'Issue, list and revoke prepaid access codes');
+
+INSERT INTO role_permissions (role_id, permission)
+SELECT id, 'access_codes:manage' FROM roles WHERE name = 'admin';
+
+-- Subscriptions not billed through Stripe end with their period (prepaid codes), rather than
+-- waiting for a renewal
+CREATE OR REPLACE FUNCTION current_membership_id(member UUID) RETURNS UUID
+LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(
+        (SELECT membership_id FROM subscriptions
+         WHERE user_id = member
+           AND (status IN ('active', 'trialing')
+                OR (status = 'past_due' AND (grace_ends_at IS NULL OR grace_ends_at > NOW())))
+           AND (stripe_subscription_id IS NOT NULL
+                OR current_period_end IS NULL
+                OR current_period_end > NOW())),
+        (SELECT id FROM memberships WHERE tier = 'basic' AND is_active)
+    )
+$$;
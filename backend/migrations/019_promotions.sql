@@ -0,0 +1,57 @@
+-- Promotion codes: discounts at checkout and on upgrades, and no-card trials
+--
+-- Discounts are mirrored to a Stripe coupon the first time one is used. Trials create a
+-- `trialing` subscription with no Stripe ids that ends with its period, like prepaid codes.
+
+CREATE TABLE promotions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    -- Upper case; members may type it in any case
+    code VARCHAR(50) NOT NULL UNIQUE,
+    description VARCHAR(255),
+    kind VARCHAR(10) NOT NULL CHECK (kind IN ('percent', 'fixed', 'trial')),
+    percent_off NUMERIC(5,2) CHECK (percent_off > 0 AND percent_off <= 100),
+    amount_off NUMERIC(10,2) CHECK (amount_off > 0),
+    currency VARCHAR(3),
+    -- Which invoices a discount applies to, in Stripe's terms
+    duration VARCHAR(10) CHECK (duration IN ('once', 'repeating', 'forever')),
+    duration_months INTEGER CHECK (duration_months BETWEEN 1 AND 36),
+    trial_days INTEGER CHECK (trial_days BETWEEN 1 AND 90),
+    -- Tier codes the promotion applies to; empty for every paid tier
+    tiers TEXT[] NOT NULL DEFAULT '{}',
+    -- NULL for unlimited
+    max_redemptions INTEGER CHECK (max_redemptions > 0),
+    starts_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    -- NULL never ends
+    ends_at TIMESTAMP WITH TIME ZONE,
+    stripe_coupon_id VARCHAR(255) UNIQUE,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    CHECK (
+        (kind = 'percent' AND percent_off IS NOT NULL AND amount_off IS NULL
+            AND duration IS NOT NULL AND trial_days IS NULL)
+        OR (kind = 'fixed' AND amount_off IS NOT NULL AND currency IS NOT NULL
+            AND percent_off IS NULL AND duration IS NOT NULL AND trial_days IS NULL)
+        OR (kind = 'trial' AND trial_days IS NOT NULL AND percent_off IS NULL
+            AND amount_off IS NULL AND duration IS NULL)
+    ),
+    CHECK ((duration = 'repeating') = (duration_months IS NOT NULL))
+);
+
+-- Each member can use a promotion once
+CREATE TABLE promotion_redemptions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    promotion_id UUID NOT NULL REFERENCES promotions(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    subscription_id UUID REFERENCES subscriptions(id) ON DELETE SET NULL,
+    redeemed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE (promotion_id, user_id)
+);
+
+-- The promotion a checkout was started with; redeemed when the checkout completes
+ALTER TABLE checkout_sessions ADD COLUMN promotion_id UUID REFERENCES promotions(id);
+
+INSERT INTO permissions (name, description) VALUES
+('promotions:manage', 'Create, list and end promotion codes and trials');
+
+INSERT INTO role_permissions (role_id, permission)
+SELECT id, 'promotions:manage' FROM roles WHERE name = 'admin';
@@ -0,0 +1,7 @@
+-- Drop the duplicate security_events created_at index from 010
+--
+-- idx_security_events_created (003) already covers the retention sweep's range scan on
+-- created_at. Databases set up while 010 briefly omitted the index never had it, hence IF
+-- EXISTS.
+
+DROP INDEX IF EXISTS idx_security_events_created_at;
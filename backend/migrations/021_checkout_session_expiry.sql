@@ -0,0 +1,15 @@
+-- Checkout session expiry
+--
+-- A member has at most one open checkout, so two completed checkouts cannot leave them with two
+-- subscriptions. Sessions are created with Stripe's `expires_at`; `checkout.session.expired`
+-- closes them early.
+
+ALTER TABLE checkout_sessions ADD COLUMN expires_at TIMESTAMP WITH TIME ZONE;
+
+-- Stripe's default lifetime for sessions created without one
+UPDATE checkout_sessions SET expires_at = created_at + INTERVAL '24 hours';
+
+ALTER TABLE checkout_sessions ALTER COLUMN expires_at SET NOT NULL;
+
+CREATE INDEX idx_checkout_sessions_open ON checkout_sessions (user_id, expires_at)
+    WHERE completed_at IS NULL;
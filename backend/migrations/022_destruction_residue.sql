@@ -0,0 +1,9 @@
+-- Forensic residue includes plain-text metadata
+--
+-- Shredding the member's key leaves plain-text columns readable in WAL and backups: the
+-- password hash, IP addresses, user agents and device fingerprints in sessions and security
+-- events, and Stripe ids. Runs that leave any of those behind record level 1, not 0, and list
+-- them in details->'plaintext_residue'.
+
+COMMENT ON COLUMN destruction_logs.forensic_residue_level IS
+    '0: member key shredded and none of their data was stored outside it; 1: key shredded but some data stays readable in WAL and backups (fields predating per-member keys, or plain-text metadata listed in details.plaintext_residue); 2: key not shredded, data recoverable from WAL and backups';
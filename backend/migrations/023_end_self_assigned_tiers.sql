@@ -0,0 +1,20 @@
+-- End the complimentary subscriptions migration 013 created
+--
+-- 013 turned each member's `membership_tier` into an open-ended complimentary subscription, but
+-- registering clients could set that column to any tier, so members who never paid kept paid
+-- tiers for good. Those subscriptions (no Stripe id, no period end) are cancelled, which moves
+-- their members to basic; canary accounts keep theirs. Members who had paid outside Stripe can be
+-- given their tier back with a prepaid access code.
+
+SET app.role = 'system';
+
+UPDATE subscriptions s
+SET status = 'canceled', current_period_end = NOW(), updated_at = NOW()
+FROM users u
+WHERE u.id = s.user_id
+  AND NOT u.is_canary
+  AND s.stripe_subscription_id IS NULL
+  AND s.current_period_end IS NULL
+  AND s.status IN ('active', 'trialing', 'past_due');
+
+RESET app.role;
@@ -0,0 +1,7 @@
+-- Webhook signing secrets are stored under the member's own key
+--
+-- The ciphertext does not fit the old 64-character column. Secrets already saved in plaintext
+-- are encrypted by the server at startup, along with the other fields written before
+-- encryption was enabled.
+
+ALTER TABLE notification_preferences ALTER COLUMN webhook_secret TYPE TEXT;
@@ -0,0 +1,48 @@
+-- Conversations and shared files: the member content plans meter
+--
+-- Creating a conversation needs the plan's `messaging` feature and room under
+-- `max_conversations`; uploading a file needs `file_sharing`, `max_file_size_mb` and room under
+-- `max_storage_gb`. Reading and deleting never do, so content over a smaller plan's limits stays
+-- readable. Titles, file names and file contents are encrypted under the member's key.
+
+CREATE TABLE conversations (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    title TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_conversations_user_id ON conversations (user_id, created_at);
+
+CREATE TABLE files (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    content_type VARCHAR(255) NOT NULL,
+    size_bytes BIGINT NOT NULL CHECK (size_bytes >= 0),
+    content TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_files_user_id ON files (user_id, created_at);
+
+-- Members see only their own content. Administrators have no route to it, so only the system
+-- role (jobs, destruction) is privileged.
+ALTER TABLE conversations ENABLE ROW LEVEL SECURITY;
+ALTER TABLE conversations FORCE ROW LEVEL SECURITY;
+ALTER TABLE files ENABLE ROW LEVEL SECURITY;
+ALTER TABLE files FORCE ROW LEVEL SECURITY;
+
+CREATE POLICY conversations_system ON conversations
+    USING (app_current_role() = 'system')
+    WITH CHECK (app_current_role() = 'system');
+CREATE POLICY conversations_member ON conversations
+    USING (app_current_role() = 'member' AND user_id = app_current_user_id())
+    WITH CHECK (user_id = app_current_user_id());
+
+CREATE POLICY files_system ON files
+    USING (app_current_role() = 'system')
+    WITH CHECK (app_current_role() = 'system');
+CREATE POLICY files_member ON files
+    USING (app_current_role() = 'member' AND user_id = app_current_user_id())
+    WITH CHECK (user_id = app_current_user_id());
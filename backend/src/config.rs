@@ -1,4 +1,137 @@
-use serde::Deserialize;
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Missing(std::env::VarError),
+    Invalid(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Missing(e) => write!(f, "Missing configuration: {}", e),
+            ConfigError::Invalid(e) => write!(f, "Invalid configuration: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::env::VarError> for ConfigError {
+    fn from(err: std::env::VarError) -> Self {
+        ConfigError::Missing(err)
+    }
+}
+
+/// One external destination for security events, e.g. a SIEM collector.
+#[derive(Debug, Deserialize, Clone)]
+pub struct EventSinkConfig {
+    #[serde(default = "default_sink_min_risk_level")]
+    pub min_risk_level: i32,
+    #[serde(flatten)]
+    pub kind: EventSinkKind,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventSinkKind {
+    Syslog {
+        address: String,
+        #[serde(default)]
+        protocol: SyslogProtocol,
+        #[serde(default)]
+        format: SyslogFormat,
+    },
+    File {
+        path: String,
+        #[serde(default = "default_file_max_bytes")]
+        max_bytes: u64,
+        #[serde(default = "default_file_max_files")]
+        max_files: u32,
+    },
+    Webhook {
+        url: String,
+        secret: Option<String>,
+    },
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogProtocol {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFormat {
+    #[default]
+    Rfc5424,
+    Cef,
+}
+
+/// A local file of addresses/CIDR blocks (one per line, `#` comments) such as the Tor exit list.
+#[derive(Debug, Deserialize, Clone)]
+pub struct IpListConfig {
+    pub name: String,
+    pub path: String,
+    pub action: IpListAction,
+    #[serde(default = "default_list_risk_adjustment")]
+    pub risk_adjustment: i32,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IpListAction {
+    Deny,
+    ElevatedRisk,
+}
+
+/// How long security records are kept; see `RetentionService`.
+#[derive(Debug, Deserialize, Clone, Copy, Serialize)]
+pub struct RetentionConfig {
+    pub security_events_days: i64,
+    pub user_sessions_days: i64,
+    /// After this many days IP addresses are cut to their /24 (IPv4) or /48 (IPv6) network
+    /// and user agents are dropped.
+    pub ip_truncation_days: i64,
+}
+
+/// Stripe Checkout and customer portal settings; see `BillingService`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BillingConfig {
+    /// Point at a local mock (e.g. stripe-mock on `http://localhost:12111`) in tests
+    pub stripe_api_base: String,
+    pub currency: String,
+    /// Frontend page members return to from Checkout and the portal
+    pub return_url: String,
+    /// How far a webhook's signed timestamp may be from now
+    pub webhook_tolerance_seconds: i64,
+    /// How long a `past_due` subscription keeps its tier before the member moves to basic
+    pub grace_period_days: i32,
+    /// Days between payment reminders during the grace period
+    pub reminder_interval_days: i32,
+    /// How often `SubscriptionScheduler` runs
+    pub scheduler_interval_seconds: u64,
+}
+
+fn default_sink_min_risk_level() -> i32 {
+    1
+}
+
+fn default_file_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_file_max_files() -> u32 {
+    5
+}
+
+fn default_list_risk_adjustment() -> i32 {
+    3
+}
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
@@ -7,18 +140,34 @@ pub struct Config {
     pub jwt_expiration: u64,
     pub host: String,
     pub port: u16,
+    // Not read yet: S3 file storage and Redis sessions are for production deployments
+    #[allow(dead_code)]
     pub aws_region: Option<String>,
+    #[allow(dead_code)]
     pub s3_bucket_name: Option<String>,
     pub stripe_secret_key: Option<String>,
     pub stripe_webhook_secret: Option<String>,
+    #[allow(dead_code)]
     pub redis_url: Option<String>,
     pub argon2_memory_cost: u32,
     pub argon2_time_cost: u32,
     pub argon2_parallelism: u32,
+    pub mail_from: String,
+    pub password_min_entropy_bits: f64,
+    pub breached_passwords_dir: Option<String>,
+    pub security_event_sinks: Vec<EventSinkConfig>,
+    pub security_event_journal: String,
+    pub ip_reputation_lists: Vec<IpListConfig>,
+    /// Reverse proxies whose `X-Forwarded-For` is believed; everyone else's is ignored
+    pub trusted_proxies: Vec<IpNetwork>,
+    pub field_encryption_keyring: String,
+    pub user_key_dir: String,
+    pub retention: RetentionConfig,
+    pub billing: BillingConfig,
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, std::env::VarError> {
+    pub fn from_env() -> Result<Self, ConfigError> {
         dotenvy::dotenv().ok();
         
         Ok(Config {
@@ -51,6 +200,84 @@ impl Config {
                 .unwrap_or_else(|_| "4".to_string())
                 .parse()
                 .unwrap_or(4),
+            mail_from: std::env::var("MAIL_FROM")
+                .unwrap_or_else(|_| "no-reply@thecircle.local".to_string()),
+            password_min_entropy_bits: std::env::var("PASSWORD_MIN_ENTROPY_BITS")
+                .unwrap_or_else(|_| "40".to_string())
+                .parse()
+                .unwrap_or(40.0),
+            breached_passwords_dir: std::env::var("BREACHED_PASSWORDS_DIR").ok(),
+            security_event_sinks: match std::env::var("SECURITY_EVENT_SINKS") {
+                Ok(raw) if !raw.trim().is_empty() => serde_json::from_str(&raw).map_err(|e| {
+                    ConfigError::Invalid(format!("SECURITY_EVENT_SINKS: {}", e))
+                })?,
+                _ => Vec::new(),
+            },
+            security_event_journal: std::env::var("SECURITY_EVENT_JOURNAL")
+                .unwrap_or_else(|_| "data/security_events.journal".to_string()),
+            ip_reputation_lists: match std::env::var("IP_REPUTATION_LISTS") {
+                Ok(raw) if !raw.trim().is_empty() => serde_json::from_str(&raw).map_err(|e| {
+                    ConfigError::Invalid(format!("IP_REPUTATION_LISTS: {}", e))
+                })?,
+                _ => Vec::new(),
+            },
+            trusted_proxies: std::env::var("TRUSTED_PROXIES")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|proxy| !proxy.is_empty())
+                .map(|proxy| {
+                    proxy.parse().map_err(|_| {
+                        ConfigError::Invalid(format!("TRUSTED_PROXIES: {:?} is not a CIDR block", proxy))
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+            field_encryption_keyring: std::env::var("FIELD_ENCRYPTION_KEYRING")
+                .unwrap_or_else(|_| "data/keyring.json".to_string()),
+            user_key_dir: std::env::var("USER_KEY_DIR")
+                .unwrap_or_else(|_| "data/user-keys".to_string()),
+            retention: RetentionConfig {
+                security_events_days: std::env::var("SECURITY_EVENT_RETENTION_DAYS")
+                    .unwrap_or_else(|_| "365".to_string())
+                    .parse()
+                    .unwrap_or(365),
+                user_sessions_days: std::env::var("SESSION_RETENTION_DAYS")
+                    .unwrap_or_else(|_| "90".to_string())
+                    .parse()
+                    .unwrap_or(90),
+                ip_truncation_days: std::env::var("IP_TRUNCATION_DAYS")
+                    .unwrap_or_else(|_| "30".to_string())
+                    .parse()
+                    .unwrap_or(30),
+            },
+            billing: BillingConfig {
+                stripe_api_base: std::env::var("STRIPE_API_BASE")
+                    .unwrap_or_else(|_| "https://api.stripe.com".to_string())
+                    .trim_end_matches('/')
+                    .to_string(),
+                currency: std::env::var("BILLING_CURRENCY")
+                    .unwrap_or_else(|_| "usd".to_string())
+                    .to_lowercase(),
+                return_url: std::env::var("BILLING_RETURN_URL")
+                    .unwrap_or_else(|_| "http://localhost:3000/account/billing".to_string()),
+                webhook_tolerance_seconds: std::env::var("STRIPE_WEBHOOK_TOLERANCE_SECONDS")
+                    .unwrap_or_else(|_| "300".to_string())
+                    .parse()
+                    .unwrap_or(300),
+                grace_period_days: std::env::var("BILLING_GRACE_PERIOD_DAYS")
+                    .unwrap_or_else(|_| "7".to_string())
+                    .parse()
+                    .unwrap_or(7),
+                reminder_interval_days: std::env::var("BILLING_REMINDER_INTERVAL_DAYS")
+                    .unwrap_or_else(|_| "2".to_string())
+                    .parse()
+                    .unwrap_or(2),
+                scheduler_interval_seconds: std::env::var("BILLING_SCHEDULER_INTERVAL_SECONDS")
+                    .unwrap_or_else(|_| "3600".to_string())
+                    .parse()
+                    .unwrap_or(3600)
+                    .max(1),
+            },
         })
     }
 }
\ No newline at end of file
@@ -0,0 +1,196 @@
+use crate::middleware::{AuthUser, Authorized, ElevatedUser, ManageAccessCodes};
+use crate::models::{CreateAccessCodesRequest, RedeemAccessCodeRequest, SecurityEventType};
+use crate::services::AccessCodeError;
+use crate::utils::{user_agent, AppState};
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Json,
+};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use uuid::Uuid;
+use validator::Validate;
+
+/// Codes are bearer credentials worth paid months, so issuing them also needs a fresh step-up.
+/// The plain-text codes are in this response only.
+pub async fn issue_access_codes(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<ManageAccessCodes>,
+    ElevatedUser(_elevated): ElevatedUser,
+    headers: HeaderMap,
+    Json(payload): Json<CreateAccessCodesRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    validate(&payload)?;
+
+    let issued = app_state
+        .access_code_service
+        .issue(admin.user_id, payload)
+        .await
+        .map_err(access_code_error)?;
+
+    app_state
+        .security_service
+        .log_security_event(
+            Some(admin.user_id),
+            SecurityEventType::AccessCodesIssued {
+                batch_id: issued.batch.id,
+                tier: issued.batch.tier.clone(),
+                months: issued.batch.months,
+                quantity: issued.batch.quantity,
+                issued_by: admin.user_id,
+            },
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await;
+
+    Ok(Json(json!({
+        "batch": issued.batch,
+        "codes": issued.codes
+    })))
+}
+
+pub async fn list_access_code_batches(
+    State(app_state): State<AppState>,
+    Authorized(_admin, _): Authorized<ManageAccessCodes>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.access_code_service.list_batches().await {
+        Ok(batches) => Ok(Json(json!({ "batches": batches }))),
+        Err(e) => Err(access_code_error(e)),
+    }
+}
+
+/// Revokes the batch's unredeemed codes.
+pub async fn revoke_access_codes(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<ManageAccessCodes>,
+    headers: HeaderMap,
+    Path(batch_id): Path<Uuid>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let (batch, revoked) = app_state
+        .access_code_service
+        .revoke(batch_id)
+        .await
+        .map_err(access_code_error)?;
+
+    app_state
+        .security_service
+        .log_security_event(
+            Some(admin.user_id),
+            SecurityEventType::AccessCodesRevoked {
+                batch_id,
+                revoked,
+                revoked_by: admin.user_id,
+            },
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await;
+
+    Ok(Json(json!({
+        "batch": batch,
+        "revoked": revoked
+    })))
+}
+
+/// Starts or extends the caller's prepaid membership.
+pub async fn redeem_access_code(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    auth: AuthUser,
+    headers: HeaderMap,
+    Json(payload): Json<RedeemAccessCodeRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    validate(&payload)?;
+
+    let event = match app_state
+        .access_code_service
+        .redeem(auth.user_id, &payload.code)
+        .await
+    {
+        Ok(redemption) => {
+            app_state
+                .security_service
+                .log_security_event(
+                    Some(auth.user_id),
+                    SecurityEventType::AccessCodeRedeemed {
+                        batch_id: redemption.batch_id,
+                        subscription_id: redemption.subscription_id,
+                        tier: redemption.tier.clone(),
+                        months: redemption.months,
+                    },
+                    Some(addr.ip()),
+                    user_agent(&headers),
+                )
+                .await;
+            return Ok(Json(json!({ "redemption": redemption })));
+        }
+        Err(AccessCodeError::InvalidCode(reason)) => SecurityEventType::AccessCodeRejected {
+            reason: reason.to_string(),
+        },
+        Err(e) => return Err(access_code_error(e)),
+    };
+
+    app_state
+        .security_service
+        .log_security_event(
+            Some(auth.user_id),
+            event,
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await;
+
+    // Same answer whatever was wrong, so codes can't be probed for state
+    Err((
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "error": "Invalid or expired access code"
+        })),
+    ))
+}
+
+fn validate(payload: &impl Validate) -> Result<(), (StatusCode, Json<Value>)> {
+    payload.validate().map_err(|errors| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        )
+    })
+}
+
+fn access_code_error(error: AccessCodeError) -> (StatusCode, Json<Value>) {
+    let (status, message) = match error {
+        AccessCodeError::TierNotOffered | AccessCodeError::BatchNotFound => {
+            (StatusCode::NOT_FOUND, error.to_string())
+        }
+        AccessCodeError::Invalid(_) => (StatusCode::BAD_REQUEST, error.to_string()),
+        AccessCodeError::InvalidCode(_) => (
+            StatusCode::BAD_REQUEST,
+            "Invalid or expired access code".to_string(),
+        ),
+        AccessCodeError::AlreadySubscribed => (
+            StatusCode::CONFLICT,
+            "Codes can only start or extend a prepaid membership; manage your subscription \
+             from billing instead"
+                .to_string(),
+        ),
+        AccessCodeError::DatabaseError(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Access code operation failed".to_string(),
+        ),
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
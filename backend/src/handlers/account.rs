@@ -0,0 +1,116 @@
+use crate::handlers::auth::weak_password_response;
+use crate::middleware::AuthUser;
+use crate::models::{ChangeEmailRequest, ChangePasswordRequest, ConfirmEmailChangeRequest};
+use crate::services::AuthError;
+use crate::utils::{user_agent, AppState};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    response::Json,
+};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use validator::Validate;
+
+pub async fn change_password(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    auth: AuthUser,
+    headers: HeaderMap,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state
+        .auth_service
+        .change_password(
+            auth.user_id,
+            auth.session_id,
+            payload,
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await
+    {
+        Ok(()) => Ok(Json(json!({
+            "message": "Password changed successfully. Other sessions have been signed out."
+        }))),
+        Err(e) => Err(account_error(e, "Password change failed")),
+    }
+}
+
+pub async fn request_email_change(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    auth: AuthUser,
+    headers: HeaderMap,
+    Json(payload): Json<ChangeEmailRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        ));
+    }
+
+    match app_state
+        .auth_service
+        .request_email_change(auth.user_id, payload, Some(addr.ip()), user_agent(&headers))
+        .await
+    {
+        Ok(expires_at) => Ok(Json(json!({
+            "message": "Check your new email address for a confirmation code.",
+            "expires_at": expires_at
+        }))),
+        Err(e) => Err(account_error(e, "Email change failed")),
+    }
+}
+
+pub async fn confirm_email_change(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    auth: AuthUser,
+    headers: HeaderMap,
+    Json(payload): Json<ConfirmEmailChangeRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state
+        .auth_service
+        .confirm_email_change(
+            auth.user_id,
+            auth.session_id,
+            &payload.token,
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await
+    {
+        Ok(user) => Ok(Json(json!({
+            "message": "Email address updated. Other sessions have been signed out.",
+            "user": user
+        }))),
+        Err(e) => Err(account_error(e, "Email change failed")),
+    }
+}
+
+fn account_error(error: AuthError, fallback: &str) -> (StatusCode, Json<Value>) {
+    let (status, message) = match error {
+        AuthError::WeakPassword(violations) => return weak_password_response(&violations),
+        AuthError::InvalidCredentials => (StatusCode::UNAUTHORIZED, "Current password is incorrect"),
+        AuthError::AccountLocked => (StatusCode::LOCKED, "Account is locked"),
+        AuthError::DestructionTriggered => {
+            (StatusCode::GONE, "Account has been destroyed due to security policy")
+        }
+        AuthError::UserAlreadyExists => (StatusCode::CONFLICT, "Email address is already in use"),
+        AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "Invalid or expired confirmation code"),
+        AuthError::UserNotFound => (StatusCode::NOT_FOUND, "User not found"),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, fallback),
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
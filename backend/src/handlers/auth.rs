@@ -1,8 +1,14 @@
-use crate::models::{CreateUserRequest, LoginRequest};
+use crate::middleware::AuthUser;
+use crate::models::{
+    CreateUserRequest, ForgotPasswordRequest, LoginRequest, RefreshTokenRequest,
+    ResetPasswordRequest, StepUpRequest,
+};
+use crate::services::PasswordViolation;
+use crate::utils::user_agent;
 use crate::utils::AppState;
 use axum::{
     extract::{ConnectInfo, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::Json,
 };
 use serde_json::{json, Value};
@@ -11,7 +17,7 @@ use validator::Validate;
 
 pub async fn register(
     State(app_state): State<AppState>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     Json(payload): Json<CreateUserRequest>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
     // Validate request
@@ -24,12 +30,20 @@ pub async fn register(
             })),
         ));
     }
+    if payload.membership_tier.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "membership_tier cannot be chosen at registration; it follows your subscription"
+            })),
+        ));
+    }
 
     match app_state.auth_service.register_user(payload).await {
         Ok(user) => {
             let response = json!({
                 "message": "User registered successfully. Please check your email for verification.",
-                "user": user.to_public()
+                "user": user
             });
             Ok(Json(response))
         }
@@ -38,6 +52,9 @@ pub async fn register(
                 crate::services::AuthError::UserAlreadyExists => {
                     (StatusCode::CONFLICT, "User already exists")
                 }
+                crate::services::AuthError::WeakPassword(violations) => {
+                    return Err(weak_password_response(&violations));
+                }
                 _ => (StatusCode::INTERNAL_SERVER_ERROR, "Registration failed"),
             };
             
@@ -95,6 +112,7 @@ pub async fn login_initiate(
 pub async fn login_complete(
     State(app_state): State<AppState>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(payload): Json<LoginRequest>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
     // Validate request
@@ -108,12 +126,9 @@ pub async fn login_complete(
         ));
     }
 
-    // Extract user agent from headers (would need request headers in real implementation)
-    let user_agent = None; // For simplicity
-
     match app_state
         .auth_service
-        .complete_login(payload, Some(addr.ip()), user_agent)
+        .complete_login(payload, Some(addr.ip()), user_agent(&headers))
         .await
     {
         Ok(login_response) => Ok(Json(serde_json::to_value(login_response).unwrap())),
@@ -128,6 +143,13 @@ pub async fn login_complete(
                 crate::services::AuthError::DestructionTriggered => {
                     (StatusCode::GONE, "Account has been destroyed due to security policy")
                 }
+                crate::services::AuthError::NetworkNotAllowed => (
+                    StatusCode::FORBIDDEN,
+                    "Your organization does not allow sign-in from this network",
+                ),
+                crate::services::AuthError::AccountDeactivated => {
+                    (StatusCode::FORBIDDEN, "Account is deactivated")
+                }
                 _ => (StatusCode::INTERNAL_SERVER_ERROR, "Login failed"),
             };
 
@@ -152,16 +174,177 @@ pub async fn logout(
     })))
 }
 
+/// Re-verifies the signed-in member and returns a short-lived elevated access token.
+pub async fn step_up(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    auth: AuthUser,
+    headers: HeaderMap,
+    Json(payload): Json<StepUpRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state
+        .auth_service
+        .step_up(
+            auth.user_id,
+            auth.session_id,
+            payload,
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await
+    {
+        Ok(step_up_response) => Ok(Json(serde_json::to_value(step_up_response).unwrap())),
+        Err(e) => {
+            let (status, message) = match e {
+                crate::services::AuthError::InvalidCredentials => {
+                    (StatusCode::UNAUTHORIZED, "Invalid credentials")
+                }
+                crate::services::AuthError::MfaRequired => {
+                    (StatusCode::UNAUTHORIZED, "MFA code required")
+                }
+                crate::services::AuthError::AccountLocked => {
+                    (StatusCode::LOCKED, "Account is locked")
+                }
+                crate::services::AuthError::DestructionTriggered => {
+                    (StatusCode::GONE, "Account has been destroyed due to security policy")
+                }
+                _ => (StatusCode::INTERNAL_SERVER_ERROR, "Step-up failed"),
+            };
+
+            Err((
+                status,
+                Json(json!({
+                    "error": message
+                })),
+            ))
+        }
+    }
+}
+
 pub async fn refresh_token(
-    State(_app_state): State<AppState>,
-    Json(_payload): Json<Value>,
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<RefreshTokenRequest>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
-    // Implement refresh token logic
-    // For now, return not implemented
-    Err((
-        StatusCode::NOT_IMPLEMENTED,
+    match app_state
+        .auth_service
+        .refresh_session(&payload.refresh_token, Some(addr.ip()), user_agent(&headers))
+        .await
+    {
+        Ok(login_response) => Ok(Json(serde_json::to_value(login_response).unwrap())),
+        Err(e) => {
+            let (status, message) = match e {
+                crate::services::AuthError::InvalidToken => {
+                    (StatusCode::UNAUTHORIZED, "Invalid refresh token")
+                }
+                crate::services::AuthError::AccountLocked => {
+                    (StatusCode::LOCKED, "Account is locked")
+                }
+                crate::services::AuthError::NetworkNotAllowed => (
+                    StatusCode::FORBIDDEN,
+                    "Your organization does not allow sign-in from this network",
+                ),
+                _ => (StatusCode::INTERNAL_SERVER_ERROR, "Token refresh failed"),
+            };
+
+            Err((
+                status,
+                Json(json!({
+                    "error": message
+                })),
+            ))
+        }
+    }
+}
+
+pub async fn forgot_password(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(payload): Json<ForgotPasswordRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        ));
+    }
+
+    match app_state
+        .auth_service
+        .request_password_reset(&payload.email, Some(addr.ip()))
+        .await
+    {
+        Ok(()) => Ok(Json(json!({
+            "message": "If an account exists for that email, a reset code has been sent."
+        }))),
+        Err(_) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "error": "Password reset failed"
+            })),
+        )),
+    }
+}
+
+pub async fn reset_password(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state
+        .auth_service
+        .reset_password(
+            &payload.token,
+            &payload.new_password,
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await
+    {
+        Ok(()) => Ok(Json(json!({
+            "message": "Password has been reset. Please log in with your new password."
+        }))),
+        Err(e) => {
+            let (status, message) = match e {
+                crate::services::AuthError::WeakPassword(violations) => {
+                    return Err(weak_password_response(&violations));
+                }
+                crate::services::AuthError::InvalidToken => {
+                    (StatusCode::BAD_REQUEST, "Invalid or expired reset code")
+                }
+                _ => (StatusCode::INTERNAL_SERVER_ERROR, "Password reset failed"),
+            };
+
+            Err((
+                status,
+                Json(json!({
+                    "error": message
+                })),
+            ))
+        }
+    }
+}
+
+pub fn weak_password_response(violations: &[PasswordViolation]) -> (StatusCode, Json<Value>) {
+    let reasons: Vec<Value> = violations
+        .iter()
+        .map(|violation| {
+            let mut reason = serde_json::to_value(violation).unwrap_or_else(|_| json!({}));
+            reason["message"] = json!(violation.message());
+            reason
+        })
+        .collect();
+
+    (
+        StatusCode::BAD_REQUEST,
         Json(json!({
-            "error": "Refresh token functionality not yet implemented"
+            "error": "Password does not meet the password policy",
+            "reasons": reasons
         })),
-    ))
-}
\ No newline at end of file
+    )
+}
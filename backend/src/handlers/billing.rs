@@ -0,0 +1,226 @@
+use crate::middleware::AuthUser;
+use crate::models::{CheckoutRequest, PlanChangeRequest};
+use crate::services::{BillingError, PromotionError};
+use crate::utils::AppState;
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::Json,
+};
+use serde_json::{json, Value};
+use validator::Validate;
+
+/// The caller's tier and subscription status, including any grace period after a failed payment.
+pub async fn billing_status(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.billing_service.status_for(auth.user_id).await {
+        Ok(status) => Ok(Json(json!(status))),
+        Err(e) => Err(billing_error(e)),
+    }
+}
+
+/// Starts Stripe Checkout for an offered tier at its monthly or yearly price.
+pub async fn checkout(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+    Json(payload): Json<CheckoutRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        ));
+    }
+
+    match app_state
+        .billing_service
+        .start_checkout(auth.user_id, payload)
+        .await
+    {
+        Ok(session) => Ok(Json(json!(session))),
+        Err(e) => Err(billing_error(e)),
+    }
+}
+
+/// A link to the Stripe customer portal for payment details, invoices and cancellation.
+pub async fn portal(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.billing_service.open_portal(auth.user_id).await {
+        Ok(url) => Ok(Json(json!({ "portal_url": url }))),
+        Err(e) => Err(billing_error(e)),
+    }
+}
+
+/// What changing to another plan or billing interval would cost now, without changing anything.
+pub async fn preview_plan_change(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+    Json(payload): Json<PlanChangeRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        ));
+    }
+
+    match app_state
+        .billing_service
+        .preview_plan_change(auth.user_id, payload)
+        .await
+    {
+        Ok(preview) => Ok(Json(json!(preview))),
+        Err(e) => Err(billing_error(e)),
+    }
+}
+
+/// Upgrades now with a prorated charge, or schedules a downgrade for the end of the period.
+pub async fn change_plan(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+    Json(payload): Json<PlanChangeRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        ));
+    }
+
+    match app_state
+        .billing_service
+        .change_plan(auth.user_id, payload)
+        .await
+    {
+        Ok(change) => Ok(Json(json!(change))),
+        Err(e) => Err(billing_error(e)),
+    }
+}
+
+pub async fn cancel_plan_change(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state
+        .billing_service
+        .cancel_scheduled_plan_change(auth.user_id)
+        .await
+    {
+        Ok(change) => Ok(Json(json!(change))),
+        Err(e) => Err(billing_error(e)),
+    }
+}
+
+pub async fn plan_history(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.billing_service.plan_changes(auth.user_id, None).await {
+        Ok(changes) => Ok(Json(json!({ "plan_changes": changes }))),
+        Err(e) => Err(billing_error(e)),
+    }
+}
+
+/// Stripe webhook deliveries, authenticated by their `Stripe-Signature` header.
+pub async fn stripe_webhook(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let signature = headers
+        .get("stripe-signature")
+        .and_then(|value| value.to_str().ok());
+
+    match app_state
+        .billing_service
+        .handle_webhook(&body, signature)
+        .await
+    {
+        Ok(applied) => Ok(Json(json!({ "received": true, "duplicate": !applied }))),
+        Err(e) => Err(billing_error(e)),
+    }
+}
+
+fn billing_error(error: BillingError) -> (StatusCode, Json<Value>) {
+    let (status, message) = match error {
+        BillingError::TierNotOffered => (StatusCode::BAD_REQUEST, "Membership tier is not offered"),
+        BillingError::NotPurchasable => (
+            StatusCode::BAD_REQUEST,
+            "Membership tier has no price for that interval",
+        ),
+        BillingError::AlreadySubscribed => (
+            StatusCode::CONFLICT,
+            "You already have a subscription; change plan or manage it from the billing portal",
+        ),
+        BillingError::CheckoutInProgress(expires_at) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "error": "You already have a checkout open; finish it or start again once it expires",
+                    "expires_at": expires_at
+                })),
+            )
+        }
+        BillingError::NoSubscription => (
+            StatusCode::NOT_FOUND,
+            "You have no paid subscription to change; start one with checkout",
+        ),
+        BillingError::SubscriptionPending => (
+            StatusCode::CONFLICT,
+            "Your subscription is still being set up; try again shortly",
+        ),
+        BillingError::PaymentOverdue => (
+            StatusCode::CONFLICT,
+            "Update your payment details before changing plan",
+        ),
+        BillingError::PaymentDeclined => {
+            (StatusCode::PAYMENT_REQUIRED, "Your payment method was declined")
+        }
+        BillingError::SamePlan => (StatusCode::BAD_REQUEST, "You are already on that plan"),
+        BillingError::NoScheduledChange => {
+            (StatusCode::NOT_FOUND, "No plan change is scheduled")
+        }
+        BillingError::Promotion(PromotionError::NotApplicable) => (
+            StatusCode::BAD_REQUEST,
+            "Promotion code does not apply to that plan",
+        ),
+        BillingError::Promotion(_) => (
+            StatusCode::BAD_REQUEST,
+            "Promotion code is invalid or has expired",
+        ),
+        BillingError::NoBillingAccount => (StatusCode::NOT_FOUND, "No billing account"),
+        BillingError::InvalidSignature => (StatusCode::BAD_REQUEST, "Invalid signature"),
+        BillingError::InvalidEvent => (StatusCode::BAD_REQUEST, "Malformed event"),
+        BillingError::NotConfigured => {
+            (StatusCode::SERVICE_UNAVAILABLE, "Billing is not configured")
+        }
+        BillingError::Provider(ref e) => {
+            tracing::error!("Billing provider error: {}", e);
+            (StatusCode::BAD_GATEWAY, "Payment provider error")
+        }
+        BillingError::DatabaseError(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, "Billing operation failed")
+        }
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
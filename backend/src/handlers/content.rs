@@ -0,0 +1,177 @@
+use crate::middleware::{entitlement_error, AuthUser, FileSharing, Messaging, RequireFeature};
+use crate::models::{CreateConversationRequest, UploadFileQuery};
+use crate::services::ContentError;
+use crate::utils::AppState;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use validator::Validate;
+
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Largest upload read whatever the plan allows: files are stored in the database, which caps a
+/// value at 1 GB and holds contents base64-encoded.
+pub const MAX_UPLOAD_BYTES: usize = 256 * 1024 * 1024;
+
+/// Every conversation the caller has, including any over their plan's limit.
+pub async fn list_conversations(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.content_service.list_conversations(auth.user_id).await {
+        Ok(conversations) => Ok(Json(json!({ "conversations": conversations }))),
+        Err(e) => Err(content_error(e)),
+    }
+}
+
+pub async fn create_conversation(
+    State(app_state): State<AppState>,
+    RequireFeature(auth, entitlements, _): RequireFeature<Messaging>,
+    Json(payload): Json<CreateConversationRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    validate(&payload)?;
+
+    match app_state
+        .content_service
+        .create_conversation(auth.user_id, &entitlements, &payload.title)
+        .await
+    {
+        Ok(conversation) => Ok(Json(json!({ "conversation": conversation }))),
+        Err(e) => Err(content_error(e)),
+    }
+}
+
+pub async fn delete_conversation(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+    Path(conversation_id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, Json<Value>)> {
+    app_state
+        .content_service
+        .delete_conversation(auth.user_id, conversation_id)
+        .await
+        .map_err(content_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Every file the caller has shared, without contents.
+pub async fn list_files(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.content_service.list_files(auth.user_id).await {
+        Ok(files) => Ok(Json(json!({ "files": files }))),
+        Err(e) => Err(content_error(e)),
+    }
+}
+
+/// The request body is the file; `?name=` names it and `Content-Type` is kept for downloads.
+pub async fn upload_file(
+    State(app_state): State<AppState>,
+    RequireFeature(auth, entitlements, _): RequireFeature<FileSharing>,
+    Query(query): Query<UploadFileQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    validate(&query)?;
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 255)
+        .unwrap_or(DEFAULT_CONTENT_TYPE);
+
+    match app_state
+        .content_service
+        .upload_file(auth.user_id, &entitlements, &query.name, content_type, &body)
+        .await
+    {
+        Ok(file) => Ok(Json(json!({ "file": file }))),
+        Err(e) => Err(content_error(e)),
+    }
+}
+
+/// Always served as a download, so stored HTML or scripts never run on the API's origin.
+pub async fn download_file(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+    Path(file_id): Path<Uuid>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let (file, content) = app_state
+        .content_service
+        .download_file(auth.user_id, file_id)
+        .await
+        .map_err(content_error)?;
+
+    let content_type = HeaderValue::from_str(&file.content_type)
+        .unwrap_or(HeaderValue::from_static(DEFAULT_CONTENT_TYPE));
+    let file_name: String = file
+        .name
+        .chars()
+        .map(|c| if c == ' ' || (c.is_ascii_graphic() && c != '"' && c != '\\') { c } else { '_' })
+        .collect();
+    let disposition = HeaderValue::from_str(&format!("attachment; filename=\"{}\"", file_name))
+        .unwrap_or(HeaderValue::from_static("attachment"));
+
+    Ok((
+        [
+            (CONTENT_TYPE, content_type),
+            (CONTENT_DISPOSITION, disposition),
+            (X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+        ],
+        content,
+    )
+        .into_response())
+}
+
+pub async fn delete_file(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+    Path(file_id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, Json<Value>)> {
+    app_state
+        .content_service
+        .delete_file(auth.user_id, file_id)
+        .await
+        .map_err(content_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn validate(payload: &impl Validate) -> Result<(), (StatusCode, Json<Value>)> {
+    payload.validate().map_err(|errors| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        )
+    })
+}
+
+fn content_error(error: ContentError) -> (StatusCode, Json<Value>) {
+    let (status, message) = match error {
+        ContentError::Entitlement(e) => return entitlement_error(e),
+        ContentError::NotFound => (StatusCode::NOT_FOUND, error.to_string()),
+        ContentError::DatabaseError(_) | ContentError::Encryption(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Content operation failed".to_string(),
+        ),
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
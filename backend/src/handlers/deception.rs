@@ -0,0 +1,127 @@
+use crate::middleware::{Authorized, ManageDeception};
+use crate::models::{CreateCanaryRequest, CreateHoneytokenRequest};
+use crate::services::DeceptionError;
+use crate::utils::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use validator::Validate;
+
+pub async fn list_canaries(
+    State(app_state): State<AppState>,
+    Authorized(admin, _): Authorized<ManageDeception>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.deception_service.list_canaries(admin.user_id).await {
+        Ok(canaries) => Ok(Json(json!({ "canaries": canaries }))),
+        Err(e) => Err(deception_error(e)),
+    }
+}
+
+pub async fn create_canary(
+    State(app_state): State<AppState>,
+    Authorized(admin, _): Authorized<ManageDeception>,
+    Json(payload): Json<CreateCanaryRequest>,
+) -> Result<(StatusCode, Json<Value>), (StatusCode, Json<Value>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        ));
+    }
+
+    match app_state.deception_service.create_canary(admin.user_id, payload).await {
+        Ok(canary) => Ok((StatusCode::CREATED, Json(json!({ "canary": canary })))),
+        Err(e) => Err(deception_error(e)),
+    }
+}
+
+pub async fn delete_canary(
+    State(app_state): State<AppState>,
+    Authorized(admin, _): Authorized<ManageDeception>,
+    Path(canary_id): Path<Uuid>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.deception_service.delete_canary(admin.user_id, canary_id).await {
+        Ok(()) => Ok(Json(json!({ "message": "Canary account deleted" }))),
+        Err(e) => Err(deception_error(e)),
+    }
+}
+
+pub async fn list_honeytokens(
+    State(app_state): State<AppState>,
+    Authorized(_admin, _): Authorized<ManageDeception>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.deception_service.list_honeytokens().await {
+        Ok(honeytokens) => Ok(Json(json!({ "honeytokens": honeytokens }))),
+        Err(e) => Err(deception_error(e)),
+    }
+}
+
+pub async fn create_honeytoken(
+    State(app_state): State<AppState>,
+    Authorized(admin, _): Authorized<ManageDeception>,
+    Json(payload): Json<CreateHoneytokenRequest>,
+) -> Result<(StatusCode, Json<Value>), (StatusCode, Json<Value>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        ));
+    }
+
+    match app_state
+        .deception_service
+        .create_honeytoken(&payload.label, admin.user_id)
+        .await
+    {
+        // The token is only ever shown here; plant it wherever a stolen refresh token would be found
+        Ok((honeytoken, token)) => Ok((
+            StatusCode::CREATED,
+            Json(json!({
+                "honeytoken": honeytoken,
+                "token": token
+            })),
+        )),
+        Err(e) => Err(deception_error(e)),
+    }
+}
+
+pub async fn delete_honeytoken(
+    State(app_state): State<AppState>,
+    Authorized(_admin, _): Authorized<ManageDeception>,
+    Path(honeytoken_id): Path<Uuid>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.deception_service.delete_honeytoken(honeytoken_id).await {
+        Ok(()) => Ok(Json(json!({ "message": "Honeytoken deleted" }))),
+        Err(e) => Err(deception_error(e)),
+    }
+}
+
+fn deception_error(error: DeceptionError) -> (StatusCode, Json<Value>) {
+    let (status, message) = match error {
+        DeceptionError::AlreadyExists => (StatusCode::CONFLICT, "User already exists"),
+        DeceptionError::NotFound => (StatusCode::NOT_FOUND, "Not found"),
+        DeceptionError::UnknownTier => (StatusCode::BAD_REQUEST, "Unknown membership tier"),
+        DeceptionError::DatabaseError(_)
+        | DeceptionError::HashingError
+        | DeceptionError::EncryptionError(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, "Deception operation failed")
+        }
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
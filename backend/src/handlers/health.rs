@@ -1,4 +1,9 @@
-use axum::{http::StatusCode, response::Json};
+use crate::utils::AppState;
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Json},
+};
 use serde_json::{json, Value};
 
 pub async fn health_check() -> Result<Json<Value>, StatusCode> {
@@ -20,4 +25,41 @@ pub async fn readiness_check() -> Result<Json<Value>, StatusCode> {
         },
         "timestamp": chrono::Utc::now().to_rfc3339()
     })))
-}
\ No newline at end of file
+}
+
+/// Prometheus text exposition of the security event pipeline counters.
+pub async fn metrics(State(app_state): State<AppState>) -> impl IntoResponse {
+    let events = app_state.security_service.event_metrics();
+
+    let body = format!(
+        "# HELP circle_security_events_queued Security events waiting to be written.\n\
+         # TYPE circle_security_events_queued gauge\n\
+         circle_security_events_queued {}\n\
+         # HELP circle_security_events_written_total Security events stored in Postgres.\n\
+         # TYPE circle_security_events_written_total counter\n\
+         circle_security_events_written_total {}\n\
+         # HELP circle_security_events_spilled_total Security events written to the local journal.\n\
+         # TYPE circle_security_events_spilled_total counter\n\
+         circle_security_events_spilled_total {}\n\
+         # HELP circle_security_events_replayed_total Journaled security events replayed into Postgres.\n\
+         # TYPE circle_security_events_replayed_total counter\n\
+         circle_security_events_replayed_total {}\n\
+         # HELP circle_security_events_dropped_total Security events that could not be stored anywhere.\n\
+         # TYPE circle_security_events_dropped_total counter\n\
+         circle_security_events_dropped_total {}\n\
+         # HELP circle_ip_requests_blocked_total Requests rejected by network deny rules.\n\
+         # TYPE circle_ip_requests_blocked_total counter\n\
+         circle_ip_requests_blocked_total {}\n",
+        events.queued,
+        events.written,
+        events.spilled,
+        events.replayed,
+        events.dropped,
+        app_state.ip_rules.blocked_total()
+    );
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        body,
+    )
+}
@@ -0,0 +1,138 @@
+use crate::middleware::{Authorized, ManageIpRules};
+use crate::models::{CreateIpRuleRequest, IpRuleQuery, SecurityEventType};
+use crate::services::IpRuleError;
+use crate::utils::{user_agent, AppState};
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Json,
+};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use uuid::Uuid;
+use validator::Validate;
+
+pub async fn list_ip_rules(
+    State(app_state): State<AppState>,
+    Authorized(_admin, _): Authorized<ManageIpRules>,
+    Query(params): Query<IpRuleQuery>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.ip_rules.list_rules(params.organization_id).await {
+        Ok(rules) => Ok(Json(json!({
+            "rules": rules,
+            "lists": app_state.ip_rules.list_summaries()
+        }))),
+        Err(e) => Err(ip_rule_error(e)),
+    }
+}
+
+pub async fn create_ip_rule(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<ManageIpRules>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateIpRuleRequest>,
+) -> Result<(StatusCode, Json<Value>), (StatusCode, Json<Value>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        ));
+    }
+
+    let rule = app_state
+        .ip_rules
+        .create_rule(payload, admin.user_id)
+        .await
+        .map_err(ip_rule_error)?;
+
+    app_state
+        .security_service
+        .log_security_event(
+            Some(admin.user_id),
+            SecurityEventType::IpRuleCreated {
+                rule_id: rule.id,
+                network: rule.network.to_string(),
+                action: rule.action.clone(),
+                organization_id: rule.organization_id,
+            },
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await;
+
+    Ok((StatusCode::CREATED, Json(json!({ "rule": rule }))))
+}
+
+pub async fn delete_ip_rule(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<ManageIpRules>,
+    headers: HeaderMap,
+    Path(rule_id): Path<Uuid>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let rule = app_state
+        .ip_rules
+        .delete_rule(rule_id)
+        .await
+        .map_err(ip_rule_error)?;
+
+    app_state
+        .security_service
+        .log_security_event(
+            Some(admin.user_id),
+            SecurityEventType::IpRuleDeleted {
+                rule_id: rule.id,
+                network: rule.network.to_string(),
+                action: rule.action,
+                organization_id: rule.organization_id,
+            },
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await;
+
+    Ok(Json(json!({
+        "message": "IP rule deleted"
+    })))
+}
+
+/// Re-reads the rule table and list files now instead of waiting for the next refresh.
+pub async fn reload_ip_rules(
+    State(app_state): State<AppState>,
+    Authorized(_admin, _): Authorized<ManageIpRules>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.ip_rules.reload().await {
+        Ok(()) => Ok(Json(json!({
+            "lists": app_state.ip_rules.list_summaries()
+        }))),
+        Err(e) => Err(ip_rule_error(e)),
+    }
+}
+
+fn ip_rule_error(error: IpRuleError) -> (StatusCode, Json<Value>) {
+    let status = match error {
+        IpRuleError::InvalidNetwork
+        | IpRuleError::InvalidAction
+        | IpRuleError::OrganizationRuleMustAllow => StatusCode::BAD_REQUEST,
+        IpRuleError::RuleNotFound => StatusCode::NOT_FOUND,
+        IpRuleError::DatabaseError(ref e) => {
+            tracing::error!("IP rule operation failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    let message = match error {
+        IpRuleError::DatabaseError(_) => "IP rule operation failed".to_string(),
+        _ => error.to_string(),
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
@@ -0,0 +1,187 @@
+use crate::middleware::{entitlement_error, AuthUser, Authorized, ManageMemberships, FEATURES};
+use crate::models::{CreateMembershipRequest, Membership, SecurityEventType, UpdateMembershipRequest};
+use crate::services::MembershipError;
+use crate::utils::{user_agent, AppState};
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Json,
+};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use uuid::Uuid;
+use validator::Validate;
+
+/// Tiers open to new subscribers, with their prices, limits and features.
+pub async fn list_memberships(
+    State(app_state): State<AppState>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.membership_service.list_offered().await {
+        Ok(memberships) => Ok(Json(json!({ "memberships": memberships }))),
+        Err(e) => Err(membership_error(e.into())),
+    }
+}
+
+/// The caller's current plan: tier, version, features and limits.
+pub async fn my_entitlements(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.entitlement_service.for_user(auth.user_id).await {
+        Ok(entitlements) => Ok(Json(json!({ "entitlements": entitlements }))),
+        Err(e) => Err(entitlement_error(e)),
+    }
+}
+
+/// Every version of every tier, including retired ones, with how many subscriptions use each.
+pub async fn list_membership_versions(
+    State(app_state): State<AppState>,
+    Authorized(_admin, _): Authorized<ManageMemberships>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.membership_service.list_versions().await {
+        Ok(versions) => Ok(Json(json!({ "memberships": versions }))),
+        Err(e) => Err(membership_error(e)),
+    }
+}
+
+pub async fn create_membership(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<ManageMemberships>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateMembershipRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    validate(&payload)?;
+    known_features(&payload.features)?;
+
+    let membership = app_state
+        .membership_service
+        .create(payload)
+        .await
+        .map_err(membership_error)?;
+    log_change(&app_state, &membership, "created", admin.user_id, addr, &headers).await;
+
+    Ok(Json(json!({ "membership": membership })))
+}
+
+/// Changing prices, features or limits publishes a new version; subscribers keep theirs.
+pub async fn update_membership(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<ManageMemberships>,
+    headers: HeaderMap,
+    Path(tier): Path<String>,
+    Json(payload): Json<UpdateMembershipRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    validate(&payload)?;
+    if let Some(features) = &payload.features {
+        known_features(features)?;
+    }
+
+    let membership = app_state
+        .membership_service
+        .update(&tier, payload)
+        .await
+        .map_err(membership_error)?;
+    log_change(&app_state, &membership, "updated", admin.user_id, addr, &headers).await;
+
+    Ok(Json(json!({ "membership": membership })))
+}
+
+/// Stops offering the tier to new subscribers.
+pub async fn retire_membership(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<ManageMemberships>,
+    headers: HeaderMap,
+    Path(tier): Path<String>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let membership = app_state
+        .membership_service
+        .retire(&tier)
+        .await
+        .map_err(membership_error)?;
+    log_change(&app_state, &membership, "retired", admin.user_id, addr, &headers).await;
+
+    Ok(Json(json!({ "membership": membership })))
+}
+
+async fn log_change(
+    app_state: &AppState,
+    membership: &Membership,
+    action: &str,
+    changed_by: Uuid,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+) {
+    app_state
+        .security_service
+        .log_security_event(
+            Some(changed_by),
+            SecurityEventType::MembershipCatalogChanged {
+                tier: membership.tier.clone(),
+                version: membership.version,
+                action: action.to_string(),
+                changed_by,
+            },
+            Some(addr.ip()),
+            user_agent(headers),
+        )
+        .await;
+}
+
+/// A misspelt feature would never be granted, since `RequireFeature` only looks for the names
+/// routes use.
+fn known_features(features: &Value) -> Result<(), (StatusCode, Json<Value>)> {
+    let unknown: Vec<&String> = features
+        .as_object()
+        .into_iter()
+        .flat_map(|features| features.keys())
+        .filter(|name| !FEATURES.contains(&name.as_str()))
+        .collect();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+
+    Err((
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "error": "Unknown features",
+            "unknown": unknown,
+            "features": FEATURES
+        })),
+    ))
+}
+
+fn validate(payload: &impl Validate) -> Result<(), (StatusCode, Json<Value>)> {
+    payload.validate().map_err(|errors| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        )
+    })
+}
+
+fn membership_error(error: MembershipError) -> (StatusCode, Json<Value>) {
+    let (status, message) = match error {
+        MembershipError::TierExists => (StatusCode::CONFLICT, error.to_string()),
+        MembershipError::TierNotFound => (StatusCode::NOT_FOUND, error.to_string()),
+        MembershipError::DefaultTierRequired | MembershipError::Invalid(_) => {
+            (StatusCode::BAD_REQUEST, error.to_string())
+        }
+        MembershipError::DatabaseError(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Membership operation failed".to_string(),
+        ),
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
@@ -1,5 +1,15 @@
+pub mod access_codes;
+pub mod account;
 pub mod auth;
+pub mod billing;
+pub mod content;
+pub mod deception;
 pub mod health;
-
-pub use auth::*;
-pub use health::*;
\ No newline at end of file
+pub mod ip_rules;
+pub mod memberships;
+pub mod notifications;
+pub mod organizations;
+pub mod promotions;
+pub mod retention;
+pub mod security;
+pub mod users;
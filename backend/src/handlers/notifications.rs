@@ -0,0 +1,172 @@
+use crate::middleware::AuthUser;
+use crate::models::UpdateNotificationPreferencesRequest;
+use crate::services::NotificationError;
+use crate::utils::AppState;
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::StatusCode,
+    response::{Json, Response},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+use validator::Validate;
+
+const SOCKET_AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+const SESSION_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SocketAuth {
+    Auth { token: String },
+}
+
+pub async fn get_preferences(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state
+        .notification_service
+        .get_preferences(auth.user_id)
+        .await
+    {
+        Ok(preferences) => Ok(Json(json!({ "preferences": preferences }))),
+        Err(e) => Err(preferences_error(e)),
+    }
+}
+
+pub async fn update_preferences(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+    Json(payload): Json<UpdateNotificationPreferencesRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        ));
+    }
+
+    match app_state
+        .notification_service
+        .update_preferences(auth.user_id, payload)
+        .await
+    {
+        Ok((preferences, Some(webhook_secret))) => Ok(Json(json!({
+            "preferences": preferences,
+            "webhook_secret": webhook_secret,
+            "message": "Store the webhook secret now; it will not be shown again."
+        }))),
+        Ok((preferences, None)) => Ok(Json(json!({ "preferences": preferences }))),
+        Err(e) => Err(preferences_error(e)),
+    }
+}
+
+/// Live security alerts for the signed-in member.
+///
+/// Browsers cannot set headers on a WebSocket handshake and tokens in URLs end up in access
+/// logs, so the client sends `{"type":"auth","token":"..."}` as its first message instead.
+pub async fn alerts_socket(State(app_state): State<AppState>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| stream_alerts(socket, app_state))
+}
+
+async fn stream_alerts(mut socket: WebSocket, app_state: AppState) {
+    let token = match tokio::time::timeout(SOCKET_AUTH_TIMEOUT, socket.recv()).await {
+        Ok(Some(Ok(Message::Text(text)))) => match serde_json::from_str(&text) {
+            Ok(SocketAuth::Auth { token }) => Some(token),
+            Err(_) => None,
+        },
+        _ => None,
+    };
+
+    let user_id = match &token {
+        Some(token) => app_state
+            .auth_service
+            .validate_session(token)
+            .await
+            .ok()
+            .and_then(|claims| Uuid::parse_str(&claims.sub).ok()),
+        None => None,
+    };
+    let (Some(token), Some(user_id)) = (token, user_id) else {
+        close(&mut socket, "Authentication required").await;
+        return;
+    };
+
+    let mut alerts = app_state.notification_service.subscribe(user_id);
+    if socket
+        .send(Message::Text(json!({ "type": "ready" }).to_string()))
+        .await
+        .is_err()
+    {
+        app_state.notification_service.unsubscribe(user_id, alerts);
+        return;
+    }
+
+    let mut session_check = tokio::time::interval(SESSION_RECHECK_INTERVAL);
+    session_check.tick().await;
+
+    loop {
+        tokio::select! {
+            alert = alerts.recv() => match alert {
+                Ok(alert) => {
+                    let message = json!({ "type": "alert", "alert": alert }).to_string();
+                    if socket.send(Message::Text(message)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Alert socket for user {} skipped {} alerts", user_id, skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            },
+            _ = session_check.tick() => {
+                // Logging out or changing the password revokes the session; stop pushing to it
+                if app_state.auth_service.validate_session(&token).await.is_err() {
+                    close(&mut socket, "Session ended").await;
+                    break;
+                }
+            }
+        }
+    }
+
+    app_state.notification_service.unsubscribe(user_id, alerts);
+}
+
+async fn close(socket: &mut WebSocket, reason: &'static str) {
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code: close_code::POLICY,
+            reason: reason.into(),
+        })))
+        .await;
+}
+
+fn preferences_error(error: NotificationError) -> (StatusCode, Json<Value>) {
+    let (status, message) = match error {
+        NotificationError::InvalidWebhookUrl(_) => (StatusCode::BAD_REQUEST, error.to_string()),
+        NotificationError::DatabaseError(_) | NotificationError::EncryptionError(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to load alert preferences".to_string(),
+        ),
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
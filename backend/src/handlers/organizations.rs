@@ -0,0 +1,98 @@
+use crate::middleware::{Authorized, ManageOrganizations};
+use crate::models::{AssignOrganizationRequest, CreateOrganizationRequest, SecurityEventType};
+use crate::services::OrganizationError;
+use crate::utils::{user_agent, AppState};
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Json,
+};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use uuid::Uuid;
+use validator::Validate;
+
+pub async fn list_organizations(
+    State(app_state): State<AppState>,
+    Authorized(_admin, _): Authorized<ManageOrganizations>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.organization_service.list().await {
+        Ok(organizations) => Ok(Json(json!({ "organizations": organizations }))),
+        Err(e) => Err(organization_error(e)),
+    }
+}
+
+pub async fn create_organization(
+    State(app_state): State<AppState>,
+    Authorized(_admin, _): Authorized<ManageOrganizations>,
+    Json(payload): Json<CreateOrganizationRequest>,
+) -> Result<(StatusCode, Json<Value>), (StatusCode, Json<Value>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        ));
+    }
+
+    match app_state.organization_service.create(&payload.name).await {
+        Ok(organization) => Ok((
+            StatusCode::CREATED,
+            Json(json!({ "organization": organization })),
+        )),
+        Err(e) => Err(organization_error(e)),
+    }
+}
+
+pub async fn assign_organization(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<ManageOrganizations>,
+    headers: HeaderMap,
+    Path(user_id): Path<Uuid>,
+    Json(payload): Json<AssignOrganizationRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    app_state
+        .organization_service
+        .assign_member(admin.user_id, user_id, payload.organization_id)
+        .await
+        .map_err(organization_error)?;
+
+    app_state
+        .security_service
+        .log_security_event(
+            Some(user_id),
+            SecurityEventType::OrganizationChanged {
+                organization_id: payload.organization_id,
+                changed_by: admin.user_id,
+            },
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await;
+
+    Ok(Json(json!({
+        "user_id": user_id,
+        "organization_id": payload.organization_id
+    })))
+}
+
+fn organization_error(error: OrganizationError) -> (StatusCode, Json<Value>) {
+    let (status, message) = match error {
+        OrganizationError::AlreadyExists => (StatusCode::CONFLICT, "Organization already exists"),
+        OrganizationError::OrganizationNotFound => (StatusCode::NOT_FOUND, "Organization not found"),
+        OrganizationError::UserNotFound => (StatusCode::NOT_FOUND, "User not found"),
+        OrganizationError::DatabaseError(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, "Organization operation failed")
+        }
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
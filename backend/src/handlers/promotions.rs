@@ -0,0 +1,181 @@
+use crate::middleware::{AuthUser, Authorized, ManagePromotions};
+use crate::models::{CreatePromotionRequest, Promotion, SecurityEventType, StartTrialRequest};
+use crate::services::PromotionError;
+use crate::utils::{user_agent, AppState};
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Json,
+};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use uuid::Uuid;
+use validator::Validate;
+
+/// Every promotion, including ended ones, with how often each was used.
+pub async fn list_promotions(
+    State(app_state): State<AppState>,
+    Authorized(_admin, _): Authorized<ManagePromotions>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.promotion_service.list().await {
+        Ok(promotions) => Ok(Json(json!({ "promotions": promotions }))),
+        Err(e) => Err(promotion_error(e)),
+    }
+}
+
+pub async fn create_promotion(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<ManagePromotions>,
+    headers: HeaderMap,
+    Json(payload): Json<CreatePromotionRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    validate(&payload)?;
+
+    let promotion = app_state
+        .promotion_service
+        .create(admin.user_id, payload)
+        .await
+        .map_err(promotion_error)?;
+    log_change(
+        &app_state,
+        &promotion,
+        "created",
+        admin.user_id,
+        addr,
+        &headers,
+    )
+    .await;
+
+    Ok(Json(json!({ "promotion": promotion })))
+}
+
+/// Ends the promotion now; it stays listed, and discounts already given keep running.
+pub async fn end_promotion(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<ManagePromotions>,
+    headers: HeaderMap,
+    Path(promotion_id): Path<Uuid>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let promotion = app_state
+        .promotion_service
+        .end(promotion_id)
+        .await
+        .map_err(promotion_error)?;
+    log_change(
+        &app_state,
+        &promotion,
+        "ended",
+        admin.user_id,
+        addr,
+        &headers,
+    )
+    .await;
+
+    Ok(Json(json!({ "promotion": promotion })))
+}
+
+/// Starts a no-card trial of a paid tier with a trial promotion code.
+pub async fn start_trial(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    auth: AuthUser,
+    headers: HeaderMap,
+    Json(payload): Json<StartTrialRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    validate(&payload)?;
+
+    let code = payload.code.trim().to_ascii_uppercase();
+    let trial = app_state
+        .promotion_service
+        .start_trial(auth.user_id, payload)
+        .await
+        .map_err(promotion_error)?;
+
+    app_state
+        .security_service
+        .log_security_event(
+            Some(auth.user_id),
+            SecurityEventType::TrialStarted {
+                subscription_id: trial.subscription_id,
+                tier: trial.tier.clone(),
+                code,
+            },
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await;
+
+    Ok(Json(json!({ "trial": trial })))
+}
+
+async fn log_change(
+    app_state: &AppState,
+    promotion: &Promotion,
+    action: &str,
+    changed_by: Uuid,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+) {
+    app_state
+        .security_service
+        .log_security_event(
+            Some(changed_by),
+            SecurityEventType::PromotionChanged {
+                promotion_id: promotion.id,
+                code: promotion.code.clone(),
+                action: action.to_string(),
+                changed_by,
+            },
+            Some(addr.ip()),
+            user_agent(headers),
+        )
+        .await;
+}
+
+fn validate(payload: &impl Validate) -> Result<(), (StatusCode, Json<Value>)> {
+    payload.validate().map_err(|errors| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        )
+    })
+}
+
+fn promotion_error(error: PromotionError) -> (StatusCode, Json<Value>) {
+    let (status, message) = match error {
+        PromotionError::CodeExists => (StatusCode::CONFLICT, error.to_string()),
+        PromotionError::NotFound => (StatusCode::NOT_FOUND, error.to_string()),
+        PromotionError::Invalid(_) | PromotionError::InvalidCode => {
+            (StatusCode::BAD_REQUEST, error.to_string())
+        }
+        PromotionError::NotApplicable => (
+            StatusCode::BAD_REQUEST,
+            "Promotion code does not apply to that plan".to_string(),
+        ),
+        PromotionError::TierNotOffered => (StatusCode::BAD_REQUEST, error.to_string()),
+        PromotionError::TrialUnavailable => (
+            StatusCode::CONFLICT,
+            "Trials are only for members who have never subscribed".to_string(),
+        ),
+        PromotionError::Provider(ref e) => {
+            tracing::error!("Billing provider error: {}", e);
+            (StatusCode::BAD_GATEWAY, "Payment provider error".to_string())
+        }
+        PromotionError::DatabaseError(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Promotion operation failed".to_string(),
+        ),
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
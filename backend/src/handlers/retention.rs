@@ -0,0 +1,46 @@
+use crate::middleware::{Authorized, ElevatedUser, ManageRetention};
+use crate::services::RetentionError;
+use crate::utils::{AppState, DbScope};
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde_json::{json, Value};
+
+pub async fn retention_report(
+    State(app_state): State<AppState>,
+    Authorized(admin, _): Authorized<ManageRetention>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.retention_service.report(admin.user_id).await {
+        Ok(report) => Ok(Json(json!({
+            "policy": app_state.retention_service.policy(),
+            "tables": report.tables,
+            "last_run": report.last_run,
+        }))),
+        Err(e) => Err(retention_error(e)),
+    }
+}
+
+pub async fn purge(
+    State(app_state): State<AppState>,
+    Authorized(admin, _): Authorized<ManageRetention>,
+    ElevatedUser(_elevated): ElevatedUser,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.retention_service.purge(DbScope::Admin(admin.user_id)).await {
+        Ok(run) => Ok(Json(json!({ "purge": run }))),
+        Err(e) => Err(retention_error(e)),
+    }
+}
+
+fn retention_error(error: RetentionError) -> (StatusCode, Json<Value>) {
+    let (status, message) = match error {
+        RetentionError::PurgeInProgress => (StatusCode::CONFLICT, "A purge is already running"),
+        RetentionError::DatabaseError(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, "Retention operation failed")
+        }
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
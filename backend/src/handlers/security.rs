@@ -0,0 +1,271 @@
+use crate::middleware::{
+    forbidden, step_up_required, AuthUser, Authorized, ExportSecurityEvents, Permission,
+    ReadDestructionLogs, ReadSecurityEvents, STEP_UP_MINUTES,
+};
+use crate::models::{DestructionLogQuery, SecurityEvent, SecurityEventQuery, SecurityEventType};
+use crate::services::{SecurityError, SecurityEventFilter, SecurityEventPage};
+use crate::utils::{begin_scoped, user_agent, AppState, DbScope};
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use ipnetwork::IpNetwork;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+
+const MEMBER_DEFAULT_LIMIT: i64 = 20;
+const MEMBER_MAX_LIMIT: i64 = 100;
+const ADMIN_DEFAULT_LIMIT: i64 = 100;
+const ADMIN_MAX_LIMIT: i64 = 1000;
+const EXPORT_MAX_LIMIT: i64 = 10_000;
+const DESTRUCTION_DEFAULT_LIMIT: i64 = 50;
+const DESTRUCTION_MAX_LIMIT: i64 = 500;
+
+/// A member's own recent security activity (logins, password changes, ...).
+pub async fn my_security_events(
+    State(app_state): State<AppState>,
+    auth: AuthUser,
+    Query(params): Query<SecurityEventQuery>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let filter = SecurityEventFilter {
+        user_id: Some(auth.user_id),
+        event_type: params.event_type,
+        from: params.from,
+        to: params.to,
+        ..Default::default()
+    };
+    let limit = params
+        .limit
+        .unwrap_or(MEMBER_DEFAULT_LIMIT)
+        .clamp(1, MEMBER_MAX_LIMIT);
+
+    let mut tx = begin_scoped(&app_state.db, DbScope::Member(auth.user_id))
+        .await
+        .map_err(|e| query_error(e.into()))?;
+    let page = app_state
+        .security_service
+        .query_events(&mut tx, &filter, params.cursor.as_deref(), limit)
+        .await
+        .map_err(query_error)?;
+    tx.commit().await.map_err(|e| query_error(e.into()))?;
+
+    Ok(Json(page_json(page)))
+}
+
+/// Administrator view across all members, exportable as CSV or NDJSON after a step-up.
+pub async fn admin_security_events(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<ReadSecurityEvents>,
+    headers: HeaderMap,
+    Query(params): Query<SecurityEventQuery>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let ip_network = match params.ip.as_deref() {
+        Some(ip) => Some(ip.parse::<IpNetwork>().map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "ip must be an IP address or CIDR block"
+                })),
+            )
+        })?),
+        None => None,
+    };
+
+    let format = params.format.as_deref().unwrap_or("json");
+    let max_limit = match format {
+        "json" => ADMIN_MAX_LIMIT,
+        "csv" | "ndjson" => EXPORT_MAX_LIMIT,
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "format must be one of json, csv, ndjson"
+                })),
+            ))
+        }
+    };
+    if format != "json" {
+        let may_export = app_state
+            .rbac_service
+            .has_permission(admin.user_id, ExportSecurityEvents::NAME)
+            .await
+            .map_err(|e| query_error(e.into()))?;
+        if !may_export {
+            return Err(forbidden(ExportSecurityEvents::NAME));
+        }
+        if !admin.elevated_within(STEP_UP_MINUTES) {
+            return Err(step_up_required(STEP_UP_MINUTES));
+        }
+    }
+    let limit = params.limit.unwrap_or(ADMIN_DEFAULT_LIMIT).clamp(1, max_limit);
+
+    let filter = SecurityEventFilter {
+        user_id: params.user_id,
+        event_type: params.event_type,
+        min_risk: params.min_risk,
+        max_risk: params.max_risk,
+        ip_network,
+        from: params.from,
+        to: params.to,
+    };
+
+    let mut tx = begin_scoped(&app_state.db, DbScope::Admin(admin.user_id))
+        .await
+        .map_err(|e| query_error(e.into()))?;
+    let page = app_state
+        .security_service
+        .query_events(&mut tx, &filter, params.cursor.as_deref(), limit)
+        .await
+        .map_err(query_error)?;
+    tx.commit().await.map_err(|e| query_error(e.into()))?;
+
+    if format == "json" {
+        return Ok(Json(page_json(page)).into_response());
+    }
+
+    // Bulk exports are themselves part of the audit trail
+    app_state
+        .security_service
+        .log_security_event(
+            Some(admin.user_id),
+            SecurityEventType::SecurityEventsExported {
+                format: format.to_string(),
+                rows: page.events.len(),
+                filter: json!({
+                    "user_id": filter.user_id,
+                    "event_type": filter.event_type,
+                    "min_risk": filter.min_risk,
+                    "max_risk": filter.max_risk,
+                    "ip": params.ip,
+                    "from": filter.from,
+                    "to": filter.to,
+                }),
+            },
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await;
+
+    let (content_type, body) = if format == "csv" {
+        ("text/csv", events_to_csv(&page.events))
+    } else {
+        ("application/x-ndjson", events_to_ndjson(&page.events))
+    };
+
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (
+                header::CONTENT_DISPOSITION,
+                if format == "csv" {
+                    "attachment; filename=\"security_events.csv\""
+                } else {
+                    "attachment; filename=\"security_events.ndjson\""
+                },
+            ),
+        ],
+        body,
+    )
+        .into_response();
+
+    // Clients page through large exports by repeating the request with this cursor
+    if let Some(cursor) = page.next_cursor.and_then(|c| HeaderValue::from_str(&c).ok()) {
+        response.headers_mut().insert("x-next-cursor", cursor);
+    }
+
+    Ok(response)
+}
+
+fn page_json(page: SecurityEventPage) -> Value {
+    json!({
+        "events": page.events,
+        "next_cursor": page.next_cursor
+    })
+}
+
+fn events_to_ndjson(events: &[SecurityEvent]) -> String {
+    events
+        .iter()
+        .filter_map(|event| serde_json::to_string(event).ok())
+        .map(|line| line + "\n")
+        .collect()
+}
+
+fn events_to_csv(events: &[SecurityEvent]) -> String {
+    let mut csv = String::from("id,user_id,event_type,ip_address,user_agent,risk_level,created_at,details\n");
+
+    for event in events {
+        let row = [
+            event.id.to_string(),
+            event.user_id.map(|id| id.to_string()).unwrap_or_default(),
+            event.event_type.clone(),
+            event.ip_address.map(|ip| ip.to_string()).unwrap_or_default(),
+            event.user_agent.clone().unwrap_or_default(),
+            event.risk_level.to_string(),
+            event.created_at.to_rfc3339(),
+            event.details.as_ref().map(|d| d.to_string()).unwrap_or_default(),
+        ];
+        let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    // Neutralise spreadsheet formula injection from attacker-controlled fields like user agents
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// Destruction protocol runs, newest first; page by passing the last `execution_time` as `before`.
+pub async fn destruction_logs(
+    State(app_state): State<AppState>,
+    Authorized(_admin, _): Authorized<ReadDestructionLogs>,
+    Query(params): Query<DestructionLogQuery>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let limit = params
+        .limit
+        .unwrap_or(DESTRUCTION_DEFAULT_LIMIT)
+        .clamp(1, DESTRUCTION_MAX_LIMIT);
+
+    match app_state
+        .security_service
+        .destruction_logs(params.user_id, params.before, limit)
+        .await
+    {
+        Ok(logs) => Ok(Json(json!({ "destruction_logs": logs }))),
+        Err(_) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "error": "Failed to load destruction logs"
+            })),
+        )),
+    }
+}
+
+fn query_error(error: SecurityError) -> (StatusCode, Json<Value>) {
+    let (status, message) = match error {
+        SecurityError::InvalidCursor => (StatusCode::BAD_REQUEST, "Invalid cursor"),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load security events"),
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
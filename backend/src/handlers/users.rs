@@ -0,0 +1,198 @@
+use crate::middleware::{Authorized, DeactivateUsers, ElevatedUser, ManageRoles, UnlockUsers};
+use crate::models::{SecurityEventType, SetUserRolesRequest};
+use crate::services::{AuthError, RbacError};
+use crate::utils::{user_agent, AppState};
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Json,
+};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use uuid::Uuid;
+use validator::Validate;
+
+pub async fn unlock_user(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<UnlockUsers>,
+    headers: HeaderMap,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    app_state
+        .auth_service
+        .unlock_account(admin.user_id, user_id)
+        .await
+        .map_err(user_error)?;
+
+    app_state
+        .security_service
+        .log_security_event(
+            Some(user_id),
+            SecurityEventType::AccountUnlocked {
+                unlocked_by: admin.user_id,
+            },
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await;
+
+    Ok(Json(json!({ "message": "Account unlocked" })))
+}
+
+pub async fn deactivate_user(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<DeactivateUsers>,
+    headers: HeaderMap,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    if user_id == admin.user_id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "You cannot deactivate your own account"
+            })),
+        ));
+    }
+
+    app_state
+        .auth_service
+        .set_account_active(admin.user_id, user_id, false)
+        .await
+        .map_err(user_error)?;
+
+    app_state
+        .security_service
+        .log_security_event(
+            Some(user_id),
+            SecurityEventType::AccountDeactivated {
+                deactivated_by: admin.user_id,
+            },
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await;
+
+    Ok(Json(json!({ "message": "Account deactivated and signed out" })))
+}
+
+pub async fn reactivate_user(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<DeactivateUsers>,
+    headers: HeaderMap,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    app_state
+        .auth_service
+        .set_account_active(admin.user_id, user_id, true)
+        .await
+        .map_err(user_error)?;
+
+    app_state
+        .security_service
+        .log_security_event(
+            Some(user_id),
+            SecurityEventType::AccountReactivated {
+                reactivated_by: admin.user_id,
+            },
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await;
+
+    Ok(Json(json!({ "message": "Account reactivated" })))
+}
+
+pub async fn list_roles(
+    State(app_state): State<AppState>,
+    Authorized(_admin, _): Authorized<ManageRoles>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match app_state.rbac_service.list_roles().await {
+        Ok(roles) => Ok(Json(json!({ "roles": roles }))),
+        Err(e) => Err(rbac_error(e)),
+    }
+}
+
+/// Granting roles is privilege escalation, so it also needs a fresh step-up.
+pub async fn set_user_roles(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Authorized(admin, _): Authorized<ManageRoles>,
+    ElevatedUser(_elevated): ElevatedUser,
+    headers: HeaderMap,
+    Path(user_id): Path<Uuid>,
+    Json(payload): Json<SetUserRolesRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "Validation failed",
+                "details": format!("{:?}", errors)
+            })),
+        ));
+    }
+
+    let roles = app_state
+        .rbac_service
+        .set_user_roles(user_id, &payload.roles, admin.user_id)
+        .await
+        .map_err(rbac_error)?;
+
+    app_state
+        .security_service
+        .log_security_event(
+            Some(user_id),
+            SecurityEventType::RolesChanged {
+                roles: roles.clone(),
+                changed_by: admin.user_id,
+            },
+            Some(addr.ip()),
+            user_agent(&headers),
+        )
+        .await;
+
+    Ok(Json(json!({
+        "user_id": user_id,
+        "roles": roles
+    })))
+}
+
+fn user_error(error: AuthError) -> (StatusCode, Json<Value>) {
+    let (status, message) = match error {
+        AuthError::UserNotFound => (StatusCode::NOT_FOUND, "User not found"),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "User update failed"),
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
+
+fn rbac_error(error: RbacError) -> (StatusCode, Json<Value>) {
+    let status = match error {
+        RbacError::UnknownRole(_) => StatusCode::BAD_REQUEST,
+        RbacError::UserNotFound => StatusCode::NOT_FOUND,
+        RbacError::LastRoleManager => StatusCode::CONFLICT,
+        RbacError::DatabaseError(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Role operation failed"
+                })),
+            )
+        }
+    };
+
+    (
+        status,
+        Json(json!({
+            "error": error.to_string()
+        })),
+    )
+}
@@ -1,20 +1,29 @@
 mod config;
 mod handlers;
+mod middleware;
 mod models;
 mod services;
 mod utils;
 
 use crate::config::Config;
-use crate::handlers::{auth, health};
-use crate::services::{AuthService, SecurityService};
+use crate::handlers::{
+    access_codes, account, auth, billing, content, deception, health, ip_rules, memberships, notifications, organizations,
+    promotions, retention, security, users,
+};
+use crate::services::{
+    rotate_master_key, AccessCodeService, AuthService, BillingService, ContentService, DeceptionService, EntitlementService, EventForwarder, EventRecorder, FieldCipher,
+    IpRuleService, MailerService, MasterKeyring, MembershipService, NotificationService,
+    OrganizationService, PasswordPolicy, PromotionService, RbacService, RetentionService, SecurityService,
+    StripeClient, SubscriptionScheduler,
+};
 use crate::utils::AppState;
 use axum::{
-    extract::ConnectInfo,
+    extract::DefaultBodyLimit,
     http::{HeaderValue, Method},
-    routing::{get, post},
+    middleware::from_fn_with_state,
+    routing::{delete, get, post, put},
     Router,
 };
-use sqlx::postgres::PgPoolOptions;
 use std::net::SocketAddr;
 use tower::ServiceBuilder;
 use tower_http::{
@@ -38,9 +47,7 @@ async fn main() {
     let config = Config::from_env().expect("Failed to load configuration");
 
     // Setup database connection
-    let db = PgPoolOptions::new()
-        .max_connections(20)
-        .connect(&config.database_url)
+    let db = utils::db::connect(&config.database_url, 20)
         .await
         .expect("Failed to connect to database");
 
@@ -50,17 +57,121 @@ async fn main() {
     //     .await
     //     .expect("Failed to run migrations");
 
+    // Field encryption keys
+    let mut keyring = MasterKeyring::load_or_create(&config.field_encryption_keyring)
+        .expect("Failed to load master keyring");
+
+    // `the-circle-backend rotate-keys [--new-master-key]` re-wraps data keys and exits
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("rotate-keys") {
+        let new_master_key = args.iter().any(|arg| arg == "--new-master-key");
+        let rotated = rotate_master_key(&db, &mut keyring, &config.user_key_dir, new_master_key)
+            .await
+            .expect("Failed to rotate data keys");
+        tracing::info!(
+            "Re-wrapped {} keys under master key {}",
+            rotated,
+            keyring.active_key_id()
+        );
+        return;
+    }
+
+    let cipher = FieldCipher::load(&db, keyring, &config.user_key_dir)
+        .await
+        .expect("Failed to load field encryption keys");
+    let encrypted = cipher
+        .encrypt_existing(&db)
+        .await
+        .expect("Failed to encrypt existing fields");
+    if encrypted > 0 {
+        tracing::info!("Encrypted sensitive fields in {} existing rows", encrypted);
+    }
+
     // Initialize services
-    let security_service = SecurityService::new(db.clone());
+    let event_forwarder = EventForwarder::start(&config.security_event_sinks);
+    let event_recorder = EventRecorder::start(
+        db.clone(),
+        config.security_event_journal.clone(),
+        event_forwarder.clone(),
+    );
+    let ip_rules = IpRuleService::start(
+        db.clone(),
+        config.ip_reputation_lists.clone(),
+        config.trusted_proxies.clone(),
+    )
+    .await;
+    let mailer = MailerService::new(config.mail_from.clone());
+    let notification_service = NotificationService::new(db.clone(), mailer.clone(), cipher.clone());
+    let security_service = SecurityService::new(
+        db.clone(),
+        event_forwarder,
+        event_recorder,
+        notification_service.clone(),
+        ip_rules.clone(),
+        cipher.clone(),
+        StripeClient::new(&config.billing.stripe_api_base, config.stripe_secret_key.clone()),
+    );
+    let password_policy = PasswordPolicy::new(
+        config.password_min_entropy_bits,
+        config.breached_passwords_dir.clone(),
+    );
     let auth_service = AuthService::new(
         db.clone(),
-        config.jwt_secret.clone(),
-        config.jwt_expiration,
+        &config,
+        security_service.clone(),
+        mailer.clone(),
+        password_policy,
+        ip_rules.clone(),
+        cipher.clone(),
+    );
+    let organization_service = OrganizationService::new(db.clone());
+    let deception_service = DeceptionService::new(db.clone(), &config, cipher.clone());
+    let retention_service =
+        RetentionService::start(db.clone(), config.retention, security_service.clone());
+    let rbac_service = RbacService::new(db.clone());
+    let membership_service = MembershipService::new(db.clone());
+    let entitlement_service = EntitlementService::new(db.clone());
+    let billing_service = BillingService::new(
+        db.clone(),
+        config.billing.clone(),
+        config.stripe_secret_key.clone(),
+        config.stripe_webhook_secret.clone(),
+        security_service.clone(),
+    );
+    let access_code_service = AccessCodeService::new(db.clone());
+    let promotion_service = PromotionService::new(
+        db.clone(),
+        &config.billing,
+        config.stripe_secret_key.clone(),
+    );
+    let content_service = ContentService::new(db.clone(), cipher.clone());
+    SubscriptionScheduler::start(
+        db.clone(),
+        config.billing.clone(),
+        billing_service.clone(),
+        mailer,
+        cipher,
         security_service.clone(),
     );
 
     // Create application state
-    let app_state = AppState::new(db, config.clone(), auth_service, security_service);
+    let app_state = AppState {
+        db,
+        auth_service,
+        security_service: security_service.clone(),
+        notification_service,
+        ip_rules,
+        organization_service,
+        deception_service,
+        retention_service,
+        rbac_service,
+        membership_service,
+        entitlement_service,
+        billing_service,
+        access_code_service,
+        promotion_service,
+        content_service,
+    };
 
     // Setup CORS
     let cors = CorsLayer::new()
@@ -68,18 +179,126 @@ async fn main() {
         .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_headers(Any);
 
-    // Build application router
-    let app = Router::new()
-        // Health checks
-        .route("/health", get(health::health_check))
-        .route("/ready", get(health::readiness_check))
+    // Build API router; network deny rules apply before any handler, including login
+    let api = Router::new()
         // Authentication routes
         .route("/api/auth/register", post(auth::register))
         .route("/api/auth/login/initiate", post(auth::login_initiate))
         .route("/api/auth/login/complete", post(auth::login_complete))
         .route("/api/auth/logout", post(auth::logout))
         .route("/api/auth/refresh", post(auth::refresh_token))
+        .route("/api/auth/step-up", post(auth::step_up))
+        .route("/api/auth/password/forgot", post(auth::forgot_password))
+        .route("/api/auth/password/reset", post(auth::reset_password))
+        // Membership catalog (public)
+        .route("/api/memberships", get(memberships::list_memberships))
+        // Account routes (require an authenticated session)
+        .route("/api/account/password", post(account::change_password))
+        .route("/api/account/email", post(account::request_email_change))
+        .route("/api/account/email/confirm", post(account::confirm_email_change))
+        .route("/api/account/security-events", get(security::my_security_events))
+        .route(
+            "/api/account/alerts/preferences",
+            get(notifications::get_preferences).put(notifications::update_preferences),
+        )
+        .route("/api/account/alerts/ws", get(notifications::alerts_socket))
+        .route("/api/account/entitlements", get(memberships::my_entitlements))
+        // Member content (writes need the plan's feature and room under its limits)
+        .route(
+            "/api/conversations",
+            get(content::list_conversations).post(content::create_conversation),
+        )
+        .route("/api/conversations/:id", delete(content::delete_conversation))
+        .route(
+            "/api/files",
+            get(content::list_files)
+                .post(content::upload_file)
+                .layer(DefaultBodyLimit::max(content::MAX_UPLOAD_BYTES)),
+        )
+        .route(
+            "/api/files/:id",
+            get(content::download_file).delete(content::delete_file),
+        )
+        // Billing (require an authenticated session)
+        .route("/api/billing", get(billing::billing_status))
+        .route("/api/billing/checkout", post(billing::checkout))
+        .route("/api/billing/portal", post(billing::portal))
+        .route("/api/billing/plan", post(billing::change_plan))
+        .route("/api/billing/plan/preview", post(billing::preview_plan_change))
+        .route("/api/billing/plan/scheduled", delete(billing::cancel_plan_change))
+        .route("/api/billing/plan/history", get(billing::plan_history))
+        .route("/api/billing/redeem", post(access_codes::redeem_access_code))
+        .route("/api/billing/trial", post(promotions::start_trial))
+        .route("/api/billing/webhook", post(billing::stripe_webhook))
+        // Admin routes
+        .route("/api/admin/security-events", get(security::admin_security_events))
+        .route(
+            "/api/admin/ip-rules",
+            get(ip_rules::list_ip_rules).post(ip_rules::create_ip_rule),
+        )
+        .route("/api/admin/ip-rules/reload", post(ip_rules::reload_ip_rules))
+        .route("/api/admin/ip-rules/:id", delete(ip_rules::delete_ip_rule))
+        .route(
+            "/api/admin/organizations",
+            get(organizations::list_organizations).post(organizations::create_organization),
+        )
+        .route(
+            "/api/admin/users/:id/organization",
+            put(organizations::assign_organization),
+        )
+        .route(
+            "/api/admin/canaries",
+            get(deception::list_canaries).post(deception::create_canary),
+        )
+        .route("/api/admin/canaries/:id", delete(deception::delete_canary))
+        .route(
+            "/api/admin/honeytokens",
+            get(deception::list_honeytokens).post(deception::create_honeytoken),
+        )
+        .route("/api/admin/honeytokens/:id", delete(deception::delete_honeytoken))
+        .route("/api/admin/retention", get(retention::retention_report))
+        .route("/api/admin/retention/purge", post(retention::purge))
+        .route("/api/admin/destruction-logs", get(security::destruction_logs))
+        .route("/api/admin/roles", get(users::list_roles))
+        .route("/api/admin/users/:id/roles", put(users::set_user_roles))
+        .route("/api/admin/users/:id/unlock", post(users::unlock_user))
+        .route("/api/admin/users/:id/deactivate", post(users::deactivate_user))
+        .route("/api/admin/users/:id/reactivate", post(users::reactivate_user))
+        .route(
+            "/api/admin/memberships",
+            get(memberships::list_membership_versions).post(memberships::create_membership),
+        )
+        .route(
+            "/api/admin/memberships/:tier",
+            put(memberships::update_membership).delete(memberships::retire_membership),
+        )
+        .route(
+            "/api/admin/access-codes",
+            get(access_codes::list_access_code_batches).post(access_codes::issue_access_codes),
+        )
+        .route("/api/admin/access-codes/:id", delete(access_codes::revoke_access_codes))
+        .route(
+            "/api/admin/promotions",
+            get(promotions::list_promotions).post(promotions::create_promotion),
+        )
+        .route("/api/admin/promotions/:id", delete(promotions::end_promotion))
+        .route_layer(from_fn_with_state(
+            app_state.clone(),
+            middleware::enforce_ip_rules,
+        ));
+
+    // Build application router
+    let app = Router::new()
+        // Health checks
+        .route("/health", get(health::health_check))
+        .route("/ready", get(health::readiness_check))
+        .route("/metrics", get(health::metrics))
+        .merge(api)
         // Add state and middleware
+        .layer(from_fn_with_state(
+            app_state.clone(),
+            middleware::resolve_client_ip,
+        ))
         .with_state(app_state)
         .layer(
             ServiceBuilder::new()
@@ -89,7 +308,8 @@ async fn main() {
         );
 
     // Start server
-    let addr = SocketAddr::from(([127, 0, 0, 1], config.port));
+    let host = config.host.parse().expect("HOST must be an IP address");
+    let addr = SocketAddr::new(host, config.port);
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     
     tracing::info!("🚀 The Circle backend server starting on http://{}", addr);
@@ -99,6 +319,35 @@ async fn main() {
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
+    .with_graceful_shutdown(shutdown_signal())
     .await
     .unwrap();
+
+    // Don't lose audit events that are still queued
+    security_service.flush_events().await;
+    tracing::info!("Security events flushed; shutting down");
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
\ No newline at end of file
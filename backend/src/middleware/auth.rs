@@ -0,0 +1,188 @@
+use crate::services::{Claims, ACR_STEP_UP};
+use crate::utils::AppState;
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+    response::Json,
+};
+use serde_json::{json, Value};
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+/// An authenticated caller, resolved from a `Bearer` access token whose session is still active.
+#[derive(Debug)]
+pub struct AuthUser {
+    pub user_id: Uuid,
+    pub session_id: Uuid,
+    pub claims: Claims,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| unauthorized("Missing bearer token"))?;
+
+        let claims = state
+            .auth_service
+            .validate_session(token)
+            .await
+            .map_err(|_| unauthorized("Invalid or expired token"))?;
+
+        let user_id = Uuid::parse_str(&claims.sub).map_err(|_| unauthorized("Invalid token"))?;
+        let session_id = Uuid::parse_str(&claims.sid).map_err(|_| unauthorized("Invalid token"))?;
+
+        // Sessions stay bound to the organization allowlist, not just the login
+        if let Some(ConnectInfo(addr)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+            let allowed = state
+                .ip_rules
+                .member_network_allowed(user_id, addr.ip())
+                .await
+                .map_err(|_| unauthorized("Invalid token"))?;
+            if !allowed {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    Json(json!({
+                        "error": "Your organization does not allow access from this network"
+                    })),
+                ));
+            }
+        }
+
+        Ok(AuthUser {
+            user_id,
+            session_id,
+            claims,
+        })
+    }
+}
+
+impl AuthUser {
+    /// True when the token came from a step-up no more than `minutes` ago.
+    pub fn elevated_within(&self, minutes: i64) -> bool {
+        let cutoff = chrono::Utc::now().timestamp() - minutes * 60;
+        self.claims.acr == ACR_STEP_UP && self.claims.auth_time as i64 >= cutoff
+    }
+}
+
+/// Default freshness for `ElevatedUser`, matching the lifetime of a step-up token.
+pub const STEP_UP_MINUTES: i64 = 5;
+
+/// An authenticated caller holding an elevated token (`POST /api/auth/step-up`) issued within
+/// the last `MINUTES`.
+#[derive(Debug)]
+pub struct ElevatedUser<const MINUTES: i64 = STEP_UP_MINUTES>(pub AuthUser);
+
+#[async_trait]
+impl<const MINUTES: i64> FromRequestParts<AppState> for ElevatedUser<MINUTES> {
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let auth = AuthUser::from_request_parts(parts, state).await?;
+
+        if !auth.elevated_within(MINUTES) {
+            return Err(step_up_required(MINUTES));
+        }
+
+        Ok(ElevatedUser(auth))
+    }
+}
+
+/// Rejection for a sensitive action attempted without a fresh step-up.
+pub fn step_up_required(minutes: i64) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({
+            "error": "Step-up authentication required",
+            "step_up_required": true,
+            "max_age_seconds": minutes * 60
+        })),
+    )
+}
+
+/// A permission checked by `Authorized`; each marker type names one row of `permissions`.
+pub trait Permission {
+    const NAME: &'static str;
+}
+
+macro_rules! permissions {
+    ($($marker:ident => $name:literal),* $(,)?) => {
+        $(
+            #[derive(Debug)]
+            pub struct $marker;
+
+            impl Permission for $marker {
+                const NAME: &'static str = $name;
+            }
+        )*
+    };
+}
+
+permissions! {
+    ReadSecurityEvents => "security_events:read",
+    ExportSecurityEvents => "security_events:export",
+    ManageIpRules => "ip_rules:manage",
+    ManageOrganizations => "organizations:manage",
+    ManageDeception => "deception:manage",
+    ManageRetention => "retention:manage",
+    UnlockUsers => "users:unlock",
+    DeactivateUsers => "users:deactivate",
+    ReadDestructionLogs => "destruction_logs:read",
+    ManageRoles => "roles:manage",
+    ManageMemberships => "memberships:manage",
+    ManageAccessCodes => "access_codes:manage",
+    ManagePromotions => "promotions:manage",
+}
+
+/// An authenticated caller holding permission `P` through one of their roles.
+#[derive(Debug)]
+pub struct Authorized<P: Permission>(pub AuthUser, pub PhantomData<P>);
+
+#[async_trait]
+impl<P: Permission> FromRequestParts<AppState> for Authorized<P> {
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let auth = AuthUser::from_request_parts(parts, state).await?;
+
+        // Checked against the database so revoking a role takes effect immediately
+        let granted = state
+            .rbac_service
+            .has_permission(auth.user_id, P::NAME)
+            .await
+            .map_err(|_| unauthorized("Invalid token"))?;
+        if !granted {
+            return Err(forbidden(P::NAME));
+        }
+
+        Ok(Authorized(auth, PhantomData))
+    }
+}
+
+/// Rejection for a caller without the permission a route needs.
+pub fn forbidden(permission: &str) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::FORBIDDEN,
+        Json(json!({
+            "error": "Permission required",
+            "permission": permission
+        })),
+    )
+}
+
+fn unauthorized(message: &str) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({
+            "error": message
+        })),
+    )
+}
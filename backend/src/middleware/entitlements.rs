@@ -0,0 +1,165 @@
+use crate::middleware::AuthUser;
+use crate::models::Entitlements;
+use crate::services::EntitlementError;
+use crate::utils::AppState;
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+    response::Json,
+};
+use serde_json::{json, Value};
+use std::marker::PhantomData;
+
+/// A plan feature checked by `RequireFeature`; each marker type names one key of
+/// `memberships.features`.
+pub trait Feature {
+    const NAME: &'static str;
+}
+
+macro_rules! features {
+    ($($marker:ident => $name:literal),* $(,)?) => {
+        $(
+            #[derive(Debug)]
+            pub struct $marker;
+
+            impl Feature for $marker {
+                const NAME: &'static str = $name;
+            }
+        )*
+
+        /// Every feature a plan can include; catalog versions may list only these.
+        pub const FEATURES: &[&str] = &[$(<$marker as Feature>::NAME),*];
+    };
+}
+
+features! {
+    Messaging => "messaging",
+    FileSharing => "file_sharing",
+    VideoCalls => "video_calls",
+    DestructionProtocols => "destruction_protocols",
+    BiometricAuth => "biometric_auth",
+    AdminControls => "admin_controls",
+}
+
+/// An authenticated caller whose current plan includes feature `F`, with the plan's entitlements
+/// so the handler can check limits without another lookup.
+#[derive(Debug)]
+pub struct RequireFeature<F: Feature>(pub AuthUser, pub Entitlements, pub PhantomData<F>);
+
+#[async_trait]
+impl<F: Feature> FromRequestParts<AppState> for RequireFeature<F> {
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let auth = AuthUser::from_request_parts(parts, state).await?;
+
+        let entitlements = state
+            .entitlement_service
+            .require_feature(auth.user_id, F::NAME)
+            .await
+            .map_err(entitlement_error)?;
+
+        Ok(RequireFeature(auth, entitlements, PhantomData))
+    }
+}
+
+/// Rejections for actions outside the member's plan: 403 for a feature the plan does not
+/// include, 402 for going over one of its limits (`read_only` if already over it).
+pub fn entitlement_error(error: EntitlementError) -> (StatusCode, Json<Value>) {
+    match error {
+        EntitlementError::FeatureNotIncluded {
+            feature,
+            tier,
+            upgrade_to,
+        } => (
+            StatusCode::FORBIDDEN,
+            Json(json!({
+                "error": "Your plan does not include this feature",
+                "feature": feature,
+                "tier": tier,
+                "upgrade_to": upgrade_to
+            })),
+        ),
+        EntitlementError::LimitExceeded {
+            limit,
+            max,
+            current,
+            requested,
+            read_only,
+            tier,
+            upgrade_to,
+        } => (
+            StatusCode::PAYMENT_REQUIRED,
+            Json(json!({
+                "error": "Plan limit reached",
+                "limit": limit.as_str(),
+                "max": max,
+                "current": current,
+                "requested": requested,
+                "read_only": read_only,
+                "tier": tier,
+                "upgrade_to": upgrade_to
+            })),
+        ),
+        EntitlementError::DatabaseError(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "error": "Failed to load plan entitlements"
+            })),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PlanLimit;
+
+    fn limit_exceeded(current: i64, requested: i64, read_only: bool) -> EntitlementError {
+        EntitlementError::LimitExceeded {
+            limit: PlanLimit::Conversations,
+            max: 10,
+            current,
+            requested,
+            read_only,
+            tier: "Basic".to_string(),
+            upgrade_to: vec!["Premium".to_string()],
+        }
+    }
+
+    #[test]
+    fn missing_features_are_forbidden() {
+        let (status, Json(body)) = entitlement_error(EntitlementError::FeatureNotIncluded {
+            feature: "file_sharing".to_string(),
+            tier: "Basic".to_string(),
+            upgrade_to: vec!["Premium".to_string(), "Enterprise".to_string()],
+        });
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["feature"], "file_sharing");
+        assert_eq!(body["tier"], "Basic");
+        assert_eq!(body["upgrade_to"], json!(["Premium", "Enterprise"]));
+    }
+
+    #[test]
+    fn exceeded_limits_require_payment() {
+        let (status, Json(body)) = entitlement_error(limit_exceeded(10, 11, false));
+
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(body["limit"], "max_conversations");
+        assert_eq!(body["max"], 10);
+        assert_eq!(body["current"], 10);
+        assert_eq!(body["requested"], 11);
+        assert_eq!(body["read_only"], false);
+        assert_eq!(body["upgrade_to"], json!(["Premium"]));
+    }
+
+    #[test]
+    fn members_already_over_a_limit_are_told_it_is_read_only() {
+        let (status, Json(body)) = entitlement_error(limit_exceeded(12, 13, true));
+
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(body["read_only"], true);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::services::IpVerdict;
+use crate::utils::AppState;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::json;
+use std::net::SocketAddr;
+
+/// Replaces the connection's `ConnectInfo` with the client address resolved through any trusted
+/// proxies, so rules, security events and auto-denies all see the real client.
+pub async fn resolve_client_ip(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let client_ip = app_state.ip_rules.client_ip(addr.ip(), request.headers());
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(SocketAddr::new(client_ip, addr.port())));
+
+    next.run(request).await
+}
+
+/// Rejects requests from denied networks before any handler (including login) runs.
+pub async fn enforce_ip_rules(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if app_state.ip_rules.evaluate(addr.ip()) == IpVerdict::Blocked {
+        app_state.ip_rules.record_block();
+        tracing::warn!("Blocked request from {} to {}", addr.ip(), request.uri().path());
+
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({
+                "error": "Access from this network is not allowed"
+            })),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,7 @@
+pub mod auth;
+pub mod entitlements;
+pub mod ip_filter;
+
+pub use auth::*;
+pub use entitlements::*;
+pub use ip_filter::*;
\ No newline at end of file
@@ -0,0 +1,60 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateAccessCodesRequest {
+    /// Code of an offered paid tier; codes are for its current version
+    #[validate(length(min = 1, max = 50))]
+    pub tier: String,
+    #[validate(range(min = 1, max = 36))]
+    pub months: i32,
+    #[validate(range(min = 1, max = 1000))]
+    pub quantity: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    #[validate(length(max = 255))]
+    pub note: Option<String>,
+}
+
+/// A batch of prepaid codes and how many have been used. The codes themselves are only ever
+/// returned when issued.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AccessCodeBatch {
+    pub id: Uuid,
+    pub tier: String,
+    pub version: i32,
+    pub months: i32,
+    pub quantity: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub note: Option<String>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub redeemed: i64,
+    pub revoked: i64,
+}
+
+/// A new batch with its codes in plain text, shown this once.
+#[derive(Debug, Serialize)]
+pub struct IssuedAccessCodes {
+    pub batch: AccessCodeBatch,
+    pub codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct RedeemAccessCodeRequest {
+    #[validate(length(min = 1, max = 64))]
+    pub code: String,
+}
+
+/// The prepaid membership a code gave the member.
+#[derive(Debug, Serialize)]
+pub struct AccessCodeRedemption {
+    #[serde(skip_serializing)]
+    pub batch_id: Uuid,
+    pub subscription_id: Uuid,
+    pub tier: String,
+    pub months: i32,
+    pub current_period_end: DateTime<Utc>,
+}
@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use validator::Validate;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BillingInterval {
+    Monthly,
+    Yearly,
+}
+
+impl BillingInterval {
+    /// As stored in `checkout_sessions.billing_interval`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BillingInterval::Monthly => "monthly",
+            BillingInterval::Yearly => "yearly",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "monthly" => Some(BillingInterval::Monthly),
+            "yearly" => Some(BillingInterval::Yearly),
+            _ => None,
+        }
+    }
+
+    /// Stripe's `recurring.interval`.
+    pub fn stripe_interval(&self) -> &'static str {
+        match self {
+            BillingInterval::Monthly => "month",
+            BillingInterval::Yearly => "year",
+        }
+    }
+}
+
+/// `subscriptions.status`, using Stripe's names.
+///
+/// A failed payment makes a subscription `PastDue`; it keeps its tier for the grace period and
+/// then becomes `Unpaid`, which does not. Paying again from either makes it `Active`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    Incomplete,
+    IncompleteExpired,
+    Trialing,
+    Active,
+    PastDue,
+    Unpaid,
+    Paused,
+    Canceled,
+}
+
+impl SubscriptionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::Incomplete => "incomplete",
+            SubscriptionStatus::IncompleteExpired => "incomplete_expired",
+            SubscriptionStatus::Trialing => "trialing",
+            SubscriptionStatus::Active => "active",
+            SubscriptionStatus::PastDue => "past_due",
+            SubscriptionStatus::Unpaid => "unpaid",
+            SubscriptionStatus::Paused => "paused",
+            SubscriptionStatus::Canceled => "canceled",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "incomplete" => Some(SubscriptionStatus::Incomplete),
+            "incomplete_expired" => Some(SubscriptionStatus::IncompleteExpired),
+            "trialing" => Some(SubscriptionStatus::Trialing),
+            "active" => Some(SubscriptionStatus::Active),
+            "past_due" => Some(SubscriptionStatus::PastDue),
+            "unpaid" => Some(SubscriptionStatus::Unpaid),
+            "paused" => Some(SubscriptionStatus::Paused),
+            "canceled" => Some(SubscriptionStatus::Canceled),
+            _ => None,
+        }
+    }
+
+    /// Entitles the member to the subscription's tier (for `PastDue`, only until grace ends),
+    /// as in `current_membership_id()`.
+    pub fn is_live(&self) -> bool {
+        matches!(
+            self,
+            SubscriptionStatus::Active | SubscriptionStatus::Trialing | SubscriptionStatus::PastDue
+        )
+    }
+
+    /// Whether a subscription in this status may move to `next`. Ended subscriptions stay
+    /// ended, and one whose grace period ran out cannot be given another by a late `past_due`.
+    pub fn can_become(&self, next: SubscriptionStatus) -> bool {
+        match self {
+            SubscriptionStatus::Canceled | SubscriptionStatus::IncompleteExpired => *self == next,
+            SubscriptionStatus::Unpaid => next != SubscriptionStatus::PastDue,
+            _ => true,
+        }
+    }
+}
+
+/// The member's plan and where their subscription stands, for the billing page.
+#[derive(Debug, Serialize)]
+pub struct BillingStatus {
+    pub tier: String,
+    /// `None` for members on the default tier without a subscription
+    pub status: Option<SubscriptionStatus>,
+    pub billing_interval: Option<String>,
+    pub current_period_end: Option<DateTime<Utc>>,
+    pub past_due_since: Option<DateTime<Utc>>,
+    /// While `past_due`: when the member moves to basic unless the payment goes through
+    pub grace_ends_at: Option<DateTime<Utc>>,
+    /// A downgrade waiting for the end of the current period
+    pub scheduled_change: Option<PlanChange>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CheckoutRequest {
+    #[validate(length(min = 1, max = 50))]
+    pub tier: String,
+    pub interval: BillingInterval,
+    #[validate(length(min = 1, max = 50))]
+    pub promotion_code: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckoutResponse {
+    pub session_id: String,
+    pub checkout_url: String,
+}
+
+/// A Stripe Checkout session we started for a member.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct CheckoutSession {
+    pub id: String,
+    pub user_id: Uuid,
+    pub membership_id: Uuid,
+    pub billing_interval: String,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// The plan a subscriber wants to move to.
+#[derive(Debug, Deserialize, Validate)]
+pub struct PlanChangeRequest {
+    #[validate(length(min = 1, max = 50))]
+    pub tier: String,
+    pub interval: BillingInterval,
+    /// Discount promotion; upgrades only
+    #[validate(length(min = 1, max = 50))]
+    pub promotion_code: Option<String>,
+}
+
+/// Upgrades (a pricier tier, or monthly to yearly) apply at once with a prorated charge;
+/// downgrades apply at the end of the period already paid for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanChangeKind {
+    Upgrade,
+    Downgrade,
+}
+
+impl PlanChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlanChangeKind::Upgrade => "upgrade",
+            PlanChangeKind::Downgrade => "downgrade",
+        }
+    }
+}
+
+/// One side of a plan change.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanQuote {
+    pub tier: String,
+    pub version: i32,
+    pub name: String,
+    pub interval: BillingInterval,
+    pub price: Decimal,
+}
+
+/// What a plan change would cost, in `currency`, if made now.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanChangePreview {
+    pub from: PlanQuote,
+    pub to: PlanQuote,
+    pub kind: PlanChangeKind,
+    pub effective_at: DateTime<Utc>,
+    pub currency: String,
+    /// Unused part of the current period's price
+    pub credit: Decimal,
+    /// The new price for the rest of the period, or a whole new period when the interval changes
+    pub charge: Decimal,
+    pub promotion_code: Option<String>,
+    /// Taken off `charge` by the promotion
+    pub discount: Decimal,
+    /// Charged now; 0 for downgrades
+    pub amount_due: Decimal,
+    /// Credit left for later invoices when `credit` is more than `charge`
+    pub credit_balance: Decimal,
+}
+
+/// A plan change from `plan_changes`, with the tiers it moved between.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct PlanChange {
+    pub id: Uuid,
+    pub subscription_id: Uuid,
+    pub from_tier: String,
+    pub from_version: i32,
+    pub from_interval: String,
+    pub to_tier: String,
+    pub to_version: i32,
+    pub to_interval: String,
+    pub kind: String,
+    pub status: String, // scheduled, applied or cancelled
+    pub amount_due: Decimal,
+    pub currency: String,
+    pub effective_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub applied_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// A member's conversation; counts towards the plan's `max_conversations`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Conversation {
+    pub id: Uuid,
+    pub title: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateConversationRequest {
+    #[validate(length(min = 1, max = 200))]
+    pub title: String,
+}
+
+/// A file a member has shared; its size counts towards the plan's `max_storage_gb`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredFile {
+    pub id: Uuid,
+    pub name: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The file's contents are the request body.
+#[derive(Debug, Deserialize, Validate)]
+pub struct UploadFileQuery {
+    #[validate(length(min = 1, max = 255))]
+    pub name: String,
+}
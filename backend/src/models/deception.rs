@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use validator::Validate;
+
+/// A decoy account; any sign-in attempt against it is hostile.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct CanaryAccount {
+    pub id: Uuid,
+    pub email: String,
+    pub membership_tier: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub failed_login_attempts: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateCanaryRequest {
+    #[validate(email)]
+    pub email: String,
+    /// Code of an offered tier; defaults to `basic`
+    pub membership_tier: Option<String>,
+}
+
+/// A decoy refresh token. Only a SHA-256 hash of the token is stored.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Honeytoken {
+    pub id: Uuid,
+    pub label: String, // Where it was planted
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub last_triggered_at: Option<DateTime<Utc>>,
+    pub trigger_count: i32,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateHoneytokenRequest {
+    #[validate(length(min = 1, max = 255))]
+    pub label: String,
+}
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use ipnetwork::IpNetwork;
+use validator::Validate;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct IpRule {
+    pub id: Uuid,
+    pub network: IpNetwork,
+    pub action: String, // allow, deny or elevated_risk
+    pub risk_adjustment: i32,
+    pub organization_id: Option<Uuid>, // None = platform-wide
+    pub description: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateIpRuleRequest {
+    pub network: String, // Single address or CIDR block
+    pub action: String,
+    #[validate(range(min = 0, max = 9))]
+    pub risk_adjustment: Option<i32>,
+    pub organization_id: Option<Uuid>,
+    #[validate(length(max = 500))]
+    pub description: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct IpRuleQuery {
+    pub organization_id: Option<Uuid>,
+}
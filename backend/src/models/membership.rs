@@ -2,27 +2,196 @@ use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use validator::Validate;
+use crate::models::BillingInterval;
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+/// One version of a membership tier's terms. Limits of -1 mean unlimited.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Membership {
     pub id: Uuid,
+    pub tier: String, // Stable code, e.g. "premium"
+    pub version: i32,
     pub name: String,
-    pub price_monthly: Option<rust_decimal::Decimal>,
-    pub price_yearly: Option<rust_decimal::Decimal>,
+    pub description: String,
+    pub price_monthly: Option<Decimal>,
+    pub price_yearly: Option<Decimal>,
     pub features: serde_json::Value,
     pub max_file_size_mb: i32,
     pub max_storage_gb: i32,
     pub max_conversations: i32,
+    pub sort_order: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
-    pub is_active: bool,
+    pub is_active: bool, // Offered to new subscribers
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+impl Membership {
+    /// Whether this version includes `feature`; features it does not list are not included.
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features
+            .get(feature)
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false)
+    }
+
+    pub fn limit(&self, limit: PlanLimit) -> Limit {
+        Limit(match limit {
+            PlanLimit::FileSizeMb => self.max_file_size_mb,
+            PlanLimit::StorageGb => self.max_storage_gb,
+            PlanLimit::Conversations => self.max_conversations,
+        })
+    }
+    pub fn price(&self, interval: BillingInterval) -> Option<Decimal> {
+        match interval {
+            BillingInterval::Monthly => self.price_monthly,
+            BillingInterval::Yearly => self.price_yearly,
+        }
+    }
+
+    /// What the tier costs per month, for ranking plans: the monthly price, or a twelfth of the
+    /// yearly one for yearly-only tiers.
+    pub fn monthly_rate(&self) -> Decimal {
+        self.price_monthly
+            .or_else(|| self.price_yearly.map(|price| price / Decimal::from(12)))
+            .unwrap_or(Decimal::ZERO)
+    }
+}
+
+/// The numeric limits a plan sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanLimit {
+    FileSizeMb,
+    StorageGb,
+    Conversations,
+}
+
+impl PlanLimit {
+    /// The catalog column, also used as the limit's name in API errors.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlanLimit::FileSizeMb => "max_file_size_mb",
+            PlanLimit::StorageGb => "max_storage_gb",
+            PlanLimit::Conversations => "max_conversations",
+        }
+    }
+}
+
+/// One plan limit; -1 means unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Limit(pub i32);
+
+impl Limit {
+    pub fn is_unlimited(self) -> bool {
+        self.0 < 0
+    }
+
+    /// Whether a total of `amount` (in the limit's unit) stays within the limit.
+    pub fn allows(self, amount: i64) -> bool {
+        self.is_unlimited() || amount <= i64::from(self.0)
+    }
+}
+
+/// What a member's current plan lets them do.
+#[derive(Debug, Clone, Serialize)]
+pub struct Entitlements {
+    pub tier: String,
+    pub version: i32,
+    pub features: BTreeMap<String, bool>,
+    pub max_file_size_mb: Limit,
+    pub max_storage_gb: Limit,
+    pub max_conversations: Limit,
+}
+
+impl Entitlements {
+    pub fn limit(&self, limit: PlanLimit) -> Limit {
+        match limit {
+            PlanLimit::FileSizeMb => self.max_file_size_mb,
+            PlanLimit::StorageGb => self.max_storage_gb,
+            PlanLimit::Conversations => self.max_conversations,
+        }
+    }
+}
+
+impl From<&Membership> for Entitlements {
+    fn from(membership: &Membership) -> Self {
+        let features = membership
+            .features
+            .as_object()
+            .map(|features| {
+                features
+                    .iter()
+                    .map(|(name, enabled)| (name.clone(), enabled.as_bool().unwrap_or(false)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Entitlements {
+            tier: membership.tier.clone(),
+            version: membership.version,
+            features,
+            max_file_size_mb: membership.limit(PlanLimit::FileSizeMb),
+            max_storage_gb: membership.limit(PlanLimit::StorageGb),
+            max_conversations: membership.limit(PlanLimit::Conversations),
+        }
+    }
+}
+
+/// A catalog version with how many subscriptions are still on its terms.
+#[derive(Debug, Serialize)]
+pub struct MembershipVersion {
+    #[serde(flatten)]
+    pub membership: Membership,
+    pub subscribers: i64,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateMembershipRequest {
+    #[validate(length(min = 1, max = 50))]
+    pub tier: String,
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    pub description: Option<String>,
+    pub price_monthly: Option<Decimal>,
+    pub price_yearly: Option<Decimal>,
+    pub features: serde_json::Value,
+    #[validate(range(min = -1))]
+    pub max_file_size_mb: i32,
+    #[validate(range(min = -1))]
+    pub max_storage_gb: i32,
+    #[validate(range(min = -1))]
+    pub max_conversations: i32,
+    pub sort_order: Option<i32>,
 }
 
+/// Fields left out keep the current version's value.
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateMembershipRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub price_monthly: Option<Decimal>,
+    pub price_yearly: Option<Decimal>,
+    pub features: Option<serde_json::Value>,
+    #[validate(range(min = -1))]
+    pub max_file_size_mb: Option<i32>,
+    #[validate(range(min = -1))]
+    pub max_storage_gb: Option<i32>,
+    #[validate(range(min = -1))]
+    pub max_conversations: Option<i32>,
+    pub sort_order: Option<i32>,
+}
+
+/// A row of `subscriptions`. Billing queries select just the columns each step needs.
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct Subscription {
     pub id: Uuid,
     pub user_id: Uuid,
-    pub membership_id: Option<Uuid>,
+    pub membership_id: Uuid,
     pub stripe_subscription_id: Option<String>,
     pub stripe_customer_id: Option<String>,
     pub status: String,
@@ -30,4 +199,30 @@ pub struct Subscription {
     pub current_period_end: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
-}
\ No newline at end of file
+    pub billing_interval: Option<String>,
+    pub provider_updated_at: Option<DateTime<Utc>>,
+    pub past_due_since: Option<DateTime<Utc>>,
+    pub grace_ends_at: Option<DateTime<Utc>>,
+    pub reminders_sent: i32,
+    pub last_reminder_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_limits_are_unlimited() {
+        assert!(Limit(-1).is_unlimited());
+        assert!(Limit(-1).allows(i64::MAX));
+        assert!(!Limit(0).is_unlimited());
+    }
+
+    #[test]
+    fn limits_allow_totals_up_to_and_including_the_max() {
+        assert!(Limit(10).allows(0));
+        assert!(Limit(10).allows(10));
+        assert!(!Limit(10).allows(11));
+        assert!(!Limit(0).allows(1));
+    }
+}
@@ -2,8 +2,30 @@ pub mod user;
 pub mod membership;
 pub mod session;
 pub mod security;
+pub mod notification;
+pub mod ip_rule;
+pub mod organization;
+pub mod deception;
+pub mod retention;
+pub mod role;
+pub mod billing;
+pub mod access_code;
+pub mod promotion;
+pub mod content;
 
 pub use user::*;
 pub use membership::*;
+// Sessions are read column by column in AuthService; the row type is kept for reference
+#[allow(unused_imports)]
 pub use session::*;
-pub use security::*;
\ No newline at end of file
+pub use security::*;
+pub use notification::*;
+pub use ip_rule::*;
+pub use organization::*;
+pub use deception::*;
+pub use retention::*;
+pub use role::*;
+pub use billing::*;
+pub use access_code::*;
+pub use promotion::*;
+pub use content::*;
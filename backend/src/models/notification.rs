@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use validator::Validate;
+
+pub const DEFAULT_ALERT_RISK_LEVEL: i32 = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct NotificationPreferences {
+    pub user_id: Uuid,
+    pub min_risk_level: i32,
+    pub email_enabled: bool,
+    pub push_enabled: bool,
+    pub webhook_url: Option<String>,
+    /// Encrypted under the member's key
+    #[serde(skip_serializing)]
+    pub webhook_secret: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl NotificationPreferences {
+    /// What a member gets before they ever save preferences.
+    pub fn defaults(user_id: Uuid) -> Self {
+        Self {
+            user_id,
+            min_risk_level: DEFAULT_ALERT_RISK_LEVEL,
+            email_enabled: true,
+            push_enabled: true,
+            webhook_url: None,
+            webhook_secret: None,
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateNotificationPreferencesRequest {
+    #[validate(range(min = 1, max = 10))]
+    pub min_risk_level: Option<i32>,
+    pub email_enabled: Option<bool>,
+    pub push_enabled: Option<bool>,
+    pub webhook_url: Option<String>, // Empty string removes the webhook
+}
+
+/// Payload pushed to live sessions and webhooks when a security event crosses a member's threshold.
+#[derive(Debug, Clone, Serialize)]
+pub struct SecurityAlert {
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub risk_level: i32,
+    pub title: String,
+    pub message: String,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,129 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PromotionKind {
+    /// `percent_off` the price
+    Percent,
+    /// `amount_off` the price, in the billing currency
+    Fixed,
+    /// `trial_days` of the tier without a card
+    Trial,
+}
+
+impl PromotionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PromotionKind::Percent => "percent",
+            PromotionKind::Fixed => "fixed",
+            PromotionKind::Trial => "trial",
+        }
+    }
+}
+
+/// Which invoices a discount applies to, as for a Stripe coupon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PromotionDuration {
+    Once,
+    /// For `duration_months`
+    Repeating,
+    Forever,
+}
+
+impl PromotionDuration {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PromotionDuration::Once => "once",
+            PromotionDuration::Repeating => "repeating",
+            PromotionDuration::Forever => "forever",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreatePromotionRequest {
+    #[validate(length(min = 3, max = 50))]
+    pub code: String,
+    #[validate(length(max = 255))]
+    pub description: Option<String>,
+    pub kind: PromotionKind,
+    pub percent_off: Option<Decimal>,
+    pub amount_off: Option<Decimal>,
+    /// Discounts only; defaults to `once`
+    pub duration: Option<PromotionDuration>,
+    #[validate(range(min = 1, max = 36))]
+    pub duration_months: Option<i32>,
+    #[validate(range(min = 1, max = 90))]
+    pub trial_days: Option<i32>,
+    /// Tier codes; empty or missing for every paid tier
+    #[serde(default)]
+    pub tiers: Vec<String>,
+    #[validate(range(min = 1))]
+    pub max_redemptions: Option<i32>,
+    pub starts_at: Option<DateTime<Utc>>,
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+/// A promotion code and how often it has been used.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Promotion {
+    pub id: Uuid,
+    pub code: String,
+    pub description: Option<String>,
+    pub kind: String, // percent, fixed or trial
+    pub percent_off: Option<Decimal>,
+    pub amount_off: Option<Decimal>,
+    pub currency: Option<String>,
+    pub duration: Option<String>,
+    pub duration_months: Option<i32>,
+    pub trial_days: Option<i32>,
+    pub tiers: Vec<String>,
+    pub max_redemptions: Option<i32>,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: Option<DateTime<Utc>>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub redemptions: i64,
+}
+
+impl Promotion {
+    pub fn is_trial(&self) -> bool {
+        self.kind == PromotionKind::Trial.as_str()
+    }
+
+    pub fn applies_to(&self, tier: &str) -> bool {
+        self.tiers.is_empty() || self.tiers.iter().any(|t| t == tier)
+    }
+
+    /// How much comes off `amount`; never more than `amount`, and nothing for trials.
+    pub fn discount(&self, amount: Decimal) -> Decimal {
+        let discount = match (self.percent_off, self.amount_off) {
+            (Some(percent), _) => (amount * percent / Decimal::ONE_HUNDRED).round_dp(2),
+            (None, Some(fixed)) => fixed,
+            (None, None) => Decimal::ZERO,
+        };
+        discount.min(amount).max(Decimal::ZERO)
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct StartTrialRequest {
+    #[validate(length(min = 1, max = 50))]
+    pub code: String,
+    #[validate(length(min = 1, max = 50))]
+    pub tier: String,
+}
+
+/// A no-card trial; the member moves back to basic when it ends.
+#[derive(Debug, Serialize)]
+pub struct Trial {
+    pub subscription_id: Uuid,
+    pub tier: String,
+    pub trial_ends_at: DateTime<Utc>,
+}
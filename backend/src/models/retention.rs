@@ -0,0 +1,35 @@
+use serde::Serialize;
+use chrono::{DateTime, Utc};
+
+/// What one table holds and how long it keeps it.
+#[derive(Debug, Clone, Serialize)]
+pub struct TableRetention {
+    pub table: &'static str,
+    pub retention_days: i64,
+    pub ip_truncation_days: i64,
+    pub rows: i64,
+    pub oldest_record: Option<DateTime<Utc>>,
+    /// Rows still holding a full IP address or a user agent.
+    pub rows_with_full_ip: i64,
+    /// Rows past the truncation window that still hold a full IP address or a user agent.
+    pub rows_due_for_minimizing: i64,
+    /// Rows past their retention window, deleted by the next purge.
+    pub rows_due_for_deletion: i64,
+}
+
+/// Outcome of one purge pass.
+#[derive(Debug, Clone, Serialize)]
+pub struct PurgeRun {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub security_events_deleted: u64,
+    pub user_sessions_deleted: u64,
+    pub rows_minimized: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RetentionReport {
+    pub tables: Vec<TableRetention>,
+    /// Last purge run by this instance, if any since it started.
+    pub last_run: Option<PurgeRun>,
+}
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use validator::Validate;
+
+/// A named set of permissions granted to members through `user_roles`.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Role {
+    pub id: Uuid,
+    pub name: String,
+    pub description: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub permissions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SetUserRolesRequest {
+    #[validate(length(max = 20))]
+    pub roles: Vec<String>,
+}
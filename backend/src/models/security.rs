@@ -4,7 +4,7 @@ use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use std::net::IpAddr;
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct SecurityEvent {
     pub id: Uuid,
     pub user_id: Option<Uuid>,
@@ -16,6 +16,254 @@ pub struct SecurityEvent {
     pub created_at: DateTime<Utc>,
 }
 
+impl SecurityEvent {
+    /// Typed view of the stored `event_type`/`details` pair, or `None` for a type this build does not know.
+    pub fn kind(&self) -> Option<SecurityEventType> {
+        SecurityEventType::from_stored(&self.event_type, self.details.clone())
+    }
+}
+
+/// Catalog of every security event the backend records.
+///
+/// Stored as `security_events.event_type` (the snake_case variant name) with the variant's
+/// fields, if any, in `security_events.details`. Rows written before this enum existed used the
+/// same names and payloads, so they read back through [`SecurityEventType::from_stored`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event_type", content = "details", rename_all = "snake_case")]
+pub enum SecurityEventType {
+    /// A new account was created.
+    UserRegistered,
+    /// Password login completed and a session was issued.
+    LoginSuccess,
+    /// Login from a device (user agent fingerprint) the member has not signed in from before.
+    NewDeviceLogin { device_fingerprint: String },
+    /// Wrong password at login; `failed_attempts` is the running count after this failure.
+    LoginFailed { failed_attempts: i32 },
+    /// The account was locked after too many failed logins.
+    AccountLocked { failed_attempts: i32 },
+    /// One more failed login will trigger the destruction protocol.
+    DestructionPending { failed_attempts: i32, threshold: i32 },
+    /// Wrong current password while confirming a sensitive account change.
+    ReauthenticationFailed,
+    /// The member re-entered their credentials for an elevated token.
+    StepUpAuthenticated,
+    /// The member changed their password while signed in.
+    PasswordChanged,
+    /// A password reset code was emailed.
+    PasswordResetRequested,
+    /// The password was replaced using a reset code.
+    PasswordReset,
+    /// A change of email address is waiting for confirmation from the new address.
+    EmailChangeRequested,
+    /// The account email address was changed.
+    EmailChanged,
+    /// The destruction protocol ran for the account.
+    DestructionTriggered { trigger_type: String },
+    /// An administrator exported security events in bulk.
+    SecurityEventsExported {
+        format: String,
+        rows: usize,
+        filter: serde_json::Value,
+    },
+    /// Request from outside the member's organization allowlist was refused.
+    NetworkNotAllowed,
+    /// An administrator added a network rule.
+    IpRuleCreated {
+        rule_id: Uuid,
+        network: String,
+        action: String,
+        organization_id: Option<Uuid>,
+    },
+    /// An administrator removed a network rule.
+    IpRuleDeleted {
+        rule_id: Uuid,
+        network: String,
+        action: String,
+        organization_id: Option<Uuid>,
+    },
+    /// An administrator moved the member into or out of an organization.
+    OrganizationChanged {
+        organization_id: Option<Uuid>,
+        changed_by: Uuid,
+    },
+    /// An administrator cleared the member's failed-login lockout.
+    AccountUnlocked { unlocked_by: Uuid },
+    /// An administrator deactivated the account and ended its sessions.
+    AccountDeactivated { deactivated_by: Uuid },
+    /// An administrator reactivated the account.
+    AccountReactivated { reactivated_by: Uuid },
+    /// An administrator changed the member's roles.
+    RolesChanged { roles: Vec<String>, changed_by: Uuid },
+    /// An administrator created, changed or retired a membership tier.
+    MembershipCatalogChanged {
+        tier: String,
+        version: i32,
+        action: String,
+        changed_by: Uuid,
+    },
+    /// The member's subscription changed status, e.g. after a payment or cancellation.
+    SubscriptionChanged {
+        subscription_id: Uuid,
+        tier: String,
+        status: String,
+    },
+    /// An administrator issued a batch of prepaid access codes.
+    AccessCodesIssued {
+        batch_id: Uuid,
+        tier: String,
+        months: i32,
+        quantity: i32,
+        issued_by: Uuid,
+    },
+    /// An administrator revoked a batch's unredeemed access codes.
+    AccessCodesRevoked {
+        batch_id: Uuid,
+        revoked: u64,
+        revoked_by: Uuid,
+    },
+    /// The member redeemed a prepaid access code.
+    AccessCodeRedeemed {
+        batch_id: Uuid,
+        subscription_id: Uuid,
+        tier: String,
+        months: i32,
+    },
+    /// The member presented an unknown, used, revoked or expired access code.
+    AccessCodeRejected { reason: String },
+    /// An administrator created or ended a promotion code.
+    PromotionChanged {
+        promotion_id: Uuid,
+        code: String,
+        action: String,
+        changed_by: Uuid,
+    },
+    /// The member started a no-card trial with a promotion code.
+    TrialStarted {
+        subscription_id: Uuid,
+        tier: String,
+        code: String,
+    },
+    /// Someone tried to sign in to a canary account.
+    CanaryLoginAttempt,
+    /// A honeytoken refresh token was presented.
+    HoneytokenUsed { honeytoken_id: Uuid, label: String },
+    /// Events could not be stored in Postgres or the local journal.
+    SecurityEventsDropped {
+        dropped: u64,
+        total_dropped: u64,
+        reason: String,
+    },
+    /// The retention job deleted or minimized expired records.
+    RetentionPurged {
+        events_deleted: u64,
+        sessions_deleted: u64,
+        rows_minimized: u64,
+    },
+}
+
+impl SecurityEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SecurityEventType::UserRegistered => "user_registered",
+            SecurityEventType::LoginSuccess => "login_success",
+            SecurityEventType::NewDeviceLogin { .. } => "new_device_login",
+            SecurityEventType::LoginFailed { .. } => "login_failed",
+            SecurityEventType::AccountLocked { .. } => "account_locked",
+            SecurityEventType::DestructionPending { .. } => "destruction_pending",
+            SecurityEventType::ReauthenticationFailed => "reauthentication_failed",
+            SecurityEventType::StepUpAuthenticated => "step_up_authenticated",
+            SecurityEventType::PasswordChanged => "password_changed",
+            SecurityEventType::PasswordResetRequested => "password_reset_requested",
+            SecurityEventType::PasswordReset => "password_reset",
+            SecurityEventType::EmailChangeRequested => "email_change_requested",
+            SecurityEventType::EmailChanged => "email_changed",
+            SecurityEventType::DestructionTriggered { .. } => "destruction_triggered",
+            SecurityEventType::SecurityEventsExported { .. } => "security_events_exported",
+            SecurityEventType::NetworkNotAllowed => "network_not_allowed",
+            SecurityEventType::IpRuleCreated { .. } => "ip_rule_created",
+            SecurityEventType::IpRuleDeleted { .. } => "ip_rule_deleted",
+            SecurityEventType::OrganizationChanged { .. } => "organization_changed",
+            SecurityEventType::AccountUnlocked { .. } => "account_unlocked",
+            SecurityEventType::AccountDeactivated { .. } => "account_deactivated",
+            SecurityEventType::AccountReactivated { .. } => "account_reactivated",
+            SecurityEventType::RolesChanged { .. } => "roles_changed",
+            SecurityEventType::MembershipCatalogChanged { .. } => "membership_catalog_changed",
+            SecurityEventType::SubscriptionChanged { .. } => "subscription_changed",
+            SecurityEventType::AccessCodesIssued { .. } => "access_codes_issued",
+            SecurityEventType::AccessCodesRevoked { .. } => "access_codes_revoked",
+            SecurityEventType::AccessCodeRedeemed { .. } => "access_code_redeemed",
+            SecurityEventType::AccessCodeRejected { .. } => "access_code_rejected",
+            SecurityEventType::PromotionChanged { .. } => "promotion_changed",
+            SecurityEventType::TrialStarted { .. } => "trial_started",
+            SecurityEventType::CanaryLoginAttempt => "canary_login_attempt",
+            SecurityEventType::HoneytokenUsed { .. } => "honeytoken_used",
+            SecurityEventType::SecurityEventsDropped { .. } => "security_events_dropped",
+            SecurityEventType::RetentionPurged { .. } => "retention_purged",
+        }
+    }
+
+    /// Risk on the 1-10 scale before any request context (IP reputation etc.) is considered.
+    pub fn base_risk_level(&self) -> i32 {
+        match self {
+            SecurityEventType::LoginSuccess | SecurityEventType::RetentionPurged { .. } => 1,
+            SecurityEventType::UserRegistered
+            | SecurityEventType::PasswordResetRequested
+            | SecurityEventType::StepUpAuthenticated
+            | SecurityEventType::SubscriptionChanged { .. }
+            | SecurityEventType::AccessCodeRedeemed { .. }
+            | SecurityEventType::TrialStarted { .. } => 2,
+            SecurityEventType::LoginFailed { .. }
+            | SecurityEventType::EmailChangeRequested
+            | SecurityEventType::SecurityEventsExported { .. }
+            | SecurityEventType::OrganizationChanged { .. }
+            | SecurityEventType::AccountUnlocked { .. }
+            | SecurityEventType::AccountReactivated { .. }
+            | SecurityEventType::MembershipCatalogChanged { .. }
+            | SecurityEventType::AccessCodesRevoked { .. }
+            | SecurityEventType::PromotionChanged { .. }
+            | SecurityEventType::AccessCodeRejected { .. } => 3,
+            SecurityEventType::ReauthenticationFailed
+            | SecurityEventType::PasswordChanged
+            | SecurityEventType::PasswordReset
+            | SecurityEventType::IpRuleCreated { .. }
+            | SecurityEventType::IpRuleDeleted { .. }
+            | SecurityEventType::AccessCodesIssued { .. } => 4,
+            SecurityEventType::NewDeviceLogin { .. }
+            | SecurityEventType::AccountLocked { .. }
+            | SecurityEventType::EmailChanged
+            | SecurityEventType::AccountDeactivated { .. }
+            | SecurityEventType::RolesChanged { .. } => 5,
+            SecurityEventType::NetworkNotAllowed => 6,
+            SecurityEventType::DestructionPending { .. } => 8,
+            SecurityEventType::SecurityEventsDropped { .. } => 9,
+            SecurityEventType::DestructionTriggered { .. }
+            | SecurityEventType::CanaryLoginAttempt
+            | SecurityEventType::HoneytokenUsed { .. } => 10,
+        }
+    }
+
+    /// The payload stored in `security_events.details`.
+    pub fn details(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|mut value| value.get_mut("details").map(serde_json::Value::take))
+    }
+
+    /// Rebuilds the typed event from a stored row. Unit events tolerate stray details from
+    /// older rows; unknown names or mismatched payloads yield `None`.
+    pub fn from_stored(event_type: &str, details: Option<serde_json::Value>) -> Option<Self> {
+        let tagged = |details: Option<serde_json::Value>| {
+            let mut value = serde_json::json!({ "event_type": event_type });
+            if let Some(details) = details.filter(|d| !d.is_null()) {
+                value["details"] = details;
+            }
+            serde_json::from_value(value).ok()
+        };
+
+        tagged(details).or_else(|| tagged(None))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct DestructionLog {
     pub id: Uuid,
@@ -26,4 +274,137 @@ pub struct DestructionLog {
     pub success: bool,
     pub forensic_residue_level: i32,
     pub details: Option<serde_json::Value>,
-}
\ No newline at end of file
+}
+
+impl DestructionLog {
+    /// The member's key was shredded and none of their data was stored outside it.
+    pub const RESIDUE_NONE: i32 = 0;
+    /// The key was shredded, but some data was not under it and stays readable in WAL and
+    /// backups: fields that predate per-member keys, or plain-text metadata such as the password
+    /// hash, session and event IP addresses and user agents, and Stripe ids.
+    pub const RESIDUE_PARTIAL: i32 = 1;
+    /// The key could not be shredded; deleted data is recoverable from WAL and backups.
+    pub const RESIDUE_RECOVERABLE: i32 = 2;
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DestructionLogQuery {
+    pub user_id: Option<Uuid>,
+    pub before: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SecurityEventQuery {
+    pub user_id: Option<Uuid>,
+    pub event_type: Option<String>,
+    pub min_risk: Option<i32>,
+    pub max_risk: Option<i32>,
+    pub ip: Option<String>, // Single address or CIDR block
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub format: Option<String>, // json (default), csv or ndjson
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn rows_from_older_builds_read_back() {
+        // The baseline stored login failures with their count and everything else bare
+        assert_eq!(
+            SecurityEventType::from_stored("login_failed", Some(json!({ "failed_attempts": 2 }))),
+            Some(SecurityEventType::LoginFailed { failed_attempts: 2 })
+        );
+        assert_eq!(
+            SecurityEventType::from_stored("login_success", None),
+            Some(SecurityEventType::LoginSuccess)
+        );
+        assert_eq!(
+            SecurityEventType::from_stored("user_registered", Some(serde_json::Value::Null)),
+            Some(SecurityEventType::UserRegistered)
+        );
+    }
+
+    #[test]
+    fn unit_events_tolerate_stray_details() {
+        assert_eq!(
+            SecurityEventType::from_stored("password_reset", Some(json!({ "via": "email" }))),
+            Some(SecurityEventType::PasswordReset)
+        );
+        assert_eq!(
+            SecurityEventType::from_stored("canary_login_attempt", Some(json!({}))),
+            Some(SecurityEventType::CanaryLoginAttempt)
+        );
+    }
+
+    #[test]
+    fn unknown_names_and_mismatched_payloads_are_none() {
+        // Names older builds could write that nothing emits any more
+        assert_eq!(
+            SecurityEventType::from_stored("suspicious_activity", Some(json!({ "reason": "x" }))),
+            None
+        );
+        assert_eq!(
+            SecurityEventType::from_stored("multiple_failed_logins", Some(json!({ "failed_attempts": 4 }))),
+            None
+        );
+        assert_eq!(SecurityEventType::from_stored("", None), None);
+
+        assert_eq!(SecurityEventType::from_stored("login_failed", None), None);
+        assert_eq!(
+            SecurityEventType::from_stored("login_failed", Some(json!({ "failed_attempts": "two" }))),
+            None
+        );
+        assert_eq!(SecurityEventType::from_stored("destruction_triggered", Some(json!({}))), None);
+    }
+
+    #[test]
+    fn stored_events_round_trip() {
+        let events = [
+            SecurityEventType::DestructionTriggered {
+                trigger_type: "failed_login_threshold".to_string(),
+            },
+            SecurityEventType::DestructionPending {
+                failed_attempts: 4,
+                threshold: 5,
+            },
+            SecurityEventType::RolesChanged {
+                roles: vec!["support".to_string()],
+                changed_by: Uuid::nil(),
+            },
+            SecurityEventType::StepUpAuthenticated,
+        ];
+
+        for event in events {
+            assert_eq!(
+                SecurityEventType::from_stored(event.as_str(), event.details()),
+                Some(event.clone())
+            );
+        }
+        assert_eq!(SecurityEventType::StepUpAuthenticated.details(), None);
+    }
+
+    #[test]
+    fn events_know_their_kind() {
+        let event = SecurityEvent {
+            id: Uuid::new_v4(),
+            user_id: None,
+            event_type: "account_locked".to_string(),
+            ip_address: None,
+            user_agent: None,
+            details: Some(json!({ "failed_attempts": 3 })),
+            risk_level: 5,
+            created_at: Utc::now(),
+        };
+
+        assert_eq!(
+            event.kind(),
+            Some(SecurityEventType::AccountLocked { failed_attempts: 3 })
+        );
+    }
+}
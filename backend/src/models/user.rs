@@ -9,7 +9,6 @@ pub struct User {
     pub id: Uuid,
     pub email: String,
     pub password_hash: String,
-    pub membership_tier: String,
     pub created_at: Option<DateTime<Utc>>,    // NOT NULL with DEFAULT but SQLx treats as nullable
     pub updated_at: Option<DateTime<Utc>>,    // NOT NULL with DEFAULT but SQLx treats as nullable
     pub last_login: Option<DateTime<Utc>>,
@@ -24,6 +23,13 @@ pub struct User {
     pub email_verification_token: Option<String>,
     pub password_reset_token: Option<String>,
     pub password_reset_expires: Option<DateTime<Utc>>,
+    pub pending_email: Option<String>,
+    pub pending_email_token: Option<String>,
+    pub pending_email_expires: Option<DateTime<Utc>>,
+    pub password_changed_at: Option<DateTime<Utc>>,
+    pub organization_id: Option<Uuid>,
+    pub is_canary: bool,
+    pub email_index: Option<String>, // Blind index of the (encrypted) email
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -41,9 +47,10 @@ pub struct UserPublic {
 pub struct CreateUserRequest {
     #[validate(email)]
     pub email: String,
-    #[validate(length(min = 8, max = 128))]
     pub password: String,
-    pub membership_tier: Option<String>,
+    /// Not accepted: the tier follows the member's subscription. Kept so a request that still
+    /// sends one is refused instead of silently landing on the default tier.
+    pub membership_tier: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -53,19 +60,63 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// Fresh credentials for an elevated token; `mfa_code` is required once MFA is enabled.
+#[derive(Debug, Deserialize)]
+pub struct StepUpRequest {
+    pub password: String,
+    pub mfa_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangeEmailRequest {
+    pub current_password: String,
+    #[validate(email)]
+    pub new_email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmEmailChangeRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ForgotPasswordRequest {
+    #[validate(email)]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+// Password and email changes have their own requests; nothing edits the rest of a profile yet
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct UpdateUserRequest {
-    pub membership_tier: Option<String>,
     pub mfa_enabled: Option<bool>,
 }
 
 impl User {
-    pub fn to_public(&self) -> UserPublic {
+    /// `membership_tier` is the code of the member's current tier (see `MembershipService::current_for`).
+    pub fn to_public(&self, membership_tier: String) -> UserPublic {
         UserPublic {
             id: self.id,
             email: self.email.clone(),
-            membership_tier: self.membership_tier.clone(),
-            created_at: self.created_at.unwrap_or_else(|| Utc::now()),
+            membership_tier,
+            created_at: self.created_at.unwrap_or_else(Utc::now),
             last_login: self.last_login,
             mfa_enabled: self.mfa_enabled.unwrap_or(false),
             email_verified: self.email_verified.unwrap_or(false),
@@ -0,0 +1,368 @@
+use crate::models::{
+    AccessCodeBatch, AccessCodeRedemption, CreateAccessCodesRequest, IssuedAccessCodes,
+};
+use crate::services::{MembershipService, DEFAULT_TIER};
+use crate::utils::sha256_hex;
+use chrono::Utc;
+use ring::rand::{SecureRandom, SystemRandom};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Crockford base32: no I, L, O or U, so codes survive being read aloud or copied by hand
+const CODE_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+// 20 characters of 5 bits each
+const CODE_LENGTH: usize = 20;
+
+/// Prepaid access codes: membership paid for outside Stripe (cash, crypto, a reseller), with no
+/// card tied to the member.
+///
+/// Each code is worth `months` of one catalog version. Only SHA-256 hashes are stored; with 100
+/// random bits per code a stolen table cannot be turned back into codes. Redeeming a code
+/// creates a subscription with no Stripe ids whose period ends when the months run out, so
+/// entitlements treat it like any other subscription; `SubscriptionScheduler` ends it.
+#[derive(Debug, Clone)]
+pub struct AccessCodeService {
+    db: PgPool,
+    memberships: MembershipService,
+    rng: SystemRandom,
+}
+
+#[derive(Debug)]
+pub enum AccessCodeError {
+    DatabaseError(sqlx::Error),
+    TierNotOffered,
+    Invalid(&'static str),
+    BatchNotFound,
+    /// Unknown, already redeemed, revoked or expired; the reason is for the audit trail only.
+    InvalidCode(&'static str),
+    /// The member already has a subscription a code cannot be added to.
+    AlreadySubscribed,
+}
+
+impl std::fmt::Display for AccessCodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AccessCodeError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            AccessCodeError::TierNotOffered => write!(f, "Membership tier is not offered"),
+            AccessCodeError::Invalid(reason) => write!(f, "{}", reason),
+            AccessCodeError::BatchNotFound => write!(f, "Access code batch not found"),
+            AccessCodeError::InvalidCode(reason) => write!(f, "Access code is {}", reason),
+            AccessCodeError::AlreadySubscribed => write!(f, "Member already has a subscription"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AccessCodeError {
+    fn from(err: sqlx::Error) -> Self {
+        AccessCodeError::DatabaseError(err)
+    }
+}
+
+impl AccessCodeService {
+    pub fn new(db: PgPool) -> Self {
+        Self {
+            memberships: MembershipService::new(db.clone()),
+            db,
+            rng: SystemRandom::new(),
+        }
+    }
+
+    /// Generates a batch of codes for the offered version of `request.tier`.
+    pub async fn issue(
+        &self,
+        issued_by: Uuid,
+        request: CreateAccessCodesRequest,
+    ) -> Result<IssuedAccessCodes, AccessCodeError> {
+        if request.tier == DEFAULT_TIER {
+            return Err(AccessCodeError::Invalid("Codes are for paid tiers"));
+        }
+        if request.expires_at.is_some_and(|at| at <= Utc::now()) {
+            return Err(AccessCodeError::Invalid("expires_at must be in the future"));
+        }
+        let membership = self
+            .memberships
+            .list_offered()
+            .await?
+            .into_iter()
+            .find(|membership| membership.tier == request.tier)
+            .ok_or(AccessCodeError::TierNotOffered)?;
+
+        let codes: Vec<String> = (0..request.quantity)
+            .map(|_| self.generate_code())
+            .collect();
+        let hashes: Vec<String> = codes.iter().map(|code| hash_code(code)).collect();
+
+        let mut tx = self.db.begin().await?;
+        let batch_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO access_code_batches
+                (membership_id, months, quantity, expires_at, note, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id
+            "#,
+            membership.id,
+            request.months,
+            request.quantity,
+            request.expires_at,
+            request.note,
+            issued_by
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "INSERT INTO access_codes (batch_id, code_hash) SELECT $1, UNNEST($2::text[])",
+            batch_id,
+            &hashes[..]
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(IssuedAccessCodes {
+            batch: self.batch(batch_id).await?,
+            codes,
+        })
+    }
+
+    pub async fn list_batches(&self) -> Result<Vec<AccessCodeBatch>, AccessCodeError> {
+        Ok(sqlx::query_as!(
+            AccessCodeBatch,
+            r#"
+            SELECT b.id, m.tier, m.version, b.months, b.quantity, b.expires_at, b.note,
+                   b.created_by, b.created_at,
+                   COUNT(c.redeemed_at) AS "redeemed!",
+                   COUNT(c.revoked_at) AS "revoked!"
+            FROM access_code_batches b
+            JOIN memberships m ON m.id = b.membership_id
+            LEFT JOIN access_codes c ON c.batch_id = b.id
+            GROUP BY b.id, m.tier, m.version
+            ORDER BY b.created_at DESC
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    /// Revokes the batch's unredeemed codes. Memberships already redeemed run their course.
+    pub async fn revoke(&self, batch_id: Uuid) -> Result<(AccessCodeBatch, u64), AccessCodeError> {
+        let batch = self.batch(batch_id).await?;
+        let revoked = sqlx::query!(
+            r#"
+            UPDATE access_codes SET revoked_at = NOW()
+            WHERE batch_id = $1 AND redeemed_at IS NULL AND revoked_at IS NULL
+            "#,
+            batch_id
+        )
+        .execute(&self.db)
+        .await?
+        .rows_affected();
+
+        Ok((self.batch(batch.id).await?, revoked))
+    }
+
+    /// Starts a prepaid subscription, or extends the member's prepaid subscription to the same
+    /// plan. Members with any other live subscription are refused.
+    pub async fn redeem(
+        &self,
+        user_id: Uuid,
+        code: &str,
+    ) -> Result<AccessCodeRedemption, AccessCodeError> {
+        let mut tx = self.db.begin().await?;
+        let found = sqlx::query!(
+            r#"
+            SELECT c.id, c.batch_id, c.redeemed_at, c.revoked_at,
+                   b.expires_at, b.months, b.membership_id, m.tier
+            FROM access_codes c
+            JOIN access_code_batches b ON b.id = c.batch_id
+            JOIN memberships m ON m.id = b.membership_id
+            WHERE c.code_hash = $1
+            FOR UPDATE OF c
+            "#,
+            hash_code(code)
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AccessCodeError::InvalidCode("unknown"))?;
+
+        if found.redeemed_at.is_some() {
+            return Err(AccessCodeError::InvalidCode("already redeemed"));
+        }
+        if found.revoked_at.is_some() {
+            return Err(AccessCodeError::InvalidCode("revoked"));
+        }
+        if found.expires_at.is_some_and(|at| at <= Utc::now()) {
+            return Err(AccessCodeError::InvalidCode("expired"));
+        }
+
+        let live = sqlx::query!(
+            r#"
+            SELECT id, membership_id, status, stripe_subscription_id, current_period_end
+            FROM subscriptions
+            WHERE user_id = $1 AND status IN ('active', 'trialing', 'past_due')
+            FOR UPDATE
+            "#,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        // Prepaid subscriptions and trials are the only ones without Stripe ids that have a
+        // period end
+        let now = Utc::now();
+        let extend = match live {
+            None => None,
+            Some(live) => match (live.stripe_subscription_id, live.current_period_end) {
+                // Ran out but not yet ended by the scheduler, or a no-card trial giving way to
+                // paid-for months
+                (None, Some(end))
+                    if (live.status == "active" && end <= now) || live.status == "trialing" =>
+                {
+                    sqlx::query!(
+                        "UPDATE subscriptions SET status = 'canceled', updated_at = NOW() WHERE id = $1",
+                        live.id
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                    None
+                }
+                (None, Some(_))
+                    if live.status == "active" && live.membership_id == found.membership_id =>
+                {
+                    Some(live.id)
+                }
+                _ => return Err(AccessCodeError::AlreadySubscribed),
+            },
+        };
+
+        let (subscription_id, current_period_end) = match extend {
+            Some(subscription_id) => {
+                let end = sqlx::query_scalar!(
+                    r#"
+                    UPDATE subscriptions
+                    SET current_period_end = current_period_end + make_interval(months => $2),
+                        updated_at = NOW()
+                    WHERE id = $1
+                    RETURNING current_period_end AS "current_period_end!"
+                    "#,
+                    subscription_id,
+                    found.months
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+                (subscription_id, end)
+            }
+            None => {
+                let subscription = sqlx::query!(
+                    r#"
+                    INSERT INTO subscriptions
+                        (user_id, membership_id, status, current_period_start, current_period_end)
+                    VALUES ($1, $2, 'active', NOW(), NOW() + make_interval(months => $3))
+                    RETURNING id, current_period_end AS "current_period_end!"
+                    "#,
+                    user_id,
+                    found.membership_id,
+                    found.months
+                )
+                .fetch_one(&mut *tx)
+                .await?;
+                (subscription.id, subscription.current_period_end)
+            }
+        };
+
+        sqlx::query!(
+            r#"
+            UPDATE access_codes SET redeemed_by = $2, redeemed_at = NOW(), subscription_id = $3
+            WHERE id = $1
+            "#,
+            found.id,
+            user_id,
+            subscription_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(AccessCodeRedemption {
+            batch_id: found.batch_id,
+            subscription_id,
+            tier: found.tier,
+            months: found.months,
+            current_period_end,
+        })
+    }
+
+    async fn batch(&self, batch_id: Uuid) -> Result<AccessCodeBatch, AccessCodeError> {
+        sqlx::query_as!(
+            AccessCodeBatch,
+            r#"
+            SELECT b.id, m.tier, m.version, b.months, b.quantity, b.expires_at, b.note,
+                   b.created_by, b.created_at,
+                   COUNT(c.redeemed_at) AS "redeemed!",
+                   COUNT(c.revoked_at) AS "revoked!"
+            FROM access_code_batches b
+            JOIN memberships m ON m.id = b.membership_id
+            LEFT JOIN access_codes c ON c.batch_id = b.id
+            WHERE b.id = $1
+            GROUP BY b.id, m.tier, m.version
+            "#,
+            batch_id
+        )
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AccessCodeError::BatchNotFound)
+    }
+
+    /// `XXXXX-XXXXX-XXXXX-XXXXX`
+    fn generate_code(&self) -> String {
+        let mut bytes = [0u8; CODE_LENGTH];
+        self.rng.fill(&mut bytes).unwrap();
+
+        bytes
+            .chunks(5)
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|byte| CODE_ALPHABET[usize::from(byte & 0x1f)] as char)
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+}
+
+/// Codes as typed by members: any case, with or without separators, and with the letters
+/// Crockford base32 leaves out read as the digits they look like.
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            other => other,
+        })
+        .collect()
+}
+
+fn hash_code(code: &str) -> String {
+    sha256_hex(normalize_code(code).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_are_read_in_any_case_with_or_without_separators() {
+        assert_eq!(normalize_code("abcde-fghjk-mnpqr-stvwx"), "ABCDEFGHJKMNPQRSTVWX");
+        assert_eq!(normalize_code(" 01234 56789\t"), "0123456789");
+        assert_eq!(hash_code("abcde-12345"), hash_code("ABCDE12345"));
+    }
+
+    #[test]
+    fn letters_left_out_of_the_alphabet_read_as_the_digits_they_look_like() {
+        assert_eq!(normalize_code("O-o-I-i-L-l"), "001111");
+        assert_eq!(hash_code("1O0L-IL1O"), hash_code("1001-1110"));
+        assert_ne!(hash_code("AAAAA-BBBBB"), hash_code("AAAAA-BBBBC"));
+    }
+}
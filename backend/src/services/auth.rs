@@ -1,17 +1,53 @@
-use crate::models::{User, CreateUserRequest, LoginRequest, UserPublic};
-use crate::services::SecurityService;
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
+use crate::models::{
+    ChangeEmailRequest, ChangePasswordRequest, CreateUserRequest, LoginRequest, SecurityEventType,
+    StepUpRequest, User, UserPublic,
+};
+use crate::config::Config;
+use crate::services::{
+    FieldCipher, FieldEncryptionError, IpRuleService, MailerService, MembershipService,
+    PasswordPolicy, PasswordViolation, RbacService, SecurityService, EMAIL_FIELD,
+    PENDING_EMAIL_FIELD,
+};
+use crate::utils::{begin_scoped, sha256_hex, verify_totp, DbScope};
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+    password_hash::SaltString,
+};
 use chrono::{DateTime, Duration, Utc};
 use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey};
 use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
 use base64::Engine;
 use ring::rand::{SecureRandom, SystemRandom};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use std::net::IpAddr;
 use ipnetwork::IpNetwork;
 use uuid::Uuid;
 
+const LOCKOUT_THRESHOLD: i32 = 3;
+const DESTRUCTION_THRESHOLD: i32 = 5;
+const REFRESH_TOKEN_DAYS: i64 = 7;
+const STEP_UP_TOKEN_MINUTES: i64 = 5;
+
+/// `acr` of access tokens issued at login or refresh.
+pub const ACR_PASSWORD: &str = "password";
+/// `acr` of the short-lived tokens issued by a step-up.
+pub const ACR_STEP_UP: &str = "step_up";
+
+/// Argon2id with the configured costs. Every stored password hash, canaries' included, is made
+/// with it so no account verifies faster than another.
+pub fn password_hasher(config: &Config) -> Argon2<'static> {
+    let params = Params::new(
+        config.argon2_memory_cost,
+        config.argon2_time_cost,
+        config.argon2_parallelism,
+        None,
+    )
+    .expect("ARGON2_* settings must be valid Argon2 parameters");
+
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
 #[derive(Debug, Clone)]
 pub struct AuthService {
     db: PgPool,
@@ -19,6 +55,12 @@ pub struct AuthService {
     jwt_secret: String,
     jwt_expiration: u64,
     security_service: SecurityService,
+    mailer: MailerService,
+    password_policy: PasswordPolicy,
+    ip_rules: IpRuleService,
+    cipher: FieldCipher,
+    rbac: RbacService,
+    memberships: MembershipService,
     rng: SystemRandom,
 }
 
@@ -27,8 +69,26 @@ pub struct Claims {
     pub sub: String, // Subject (user ID)
     pub exp: usize,  // Expiration time
     pub iat: usize,  // Issued at
+    pub sid: String, // Session ID
+    /// Tier code when the token was issued
     pub membership_tier: String,
     pub mfa_verified: bool,
+    /// When the member last proved their credentials (Unix time)
+    #[serde(default)]
+    pub auth_time: usize,
+    /// How: `password` at login, `step_up` for an elevated token
+    #[serde(default)]
+    pub acr: String,
+    /// Role names when the token was issued; permissions are checked against current roles
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepUpResponse {
+    pub access_token: String,
+    pub acr: String,
+    pub expires_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Serialize)]
@@ -53,7 +113,6 @@ pub enum AuthError {
     InvalidCredentials,
     UserNotFound,
     AccountLocked,
-    EmailNotVerified,
     MfaRequired,
     InvalidToken,
     DatabaseError(sqlx::Error),
@@ -61,6 +120,10 @@ pub enum AuthError {
     TokenGenerationError,
     DestructionTriggered,
     UserAlreadyExists,
+    WeakPassword(Vec<PasswordViolation>),
+    NetworkNotAllowed,
+    AccountDeactivated,
+    EncryptionError(FieldEncryptionError),
 }
 
 impl std::fmt::Display for AuthError {
@@ -69,7 +132,6 @@ impl std::fmt::Display for AuthError {
             AuthError::InvalidCredentials => write!(f, "Invalid credentials"),
             AuthError::UserNotFound => write!(f, "User not found"),
             AuthError::AccountLocked => write!(f, "Account is locked"),
-            AuthError::EmailNotVerified => write!(f, "Email not verified"),
             AuthError::MfaRequired => write!(f, "MFA verification required"),
             AuthError::InvalidToken => write!(f, "Invalid token"),
             AuthError::DatabaseError(e) => write!(f, "Database error: {}", e),
@@ -77,6 +139,12 @@ impl std::fmt::Display for AuthError {
             AuthError::TokenGenerationError => write!(f, "Token generation error"),
             AuthError::DestructionTriggered => write!(f, "Account destruction triggered"),
             AuthError::UserAlreadyExists => write!(f, "User already exists"),
+            AuthError::WeakPassword(violations) => {
+                write!(f, "Password rejected by policy ({} violations)", violations.len())
+            }
+            AuthError::NetworkNotAllowed => write!(f, "Network not allowed for this account"),
+            AuthError::AccountDeactivated => write!(f, "Account is deactivated"),
+            AuthError::EncryptionError(e) => write!(f, "Field encryption error: {}", e),
         }
     }
 }
@@ -89,67 +157,102 @@ impl From<sqlx::Error> for AuthError {
     }
 }
 
+impl From<FieldEncryptionError> for AuthError {
+    fn from(err: FieldEncryptionError) -> Self {
+        AuthError::EncryptionError(err)
+    }
+}
+
 impl AuthService {
     pub fn new(
         db: PgPool,
-        jwt_secret: String,
-        jwt_expiration: u64,
+        config: &Config,
         security_service: SecurityService,
+        mailer: MailerService,
+        password_policy: PasswordPolicy,
+        ip_rules: IpRuleService,
+        cipher: FieldCipher,
     ) -> Self {
         Self {
+            rbac: RbacService::new(db.clone()),
+            memberships: MembershipService::new(db.clone()),
             db,
-            argon2: Argon2::default(),
-            jwt_secret,
-            jwt_expiration,
+            argon2: password_hasher(config),
+            jwt_secret: config.jwt_secret.clone(),
+            jwt_expiration: config.jwt_expiration,
             security_service,
+            mailer,
+            password_policy,
+            ip_rules,
+            cipher,
             rng: SystemRandom::new(),
         }
     }
 
-    pub async fn register_user(&self, request: CreateUserRequest) -> Result<User, AuthError> {
+    pub async fn register_user(&self, request: CreateUserRequest) -> Result<UserPublic, AuthError> {
         // Check if user already exists
         if self.find_user_by_email(&request.email).await.is_ok() {
             return Err(AuthError::UserAlreadyExists);
         }
 
+        self.enforce_password_policy(&request.password, &request.email).await?;
+
         // Hash password
-        let password_hash = self.hash_password(&request.password)?;
+        let password_hash = self.hash_password(&request.password).await?;
         
         // Generate email verification token
         let verification_token = self.generate_secure_token();
         
-        // Insert user
+        // The member's key exists before anything is encrypted under it
+        let user_id = Uuid::new_v4();
+        self.cipher.create_user_key(user_id)?;
+
+        // Insert user; nobody is signed in yet
+        let mut tx = begin_scoped(&self.db, DbScope::System).await?;
         let user = sqlx::query_as!(
             User,
             r#"
-            INSERT INTO users (email, password_hash, membership_tier, email_verification_token)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO users (id, email, email_index, password_hash, email_verification_token)
+            VALUES ($1, $2, $3, $4, $5)
             RETURNING *
             "#,
-            request.email,
+            user_id,
+            self.cipher.encrypt(user_id, EMAIL_FIELD, &request.email)?,
+            self.cipher.email_index(&request.email),
             password_hash,
-            request.membership_tier.unwrap_or_else(|| "basic".to_string()),
             verification_token
         )
-        .fetch_one(&self.db)
-        .await?;
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(ref db_err) if db_err.is_unique_violation() => {
+                AuthError::UserAlreadyExists
+            }
+            _ => AuthError::DatabaseError(e),
+        })?;
+        tx.commit().await?;
+        let user = self.cipher.open_user(user)?;
 
         // Log security event
         self.security_service
             .log_security_event(
                 Some(user.id),
-                "user_registered".to_string(),
-                None,
+                SecurityEventType::UserRegistered,
                 None,
                 None,
             )
             .await;
 
-        Ok(user)
+        self.public_profile(&user).await
     }
 
-    pub async fn initiate_login(&self, email: &str, ip_address: Option<IpAddr>) -> Result<LoginStep, AuthError> {
+    pub async fn initiate_login(&self, email: &str, _ip_address: Option<IpAddr>) -> Result<LoginStep, AuthError> {
         let user = self.find_user_by_email(email).await?;
+
+        // A canary that has "self-destructed" must look gone, like a real destroyed account
+        if user.is_canary && user.should_trigger_destruction() {
+            return Err(AuthError::UserNotFound);
+        }
         
         // Check if account is locked
         if user.is_locked() {
@@ -172,38 +275,94 @@ impl AuthService {
 
     pub async fn complete_login(&self, request: LoginRequest, ip_address: Option<IpAddr>, user_agent: Option<String>) -> Result<LoginResponse, AuthError> {
         let user = self.find_user_by_email(&request.email).await?;
-        
-        // Verify password
-        if !self.verify_password(&request.password, &user.password_hash) {
-            // Increment failed attempts
-            let failed_count = self.increment_failed_attempts(user.id, ip_address).await?;
-            return Err(AuthError::InvalidCredentials);
+
+        if user.is_canary {
+            return Err(self
+                .canary_login(&user, &request.password, ip_address, user_agent)
+                .await);
         }
 
-        // Check if account is locked (could have been locked during failed attempt check)
+        // Refuse locked accounts before spending argon2 time on them
         if user.is_locked() {
             return Err(AuthError::AccountLocked);
         }
 
+        let password_valid = self.verify_password(&request.password, &user.password_hash).await;
+        if password_valid {
+            // Checked after the password so the allowlist does not reveal which emails are members
+            self.check_network_access(user.id, ip_address, user_agent.clone()).await?;
+        }
+
+        let device_fingerprint = user_agent.as_deref().map(device_fingerprint);
+        let new_device = match (&device_fingerprint, password_valid) {
+            (Some(fingerprint), true) => self.is_new_device(user.id, fingerprint).await?,
+            _ => false,
+        };
+
+        // Attempt accounting and session creation happen under a row lock, so parallel guesses
+        // are counted one at a time and none gets past a lockout set by another. Until the
+        // session exists the caller is nobody, so this runs as system.
+        let mut tx = begin_scoped(&self.db, DbScope::System).await?;
+        let current = sqlx::query!(
+            "SELECT password_hash, account_locked_until, is_active FROM users WHERE id = $1 FOR UPDATE",
+            user.id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AuthError::UserNotFound)?;
+
+        if current.account_locked_until.is_some_and(|until| until > Utc::now()) {
+            return Err(AuthError::AccountLocked);
+        }
+        // The password changed while this attempt was being verified
+        if current.password_hash != user.password_hash {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        if !password_valid {
+            let failed_count = self.bump_failed_attempts(&mut tx, user.id).await?;
+            tx.commit().await?;
+            self.record_failed_attempt(
+                user.id,
+                failed_count,
+                SecurityEventType::LoginFailed {
+                    failed_attempts: failed_count,
+                },
+                ip_address,
+                user_agent,
+            )
+            .await?;
+            return Err(AuthError::InvalidCredentials);
+        }
+        // Only revealed to someone who knows the password
+        if !current.is_active.unwrap_or(false) {
+            return Err(AuthError::AccountDeactivated);
+        }
+
         // Generate JWT tokens
-        let access_token = self.generate_access_token(&user)?;
+        let session_id = Uuid::new_v4();
+        let access_token = self.generate_access_token(&user, session_id, Utc::now()).await?;
         let refresh_token = self.generate_refresh_token();
         let expires_at = Utc::now() + Duration::seconds(self.jwt_expiration as i64);
+        let refresh_expires_at = Utc::now() + Duration::days(REFRESH_TOKEN_DAYS);
 
         // Create session record
         sqlx::query!(
             r#"
-            INSERT INTO user_sessions (user_id, session_token, refresh_token, expires_at, ip_address, user_agent)
-            VALUES ($1, $2, $3, $4, $5, $6)
+            INSERT INTO user_sessions (id, user_id, session_token, refresh_token, expires_at, refresh_expires_at, ip_address, user_agent, device_fingerprint)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
             "#,
+            session_id,
             user.id,
             access_token,
             refresh_token,
             expires_at,
+            refresh_expires_at,
             ip_address.map(|ip| IpNetwork::from(ip)),
-            user_agent
+            user_agent,
+            device_fingerprint
         )
-        .execute(&self.db)
+        .execute(&mut *tx)
         .await?;
 
         // Update last login and reset failed attempts
@@ -211,108 +370,862 @@ impl AuthService {
             "UPDATE users SET last_login = NOW(), failed_login_attempts = 0 WHERE id = $1",
             user.id
         )
-        .execute(&self.db)
+        .execute(&mut *tx)
         .await?;
 
+        tx.commit().await?;
+
         // Log successful login
         self.security_service
             .log_security_event(
                 Some(user.id),
-                "login_success".to_string(),
+                SecurityEventType::LoginSuccess,
                 ip_address,
-                user_agent,
-                None,
+                user_agent.clone(),
             )
             .await;
 
+        if let (true, Some(device_fingerprint)) = (new_device, device_fingerprint) {
+            self.security_service
+                .log_security_event(
+                    Some(user.id),
+                    SecurityEventType::NewDeviceLogin { device_fingerprint },
+                    ip_address,
+                    user_agent,
+                )
+                .await;
+        }
+
         Ok(LoginResponse {
             access_token,
             refresh_token,
-            user: user.to_public(),
+            user: self.public_profile(&user).await?,
             expires_at,
         })
     }
 
+    /// The member's profile with the tier derived from their subscription.
+    pub async fn public_profile(&self, user: &User) -> Result<UserPublic, AuthError> {
+        let membership = self.memberships.current_for(user.id).await?;
+        Ok(user.to_public(membership.tier))
+    }
+
+    /// Looks up any account by email, so it runs as system: only for flows that act before the
+    /// member is known, or that must check an address is not someone else's.
     pub async fn find_user_by_email(&self, email: &str) -> Result<User, AuthError> {
-        sqlx::query_as!(User, "SELECT * FROM users WHERE email = $1", email)
-            .fetch_one(&self.db)
+        let mut tx = begin_scoped(&self.db, DbScope::System).await?;
+        let user = sqlx::query_as!(
+            User,
+            "SELECT * FROM users WHERE email_index = $1",
+            self.cipher.email_index(email)
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => AuthError::UserNotFound,
+            _ => AuthError::DatabaseError(e),
+        })?;
+        tx.commit().await?;
+
+        Ok(self.cipher.open_user(user)?)
+    }
+
+    /// The member's own account, read as the member.
+    pub async fn find_user_by_id(&self, user_id: Uuid) -> Result<User, AuthError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+        let user = sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", user_id)
+            .fetch_one(&mut *tx)
             .await
             .map_err(|e| match e {
                 sqlx::Error::RowNotFound => AuthError::UserNotFound,
                 _ => AuthError::DatabaseError(e),
-            })
+            })?;
+        tx.commit().await?;
+
+        Ok(self.cipher.open_user(user)?)
     }
 
-    pub async fn increment_failed_attempts(&self, user_id: Uuid, ip_address: Option<IpAddr>) -> Result<i32, AuthError> {
-        let result = sqlx::query!(
+    pub async fn change_password(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+        request: ChangePasswordRequest,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Result<(), AuthError> {
+        let user = self.find_user_by_id(user_id).await?;
+        self.reauthenticate(&user, &request.current_password, None, ip_address, user_agent.clone())
+            .await?;
+
+        self.enforce_password_policy(&request.new_password, &user.email).await?;
+        let password_hash = self.hash_password(&request.new_password).await?;
+
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user.id)).await?;
+
+        sqlx::query!(
             r#"
-            UPDATE users 
-            SET failed_login_attempts = failed_login_attempts + 1,
-                account_locked_until = CASE 
-                    WHEN failed_login_attempts + 1 >= 3 THEN NOW() + INTERVAL '15 minutes'
-                    ELSE account_locked_until
-                END
+            UPDATE users
+            SET password_hash = $2,
+                password_changed_at = NOW(),
+                password_reset_token = NULL,
+                password_reset_expires = NULL,
+                updated_at = NOW()
             WHERE id = $1
-            RETURNING failed_login_attempts
             "#,
-            user_id
+            user.id,
+            password_hash
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // Anyone holding an older session must sign in with the new password
+        sqlx::query!(
+            "UPDATE user_sessions SET is_active = false WHERE user_id = $1 AND id <> $2",
+            user.id,
+            session_id
         )
-        .fetch_one(&self.db)
+        .execute(&mut *tx)
         .await?;
 
-        let failed_count = result.failed_login_attempts;
+        tx.commit().await?;
 
-        // Log failed login attempt
         self.security_service
             .log_security_event(
-                Some(user_id),
-                "login_failed".to_string(),
+                Some(user.id),
+                SecurityEventType::PasswordChanged,
+                ip_address,
+                user_agent,
+            )
+            .await;
+
+        self.mailer
+            .send(
+                &user.email,
+                "Your password was changed",
+                "The password for your account was just changed and all other sessions were signed out. \
+                 If you did not make this change, contact support immediately.",
+            )
+            .await;
+
+        Ok(())
+    }
+
+    pub async fn request_email_change(
+        &self,
+        user_id: Uuid,
+        request: ChangeEmailRequest,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Result<DateTime<Utc>, AuthError> {
+        let user = self.find_user_by_id(user_id).await?;
+        self.reauthenticate(&user, &request.current_password, None, ip_address, user_agent.clone())
+            .await?;
+
+        if self.find_user_by_email(&request.new_email).await.is_ok() {
+            return Err(AuthError::UserAlreadyExists);
+        }
+
+        let token = self.generate_secure_token();
+        let expires_at = Utc::now() + Duration::hours(24);
+
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user.id)).await?;
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET pending_email = $2, pending_email_token = $3, pending_email_expires = $4, updated_at = NOW()
+            WHERE id = $1
+            "#,
+            user.id,
+            self.cipher.encrypt(user.id, PENDING_EMAIL_FIELD, &request.new_email)?,
+            token,
+            expires_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.security_service
+            .log_security_event(
+                Some(user.id),
+                SecurityEventType::EmailChangeRequested,
+                ip_address,
+                user_agent,
+            )
+            .await;
+
+        self.mailer
+            .send(
+                &request.new_email,
+                "Confirm your new email address",
+                &format!(
+                    "Use this code to confirm your new email address: {}\nThe code expires at {}.",
+                    token, expires_at
+                ),
+            )
+            .await;
+
+        Ok(expires_at)
+    }
+
+    pub async fn confirm_email_change(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+        token: &str,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Result<UserPublic, AuthError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            SELECT * FROM users
+            WHERE id = $1 AND pending_email_token = $2 AND pending_email_expires > NOW()
+            "#,
+            user_id,
+            token
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AuthError::InvalidToken)?;
+        let user = self.cipher.open_user(user)?;
+
+        let new_email = user.pending_email.clone().ok_or(AuthError::InvalidToken)?;
+
+        let updated = sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users
+            SET email = $2,
+                email_index = $3,
+                email_verified = true,
+                pending_email = NULL,
+                pending_email_token = NULL,
+                pending_email_expires = NULL,
+                updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#,
+            user.id,
+            self.cipher.encrypt(user.id, EMAIL_FIELD, &new_email)?,
+            self.cipher.email_index(&new_email)
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(ref db_err) if db_err.is_unique_violation() => {
+                AuthError::UserAlreadyExists
+            }
+            _ => AuthError::DatabaseError(e),
+        })?;
+
+        sqlx::query!(
+            "UPDATE user_sessions SET is_active = false WHERE user_id = $1 AND id <> $2",
+            user.id,
+            session_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        self.security_service
+            .log_security_event(
+                Some(user.id),
+                SecurityEventType::EmailChanged,
+                ip_address,
+                user_agent,
+            )
+            .await;
+
+        // Tell the previous address so a hijacked account does not go unnoticed
+        self.mailer
+            .send(
+                &user.email,
+                "Your email address was changed",
+                &format!(
+                    "The email address for your account was changed to {} and all other sessions were signed out. \
+                     If you did not make this change, contact support immediately.",
+                    new_email
+                ),
+            )
+            .await;
+
+        let updated = self.cipher.open_user(updated)?;
+        self.public_profile(&updated).await
+    }
+
+    pub async fn request_password_reset(&self, email: &str, ip_address: Option<IpAddr>) -> Result<(), AuthError> {
+        // Unknown addresses succeed silently so the endpoint cannot be used to enumerate accounts
+        let user = match self.find_user_by_email(email).await {
+            Ok(user) => user,
+            Err(AuthError::UserNotFound) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if user.is_canary {
+            return Ok(());
+        }
+
+        let token = self.generate_secure_token();
+        let expires_at = Utc::now() + Duration::hours(1);
+
+        let mut tx = begin_scoped(&self.db, DbScope::System).await?;
+        sqlx::query!(
+            "UPDATE users SET password_reset_token = $2, password_reset_expires = $3 WHERE id = $1",
+            user.id,
+            token,
+            expires_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.security_service
+            .log_security_event(
+                Some(user.id),
+                SecurityEventType::PasswordResetRequested,
                 ip_address,
                 None,
-                Some(serde_json::json!({
-                    "failed_attempts": failed_count
-                })),
             )
             .await;
 
+        self.mailer
+            .send(
+                &user.email,
+                "Reset your password",
+                &format!(
+                    "Use this code to reset your password: {}\nThe code expires at {}. \
+                     If you did not ask for a reset you can ignore this message.",
+                    token, expires_at
+                ),
+            )
+            .await;
+
+        Ok(())
+    }
+
+    pub async fn reset_password(
+        &self,
+        token: &str,
+        new_password: &str,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Result<(), AuthError> {
+        // The reset code is the only proof of who the caller is
+        let mut tx = begin_scoped(&self.db, DbScope::System).await?;
+        let user = sqlx::query_as!(
+            User,
+            "SELECT * FROM users WHERE password_reset_token = $1 AND password_reset_expires > NOW()",
+            token
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AuthError::InvalidToken)?;
+        tx.commit().await?;
+        let user = self.cipher.open_user(user)?;
+
+        self.enforce_password_policy(new_password, &user.email).await?;
+        let password_hash = self.hash_password(new_password).await?;
+
+        let mut tx = begin_scoped(&self.db, DbScope::System).await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET password_hash = $2,
+                password_changed_at = NOW(),
+                password_reset_token = NULL,
+                password_reset_expires = NULL,
+                updated_at = NOW()
+            WHERE id = $1
+            "#,
+            user.id,
+            password_hash
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!("UPDATE user_sessions SET is_active = false WHERE user_id = $1", user.id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        self.security_service
+            .log_security_event(
+                Some(user.id),
+                SecurityEventType::PasswordReset,
+                ip_address,
+                user_agent,
+            )
+            .await;
+
+        self.mailer
+            .send(
+                &user.email,
+                "Your password was reset",
+                "The password for your account was reset and all sessions were signed out. \
+                 If you did not make this change, contact support immediately.",
+            )
+            .await;
+
+        Ok(())
+    }
+
+    async fn enforce_password_policy(&self, password: &str, email: &str) -> Result<(), AuthError> {
+        let violations = self.password_policy.check(password, email).await;
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(AuthError::WeakPassword(violations))
+        }
+    }
+
+    /// Re-verifies the member's password, and MFA code if they use MFA, and issues a short-lived
+    /// elevated access token for the same session.
+    pub async fn step_up(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+        request: StepUpRequest,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Result<StepUpResponse, AuthError> {
+        let user = self.find_user_by_id(user_id).await?;
+        let mfa_code = if user.mfa_enabled.unwrap_or(false) {
+            let code = request.mfa_code.as_deref().filter(|_| user.mfa_secret.is_some());
+            Some(code.ok_or(AuthError::MfaRequired)?)
+        } else {
+            None
+        };
+        self.reauthenticate(
+            &user,
+            &request.password,
+            mfa_code,
+            ip_address,
+            user_agent.clone(),
+        )
+        .await?;
+
+        let now = Utc::now();
+        let expires_at = now + Duration::minutes(STEP_UP_TOKEN_MINUTES);
+        let access_token = self.issue_access_token(&user, session_id, now, ACR_STEP_UP, expires_at)
+            .await?;
+
+        self.security_service
+            .log_security_event(
+                Some(user.id),
+                SecurityEventType::StepUpAuthenticated,
+                ip_address,
+                user_agent,
+            )
+            .await;
+
+        Ok(StepUpResponse {
+            access_token,
+            acr: ACR_STEP_UP.to_string(),
+            expires_at,
+        })
+    }
+
+    /// Re-verifies the member's password, and `mfa_code` against their TOTP secret when given.
+    /// Wrong passwords and codes count towards the same lockout and destruction thresholds as
+    /// failed logins, under the same row lock.
+    async fn reauthenticate(
+        &self,
+        user: &User,
+        password: &str,
+        mfa_code: Option<&str>,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Result<(), AuthError> {
+        // Refuse locked accounts before spending argon2 time on them
+        if user.is_locked() {
+            return Err(AuthError::AccountLocked);
+        }
+        let password_valid = self.verify_password(password, &user.password_hash).await;
+        let mfa_valid = match (mfa_code, user.mfa_secret.as_deref()) {
+            (Some(code), Some(secret)) => verify_totp(secret, code, Utc::now().timestamp()),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user.id)).await?;
+        let current = sqlx::query!(
+            "SELECT password_hash, account_locked_until FROM users WHERE id = $1 FOR UPDATE",
+            user.id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AuthError::UserNotFound)?;
+
+        if current.account_locked_until.is_some_and(|until| until > Utc::now()) {
+            return Err(AuthError::AccountLocked);
+        }
+        // The password changed while this attempt was being verified
+        if current.password_hash != user.password_hash {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        if password_valid && mfa_valid {
+            sqlx::query!(
+                "UPDATE users SET failed_login_attempts = 0 WHERE id = $1 AND failed_login_attempts <> 0",
+                user.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            return Ok(());
+        }
+
+        let failed_count = self.bump_failed_attempts(&mut tx, user.id).await?;
+        tx.commit().await?;
+        self.record_failed_attempt(
+            user.id,
+            failed_count,
+            SecurityEventType::ReauthenticationFailed,
+            ip_address,
+            user_agent,
+        )
+        .await?;
+
+        Err(AuthError::InvalidCredentials)
+    }
+
+    /// Logs a counted failed login or re-authentication and runs any escalation it reaches.
+    async fn record_failed_attempt(
+        &self,
+        user_id: Uuid,
+        failed_count: i32,
+        failure: SecurityEventType,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Result<i32, AuthError> {
+        self.security_service
+            .log_security_event(Some(user_id), failure, ip_address, user_agent.clone())
+            .await;
+
+        // Escalations the member is alerted about
+        let escalation = if failed_count == LOCKOUT_THRESHOLD {
+            Some(SecurityEventType::AccountLocked {
+                failed_attempts: failed_count,
+            })
+        } else if failed_count == DESTRUCTION_THRESHOLD - 1 {
+            Some(SecurityEventType::DestructionPending {
+                failed_attempts: failed_count,
+                threshold: DESTRUCTION_THRESHOLD,
+            })
+        } else {
+            None
+        };
+        if let Some(event_type) = escalation {
+            self.security_service
+                .log_security_event(Some(user_id), event_type, ip_address, user_agent)
+                .await;
+        }
+
         // Check if destruction should be triggered
-        if failed_count.unwrap_or(0) >= 5 {
+        if failed_count >= DESTRUCTION_THRESHOLD {
             if let Err(e) = self.security_service
                 .trigger_destruction(user_id, "failed_login_threshold".to_string())
                 .await {
-                tracing::error!("Failed to trigger destruction: {:?}", e);
+                tracing::error!("Failed to trigger destruction: {}", e);
             }
             return Err(AuthError::DestructionTriggered);
         }
 
+        Ok(failed_count)
+    }
+
+    async fn bump_failed_attempts(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Uuid,
+    ) -> Result<i32, sqlx::Error> {
+        let failed_count = sqlx::query_scalar!(
+            r#"
+            UPDATE users 
+            SET failed_login_attempts = failed_login_attempts + 1,
+                account_locked_until = CASE 
+                    WHEN failed_login_attempts + 1 >= $2 THEN NOW() + INTERVAL '15 minutes'
+                    ELSE account_locked_until
+                END
+            WHERE id = $1
+            RETURNING failed_login_attempts
+            "#,
+            user_id,
+            LOCKOUT_THRESHOLD
+        )
+        .fetch_one(conn)
+        .await?;
+
         Ok(failed_count.unwrap_or(0))
     }
 
-    fn hash_password(&self, password: &str) -> Result<String, AuthError> {
-        let salt = SaltString::generate(&mut rand::thread_rng());
-        
-        self.argon2
-            .hash_password(password.as_bytes(), &salt)
-            .map(|hash| hash.to_string())
-            .map_err(|_| AuthError::HashingError)
+    /// Plays the part of a real account with a wrong password, so the attacker learns nothing,
+    /// while raising the alarm and denying their address.
+    async fn canary_login(
+        &self,
+        user: &User,
+        password: &str,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> AuthError {
+        self.security_service
+            .trip_wire(
+                Some(user.id),
+                SecurityEventType::CanaryLoginAttempt,
+                ip_address,
+                user_agent,
+            )
+            .await;
+
+        if user.should_trigger_destruction() {
+            return AuthError::UserNotFound;
+        }
+        // A locked real account answers before any argon2 work
+        if user.is_locked() {
+            return AuthError::AccountLocked;
+        }
+
+        // Same argon2 work as a real attempt
+        let _ = self.verify_password(password, &user.password_hash).await;
+
+        let counted = async {
+            let mut tx = begin_scoped(&self.db, DbScope::System).await?;
+            let failed_count = self.bump_failed_attempts(&mut tx, user.id).await?;
+            tx.commit().await?;
+            Ok::<_, sqlx::Error>(failed_count)
+        };
+        match counted.await {
+            Ok(failed_count) if failed_count >= DESTRUCTION_THRESHOLD => {
+                AuthError::DestructionTriggered
+            }
+            Ok(_) => AuthError::InvalidCredentials,
+            Err(e) => AuthError::DatabaseError(e),
+        }
     }
 
-    fn verify_password(&self, password: &str, hash: &str) -> bool {
-        if let Ok(parsed_hash) = PasswordHash::new(hash) {
-            self.argon2.verify_password(password.as_bytes(), &parsed_hash).is_ok()
-        } else {
-            false
+    /// Exchanges a refresh token for a new access token, rotating the refresh token.
+    pub async fn refresh_session(
+        &self,
+        refresh_token: &str,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Result<LoginResponse, AuthError> {
+        // The refresh token is the only proof of who the caller is
+        let mut tx = begin_scoped(&self.db, DbScope::System).await?;
+        let session = sqlx::query!(
+            r#"
+            SELECT id, user_id, created_at FROM user_sessions
+            WHERE refresh_token = $1 AND is_active = true AND refresh_expires_at > NOW()
+            "#,
+            refresh_token
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        let Some((session_id, Some(user_id), signed_in_at)) =
+            session.map(|s| (s.id, s.user_id, s.created_at))
+        else {
+            self.check_honeytoken(refresh_token, ip_address, user_agent).await?;
+            return Err(AuthError::InvalidToken);
+        };
+
+        let user = self.find_user_by_id(user_id).await?;
+        if !user.is_active.unwrap_or(false) {
+            return Err(AuthError::InvalidToken);
+        }
+        if user.is_locked() {
+            return Err(AuthError::AccountLocked);
+        }
+        self.check_network_access(user.id, ip_address, user_agent).await?;
+
+        // A refresh is not a fresh sign-in: auth_time stays at the login
+        let auth_time = signed_in_at.unwrap_or_else(Utc::now);
+        let access_token = self.generate_access_token(&user, session_id, auth_time).await?;
+        let new_refresh_token = self.generate_refresh_token();
+        let expires_at = Utc::now() + Duration::seconds(self.jwt_expiration as i64);
+        let refresh_expires_at = Utc::now() + Duration::days(REFRESH_TOKEN_DAYS);
+
+        // Conditional on the old token so two concurrent refreshes cannot both succeed
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user.id)).await?;
+        let rotated = sqlx::query!(
+            r#"
+            UPDATE user_sessions
+            SET session_token = $2, refresh_token = $3, expires_at = $4,
+                refresh_expires_at = $5, last_used_at = NOW()
+            WHERE id = $1 AND refresh_token = $6
+            "#,
+            session_id,
+            access_token,
+            new_refresh_token,
+            expires_at,
+            refresh_expires_at,
+            refresh_token
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        if rotated.rows_affected() == 0 {
+            return Err(AuthError::InvalidToken);
+        }
+
+        Ok(LoginResponse {
+            access_token,
+            refresh_token: new_refresh_token,
+            user: self.public_profile(&user).await?,
+            expires_at,
+        })
+    }
+
+    async fn check_honeytoken(
+        &self,
+        token: &str,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Result<(), AuthError> {
+        let honeytoken = sqlx::query!(
+            r#"
+            UPDATE honeytokens
+            SET last_triggered_at = NOW(), trigger_count = trigger_count + 1
+            WHERE token_hash = $1
+            RETURNING id, label
+            "#,
+            sha256_hex(token.as_bytes())
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        if let Some(honeytoken) = honeytoken {
+            self.security_service
+                .trip_wire(
+                    None,
+                    SecurityEventType::HoneytokenUsed {
+                        honeytoken_id: honeytoken.id,
+                        label: honeytoken.label,
+                    },
+                    ip_address,
+                    user_agent,
+                )
+                .await;
+        }
+
+        Ok(())
+    }
+
+    /// Enforces the member's organization allowlist, if they have one.
+    pub async fn check_network_access(
+        &self,
+        user_id: Uuid,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Result<(), AuthError> {
+        let Some(ip) = ip_address else {
+            return Ok(());
+        };
+
+        if self.ip_rules.member_network_allowed(user_id, ip).await? {
+            return Ok(());
         }
+
+        self.security_service
+            .log_security_event(
+                Some(user_id),
+                SecurityEventType::NetworkNotAllowed,
+                ip_address,
+                user_agent,
+            )
+            .await;
+
+        Err(AuthError::NetworkNotAllowed)
+    }
+
+    /// True when the member has signed in before, but never from this device.
+    async fn is_new_device(&self, user_id: Uuid, fingerprint: &str) -> Result<bool, AuthError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+        let seen = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "sessions!",
+                   COUNT(*) FILTER (WHERE device_fingerprint = $2) AS "matching!"
+            FROM user_sessions
+            WHERE user_id = $1
+            "#,
+            user_id,
+            fingerprint
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        // The very first login is not worth an alert
+        Ok(seen.sessions > 0 && seen.matching == 0)
+    }
+
+    /// Argon2 is deliberately slow, so it runs on the blocking pool rather than a runtime worker.
+    async fn hash_password(&self, password: &str) -> Result<String, AuthError> {
+        let argon2 = self.argon2.clone();
+        let password = password.to_owned();
+
+        tokio::task::spawn_blocking(move || {
+            let salt = SaltString::generate(&mut rand::thread_rng());
+            argon2
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+        })
+        .await
+        .map_err(|_| AuthError::HashingError)?
+        .map_err(|_| AuthError::HashingError)
+    }
+
+    async fn verify_password(&self, password: &str, hash: &str) -> bool {
+        let argon2 = self.argon2.clone();
+        let password = password.to_owned();
+        let hash = hash.to_owned();
+
+        tokio::task::spawn_blocking(move || match PasswordHash::new(&hash) {
+            Ok(parsed_hash) => argon2.verify_password(password.as_bytes(), &parsed_hash).is_ok(),
+            Err(_) => false,
+        })
+        .await
+        .unwrap_or(false)
     }
 
-    fn generate_access_token(&self, user: &User) -> Result<String, AuthError> {
+    async fn generate_access_token(
+        &self,
+        user: &User,
+        session_id: Uuid,
+        auth_time: DateTime<Utc>,
+    ) -> Result<String, AuthError> {
+        let expires_at = Utc::now() + Duration::seconds(self.jwt_expiration as i64);
+        self.issue_access_token(user, session_id, auth_time, ACR_PASSWORD, expires_at)
+            .await
+    }
+
+    async fn issue_access_token(
+        &self,
+        user: &User,
+        session_id: Uuid,
+        auth_time: DateTime<Utc>,
+        acr: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<String, AuthError> {
+        let roles = self.rbac.roles_for(user.id).await?;
+        let membership = self.memberships.current_for(user.id).await?;
         let now = Utc::now();
         let claims = Claims {
             sub: user.id.to_string(),
-            exp: (now + Duration::seconds(self.jwt_expiration as i64)).timestamp() as usize,
+            exp: expires_at.timestamp() as usize,
             iat: now.timestamp() as usize,
-            membership_tier: user.membership_tier.clone(),
+            sid: session_id.to_string(),
+            membership_tier: membership.tier,
             mfa_verified: !user.mfa_enabled.unwrap_or(false), // If MFA is disabled, consider it verified
+            auth_time: auth_time.timestamp() as usize,
+            acr: acr.to_string(),
+            roles,
         };
 
         encode(
@@ -330,7 +1243,7 @@ impl AuthService {
     fn generate_secure_token(&self) -> String {
         let mut bytes = [0u8; 32];
         self.rng.fill(&mut bytes).unwrap();
-        BASE64_ENGINE.encode(&bytes)
+        BASE64_ENGINE.encode(bytes)
     }
 
     pub fn verify_token(&self, token: &str) -> Result<Claims, AuthError> {
@@ -342,4 +1255,85 @@ impl AuthService {
         .map(|token_data| token_data.claims)
         .map_err(|_| AuthError::InvalidToken)
     }
-}
\ No newline at end of file
+
+    /// Clears a failed-login lockout on behalf of the administrator `admin_id`.
+    pub async fn unlock_account(&self, admin_id: Uuid, user_id: Uuid) -> Result<(), AuthError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Admin(admin_id)).await?;
+        let unlocked = sqlx::query!(
+            r#"
+            UPDATE users SET account_locked_until = NULL, failed_login_attempts = 0
+            WHERE id = $1 AND NOT is_canary
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if unlocked.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Deactivating also signs the member out everywhere.
+    pub async fn set_account_active(
+        &self,
+        admin_id: Uuid,
+        user_id: Uuid,
+        active: bool,
+    ) -> Result<(), AuthError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Admin(admin_id)).await?;
+
+        let updated = sqlx::query!(
+            "UPDATE users SET is_active = $2, updated_at = NOW() WHERE id = $1 AND NOT is_canary",
+            user_id,
+            active
+        )
+        .execute(&mut *tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        if !active {
+            sqlx::query!("UPDATE user_sessions SET is_active = false WHERE user_id = $1", user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Verifies the token signature and that its session has not been revoked.
+    pub async fn validate_session(&self, token: &str) -> Result<Claims, AuthError> {
+        let claims = self.verify_token(token)?;
+        let session_id = Uuid::parse_str(&claims.sid).map_err(|_| AuthError::InvalidToken)?;
+        let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+        let active = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM user_sessions
+                WHERE id = $1 AND is_active = true AND expires_at > NOW()
+            )
+            "#,
+            session_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        if !active.unwrap_or(false) {
+            return Err(AuthError::InvalidToken);
+        }
+
+        Ok(claims)
+    }
+}
+
+fn device_fingerprint(user_agent: &str) -> String {
+    sha256_hex(user_agent.as_bytes())
+}
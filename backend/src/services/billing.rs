@@ -0,0 +1,1647 @@
+use crate::config::BillingConfig;
+use crate::models::{
+    BillingInterval, BillingStatus, CheckoutRequest, CheckoutResponse, Membership, PlanChange,
+    PlanChangeKind, PlanChangePreview, PlanChangeRequest, PlanQuote, Promotion, SecurityEventType,
+    SubscriptionStatus,
+};
+use crate::services::{
+    verify_webhook_signature, MembershipService, PromotionError, PromotionService,
+    SecurityService, StripeCheckoutSession, StripeClient, StripeError, StripeEvent, StripeInvoice,
+    StripeSubscription,
+};
+use crate::utils::{begin_scoped, DbScope};
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+/// Subscription statuses that entitle the member to the subscription's tier, as in
+/// `current_membership_id()`.
+const LIVE_STATUSES: [&str; 3] = ["active", "trialing", "past_due"];
+
+/// Stripe's shortest Checkout session lifetime; a member who abandons one can start another
+/// after this.
+const CHECKOUT_SESSION_MINUTES: i64 = 30;
+// Id of a checkout session recorded before Stripe has created it
+const PENDING_SESSION_PREFIX: &str = "pending_";
+
+/// Paid memberships through Stripe Checkout, and the Stripe customer portal for managing them.
+///
+/// Checkout is priced from the catalog version on offer when the member starts it, so Stripe
+/// needs no products or prices configured in advance. Promotion discounts become Stripe coupons
+/// the first time they are used.
+#[derive(Debug, Clone)]
+pub struct BillingService {
+    db: PgPool,
+    stripe: StripeClient,
+    webhook_secret: Option<String>,
+    config: BillingConfig,
+    memberships: MembershipService,
+    promotions: PromotionService,
+    security_service: SecurityService,
+}
+
+#[derive(Debug)]
+pub enum BillingError {
+    DatabaseError(sqlx::Error),
+    NotConfigured,
+    Provider(StripeError),
+    TierNotOffered,
+    NotPurchasable,
+    AlreadySubscribed,
+    /// The member has an open Checkout session until then.
+    CheckoutInProgress(DateTime<Utc>),
+    NoBillingAccount,
+    InvalidSignature,
+    InvalidEvent,
+    NoSubscription,
+    SubscriptionPending,
+    PaymentOverdue,
+    PaymentDeclined,
+    SamePlan,
+    NoScheduledChange,
+    Promotion(PromotionError),
+}
+
+impl std::fmt::Display for BillingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BillingError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            BillingError::NotConfigured => write!(f, "Billing is not configured"),
+            BillingError::Provider(e) => write!(f, "{}", e),
+            BillingError::TierNotOffered => write!(f, "Membership tier is not offered"),
+            BillingError::NotPurchasable => {
+                write!(f, "Membership tier has no price for that interval")
+            }
+            BillingError::AlreadySubscribed => write!(f, "Member already has a subscription"),
+            BillingError::CheckoutInProgress(expires_at) => {
+                write!(f, "Member has a checkout open until {}", expires_at)
+            }
+            BillingError::NoBillingAccount => write!(f, "Member has no billing account"),
+            BillingError::InvalidSignature => write!(f, "Invalid webhook signature"),
+            BillingError::InvalidEvent => write!(f, "Malformed webhook event"),
+            BillingError::NoSubscription => write!(f, "Member has no paid subscription"),
+            BillingError::SubscriptionPending => {
+                write!(f, "Subscription has no billing period yet")
+            }
+            BillingError::PaymentOverdue => write!(f, "Subscription payment is overdue"),
+            BillingError::PaymentDeclined => write!(f, "Payment was declined"),
+            BillingError::SamePlan => write!(f, "Member is already on that plan"),
+            BillingError::NoScheduledChange => write!(f, "No plan change is scheduled"),
+            BillingError::Promotion(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for BillingError {
+    fn from(err: sqlx::Error) -> Self {
+        BillingError::DatabaseError(err)
+    }
+}
+
+impl From<PromotionError> for BillingError {
+    fn from(err: PromotionError) -> Self {
+        match err {
+            PromotionError::DatabaseError(e) => BillingError::DatabaseError(e),
+            PromotionError::Provider(e) => e.into(),
+            other => BillingError::Promotion(other),
+        }
+    }
+}
+
+impl From<StripeError> for BillingError {
+    fn from(err: StripeError) -> Self {
+        match err {
+            StripeError::NotConfigured => BillingError::NotConfigured,
+            // Card errors, e.g. an upgrade charge with `payment_behavior=error_if_incomplete`
+            StripeError::Api { status: 402, .. } => BillingError::PaymentDeclined,
+            other => BillingError::Provider(other),
+        }
+    }
+}
+
+impl BillingService {
+    pub fn new(
+        db: PgPool,
+        config: BillingConfig,
+        stripe_secret_key: Option<String>,
+        stripe_webhook_secret: Option<String>,
+        security_service: SecurityService,
+    ) -> Self {
+        Self {
+            promotions: PromotionService::new(db.clone(), &config, stripe_secret_key.clone()),
+            stripe: StripeClient::new(&config.stripe_api_base, stripe_secret_key),
+            webhook_secret: stripe_webhook_secret.filter(|secret| !secret.is_empty()),
+            memberships: MembershipService::new(db.clone()),
+            db,
+            config,
+            security_service,
+        }
+    }
+
+    pub async fn start_checkout(
+        &self,
+        user_id: Uuid,
+        request: CheckoutRequest,
+    ) -> Result<CheckoutResponse, BillingError> {
+        if !self.stripe.is_configured() {
+            return Err(BillingError::NotConfigured);
+        }
+
+        let membership = self.offered(&request.tier).await?;
+        let unit_amount = unit_amount(&membership, request.interval)?;
+        let customer_id = self.customer_for(user_id).await?;
+
+        let promotion = match request.promotion_code.as_deref() {
+            Some(code) => Some(self.discount_for(user_id, code, &membership.tier).await?),
+            None => None,
+        };
+        let coupon = match &promotion {
+            Some(promotion) => Some(self.coupon_for(promotion).await?),
+            None => None,
+        };
+        let trial_end = sqlx::query_scalar!(
+            r#"
+            SELECT s.current_period_end AS "current_period_end!"
+            FROM subscriptions s
+            WHERE s.user_id = $1 AND s.membership_id = $2 AND s.status = 'trialing'
+              AND s.stripe_subscription_id IS NULL AND s.current_period_end IS NOT NULL
+            "#,
+            user_id,
+            membership.id
+        )
+        .fetch_optional(&self.db)
+        .await?
+        // Stripe only accepts trial ends at least 48 hours away
+        .filter(|end| *end > Utc::now() + Duration::hours(48));
+
+        // Stripe is not called while the member's billing account is locked. A placeholder
+        // session is recorded under the lock instead, so concurrent requests still see each
+        // other's checkout, and is replaced by Stripe's once the session exists. If this
+        // instance dies in between, the placeholder blocks checkout only until it expires.
+        let mut tx = self.db.begin().await?;
+        sqlx::query!(
+            "SELECT user_id FROM billing_customers WHERE user_id = $1 FOR UPDATE",
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Plan changes for existing subscribers go through the portal, not a second subscription.
+        // A no-card trial is replaced once the checkout completes.
+        let subscribed = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM subscriptions
+                WHERE user_id = $1 AND status = ANY($2)
+                  AND NOT (status = 'trialing' AND stripe_subscription_id IS NULL)
+            ) AS "exists!"
+            "#,
+            user_id,
+            &LIVE_STATUSES.map(String::from)[..]
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if subscribed {
+            return Err(BillingError::AlreadySubscribed);
+        }
+        // Completing two open sessions would leave the member with two subscriptions
+        let open_until = sqlx::query_scalar!(
+            r#"
+            SELECT MAX(expires_at) AS expires_at FROM checkout_sessions
+            WHERE user_id = $1 AND completed_at IS NULL AND expires_at > NOW()
+            "#,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if let Some(expires_at) = open_until {
+            return Err(BillingError::CheckoutInProgress(expires_at));
+        }
+
+        let reservation = Uuid::new_v4();
+        let placeholder = format!("{}{}", PENDING_SESSION_PREFIX, reservation);
+        let expires_at = Utc::now() + Duration::minutes(CHECKOUT_SESSION_MINUTES);
+        sqlx::query!(
+            r#"
+            INSERT INTO checkout_sessions
+                (id, user_id, membership_id, billing_interval, promotion_id, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            placeholder,
+            user_id,
+            membership.id,
+            request.interval.as_str(),
+            promotion.as_ref().map(|promotion| promotion.id),
+            expires_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        let metadata = [
+            ("user_id", user_id.to_string()),
+            ("membership_id", membership.id.to_string()),
+            ("interval", request.interval.as_str().to_string()),
+        ];
+        let mut params = vec![
+            ("mode".to_string(), "subscription".to_string()),
+            ("customer".to_string(), customer_id),
+            ("client_reference_id".to_string(), user_id.to_string()),
+            ("expires_at".to_string(), expires_at.timestamp().to_string()),
+            (
+                "success_url".to_string(),
+                format!("{}?checkout=success&session_id={{CHECKOUT_SESSION_ID}}", self.config.return_url),
+            ),
+            (
+                "cancel_url".to_string(),
+                format!("{}?checkout=cancelled", self.config.return_url),
+            ),
+            ("line_items[0][quantity]".to_string(), "1".to_string()),
+            (
+                "line_items[0][price_data][currency]".to_string(),
+                self.config.currency.clone(),
+            ),
+            (
+                "line_items[0][price_data][unit_amount]".to_string(),
+                unit_amount.to_string(),
+            ),
+            (
+                "line_items[0][price_data][recurring][interval]".to_string(),
+                request.interval.stripe_interval().to_string(),
+            ),
+            (
+                "line_items[0][price_data][product_data][name]".to_string(),
+                membership.name.clone(),
+            ),
+        ];
+        // On the session for checkout.session.completed, and on the subscription for later events
+        for (key, value) in &metadata {
+            params.push((format!("metadata[{}]", key), value.clone()));
+            params.push((format!("subscription_data[metadata][{}]", key), value.clone()));
+        }
+        if let Some(coupon) = coupon {
+            params.push(("discounts[0][coupon]".to_string(), coupon));
+        }
+        // Converting a trial of the same tier: billing starts when the trial would have ended
+        if let Some(trial_end) = trial_end {
+            params.push((
+                "subscription_data[trial_end]".to_string(),
+                trial_end.timestamp().to_string(),
+            ));
+        }
+
+        // Keyed by the reservation, so a retry of this call returns the same session
+        let session = match self
+            .stripe
+            .create_checkout_session(&params, &format!("checkout-{}", reservation))
+            .await
+        {
+            Ok(session) => session,
+            Err(e) => {
+                // Free the member to try again
+                sqlx::query!("DELETE FROM checkout_sessions WHERE id = $1", placeholder)
+                    .execute(&self.db)
+                    .await?;
+                return Err(e.into());
+            }
+        };
+
+        sqlx::query!(
+            "UPDATE checkout_sessions SET id = $2 WHERE id = $1",
+            placeholder,
+            session.id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(CheckoutResponse {
+            session_id: session.id,
+            checkout_url: session.url,
+        })
+    }
+
+    /// A Stripe customer portal link for updating payment details, invoices and cancelling.
+    pub async fn open_portal(&self, user_id: Uuid) -> Result<String, BillingError> {
+        if !self.stripe.is_configured() {
+            return Err(BillingError::NotConfigured);
+        }
+
+        let customer_id = sqlx::query_scalar!(
+            "SELECT stripe_customer_id FROM billing_customers WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(BillingError::NoBillingAccount)?;
+
+        let session = self
+            .stripe
+            .create_portal_session(&customer_id, &self.config.return_url)
+            .await?;
+        Ok(session.url)
+    }
+
+    /// The member's tier and their most relevant subscription: the live one, else the latest.
+    pub async fn status_for(&self, user_id: Uuid) -> Result<BillingStatus, BillingError> {
+        let tier = sqlx::query_scalar!(
+            "SELECT tier FROM memberships WHERE id = current_membership_id($1)",
+            user_id
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        let subscription = sqlx::query!(
+            r#"
+            SELECT status, billing_interval, current_period_end, past_due_since, grace_ends_at
+            FROM subscriptions
+            WHERE user_id = $1
+            ORDER BY status = ANY($2) DESC, updated_at DESC
+            LIMIT 1
+            "#,
+            user_id,
+            &LIVE_STATUSES.map(String::from)[..]
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        let scheduled_change = self
+            .plan_changes(user_id, None)
+            .await?
+            .into_iter()
+            .find(|change| change.status == "scheduled");
+
+        Ok(match subscription {
+            Some(subscription) => BillingStatus {
+                tier,
+                status: SubscriptionStatus::parse(&subscription.status),
+                billing_interval: subscription.billing_interval,
+                current_period_end: subscription.current_period_end,
+                past_due_since: subscription.past_due_since,
+                grace_ends_at: subscription.grace_ends_at,
+                scheduled_change,
+            },
+            None => BillingStatus {
+                tier,
+                status: None,
+                billing_interval: None,
+                current_period_end: None,
+                past_due_since: None,
+                grace_ends_at: None,
+                scheduled_change,
+            },
+        })
+    }
+
+    /// Verifies and applies a Stripe webhook delivery. Returns false for an event already applied.
+    ///
+    /// The event is recorded in `stripe_events` in the same transaction as its effects, so a
+    /// failure leaves it unrecorded for Stripe to redeliver.
+    pub async fn handle_webhook(
+        &self,
+        payload: &[u8],
+        signature: Option<&str>,
+    ) -> Result<bool, BillingError> {
+        let secret = self.webhook_secret.as_deref().ok_or(BillingError::NotConfigured)?;
+        verify_webhook_signature(
+            secret,
+            signature.ok_or(BillingError::InvalidSignature)?,
+            payload,
+            Utc::now().timestamp(),
+            self.config.webhook_tolerance_seconds,
+        )
+        .map_err(|_| BillingError::InvalidSignature)?;
+        let event: StripeEvent =
+            serde_json::from_slice(payload).map_err(|_| BillingError::InvalidEvent)?;
+        let event_at = DateTime::from_timestamp(event.created, 0).ok_or(BillingError::InvalidEvent)?;
+
+        // Stripe acts for no particular member
+        let mut tx = begin_scoped(&self.db, DbScope::System).await?;
+        let recorded = sqlx::query!(
+            "INSERT INTO stripe_events (id, event_type) VALUES ($1, $2) ON CONFLICT (id) DO NOTHING",
+            event.id,
+            event.event_type
+        )
+        .execute(&mut *tx)
+        .await?;
+        if recorded.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        let object = event.data.object;
+        let mut completed_checkout = None;
+        let update = match event.event_type.as_str() {
+            "checkout.session.completed" => {
+                let session: StripeCheckoutSession = parse(object)?;
+                completed_checkout = Some((session.id.clone(), session.subscription.clone()));
+                checkout_completed(&mut tx, session).await?
+            }
+            "checkout.session.expired" => {
+                let session: StripeCheckoutSession = parse(object)?;
+                // The member may start another checkout straight away
+                sqlx::query!(
+                    r#"
+                    UPDATE checkout_sessions SET expires_at = LEAST(expires_at, NOW())
+                    WHERE id = $1 AND completed_at IS NULL
+                    "#,
+                    session.id
+                )
+                .execute(&mut *tx)
+                .await?;
+                None
+            }
+            "invoice.paid" | "invoice.payment_failed" => {
+                let invoice: StripeInvoice = parse(object)?;
+                let period = invoice
+                    .lines
+                    .and_then(|lines| lines.data.into_iter().find_map(|line| line.period));
+                invoice.subscription.map(|stripe_subscription_id| SubscriptionUpdate {
+                    stripe_subscription_id,
+                    customer: None,
+                    status: if event.event_type == "invoice.paid" {
+                        SubscriptionStatus::Active
+                    } else {
+                        SubscriptionStatus::PastDue
+                    },
+                    period_start: period.as_ref().and_then(|p| DateTime::from_timestamp(p.start, 0)),
+                    period_end: period.as_ref().and_then(|p| DateTime::from_timestamp(p.end, 0)),
+                    owner: None,
+                })
+            }
+            "customer.subscription.created"
+            | "customer.subscription.updated"
+            | "customer.subscription.deleted" => {
+                let subscription: StripeSubscription = parse(object)?;
+                Some(SubscriptionUpdate {
+                    owner: owner_from_metadata(&subscription),
+                    status: SubscriptionStatus::parse(&subscription.status)
+                        .ok_or(BillingError::InvalidEvent)?,
+                    stripe_subscription_id: subscription.id,
+                    customer: subscription.customer,
+                    period_start: subscription
+                        .current_period_start
+                        .and_then(|t| DateTime::from_timestamp(t, 0)),
+                    period_end: subscription
+                        .current_period_end
+                        .and_then(|t| DateTime::from_timestamp(t, 0)),
+                })
+            }
+            _ => None,
+        };
+
+        let mut replaced = Vec::new();
+        let changed = match update {
+            Some(update) => {
+                let grace_period = Duration::days(self.config.grace_period_days.into());
+                apply_subscription_update(&mut tx, update, event_at, grace_period, &mut replaced)
+                    .await?
+            }
+            None => None,
+        };
+        // Stripe would go on billing them; if cancelling fails the event stays unrecorded and
+        // Stripe redelivers it
+        for stripe_subscription_id in &replaced {
+            self.stripe.cancel_subscription(stripe_subscription_id).await?;
+            tracing::info!("Cancelled replaced Stripe subscription {}", stripe_subscription_id);
+        }
+        if let Some((session_id, Some(stripe_subscription_id))) = completed_checkout {
+            redeem_checkout_promotion(&mut tx, &session_id, &stripe_subscription_id).await?;
+        }
+        tx.commit().await?;
+
+        if let Some(change) = changed {
+            self.security_service
+                .log_security_event(
+                    Some(change.user_id),
+                    SecurityEventType::SubscriptionChanged {
+                        subscription_id: change.subscription_id,
+                        tier: change.tier,
+                        status: change.status,
+                    },
+                    None,
+                    None,
+                )
+                .await;
+        }
+
+        Ok(true)
+    }
+
+    /// What moving to `request`'s plan would cost if made now.
+    pub async fn preview_plan_change(
+        &self,
+        user_id: Uuid,
+        request: PlanChangeRequest,
+    ) -> Result<PlanChangePreview, BillingError> {
+        let target = self.offered(&request.tier).await?;
+        let mut tx = self.db.begin().await?;
+        let current = current_plan(&mut tx, user_id).await?;
+        let promotion = match request.promotion_code.as_deref() {
+            Some(code) => Some(self.discount_in(&mut tx, user_id, code, &target.tier).await?),
+            None => None,
+        };
+        tx.rollback().await?;
+
+        quote(
+            &current,
+            &target,
+            request.interval,
+            Utc::now(),
+            &self.config.currency,
+            promotion.as_ref(),
+        )
+    }
+
+    /// Upgrades at once, charging the prorated difference, or schedules a downgrade for the end
+    /// of the current period. Either replaces a downgrade already scheduled.
+    pub async fn change_plan(
+        &self,
+        user_id: Uuid,
+        request: PlanChangeRequest,
+    ) -> Result<PlanChange, BillingError> {
+        if !self.stripe.is_configured() {
+            return Err(BillingError::NotConfigured);
+        }
+        let target = self.offered(&request.tier).await?;
+
+        // The subscription stays locked until the change is recorded, so concurrent requests and
+        // webhooks queue behind it
+        let mut tx = self.db.begin().await?;
+        let current = current_plan(&mut tx, user_id).await?;
+        let promotion = match request.promotion_code.as_deref() {
+            Some(code) => Some(self.discount_in(&mut tx, user_id, code, &target.tier).await?),
+            None => None,
+        };
+        let now = Utc::now();
+        let preview = quote(
+            &current,
+            &target,
+            request.interval,
+            now,
+            &self.config.currency,
+            promotion.as_ref(),
+        )?;
+        let coupon = match &promotion {
+            Some(promotion) => Some(self.coupon_for(promotion).await?),
+            None => None,
+        };
+
+        sqlx::query!(
+            "UPDATE plan_changes SET status = 'cancelled' WHERE subscription_id = $1 AND status = 'scheduled'",
+            current.subscription_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let change_id = Uuid::new_v4();
+        let applied = preview.kind == PlanChangeKind::Upgrade;
+        if applied {
+            self.push_plan(
+                &current.stripe_subscription_id,
+                &target,
+                request.interval,
+                Some(now),
+                coupon,
+                &format!("plan-change-{}", change_id),
+            )
+            .await?;
+            sqlx::query!(
+                r#"
+                UPDATE subscriptions SET membership_id = $2, billing_interval = $3, updated_at = NOW()
+                WHERE id = $1
+                "#,
+                current.subscription_id,
+                target.id,
+                request.interval.as_str()
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query!(
+            r#"
+            INSERT INTO plan_changes
+                (id, user_id, subscription_id, from_membership_id, from_interval, to_membership_id,
+                 to_interval, kind, status, amount_due, currency, effective_at, applied_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+            "#,
+            change_id,
+            user_id,
+            current.subscription_id,
+            current.membership.id,
+            current.interval.as_str(),
+            target.id,
+            request.interval.as_str(),
+            preview.kind.as_str(),
+            if applied { "applied" } else { "scheduled" },
+            preview.amount_due,
+            self.config.currency,
+            preview.effective_at,
+            applied.then_some(now)
+        )
+        .execute(&mut *tx)
+        .await?;
+        if let Some(promotion) = promotion {
+            self.promotions
+                .redeem(&mut tx, promotion.id, user_id, Some(current.subscription_id))
+                .await?;
+        }
+        tx.commit().await?;
+
+        self.plan_changes(user_id, Some(change_id))
+            .await?
+            .pop()
+            .ok_or(BillingError::DatabaseError(sqlx::Error::RowNotFound))
+    }
+
+    pub async fn cancel_scheduled_plan_change(&self, user_id: Uuid) -> Result<PlanChange, BillingError> {
+        let change_id = sqlx::query_scalar!(
+            r#"
+            UPDATE plan_changes SET status = 'cancelled'
+            WHERE user_id = $1 AND status = 'scheduled'
+            RETURNING id
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(BillingError::NoScheduledChange)?;
+
+        self.plan_changes(user_id, Some(change_id))
+            .await?
+            .pop()
+            .ok_or(BillingError::NoScheduledChange)
+    }
+
+    /// The member's plan changes, newest first; just `change_id` if given.
+    pub async fn plan_changes(
+        &self,
+        user_id: Uuid,
+        change_id: Option<Uuid>,
+    ) -> Result<Vec<PlanChange>, BillingError> {
+        Ok(sqlx::query_as!(
+            PlanChange,
+            r#"
+            SELECT pc.id, pc.subscription_id,
+                   f.tier AS from_tier, f.version AS from_version, pc.from_interval,
+                   t.tier AS to_tier, t.version AS to_version, pc.to_interval,
+                   pc.kind, pc.status, pc.amount_due, pc.currency,
+                   pc.effective_at, pc.created_at, pc.applied_at
+            FROM plan_changes pc
+            JOIN memberships f ON f.id = pc.from_membership_id
+            JOIN memberships t ON t.id = pc.to_membership_id
+            WHERE pc.user_id = $1 AND ($2::uuid IS NULL OR pc.id = $2)
+            ORDER BY pc.created_at DESC
+            "#,
+            user_id,
+            change_id
+        )
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    /// Applies scheduled downgrades that take effect before `before`. Each is pushed to Stripe
+    /// without proration, so the renewal that ends the current period is billed at the new price.
+    pub async fn apply_due_plan_changes(&self, before: DateTime<Utc>) -> Result<u64, BillingError> {
+        let due = sqlx::query_scalar!(
+            r#"
+            SELECT id FROM plan_changes
+            WHERE status = 'scheduled' AND effective_at <= $1
+            ORDER BY effective_at
+            LIMIT 500
+            "#,
+            before
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut applied = 0;
+        for change_id in due {
+            match self.apply_scheduled_plan_change(change_id, before).await {
+                Ok(true) => applied += 1,
+                Ok(false) => {}
+                Err(e) => tracing::error!("Failed to apply plan change {}: {}", change_id, e),
+            }
+        }
+        Ok(applied)
+    }
+
+    async fn apply_scheduled_plan_change(
+        &self,
+        change_id: Uuid,
+        before: DateTime<Utc>,
+    ) -> Result<bool, BillingError> {
+        let mut tx = self.db.begin().await?;
+        let scheduled = sqlx::query!(
+            r#"
+            SELECT pc.subscription_id, pc.to_membership_id, pc.to_interval,
+                   s.status, s.stripe_subscription_id, s.current_period_end
+            FROM plan_changes pc
+            JOIN subscriptions s ON s.id = pc.subscription_id
+            WHERE pc.id = $1 AND pc.status = 'scheduled'
+            FOR UPDATE OF pc, s
+            "#,
+            change_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(scheduled) = scheduled else {
+            return Ok(false);
+        };
+
+        // Overdue, cancelled and lapsed subscriptions are handled by dunning, not downgraded
+        let in_good_standing = matches!(
+            SubscriptionStatus::parse(&scheduled.status),
+            Some(SubscriptionStatus::Active | SubscriptionStatus::Trialing)
+        );
+        let (Some(stripe_subscription_id), true) = (scheduled.stripe_subscription_id, in_good_standing)
+        else {
+            sqlx::query!("UPDATE plan_changes SET status = 'cancelled' WHERE id = $1", change_id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            return Ok(false);
+        };
+
+        // Already renewed at the old price (e.g. the scheduler was down): wait for this period
+        if let Some(period_end) = scheduled.current_period_end.filter(|end| *end > before) {
+            sqlx::query!(
+                "UPDATE plan_changes SET effective_at = $2 WHERE id = $1",
+                change_id,
+                period_end
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            return Ok(false);
+        }
+
+        let target = sqlx::query_as!(
+            Membership,
+            "SELECT * FROM memberships WHERE id = $1",
+            scheduled.to_membership_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let interval = BillingInterval::parse(&scheduled.to_interval).ok_or(BillingError::InvalidEvent)?;
+
+        self.push_plan(
+            &stripe_subscription_id,
+            &target,
+            interval,
+            None,
+            None,
+            &format!("plan-change-{}", change_id),
+        )
+        .await?;
+        sqlx::query!(
+            r#"
+            UPDATE subscriptions SET membership_id = $2, billing_interval = $3, updated_at = NOW()
+            WHERE id = $1
+            "#,
+            scheduled.subscription_id,
+            target.id,
+            interval.as_str()
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "UPDATE plan_changes SET status = 'applied', applied_at = NOW() WHERE id = $1",
+            change_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Moves the Stripe subscription's one item to `target`'s price. With `prorate_from` the
+    /// difference is invoiced and charged at once, and the change fails if the charge does;
+    /// without it the new price starts at the next renewal. `coupon` discounts the subscription
+    /// from that invoice on.
+    async fn push_plan(
+        &self,
+        stripe_subscription_id: &str,
+        target: &Membership,
+        interval: BillingInterval,
+        prorate_from: Option<DateTime<Utc>>,
+        coupon: Option<String>,
+        idempotency_key: &str,
+    ) -> Result<(), BillingError> {
+        let unit_amount = unit_amount(target, interval)?;
+        let product_id = self.product_for(target).await?;
+        let item_id = self
+            .stripe
+            .retrieve_subscription(stripe_subscription_id)
+            .await?
+            .items
+            .and_then(|items| items.data.into_iter().next())
+            .map(|item| item.id)
+            .ok_or(StripeError::UnexpectedResponse("subscription has no items"))?;
+
+        let mut params = vec![
+            ("items[0][id]".to_string(), item_id),
+            (
+                "items[0][price_data][currency]".to_string(),
+                self.config.currency.clone(),
+            ),
+            ("items[0][price_data][product]".to_string(), product_id),
+            (
+                "items[0][price_data][unit_amount]".to_string(),
+                unit_amount.to_string(),
+            ),
+            (
+                "items[0][price_data][recurring][interval]".to_string(),
+                interval.stripe_interval().to_string(),
+            ),
+            ("metadata[membership_id]".to_string(), target.id.to_string()),
+            ("metadata[interval]".to_string(), interval.as_str().to_string()),
+        ];
+        match prorate_from {
+            Some(at) => {
+                params.push(("proration_behavior".to_string(), "always_invoice".to_string()));
+                params.push(("proration_date".to_string(), at.timestamp().to_string()));
+                params.push(("payment_behavior".to_string(), "error_if_incomplete".to_string()));
+            }
+            None => params.push(("proration_behavior".to_string(), "none".to_string())),
+        }
+        if let Some(coupon) = coupon {
+            params.push(("discounts[0][coupon]".to_string(), coupon));
+        }
+
+        self.stripe
+            .update_subscription(stripe_subscription_id, &params, idempotency_key)
+            .await?;
+        Ok(())
+    }
+
+    async fn product_for(&self, membership: &Membership) -> Result<String, BillingError> {
+        let existing = sqlx::query_scalar!(
+            "SELECT stripe_product_id FROM stripe_products WHERE membership_id = $1",
+            membership.id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        if let Some(product_id) = existing {
+            return Ok(product_id);
+        }
+
+        let product_id = self.stripe.create_product(membership.id, &membership.name).await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO stripe_products (membership_id, stripe_product_id) VALUES ($1, $2)
+            ON CONFLICT (membership_id) DO NOTHING
+            "#,
+            membership.id,
+            product_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(sqlx::query_scalar!(
+            "SELECT stripe_product_id FROM stripe_products WHERE membership_id = $1",
+            membership.id
+        )
+        .fetch_one(&self.db)
+        .await?)
+    }
+
+    /// A discount promotion the member can use on `tier` now; trials start elsewhere.
+    async fn discount_for(
+        &self,
+        user_id: Uuid,
+        code: &str,
+        tier: &str,
+    ) -> Result<Promotion, BillingError> {
+        let mut tx = self.db.begin().await?;
+        let promotion = self.discount_in(&mut tx, user_id, code, tier).await?;
+        tx.rollback().await?;
+        Ok(promotion)
+    }
+
+    /// As `discount_for`, keeping the promotion locked until `conn`'s transaction ends.
+    async fn discount_in(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        code: &str,
+        tier: &str,
+    ) -> Result<Promotion, BillingError> {
+        let promotion = self.promotions.redeemable(conn, code, user_id, tier).await?;
+        if promotion.is_trial() {
+            return Err(PromotionError::NotApplicable.into());
+        }
+        Ok(promotion)
+    }
+
+    /// The promotion's Stripe coupon, created on first use. Stripe also enforces the
+    /// promotion's redemption limit and end, for checkouts completing after it ran out.
+    async fn coupon_for(&self, promotion: &Promotion) -> Result<String, BillingError> {
+        let existing = sqlx::query_scalar!(
+            "SELECT stripe_coupon_id FROM promotions WHERE id = $1",
+            promotion.id
+        )
+        .fetch_one(&self.db)
+        .await?;
+        if let Some(coupon_id) = existing {
+            return Ok(coupon_id);
+        }
+
+        let mut params = vec![
+            ("name".to_string(), promotion.code.clone()),
+            ("metadata[promotion_id]".to_string(), promotion.id.to_string()),
+        ];
+        match (promotion.percent_off, promotion.amount_off) {
+            (Some(percent), _) => params.push(("percent_off".to_string(), percent.to_string())),
+            (None, Some(amount)) => {
+                let cents = (amount * Decimal::ONE_HUNDRED)
+                    .round()
+                    .to_i64()
+                    .ok_or(BillingError::NotPurchasable)?;
+                params.push(("amount_off".to_string(), cents.to_string()));
+                params.push((
+                    "currency".to_string(),
+                    promotion
+                        .currency
+                        .clone()
+                        .unwrap_or_else(|| self.config.currency.clone()),
+                ));
+            }
+            (None, None) => return Err(PromotionError::NotApplicable.into()),
+        }
+        if let Some(duration) = &promotion.duration {
+            params.push(("duration".to_string(), duration.clone()));
+        }
+        if let Some(months) = promotion.duration_months {
+            params.push(("duration_in_months".to_string(), months.to_string()));
+        }
+        if let Some(max) = promotion.max_redemptions {
+            params.push(("max_redemptions".to_string(), max.to_string()));
+        }
+        if let Some(ends_at) = promotion.ends_at {
+            params.push(("redeem_by".to_string(), ends_at.timestamp().to_string()));
+        }
+
+        let coupon_id = self.stripe.create_coupon(promotion.id, &params).await?;
+        Ok(sqlx::query_scalar!(
+            r#"
+            UPDATE promotions SET stripe_coupon_id = COALESCE(stripe_coupon_id, $2)
+            WHERE id = $1
+            RETURNING stripe_coupon_id AS "stripe_coupon_id!"
+            "#,
+            promotion.id,
+            coupon_id
+        )
+        .fetch_one(&self.db)
+        .await?)
+    }
+
+    async fn offered(&self, tier: &str) -> Result<Membership, BillingError> {
+        self.memberships
+            .list_offered()
+            .await?
+            .into_iter()
+            .find(|membership| membership.tier == tier)
+            .ok_or(BillingError::TierNotOffered)
+    }
+
+    async fn customer_for(&self, user_id: Uuid) -> Result<String, BillingError> {
+        let existing = sqlx::query_scalar!(
+            "SELECT stripe_customer_id FROM billing_customers WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+        if let Some(customer_id) = existing {
+            return Ok(customer_id);
+        }
+
+        // Concurrent first checkouts get the same customer from Stripe's idempotency key
+        let customer_id = self.stripe.create_customer(user_id).await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO billing_customers (user_id, stripe_customer_id) VALUES ($1, $2)
+            ON CONFLICT (user_id) DO NOTHING
+            "#,
+            user_id,
+            customer_id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(sqlx::query_scalar!(
+            "SELECT stripe_customer_id FROM billing_customers WHERE user_id = $1",
+            user_id
+        )
+        .fetch_one(&self.db)
+        .await?)
+    }
+}
+
+/// The price for `interval` in the currency's minor unit (cents).
+fn unit_amount(membership: &Membership, interval: BillingInterval) -> Result<i64, BillingError> {
+    membership
+        .price(interval)
+        .filter(|price| *price > Decimal::ZERO)
+        .and_then(|price| (price * Decimal::ONE_HUNDRED).round().to_i64())
+        .ok_or(BillingError::NotPurchasable)
+}
+
+/// A member's paid Stripe subscription, on the catalog version it was bought at.
+struct CurrentPlan {
+    subscription_id: Uuid,
+    stripe_subscription_id: String,
+    membership: Membership,
+    interval: BillingInterval,
+    period_start: DateTime<Utc>,
+    period_end: DateTime<Utc>,
+}
+
+/// The member's live subscription, locked until `conn`'s transaction ends, if its plan can be
+/// changed: billed through Stripe, paid up and with a known billing period.
+async fn current_plan(conn: &mut PgConnection, user_id: Uuid) -> Result<CurrentPlan, BillingError> {
+    let subscription = sqlx::query!(
+        r#"
+        SELECT id, membership_id, status, stripe_subscription_id, billing_interval,
+               current_period_start, current_period_end
+        FROM subscriptions
+        WHERE user_id = $1 AND status = ANY($2)
+        FOR UPDATE
+        "#,
+        user_id,
+        &LIVE_STATUSES.map(String::from)[..]
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(BillingError::NoSubscription)?;
+
+    if subscription.status == SubscriptionStatus::PastDue.as_str() {
+        return Err(BillingError::PaymentOverdue);
+    }
+    // Complimentary subscriptions have nothing to prorate against
+    let (Some(stripe_subscription_id), Some(interval)) = (
+        subscription.stripe_subscription_id,
+        subscription
+            .billing_interval
+            .as_deref()
+            .and_then(BillingInterval::parse),
+    ) else {
+        return Err(BillingError::NoSubscription);
+    };
+    let (Some(period_start), Some(period_end)) =
+        (subscription.current_period_start, subscription.current_period_end)
+    else {
+        return Err(BillingError::SubscriptionPending);
+    };
+
+    let membership = sqlx::query_as!(
+        Membership,
+        "SELECT * FROM memberships WHERE id = $1",
+        subscription.membership_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(CurrentPlan {
+        subscription_id: subscription.id,
+        stripe_subscription_id,
+        membership,
+        interval,
+        period_start,
+        period_end,
+    })
+}
+
+/// Prices moving from `current` to `target` at `interval` at time `now`.
+///
+/// A tier with a higher monthly rate, or the same rate billed yearly instead of monthly, is an
+/// upgrade: the unused part of the current period is credited against the new price for the
+/// rest of it, or against a whole new period when the interval changes (Stripe restarts the
+/// billing cycle then). Anything else is a downgrade at the end of the period, charged nothing.
+/// A `promotion` discounts the new charge, so it only applies to upgrades.
+fn quote(
+    current: &CurrentPlan,
+    target: &Membership,
+    interval: BillingInterval,
+    now: DateTime<Utc>,
+    currency: &str,
+    promotion: Option<&Promotion>,
+) -> Result<PlanChangePreview, BillingError> {
+    if target.id == current.membership.id && interval == current.interval {
+        return Err(BillingError::SamePlan);
+    }
+    unit_amount(target, interval)?;
+    let from_price = current.membership.price(current.interval).unwrap_or(Decimal::ZERO);
+    let to_price = target.price(interval).unwrap_or(Decimal::ZERO);
+
+    let kind = match target.monthly_rate().cmp(&current.membership.monthly_rate()) {
+        std::cmp::Ordering::Greater => PlanChangeKind::Upgrade,
+        std::cmp::Ordering::Less => PlanChangeKind::Downgrade,
+        std::cmp::Ordering::Equal
+            if current.interval == BillingInterval::Monthly
+                && interval == BillingInterval::Yearly =>
+        {
+            PlanChangeKind::Upgrade
+        }
+        std::cmp::Ordering::Equal => PlanChangeKind::Downgrade,
+    };
+
+    let (effective_at, credit, charge) = match kind {
+        PlanChangeKind::Upgrade => {
+            let period = (current.period_end - current.period_start).num_seconds().max(1);
+            let left = (current.period_end - now).num_seconds().clamp(0, period);
+            let unused = Decimal::from(left) / Decimal::from(period);
+
+            let credit = (from_price * unused).round_dp(2);
+            let charge = if interval == current.interval {
+                (to_price * unused).round_dp(2)
+            } else {
+                to_price
+            };
+            (now, credit, charge)
+        }
+        PlanChangeKind::Downgrade => (current.period_end, Decimal::ZERO, Decimal::ZERO),
+    };
+    if promotion.is_some() && kind == PlanChangeKind::Downgrade {
+        return Err(PromotionError::NotApplicable.into());
+    }
+    let discount = promotion.map_or(Decimal::ZERO, |promotion| promotion.discount(charge));
+    let discounted = charge - discount;
+
+    Ok(PlanChangePreview {
+        from: PlanQuote {
+            tier: current.membership.tier.clone(),
+            version: current.membership.version,
+            name: current.membership.name.clone(),
+            interval: current.interval,
+            price: from_price,
+        },
+        to: PlanQuote {
+            tier: target.tier.clone(),
+            version: target.version,
+            name: target.name.clone(),
+            interval,
+            price: to_price,
+        },
+        kind,
+        effective_at,
+        currency: currency.to_string(),
+        credit,
+        charge,
+        promotion_code: promotion.map(|promotion| promotion.code.clone()),
+        discount,
+        amount_due: (discounted - credit).max(Decimal::ZERO),
+        credit_balance: (credit - discounted).max(Decimal::ZERO),
+    })
+}
+
+fn parse<T: for<'de> serde::Deserialize<'de>>(object: serde_json::Value) -> Result<T, BillingError> {
+    serde_json::from_value(object).map_err(|_| BillingError::InvalidEvent)
+}
+
+/// Whose subscription a Stripe subscription is, for one we have not stored yet.
+struct SubscriptionOwner {
+    user_id: Uuid,
+    membership_id: Uuid,
+    interval: BillingInterval,
+}
+
+/// Stripe's view of a subscription after one event; `None` fields leave stored values alone.
+struct SubscriptionUpdate {
+    stripe_subscription_id: String,
+    customer: Option<String>,
+    status: SubscriptionStatus,
+    period_start: Option<DateTime<Utc>>,
+    period_end: Option<DateTime<Utc>>,
+    owner: Option<SubscriptionOwner>,
+}
+
+/// A subscription whose status changed.
+struct SubscriptionChange {
+    user_id: Uuid,
+    subscription_id: Uuid,
+    tier: String,
+    status: String,
+}
+
+/// Set by `start_checkout` in `subscription_data[metadata]`.
+fn owner_from_metadata(subscription: &StripeSubscription) -> Option<SubscriptionOwner> {
+    let field = |key: &str| subscription.metadata.get(key).map(String::as_str);
+    Some(SubscriptionOwner {
+        user_id: field("user_id")?.parse().ok()?,
+        membership_id: field("membership_id")?.parse().ok()?,
+        interval: BillingInterval::parse(field("interval")?)?,
+    })
+}
+
+async fn checkout_completed(
+    conn: &mut PgConnection,
+    session: StripeCheckoutSession,
+) -> Result<Option<SubscriptionUpdate>, BillingError> {
+    // Terms come from the session we recorded, not from anything in the event
+    let started = sqlx::query!(
+        r#"
+        UPDATE checkout_sessions SET completed_at = COALESCE(completed_at, NOW())
+        WHERE id = $1
+        RETURNING user_id, membership_id, billing_interval
+        "#,
+        session.id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let (Some(started), Some(stripe_subscription_id)) = (started, session.subscription) else {
+        tracing::warn!("Ignoring completion of unknown checkout session {}", session.id);
+        return Ok(None);
+    };
+    let interval = BillingInterval::parse(&started.billing_interval).ok_or(BillingError::InvalidEvent)?;
+    let paid = matches!(
+        session.payment_status.as_deref(),
+        Some("paid") | Some("no_payment_required")
+    );
+
+    Ok(Some(SubscriptionUpdate {
+        stripe_subscription_id,
+        customer: session.customer,
+        status: if paid {
+            SubscriptionStatus::Active
+        } else {
+            SubscriptionStatus::Incomplete
+        },
+        period_start: None,
+        period_end: None,
+        owner: Some(SubscriptionOwner {
+            user_id: started.user_id,
+            membership_id: started.membership_id,
+            interval,
+        }),
+    }))
+}
+
+/// Records the promotion a completed checkout was started with, once the subscription exists.
+async fn redeem_checkout_promotion(
+    conn: &mut PgConnection,
+    session_id: &str,
+    stripe_subscription_id: &str,
+) -> Result<(), BillingError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO promotion_redemptions (promotion_id, user_id, subscription_id)
+        SELECT cs.promotion_id, cs.user_id,
+               (SELECT id FROM subscriptions WHERE stripe_subscription_id = $2)
+        FROM checkout_sessions cs
+        WHERE cs.id = $1 AND cs.promotion_id IS NOT NULL
+        ON CONFLICT (promotion_id, user_id) DO NOTHING
+        "#,
+        session_id,
+        stripe_subscription_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Applies `update` if the status machine allows it (`SubscriptionStatus::can_become`).
+///
+/// Becoming `past_due` starts a grace period of `grace_period` from the event, during which the
+/// member keeps the tier; becoming `active` or `trialing` again ends it. The Stripe ids of
+/// subscriptions a newly live one replaced are added to `replaced`, for cancelling there.
+async fn apply_subscription_update(
+    conn: &mut PgConnection,
+    update: SubscriptionUpdate,
+    event_at: DateTime<Utc>,
+    grace_period: Duration,
+    replaced: &mut Vec<String>,
+) -> Result<Option<SubscriptionChange>, BillingError> {
+    let existing = sqlx::query!(
+        r#"
+        SELECT id, user_id, membership_id, billing_interval, status, provider_updated_at,
+               past_due_since, grace_ends_at
+        FROM subscriptions
+        WHERE stripe_subscription_id = $1
+        FOR UPDATE
+        "#,
+        update.stripe_subscription_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let live = update.status.is_live();
+    let recovered = matches!(
+        update.status,
+        SubscriptionStatus::Active | SubscriptionStatus::Trialing
+    );
+    let (subscription_id, user_id) = match existing {
+        Some(existing) => {
+            if existing.provider_updated_at.is_some_and(|at| at > event_at) {
+                return Ok(None); // A newer event already applied
+            }
+            let current = SubscriptionStatus::parse(&existing.status);
+            if current.is_some_and(|current| !current.can_become(update.status)) {
+                tracing::warn!(
+                    "Ignoring {} for Stripe subscription {} in status {}",
+                    update.status.as_str(),
+                    update.stripe_subscription_id,
+                    existing.status
+                );
+                return Ok(None);
+            }
+
+            let (past_due_since, grace_ends_at) = match update.status {
+                SubscriptionStatus::PastDue => (
+                    Some(existing.past_due_since.unwrap_or(event_at)),
+                    Some(existing.grace_ends_at.unwrap_or(event_at + grace_period)),
+                ),
+                _ if recovered => (None, None),
+                _ => (existing.past_due_since, existing.grace_ends_at),
+            };
+            if live {
+                replaced.extend(end_other_subscriptions(conn, existing.user_id, existing.id).await?);
+            }
+            // `push_plan` moves the metadata along with the price, so a plan change Stripe has
+            // charged for is kept even if recording it here failed
+            let plan = update
+                .owner
+                .filter(|owner| owner.user_id == existing.user_id);
+            let plan_changed = plan.as_ref().is_some_and(|plan| {
+                plan.membership_id != existing.membership_id
+                    || existing.billing_interval.as_deref() != Some(plan.interval.as_str())
+            });
+
+            sqlx::query!(
+                r#"
+                UPDATE subscriptions SET
+                    membership_id = COALESCE(
+                        (SELECT id FROM memberships WHERE id = $10), membership_id
+                    ),
+                    billing_interval = COALESCE($11, billing_interval),
+                    status = $2,
+                    stripe_customer_id = COALESCE($3, stripe_customer_id),
+                    current_period_start = COALESCE($4, current_period_start),
+                    current_period_end = COALESCE($5, current_period_end),
+                    provider_updated_at = $6,
+                    past_due_since = $7,
+                    grace_ends_at = $8,
+                    reminders_sent = CASE WHEN $9 THEN 0 ELSE reminders_sent END,
+                    last_reminder_at = CASE WHEN $9 THEN NULL ELSE last_reminder_at END,
+                    updated_at = NOW()
+                WHERE id = $1
+                "#,
+                existing.id,
+                update.status.as_str(),
+                update.customer,
+                update.period_start,
+                update.period_end,
+                event_at,
+                past_due_since,
+                grace_ends_at,
+                recovered,
+                plan.as_ref().map(|plan| plan.membership_id),
+                plan.as_ref().map(|plan| plan.interval.as_str())
+            )
+            .execute(&mut *conn)
+            .await?;
+
+            if existing.status == update.status.as_str() && !plan_changed {
+                return Ok(None);
+            }
+            (existing.id, existing.user_id)
+        }
+        None => {
+            let Some(owner) = update.owner else {
+                tracing::warn!(
+                    "Ignoring event for unknown Stripe subscription {}",
+                    update.stripe_subscription_id
+                );
+                return Ok(None);
+            };
+            let member_exists = sqlx::query_scalar!(
+                r#"SELECT EXISTS (SELECT 1 FROM users WHERE id = $1) AS "exists!""#,
+                owner.user_id
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+            if !member_exists {
+                tracing::warn!(
+                    "Ignoring Stripe subscription {} of a deleted member",
+                    update.stripe_subscription_id
+                );
+                return Ok(None);
+            }
+
+            let subscription_id = Uuid::new_v4();
+            if live {
+                replaced.extend(end_other_subscriptions(conn, owner.user_id, subscription_id).await?);
+            }
+            let past_due = update.status == SubscriptionStatus::PastDue;
+            sqlx::query!(
+                r#"
+                INSERT INTO subscriptions
+                    (id, user_id, membership_id, stripe_subscription_id, stripe_customer_id, status,
+                     billing_interval, current_period_start, current_period_end, provider_updated_at,
+                     past_due_since, grace_ends_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+                "#,
+                subscription_id,
+                owner.user_id,
+                owner.membership_id,
+                update.stripe_subscription_id,
+                update.customer,
+                update.status.as_str(),
+                owner.interval.as_str(),
+                update.period_start,
+                update.period_end,
+                event_at,
+                past_due.then_some(event_at),
+                past_due.then_some(event_at + grace_period)
+            )
+            .execute(&mut *conn)
+            .await?;
+            (subscription_id, owner.user_id)
+        }
+    };
+
+    let tier = sqlx::query_scalar!(
+        r#"
+        SELECT m.tier FROM subscriptions s JOIN memberships m ON m.id = s.membership_id
+        WHERE s.id = $1
+        "#,
+        subscription_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(Some(SubscriptionChange {
+        user_id,
+        subscription_id,
+        tier,
+        status: update.status.as_str().to_string(),
+    }))
+}
+
+/// A member has one live subscription; a newly live one replaces any other (e.g. complimentary).
+/// Returns the Stripe ids of the ones it ended.
+async fn end_other_subscriptions(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    keep: Uuid,
+) -> Result<Vec<String>, BillingError> {
+    let ended = sqlx::query_scalar!(
+        r#"
+        UPDATE subscriptions SET status = 'canceled', updated_at = NOW()
+        WHERE user_id = $1 AND id <> $2 AND status = ANY($3)
+        RETURNING stripe_subscription_id
+        "#,
+        user_id,
+        keep,
+        &LIVE_STATUSES.map(String::from)[..]
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(ended.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn money(cents: i64) -> Decimal {
+        Decimal::new(cents, 2)
+    }
+
+    fn tier(tier: &str, monthly: i64, yearly: i64) -> Membership {
+        Membership {
+            id: Uuid::new_v4(),
+            tier: tier.to_string(),
+            version: 1,
+            name: tier.to_string(),
+            description: String::new(),
+            price_monthly: Some(money(monthly)),
+            price_yearly: Some(money(yearly)),
+            features: serde_json::json!([]),
+            max_file_size_mb: -1,
+            max_storage_gb: -1,
+            max_conversations: -1,
+            sort_order: 0,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            is_active: true,
+            retired_at: None,
+        }
+    }
+
+    /// A plan a third of the way through a 30-day period, so two thirds of it is unused.
+    fn plan(membership: &Membership, interval: BillingInterval, now: DateTime<Utc>) -> CurrentPlan {
+        CurrentPlan {
+            subscription_id: Uuid::new_v4(),
+            stripe_subscription_id: "sub_test".to_string(),
+            membership: membership.clone(),
+            interval,
+            period_start: now - Duration::days(10),
+            period_end: now + Duration::days(20),
+        }
+    }
+
+    fn promotion(percent_off: i64) -> Promotion {
+        Promotion {
+            id: Uuid::new_v4(),
+            code: "SPRING".to_string(),
+            description: None,
+            kind: "percent".to_string(),
+            percent_off: Some(Decimal::from(percent_off)),
+            amount_off: None,
+            currency: None,
+            duration: Some("once".to_string()),
+            duration_months: None,
+            trial_days: None,
+            tiers: Vec::new(),
+            max_redemptions: None,
+            starts_at: Utc::now(),
+            ends_at: None,
+            created_by: None,
+            created_at: Utc::now(),
+            redemptions: 0,
+        }
+    }
+
+    #[test]
+    fn upgrades_within_an_interval_are_prorated() {
+        let now = Utc::now();
+        let (standard, premium) = (tier("standard", 1000, 10000), tier("premium", 2000, 20000));
+        let current = plan(&standard, BillingInterval::Monthly, now);
+
+        let preview = quote(&current, &premium, BillingInterval::Monthly, now, "usd", None).unwrap();
+        assert_eq!(preview.kind, PlanChangeKind::Upgrade);
+        assert_eq!(preview.effective_at, now);
+        assert_eq!(preview.credit, money(667));
+        assert_eq!(preview.charge, money(1333));
+        assert_eq!(preview.amount_due, money(666));
+        assert_eq!(preview.credit_balance, Decimal::ZERO);
+    }
+
+    #[test]
+    fn moving_to_yearly_charges_a_whole_new_period() {
+        let now = Utc::now();
+        let standard = tier("standard", 1000, 10000);
+        let current = plan(&standard, BillingInterval::Monthly, now);
+
+        let preview = quote(&current, &standard, BillingInterval::Yearly, now, "usd", None).unwrap();
+        assert_eq!(preview.kind, PlanChangeKind::Upgrade);
+        assert_eq!(preview.credit, money(667));
+        assert_eq!(preview.charge, money(10000));
+        assert_eq!(preview.amount_due, money(9333));
+    }
+
+    #[test]
+    fn credit_beyond_the_new_charge_is_kept_as_a_balance() {
+        let now = Utc::now();
+        let (standard, premium) = (tier("standard", 1000, 10000), tier("premium", 2000, 20000));
+        let current = plan(&standard, BillingInterval::Yearly, now);
+
+        let preview = quote(&current, &premium, BillingInterval::Monthly, now, "usd", None).unwrap();
+        assert_eq!(preview.kind, PlanChangeKind::Upgrade);
+        assert_eq!(preview.credit, money(6667));
+        assert_eq!(preview.charge, money(2000));
+        assert_eq!(preview.amount_due, Decimal::ZERO);
+        assert_eq!(preview.credit_balance, money(4667));
+    }
+
+    #[test]
+    fn moving_to_monthly_at_the_same_rate_is_a_downgrade() {
+        let now = Utc::now();
+        let standard = tier("standard", 1000, 10000);
+        let current = plan(&standard, BillingInterval::Yearly, now);
+
+        let preview = quote(&current, &standard, BillingInterval::Monthly, now, "usd", None).unwrap();
+        assert_eq!(preview.kind, PlanChangeKind::Downgrade);
+        assert_eq!(preview.effective_at, current.period_end);
+        assert_eq!(preview.credit, Decimal::ZERO);
+        assert_eq!(preview.charge, Decimal::ZERO);
+        assert_eq!(preview.amount_due, Decimal::ZERO);
+    }
+
+    #[test]
+    fn promotions_discount_upgrades_only() {
+        let now = Utc::now();
+        let (standard, premium) = (tier("standard", 1000, 10000), tier("premium", 2000, 20000));
+        let spring = promotion(25);
+
+        let current = plan(&standard, BillingInterval::Monthly, now);
+        let preview =
+            quote(&current, &premium, BillingInterval::Monthly, now, "usd", Some(&spring)).unwrap();
+        assert_eq!(preview.promotion_code.as_deref(), Some("SPRING"));
+        assert_eq!(preview.discount, money(333));
+        assert_eq!(preview.amount_due, money(333));
+
+        let current = plan(&premium, BillingInterval::Monthly, now);
+        let downgrade =
+            quote(&current, &standard, BillingInterval::Monthly, now, "usd", Some(&spring));
+        assert!(matches!(
+            downgrade,
+            Err(BillingError::Promotion(PromotionError::NotApplicable))
+        ));
+    }
+}
@@ -0,0 +1,312 @@
+use crate::models::{Conversation, Entitlements, PlanLimit, StoredFile};
+use crate::services::{
+    EntitlementError, EntitlementService, FieldCipher, FieldEncryptionError, CONVERSATION_TITLE_FIELD,
+    FILE_CONTENT_FIELD, FILE_NAME_FIELD,
+};
+use crate::utils::{begin_scoped, DbScope};
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use base64::Engine;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+const MB: i64 = 1024 * 1024;
+const GB: i64 = 1024 * MB;
+
+/// Member content the plan meters: conversations and shared files.
+///
+/// Only additions are checked against the plan (`EntitlementService::check_limit`), using the
+/// entitlements `RequireFeature` resolved. Listing, reading and deleting always work, so a
+/// member left over a smaller plan's limits keeps everything they have, read-only until they
+/// delete enough or upgrade.
+#[derive(Debug, Clone)]
+pub struct ContentService {
+    db: PgPool,
+    cipher: FieldCipher,
+    entitlements: EntitlementService,
+}
+
+#[derive(Debug)]
+pub enum ContentError {
+    DatabaseError(sqlx::Error),
+    Encryption(FieldEncryptionError),
+    Entitlement(EntitlementError),
+    NotFound,
+}
+
+impl std::fmt::Display for ContentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ContentError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            ContentError::Encryption(e) => write!(f, "Encryption error: {}", e),
+            ContentError::Entitlement(e) => write!(f, "{}", e),
+            ContentError::NotFound => write!(f, "Not found"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ContentError {
+    fn from(err: sqlx::Error) -> Self {
+        ContentError::DatabaseError(err)
+    }
+}
+
+impl From<FieldEncryptionError> for ContentError {
+    fn from(err: FieldEncryptionError) -> Self {
+        ContentError::Encryption(err)
+    }
+}
+
+impl From<EntitlementError> for ContentError {
+    fn from(err: EntitlementError) -> Self {
+        ContentError::Entitlement(err)
+    }
+}
+
+impl ContentService {
+    pub fn new(db: PgPool, cipher: FieldCipher) -> Self {
+        Self {
+            entitlements: EntitlementService::new(db.clone()),
+            db,
+            cipher,
+        }
+    }
+
+    pub async fn list_conversations(&self, user_id: Uuid) -> Result<Vec<Conversation>, ContentError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+        let rows = sqlx::query!(
+            "SELECT id, title, created_at FROM conversations WHERE user_id = $1 ORDER BY created_at",
+            user_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(Conversation {
+                    id: row.id,
+                    title: self.cipher.decrypt(user_id, CONVERSATION_TITLE_FIELD, &row.title)?,
+                    created_at: row.created_at,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn create_conversation(
+        &self,
+        user_id: Uuid,
+        entitlements: &Entitlements,
+        title: &str,
+    ) -> Result<Conversation, ContentError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+        lock_member(&mut tx, user_id).await?;
+
+        let count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM conversations WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        self.entitlements
+            .check_limit(entitlements, PlanLimit::Conversations, count, 1)
+            .await?;
+
+        let row = sqlx::query!(
+            "INSERT INTO conversations (user_id, title) VALUES ($1, $2) RETURNING id, created_at",
+            user_id,
+            self.cipher.encrypt(user_id, CONVERSATION_TITLE_FIELD, title)?
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Conversation {
+            id: row.id,
+            title: title.to_string(),
+            created_at: row.created_at,
+        })
+    }
+
+    pub async fn delete_conversation(&self, user_id: Uuid, conversation_id: Uuid) -> Result<(), ContentError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+        let deleted = sqlx::query!(
+            "DELETE FROM conversations WHERE id = $1 AND user_id = $2",
+            conversation_id,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        tx.commit().await?;
+
+        if deleted == 0 {
+            return Err(ContentError::NotFound);
+        }
+        Ok(())
+    }
+
+    pub async fn list_files(&self, user_id: Uuid) -> Result<Vec<StoredFile>, ContentError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, name, content_type, size_bytes, created_at
+            FROM files WHERE user_id = $1 ORDER BY created_at
+            "#,
+            user_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(StoredFile {
+                    id: row.id,
+                    name: self.cipher.decrypt(user_id, FILE_NAME_FIELD, &row.name)?,
+                    content_type: row.content_type,
+                    size_bytes: row.size_bytes,
+                    created_at: row.created_at,
+                })
+            })
+            .collect()
+    }
+
+    /// Stores a file if the plan allows its size and there is room for it under the storage
+    /// limit.
+    pub async fn upload_file(
+        &self,
+        user_id: Uuid,
+        entitlements: &Entitlements,
+        name: &str,
+        content_type: &str,
+        content: &[u8],
+    ) -> Result<StoredFile, ContentError> {
+        let size = content.len() as i64;
+        self.entitlements
+            .check_limit(entitlements, PlanLimit::FileSizeMb, 0, whole_units(size, MB))
+            .await?;
+
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+        lock_member(&mut tx, user_id).await?;
+
+        let used = sqlx::query_scalar!(
+            r#"SELECT COALESCE(SUM(size_bytes), 0)::BIGINT AS "used!" FROM files WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        // In whole GB, rounded up: a total fits under N GB exactly when its rounded-up size does
+        let used_gb = whole_units(used, GB);
+        self.entitlements
+            .check_limit(
+                entitlements,
+                PlanLimit::StorageGb,
+                used_gb,
+                whole_units(used + size, GB) - used_gb,
+            )
+            .await?;
+
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO files (user_id, name, content_type, size_bytes, content)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, created_at
+            "#,
+            user_id,
+            self.cipher.encrypt(user_id, FILE_NAME_FIELD, name)?,
+            content_type,
+            size,
+            self.cipher
+                .encrypt(user_id, FILE_CONTENT_FIELD, &BASE64_ENGINE.encode(content))?
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(StoredFile {
+            id: row.id,
+            name: name.to_string(),
+            content_type: content_type.to_string(),
+            size_bytes: size,
+            created_at: row.created_at,
+        })
+    }
+
+    pub async fn download_file(&self, user_id: Uuid, file_id: Uuid) -> Result<(StoredFile, Vec<u8>), ContentError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+        let row = sqlx::query!(
+            r#"
+            SELECT id, name, content_type, size_bytes, content, created_at
+            FROM files WHERE id = $1 AND user_id = $2
+            "#,
+            file_id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(ContentError::NotFound)?;
+        tx.commit().await?;
+
+        let content = self.cipher.decrypt(user_id, FILE_CONTENT_FIELD, &row.content)?;
+        let content = BASE64_ENGINE
+            .decode(content)
+            .map_err(|_| ContentError::Encryption(FieldEncryptionError::Crypto))?;
+        let file = StoredFile {
+            id: row.id,
+            name: self.cipher.decrypt(user_id, FILE_NAME_FIELD, &row.name)?,
+            content_type: row.content_type,
+            size_bytes: row.size_bytes,
+            created_at: row.created_at,
+        };
+
+        Ok((file, content))
+    }
+
+    pub async fn delete_file(&self, user_id: Uuid, file_id: Uuid) -> Result<(), ContentError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+        let deleted = sqlx::query!("DELETE FROM files WHERE id = $1 AND user_id = $2", file_id, user_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        tx.commit().await?;
+
+        if deleted == 0 {
+            return Err(ContentError::NotFound);
+        }
+        Ok(())
+    }
+}
+
+/// Serializes a member's additions, so two at once cannot both take the last free slot.
+async fn lock_member(conn: &mut PgConnection, user_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+        .fetch_optional(conn)
+        .await?;
+    Ok(())
+}
+
+/// `bytes` in whole `unit`s, rounded up.
+fn whole_units(bytes: i64, unit: i64) -> i64 {
+    (bytes + unit - 1) / unit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_round_up_to_whole_units() {
+        assert_eq!(whole_units(0, MB), 0);
+        assert_eq!(whole_units(1, MB), 1);
+        assert_eq!(whole_units(MB, MB), 1);
+        assert_eq!(whole_units(MB + 1, MB), 2);
+    }
+
+    #[test]
+    fn rounded_totals_fit_a_limit_exactly_when_the_bytes_do() {
+        let max = 5;
+        for total in [5 * GB - 1, 5 * GB, 5 * GB + 1] {
+            assert_eq!(whole_units(total, GB) <= max, total <= max * GB);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use crate::models::{CanaryAccount, CreateCanaryRequest, Honeytoken};
+use crate::config::Config;
+use crate::services::{password_hasher, FieldCipher, FieldEncryptionError, DEFAULT_TIER, EMAIL_FIELD};
+use crate::utils::{begin_scoped, sha256_hex, DbScope};
+use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use base64::Engine;
+use ring::rand::{SecureRandom, SystemRandom};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const UNIQUE_VIOLATION: &str = "23505";
+
+/// Manages canary accounts and honeytokens.
+///
+/// Both are indistinguishable from the real thing: canaries are ordinary user rows with an
+/// unguessable password, and honeytokens have the same shape as refresh tokens. Tripping either
+/// is handled where they are used, in `AuthService`.
+#[derive(Debug, Clone)]
+pub struct DeceptionService {
+    db: PgPool,
+    cipher: FieldCipher,
+    argon2: Argon2<'static>,
+    rng: SystemRandom,
+}
+
+#[derive(Debug)]
+pub enum DeceptionError {
+    DatabaseError(sqlx::Error),
+    AlreadyExists,
+    NotFound,
+    UnknownTier,
+    HashingError,
+    EncryptionError(FieldEncryptionError),
+}
+
+impl std::fmt::Display for DeceptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeceptionError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            DeceptionError::AlreadyExists => write!(f, "An account with that email already exists"),
+            DeceptionError::NotFound => write!(f, "Not found"),
+            DeceptionError::UnknownTier => write!(f, "No membership tier with that code is offered"),
+            DeceptionError::HashingError => write!(f, "Password hashing error"),
+            DeceptionError::EncryptionError(e) => write!(f, "Field encryption error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for DeceptionError {
+    fn from(err: sqlx::Error) -> Self {
+        DeceptionError::DatabaseError(err)
+    }
+}
+
+impl From<FieldEncryptionError> for DeceptionError {
+    fn from(err: FieldEncryptionError) -> Self {
+        DeceptionError::EncryptionError(err)
+    }
+}
+
+impl DeceptionService {
+    pub fn new(db: PgPool, config: &Config, cipher: FieldCipher) -> Self {
+        Self {
+            db,
+            cipher,
+            argon2: password_hasher(config),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    pub async fn list_canaries(&self, admin_id: Uuid) -> Result<Vec<CanaryAccount>, DeceptionError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Admin(admin_id)).await?;
+        let canaries = sqlx::query_as!(
+            CanaryAccount,
+            r#"
+            SELECT u.id, u.email, m.tier AS membership_tier, u.created_at, u.failed_login_attempts
+            FROM users u
+            JOIN memberships m ON m.id = current_membership_id(u.id)
+            WHERE u.is_canary
+            ORDER BY u.created_at DESC
+            "#
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        canaries
+            .into_iter()
+            .map(|canary| self.open_canary(canary))
+            .collect()
+    }
+
+    pub async fn create_canary(
+        &self,
+        admin_id: Uuid,
+        request: CreateCanaryRequest,
+    ) -> Result<CanaryAccount, DeceptionError> {
+        // Nobody ever learns this password; it only has to hash like a real one
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let password_hash = self
+            .argon2
+            .hash_password(self.generate_token().as_bytes(), &salt)
+            .map_err(|_| DeceptionError::HashingError)?
+            .to_string();
+
+        // Canaries hold the same kind of subscription a real member on the tier would
+        let tier = request.membership_tier.as_deref().unwrap_or(DEFAULT_TIER);
+        let membership_id = sqlx::query_scalar!(
+            "SELECT id FROM memberships WHERE tier = $1 AND is_active",
+            tier
+        )
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(DeceptionError::UnknownTier)?;
+
+        let canary_id = Uuid::new_v4();
+        self.cipher.create_user_key(canary_id)?;
+
+        let mut tx = begin_scoped(&self.db, DbScope::Admin(admin_id)).await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO users (id, email, email_index, password_hash, email_verified, is_canary)
+            VALUES ($1, $2, $3, $4, true, true)
+            "#,
+            canary_id,
+            self.cipher.encrypt(canary_id, EMAIL_FIELD, &request.email)?,
+            self.cipher.email_index(&request.email),
+            password_hash
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db) if db.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+                DeceptionError::AlreadyExists
+            }
+            _ => DeceptionError::DatabaseError(e),
+        })?;
+
+        if tier != DEFAULT_TIER {
+            sqlx::query!(
+                "INSERT INTO subscriptions (user_id, membership_id, status) VALUES ($1, $2, 'active')",
+                canary_id,
+                membership_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let canary = sqlx::query_as!(
+            CanaryAccount,
+            r#"
+            SELECT u.id, u.email, m.tier AS membership_tier, u.created_at, u.failed_login_attempts
+            FROM users u
+            JOIN memberships m ON m.id = current_membership_id(u.id)
+            WHERE u.id = $1
+            "#,
+            canary_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.open_canary(canary)
+    }
+
+    pub async fn delete_canary(&self, admin_id: Uuid, canary_id: Uuid) -> Result<(), DeceptionError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Admin(admin_id)).await?;
+
+        let result = sqlx::query!("DELETE FROM users WHERE id = $1 AND is_canary", canary_id)
+            .execute(&mut *tx)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(DeceptionError::NotFound);
+        }
+
+        self.cipher.shred_user_key(&mut tx, canary_id).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn list_honeytokens(&self) -> Result<Vec<Honeytoken>, DeceptionError> {
+        let honeytokens = sqlx::query_as!(
+            Honeytoken,
+            r#"
+            SELECT id, label, created_by, created_at, last_triggered_at, trigger_count
+            FROM honeytokens
+            ORDER BY created_at DESC
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(honeytokens)
+    }
+
+    /// Returns the new honeytoken and its token value; only the hash is stored.
+    pub async fn create_honeytoken(
+        &self,
+        label: &str,
+        created_by: Uuid,
+    ) -> Result<(Honeytoken, String), DeceptionError> {
+        let token = self.generate_token();
+
+        let honeytoken = sqlx::query_as!(
+            Honeytoken,
+            r#"
+            INSERT INTO honeytokens (token_hash, label, created_by)
+            VALUES ($1, $2, $3)
+            RETURNING id, label, created_by, created_at, last_triggered_at, trigger_count
+            "#,
+            sha256_hex(token.as_bytes()),
+            label,
+            created_by
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok((honeytoken, token))
+    }
+
+    pub async fn delete_honeytoken(&self, honeytoken_id: Uuid) -> Result<(), DeceptionError> {
+        let result = sqlx::query!("DELETE FROM honeytokens WHERE id = $1", honeytoken_id)
+            .execute(&self.db)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(DeceptionError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    fn open_canary(&self, mut canary: CanaryAccount) -> Result<CanaryAccount, DeceptionError> {
+        canary.email = self.cipher.decrypt(canary.id, EMAIL_FIELD, &canary.email)?;
+        Ok(canary)
+    }
+
+    // Same shape as AuthService refresh tokens
+    fn generate_token(&self) -> String {
+        let mut bytes = [0u8; 32];
+        self.rng.fill(&mut bytes).unwrap();
+        BASE64_ENGINE.encode(bytes)
+    }
+}
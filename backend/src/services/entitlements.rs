@@ -0,0 +1,130 @@
+use crate::models::{Entitlements, Membership, PlanLimit};
+use crate::services::MembershipService;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Resolves what a member's plan allows and refuses what it does not.
+///
+/// The plan is the catalog version the member is on (`MembershipService::current_for`), so
+/// grandfathered subscribers are held to the terms they signed up for.
+#[derive(Debug, Clone)]
+pub struct EntitlementService {
+    memberships: MembershipService,
+}
+
+#[derive(Debug)]
+pub enum EntitlementError {
+    DatabaseError(sqlx::Error),
+    /// The plan does not include the feature; `upgrade_to` lists offered tiers that do.
+    FeatureNotIncluded {
+        feature: String,
+        tier: String,
+        upgrade_to: Vec<String>,
+    },
+    /// The action would take the member from `current` to `requested`, over the plan's `max`.
+    /// `read_only` when `current` is over `max` already, e.g. after moving to a smaller plan.
+    LimitExceeded {
+        limit: PlanLimit,
+        max: i32,
+        current: i64,
+        requested: i64,
+        read_only: bool,
+        tier: String,
+        upgrade_to: Vec<String>,
+    },
+}
+
+impl std::fmt::Display for EntitlementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EntitlementError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            EntitlementError::FeatureNotIncluded { feature, tier, .. } => {
+                write!(f, "The {} plan does not include {}", tier, feature)
+            }
+            EntitlementError::LimitExceeded { limit, max, tier, .. } => {
+                write!(f, "The {} plan allows {} of at most {}", tier, limit.as_str(), max)
+            }
+        }
+    }
+}
+
+impl From<sqlx::Error> for EntitlementError {
+    fn from(err: sqlx::Error) -> Self {
+        EntitlementError::DatabaseError(err)
+    }
+}
+
+impl EntitlementService {
+    pub fn new(db: PgPool) -> Self {
+        Self {
+            memberships: MembershipService::new(db),
+        }
+    }
+
+    pub async fn for_user(&self, user_id: Uuid) -> Result<Entitlements, EntitlementError> {
+        let membership = self.memberships.current_for(user_id).await?;
+        Ok(Entitlements::from(&membership))
+    }
+
+    pub async fn require_feature(
+        &self,
+        user_id: Uuid,
+        feature: &str,
+    ) -> Result<Entitlements, EntitlementError> {
+        let membership = self.memberships.current_for(user_id).await?;
+        if membership.has_feature(feature) {
+            return Ok(Entitlements::from(&membership));
+        }
+
+        let upgrade_to = self.offered_where(|m| m.has_feature(feature)).await?;
+        Err(EntitlementError::FeatureNotIncluded {
+            feature: feature.to_string(),
+            tier: membership.tier,
+            upgrade_to,
+        })
+    }
+
+    /// Checks that the member may add `adding` to the `current` amount they hold (e.g. one
+    /// more conversation, or an upload's MB against `FileSizeMb` with `current` 0), under the
+    /// plan `RequireFeature` resolved for them.
+    ///
+    /// Only additions are ever refused. Members over a limit, as after moving to a smaller plan,
+    /// keep everything they have; it stays readable but cannot grow until they are back under.
+    pub async fn check_limit(
+        &self,
+        entitlements: &Entitlements,
+        limit: PlanLimit,
+        current: i64,
+        adding: i64,
+    ) -> Result<(), EntitlementError> {
+        let requested = current.saturating_add(adding);
+        let max = entitlements.limit(limit);
+        if max.allows(requested) {
+            return Ok(());
+        }
+
+        let upgrade_to = self.offered_where(|m| m.limit(limit).allows(requested)).await?;
+        Err(EntitlementError::LimitExceeded {
+            limit,
+            max: max.0,
+            current,
+            requested,
+            read_only: !max.allows(current),
+            tier: entitlements.tier.clone(),
+            upgrade_to,
+        })
+    }
+
+    async fn offered_where(
+        &self,
+        predicate: impl Fn(&Membership) -> bool,
+    ) -> Result<Vec<String>, EntitlementError> {
+        let offered = self.memberships.list_offered().await?;
+
+        Ok(offered
+            .into_iter()
+            .filter(|membership| predicate(membership))
+            .map(|membership| membership.tier)
+            .collect())
+    }
+}
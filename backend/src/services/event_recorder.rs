@@ -0,0 +1,491 @@
+use crate::models::{SecurityEvent, SecurityEventType};
+use crate::services::EventForwarder;
+use crate::utils::{begin_scoped, DbScope};
+use ipnetwork::IpNetwork;
+use serde::Serialize;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use uuid::Uuid;
+
+const QUEUE_CAPACITY: usize = 10_000;
+const BATCH_SIZE: usize = 200;
+const BATCH_WINDOW: Duration = Duration::from_millis(250);
+const REPLAY_INTERVAL: Duration = Duration::from_secs(10);
+// Fail over to the journal quickly instead of waiting out the pool's acquire timeout
+const INSERT_TIMEOUT: Duration = Duration::from_secs(5);
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+
+/// Durable writer for the security audit trail.
+///
+/// Events are queued in memory and inserted in batches. When Postgres is unavailable (or the
+/// queue is full) they are appended to a local NDJSON journal instead and replayed once the
+/// database accepts writes again; inserts are idempotent on the event id so replays never
+/// duplicate rows. An event is only lost if the journal cannot be written either, and that is
+/// counted, logged and forwarded to the SIEM sinks as `security_events_dropped`.
+#[derive(Debug, Clone)]
+pub struct EventRecorder {
+    sender: mpsc::Sender<RecorderMessage>,
+    journal: Arc<Journal>,
+    metrics: Arc<RecorderMetrics>,
+    forwarder: EventForwarder,
+}
+
+#[derive(Debug)]
+enum RecorderMessage {
+    Event(SecurityEvent),
+    Flush(oneshot::Sender<()>),
+}
+
+#[derive(Debug, Default)]
+struct RecorderMetrics {
+    written: AtomicU64,
+    spilled: AtomicU64,
+    replayed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecorderMetricsSnapshot {
+    pub queued: u64,
+    pub written: u64,
+    pub spilled: u64,
+    pub replayed: u64,
+    pub dropped: u64,
+}
+
+impl EventRecorder {
+    pub fn start(db: PgPool, journal_path: String, forwarder: EventForwarder) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        let recorder = Self {
+            sender,
+            journal: Arc::new(Journal::new(PathBuf::from(journal_path))),
+            metrics: Arc::new(RecorderMetrics::default()),
+            forwarder,
+        };
+
+        tokio::spawn(recorder.clone().run(db, receiver));
+
+        recorder
+    }
+
+    pub async fn record(&self, event: SecurityEvent) {
+        match self.sender.try_send(RecorderMessage::Event(event)) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(RecorderMessage::Event(event)))
+            | Err(mpsc::error::TrySendError::Closed(RecorderMessage::Event(event))) => {
+                // Writer is saturated; go straight to disk rather than wait or drop
+                self.spill(vec![event]).await;
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Writes out everything queued so far; used on shutdown.
+    pub async fn flush(&self) {
+        let (done, wait) = oneshot::channel();
+        if self.sender.send(RecorderMessage::Flush(done)).await.is_ok() {
+            let _ = wait.await;
+        }
+    }
+
+    pub fn metrics(&self) -> RecorderMetricsSnapshot {
+        RecorderMetricsSnapshot {
+            queued: (QUEUE_CAPACITY - self.sender.capacity()) as u64,
+            written: self.metrics.written.load(Ordering::Relaxed),
+            spilled: self.metrics.spilled.load(Ordering::Relaxed),
+            replayed: self.metrics.replayed.load(Ordering::Relaxed),
+            dropped: self.metrics.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    async fn run(self, db: PgPool, mut receiver: mpsc::Receiver<RecorderMessage>) {
+        let mut replay_timer = tokio::time::interval(REPLAY_INTERVAL);
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+        loop {
+            let message = tokio::select! {
+                message = receiver.recv() => message,
+                _ = replay_timer.tick() => {
+                    self.replay_journal(&db).await;
+                    continue;
+                }
+            };
+
+            let mut flush_requests = Vec::new();
+            match message {
+                Some(RecorderMessage::Event(event)) => batch.push(event),
+                Some(RecorderMessage::Flush(done)) => flush_requests.push(done),
+                None => break,
+            }
+
+            // Gather whatever else arrives within the batch window
+            let deadline = tokio::time::Instant::now() + BATCH_WINDOW;
+            while batch.len() < BATCH_SIZE && flush_requests.is_empty() {
+                match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                    Ok(Some(RecorderMessage::Event(event))) => batch.push(event),
+                    Ok(Some(RecorderMessage::Flush(done))) => flush_requests.push(done),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+
+            if !batch.is_empty() {
+                self.write_batch(&db, std::mem::take(&mut batch)).await;
+            }
+            for done in flush_requests {
+                let _ = done.send(());
+            }
+        }
+    }
+
+    async fn write_batch(&self, db: &PgPool, events: Vec<SecurityEvent>) {
+        match insert_events(db, &events).await {
+            Ok(()) => {
+                self.metrics
+                    .written
+                    .fetch_add(events.len() as u64, Ordering::Relaxed);
+            }
+            Err(sqlx::Error::Database(e)) => {
+                // The database is up but rejected something in the batch; isolate the bad rows
+                tracing::warn!("Batch insert of security events rejected ({}); retrying row by row", e);
+                for event in events {
+                    self.write_single(db, event).await;
+                }
+            }
+            Err(e) => {
+                tracing::error!("Security event store unavailable, spilling to journal: {}", e);
+                self.spill(events).await;
+            }
+        }
+    }
+
+    async fn write_single(&self, db: &PgPool, mut event: SecurityEvent) {
+        let mut result = insert_events(db, std::slice::from_ref(&event)).await;
+
+        // The user may have been destroyed after the event was queued; keep the event unlinked
+        if let Err(sqlx::Error::Database(e)) = &result {
+            if e.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) {
+                event.user_id = None;
+                result = insert_events(db, std::slice::from_ref(&event)).await;
+            }
+        }
+
+        match result {
+            Ok(()) => {
+                self.metrics.written.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(sqlx::Error::Database(e)) => {
+                tracing::error!("Security event {} cannot be stored: {}", event.id, e);
+                self.record_drop(1, &format!("rejected by database: {}", e));
+            }
+            Err(e) => {
+                tracing::error!("Security event store unavailable, spilling to journal: {}", e);
+                self.spill(vec![event]).await;
+            }
+        }
+    }
+
+    async fn spill(&self, events: Vec<SecurityEvent>) {
+        let count = events.len() as u64;
+        match self.journal.append(&events).await {
+            Ok(()) => {
+                self.metrics.spilled.fetch_add(count, Ordering::Relaxed);
+            }
+            Err(e) => {
+                tracing::error!("Failed to write security event journal: {}", e);
+                self.record_drop(count, &format!("journal write failed: {}", e));
+            }
+        }
+    }
+
+    async fn replay_journal(&self, db: &PgPool) {
+        let events = match self.journal.take().await {
+            Ok(Some(events)) => events,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::error!("Failed to read security event journal: {}", e);
+                return;
+            }
+        };
+
+        for chunk in events.chunks(BATCH_SIZE) {
+            if let Err(e) = insert_events(db, chunk).await {
+                if !matches!(e, sqlx::Error::Database(_)) {
+                    // Still down; the journal is kept and retried on the next tick
+                    return;
+                }
+                for event in chunk {
+                    self.write_single(db, event.clone()).await;
+                }
+            }
+        }
+
+        if let Err(e) = self.journal.commit_replay().await {
+            tracing::error!("Failed to clear replayed security event journal: {}", e);
+            return;
+        }
+
+        self.metrics
+            .replayed
+            .fetch_add(events.len() as u64, Ordering::Relaxed);
+        tracing::info!("Replayed {} security events from journal", events.len());
+    }
+
+    fn record_drop(&self, count: u64, reason: &str) {
+        let total = self.metrics.dropped.fetch_add(count, Ordering::Relaxed) + count;
+        tracing::error!("{} security events dropped ({} total): {}", count, total, reason);
+
+        let event_type = SecurityEventType::SecurityEventsDropped {
+            dropped: count,
+            total_dropped: total,
+            reason: reason.to_string(),
+        };
+        self.forwarder.forward(SecurityEvent {
+            id: Uuid::new_v4(),
+            user_id: None,
+            event_type: event_type.as_str().to_string(),
+            ip_address: None,
+            user_agent: None,
+            details: event_type.details(),
+            risk_level: event_type.base_risk_level(),
+            created_at: chrono::Utc::now(),
+        });
+    }
+}
+
+async fn insert_events(db: &PgPool, events: &[SecurityEvent]) -> Result<(), sqlx::Error> {
+    let mut query = QueryBuilder::<Postgres>::new(
+        "INSERT INTO security_events (id, user_id, event_type, ip_address, user_agent, details, risk_level, created_at) ",
+    );
+    query.push_values(events, |mut row, event| {
+        row.push_bind(event.id)
+            .push_bind(event.user_id)
+            .push_bind(event.event_type.clone())
+            .push_bind(event.ip_address.map(IpNetwork::from))
+            .push_bind(event.user_agent.clone())
+            .push_bind(event.details.clone())
+            .push_bind(event.risk_level)
+            .push_bind(event.created_at);
+    });
+    query.push(" ON CONFLICT (id) DO NOTHING");
+
+    let insert = async {
+        let mut tx = begin_scoped(db, DbScope::System).await?;
+        query.build().execute(&mut *tx).await?;
+        tx.commit().await
+    };
+    tokio::time::timeout(INSERT_TIMEOUT, insert)
+        .await
+        .map_err(|_| sqlx::Error::PoolTimedOut)?
+}
+
+/// Append-only NDJSON spill file. Replay moves it aside first so new spills are never lost
+/// while a replay is in progress.
+#[derive(Debug)]
+struct Journal {
+    path: PathBuf,
+    replay_path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl Journal {
+    fn new(path: PathBuf) -> Self {
+        let replay_path = PathBuf::from(format!("{}.replay", path.display()));
+        Self {
+            path,
+            replay_path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    async fn append(&self, events: &[SecurityEvent]) -> std::io::Result<()> {
+        let _guard = self.lock.lock().await;
+
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut lines = String::new();
+        for event in events {
+            lines.push_str(&serde_json::to_string(event)?);
+            lines.push('\n');
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(lines.as_bytes()).await?;
+        file.sync_data().await
+    }
+
+    /// Returns pending events, preferring a replay file left over from an earlier failed attempt.
+    async fn take(&self) -> std::io::Result<Option<Vec<SecurityEvent>>> {
+        let _guard = self.lock.lock().await;
+
+        if !tokio::fs::try_exists(&self.replay_path).await? {
+            match tokio::fs::metadata(&self.path).await {
+                Ok(metadata) if metadata.len() > 0 => {
+                    tokio::fs::rename(&self.path, &self.replay_path).await?
+                }
+                Ok(_) => return Ok(None),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let contents = tokio::fs::read_to_string(&self.replay_path).await?;
+        let events = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(event) => Some(event),
+                Err(e) => {
+                    tracing::error!("Skipping corrupt security event journal line: {}", e);
+                    None
+                }
+            })
+            .collect();
+
+        Ok(Some(events))
+    }
+
+    async fn commit_replay(&self) -> std::io::Result<()> {
+        let _guard = self.lock.lock().await;
+        tokio::fs::remove_file(&self.replay_path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A journal in its own scratch directory, removed when dropped.
+    struct TempJournal {
+        dir: PathBuf,
+        journal: Journal,
+    }
+
+    impl TempJournal {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("circle-journal-{}", Uuid::new_v4().simple()));
+            let journal = Journal::new(dir.join("spill").join("security_events.journal"));
+            TempJournal { dir, journal }
+        }
+    }
+
+    impl Drop for TempJournal {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn event(event_type: &str) -> SecurityEvent {
+        SecurityEvent {
+            id: Uuid::new_v4(),
+            user_id: Some(Uuid::new_v4()),
+            event_type: event_type.to_string(),
+            ip_address: Some("198.51.100.4".parse().unwrap()),
+            user_agent: Some("tests".to_string()),
+            details: Some(serde_json::json!({ "failed_attempts": 1 })),
+            risk_level: 3,
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    fn ids(events: &[SecurityEvent]) -> Vec<Uuid> {
+        events.iter().map(|event| event.id).collect()
+    }
+
+    #[tokio::test]
+    async fn appended_events_are_taken_once_committed() {
+        let temp = TempJournal::new();
+        let journal = &temp.journal;
+        assert!(journal.take().await.unwrap().is_none());
+
+        let first = vec![event("failed_login"), event("account_locked")];
+        let second = vec![event("login_success")];
+        journal.append(&first).await.unwrap();
+        journal.append(&second).await.unwrap();
+
+        let taken = journal.take().await.unwrap().unwrap();
+        assert_eq!(ids(&taken), ids(&[first, second].concat()));
+        assert_eq!(taken[0].details, Some(serde_json::json!({ "failed_attempts": 1 })));
+        assert!(!journal.path.exists());
+
+        journal.commit_replay().await.unwrap();
+        assert!(!journal.replay_path.exists());
+        assert!(journal.take().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn spills_during_a_replay_wait_for_the_next_one() {
+        let temp = TempJournal::new();
+        let journal = &temp.journal;
+        let replaying = vec![event("failed_login")];
+        let spilled = vec![event("account_locked")];
+
+        journal.append(&replaying).await.unwrap();
+        assert_eq!(ids(&journal.take().await.unwrap().unwrap()), ids(&replaying));
+        journal.append(&spilled).await.unwrap();
+
+        // The replay failed and was not committed: the same events come back first
+        assert_eq!(ids(&journal.take().await.unwrap().unwrap()), ids(&replaying));
+        journal.commit_replay().await.unwrap();
+        assert_eq!(ids(&journal.take().await.unwrap().unwrap()), ids(&spilled));
+    }
+
+    #[tokio::test]
+    async fn a_replay_file_left_by_a_crash_is_taken_first() {
+        let temp = TempJournal::new();
+        let leftover = event("failed_login");
+        std::fs::create_dir_all(temp.journal.path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &temp.journal.replay_path,
+            format!("{}\n", serde_json::to_string(&leftover).unwrap()),
+        )
+        .unwrap();
+        let newer = vec![event("login_success")];
+        temp.journal.append(&newer).await.unwrap();
+
+        assert_eq!(ids(&temp.journal.take().await.unwrap().unwrap()), vec![leftover.id]);
+        temp.journal.commit_replay().await.unwrap();
+        assert_eq!(ids(&temp.journal.take().await.unwrap().unwrap()), ids(&newer));
+    }
+
+    #[tokio::test]
+    async fn corrupt_and_blank_lines_are_skipped() {
+        let temp = TempJournal::new();
+        let (before, after) = (event("failed_login"), event("account_locked"));
+        std::fs::create_dir_all(temp.journal.path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &temp.journal.path,
+            format!(
+                "{}\n{{\"id\": \"torn\n\n   \n{}\n{{\"id\":",
+                serde_json::to_string(&before).unwrap(),
+                serde_json::to_string(&after).unwrap()
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            ids(&temp.journal.take().await.unwrap().unwrap()),
+            vec![before.id, after.id]
+        );
+    }
+
+    #[tokio::test]
+    async fn an_empty_journal_has_nothing_to_replay() {
+        let temp = TempJournal::new();
+        std::fs::create_dir_all(temp.journal.path.parent().unwrap()).unwrap();
+        std::fs::write(&temp.journal.path, "").unwrap();
+
+        assert!(temp.journal.take().await.unwrap().is_none());
+        assert!(!temp.journal.replay_path.exists());
+    }
+}
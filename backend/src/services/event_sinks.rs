@@ -0,0 +1,557 @@
+use crate::config::{EventSinkConfig, EventSinkKind, SyslogFormat, SyslogProtocol};
+use crate::models::SecurityEvent;
+use crate::utils::webhook_signature;
+use ring::hmac;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+const SINK_QUEUE_CAPACITY: usize = 1024;
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+const APP_NAME: &str = "the-circle";
+// IANA enterprise number reserved for documentation (RFC 5612)
+const SD_ID: &str = "circle@32473";
+// syslog facility 10: security/authorization messages
+const SYSLOG_FACILITY_AUTHPRIV: u8 = 10;
+
+/// Fans security events out to external sinks (syslog, CEF, NDJSON file, webhook).
+///
+/// Every sink has its own bounded queue and delivery task, so a slow or unreachable SIEM only
+/// delays its own deliveries and never the request that produced the event.
+#[derive(Debug, Clone, Default)]
+pub struct EventForwarder {
+    sinks: Vec<SinkHandle>,
+}
+
+#[derive(Debug, Clone)]
+struct SinkHandle {
+    name: String,
+    min_risk_level: i32,
+    sender: mpsc::Sender<Arc<SecurityEvent>>,
+}
+
+impl EventForwarder {
+    pub fn start(configs: &[EventSinkConfig]) -> Self {
+        let sinks = configs
+            .iter()
+            .map(|config| {
+                let (sender, receiver) = mpsc::channel(SINK_QUEUE_CAPACITY);
+                let sink = Sink::from_config(&config.kind);
+                let name = sink.name();
+
+                tracing::info!(
+                    "Forwarding security events with risk >= {} to {}",
+                    config.min_risk_level,
+                    name
+                );
+                tokio::spawn(run_sink(sink, receiver));
+
+                SinkHandle {
+                    name,
+                    min_risk_level: config.min_risk_level,
+                    sender,
+                }
+            })
+            .collect();
+
+        Self { sinks }
+    }
+
+    pub fn forward(&self, event: SecurityEvent) {
+        let event = Arc::new(event);
+
+        for sink in &self.sinks {
+            if event.risk_level < sink.min_risk_level {
+                continue;
+            }
+
+            match sink.sender.try_send(event.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => tracing::error!(
+                    "Security event sink {} is backed up; dropped event {}",
+                    sink.name,
+                    event.id
+                ),
+                Err(TrySendError::Closed(_)) => tracing::error!(
+                    "Security event sink {} has stopped; dropped event {}",
+                    sink.name,
+                    event.id
+                ),
+            }
+        }
+    }
+}
+
+async fn run_sink(mut sink: Sink, mut receiver: mpsc::Receiver<Arc<SecurityEvent>>) {
+    while let Some(event) = receiver.recv().await {
+        let mut attempt = 1;
+        loop {
+            match sink.deliver(&event).await {
+                Ok(()) => break,
+                Err(e) if attempt < MAX_DELIVERY_ATTEMPTS => {
+                    tracing::warn!(
+                        "Delivery of event {} to {} failed (attempt {}): {}",
+                        event.id,
+                        sink.name(),
+                        attempt,
+                        e
+                    );
+                    tokio::time::sleep(retry_delay(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "Giving up on event {} for {} after {} attempts: {}",
+                        event.id,
+                        sink.name(),
+                        attempt,
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn retry_delay(attempt: u32) -> Duration {
+    Duration::from_millis((250u64 << attempt.min(5)).min(8_000))
+}
+
+enum Sink {
+    Syslog(SyslogSink),
+    File(FileSink),
+    Webhook(WebhookSink),
+}
+
+impl Sink {
+    fn from_config(kind: &EventSinkKind) -> Self {
+        match kind {
+            EventSinkKind::Syslog {
+                address,
+                protocol,
+                format,
+            } => Sink::Syslog(SyslogSink {
+                address: address.clone(),
+                protocol: *protocol,
+                format: *format,
+                hostname: std::env::var("HOSTNAME").unwrap_or_else(|_| "-".to_string()),
+                udp: None,
+                tcp: None,
+            }),
+            EventSinkKind::File {
+                path,
+                max_bytes,
+                max_files,
+            } => Sink::File(FileSink {
+                path: PathBuf::from(path),
+                max_bytes: *max_bytes,
+                max_files: *max_files,
+                file: None,
+                written: 0,
+            }),
+            EventSinkKind::Webhook { url, secret } => Sink::Webhook(WebhookSink {
+                client: reqwest::Client::builder()
+                    .timeout(Duration::from_secs(10))
+                    .build()
+                    .unwrap_or_default(),
+                url: url.clone(),
+                secret: secret
+                    .as_ref()
+                    .map(|secret| hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())),
+            }),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Sink::Syslog(sink) => format!(
+                "syslog+{}://{} ({})",
+                if sink.protocol == SyslogProtocol::Tcp { "tcp" } else { "udp" },
+                sink.address,
+                if sink.format == SyslogFormat::Cef { "cef" } else { "rfc5424" }
+            ),
+            Sink::File(sink) => format!("file://{}", sink.path.display()),
+            Sink::Webhook(sink) => sink.url.clone(),
+        }
+    }
+
+    async fn deliver(&mut self, event: &SecurityEvent) -> Result<(), String> {
+        match self {
+            Sink::Syslog(sink) => sink.deliver(event).await,
+            Sink::File(sink) => sink.deliver(event).await,
+            Sink::Webhook(sink) => sink.deliver(event).await,
+        }
+    }
+}
+
+struct SyslogSink {
+    address: String,
+    protocol: SyslogProtocol,
+    format: SyslogFormat,
+    hostname: String,
+    udp: Option<UdpSocket>,
+    tcp: Option<TcpStream>,
+}
+
+impl SyslogSink {
+    async fn deliver(&mut self, event: &SecurityEvent) -> Result<(), String> {
+        let message = match self.format {
+            SyslogFormat::Rfc5424 => rfc5424_message(event, &self.hostname),
+            SyslogFormat::Cef => format!(
+                "{} - {}",
+                rfc5424_header(event, &self.hostname),
+                cef_message(event)
+            ),
+        };
+
+        match self.protocol {
+            SyslogProtocol::Udp => {
+                if self.udp.is_none() {
+                    let target = resolve(&self.address).await?;
+                    let bind_addr: SocketAddr = if target.is_ipv6() {
+                        "[::]:0".parse().unwrap()
+                    } else {
+                        "0.0.0.0:0".parse().unwrap()
+                    };
+                    let socket = UdpSocket::bind(bind_addr).await.map_err(|e| e.to_string())?;
+                    socket.connect(target).await.map_err(|e| e.to_string())?;
+                    self.udp = Some(socket);
+                }
+
+                let socket = self.udp.as_ref().expect("socket was just connected");
+                if let Err(e) = socket.send(message.as_bytes()).await {
+                    self.udp = None;
+                    return Err(e.to_string());
+                }
+            }
+            SyslogProtocol::Tcp => {
+                if self.tcp.is_none() {
+                    let stream = TcpStream::connect(&self.address)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    self.tcp = Some(stream);
+                }
+
+                // RFC 6587 octet-counting framing
+                let frame = format!("{} {}", message.len(), message);
+                let stream = self.tcp.as_mut().expect("stream was just connected");
+                if let Err(e) = stream.write_all(frame.as_bytes()).await {
+                    // Reconnect on the next attempt
+                    self.tcp = None;
+                    return Err(e.to_string());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn resolve(address: &str) -> Result<SocketAddr, String> {
+    tokio::net::lookup_host(address)
+        .await
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("{} did not resolve", address))
+}
+
+fn syslog_severity(risk_level: i32) -> u8 {
+    match risk_level {
+        9..=10 => 2, // critical
+        7..=8 => 3,  // error
+        4..=6 => 4,  // warning
+        _ => 6,      // informational
+    }
+}
+
+fn rfc5424_header(event: &SecurityEvent, hostname: &str) -> String {
+    let priority = SYSLOG_FACILITY_AUTHPRIV * 8 + syslog_severity(event.risk_level);
+    let msg_id: String = event
+        .event_type
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(32)
+        .collect();
+
+    format!(
+        "<{}>1 {} {} {} {} {}",
+        priority,
+        event.created_at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        hostname,
+        APP_NAME,
+        std::process::id(),
+        if msg_id.is_empty() { "-".to_string() } else { msg_id }
+    )
+}
+
+fn rfc5424_message(event: &SecurityEvent, hostname: &str) -> String {
+    let mut structured_data = format!(
+        "[{} eventId=\"{}\" risk=\"{}\"",
+        SD_ID, event.id, event.risk_level
+    );
+    if let Some(user_id) = event.user_id {
+        structured_data.push_str(&format!(" userId=\"{}\"", user_id));
+    }
+    if let Some(ip_address) = event.ip_address {
+        structured_data.push_str(&format!(" ip=\"{}\"", ip_address));
+    }
+    if let Some(user_agent) = &event.user_agent {
+        structured_data.push_str(&format!(" userAgent=\"{}\"", escape_sd_value(user_agent)));
+    }
+    structured_data.push(']');
+
+    format!(
+        "{} {} {}",
+        rfc5424_header(event, hostname),
+        structured_data,
+        serde_json::to_string(event).unwrap_or_default()
+    )
+}
+
+fn escape_sd_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+/// ArcSight Common Event Format; CEF severity runs 0-10 like our risk levels.
+fn cef_message(event: &SecurityEvent) -> String {
+    let mut extensions = vec![
+        format!("rt={}", event.created_at.timestamp_millis()),
+        format!("externalId={}", event.id),
+    ];
+    if let Some(user_id) = event.user_id {
+        extensions.push(format!("suid={}", user_id));
+    }
+    match event.ip_address {
+        Some(IpAddr::V4(ip)) => extensions.push(format!("src={}", ip)),
+        Some(IpAddr::V6(ip)) => {
+            extensions.push(format!("c6a2={}", ip));
+            extensions.push("c6a2Label=sourceIPv6Address".to_string());
+        }
+        None => {}
+    }
+    if let Some(user_agent) = &event.user_agent {
+        extensions.push(format!(
+            "requestClientApplication={}",
+            escape_cef_extension(user_agent)
+        ));
+    }
+    if let Some(details) = &event.details {
+        extensions.push("cs1Label=details".to_string());
+        extensions.push(format!("cs1={}", escape_cef_extension(&details.to_string())));
+    }
+
+    format!(
+        "CEF:0|The Circle|{}|{}|{}|{}|{}|{}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        escape_cef_header(&event.event_type),
+        escape_cef_header(&event.event_type.replace('_', " ")),
+        event.risk_level.clamp(0, 10),
+        extensions.join(" ")
+    )
+}
+
+fn escape_cef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+fn escape_cef_extension(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+struct FileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: u32,
+    file: Option<tokio::fs::File>,
+    written: u64,
+}
+
+impl FileSink {
+    async fn deliver(&mut self, event: &SecurityEvent) -> Result<(), String> {
+        let mut line = serde_json::to_string(event).map_err(|e| e.to_string())?;
+        line.push('\n');
+
+        if self.file.is_none() {
+            self.open().await?;
+        }
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate().await?;
+        }
+
+        let file = self.file.as_mut().expect("file was just opened");
+        if let Err(e) = file.write_all(line.as_bytes()).await {
+            self.file = None;
+            return Err(e.to_string());
+        }
+        file.flush().await.map_err(|e| e.to_string())?;
+        self.written += line.len() as u64;
+
+        Ok(())
+    }
+
+    async fn open(&mut self) -> Result<(), String> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| e.to_string())?;
+        self.written = file.metadata().await.map(|m| m.len()).unwrap_or(0);
+        self.file = Some(file);
+        Ok(())
+    }
+
+    /// Shifts `events.ndjson.1` -> `.2` and so on, keeping at most `max_files` rotated files.
+    async fn rotate(&mut self) -> Result<(), String> {
+        self.file = None;
+
+        let rotated = |n: u32| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if self.max_files == 0 {
+            let _ = tokio::fs::remove_file(&self.path).await;
+        } else {
+            for n in (1..self.max_files).rev() {
+                let _ = tokio::fs::rename(rotated(n), rotated(n + 1)).await;
+            }
+            tokio::fs::rename(&self.path, rotated(1))
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        self.open().await
+    }
+}
+
+struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<hmac::Key>,
+}
+
+impl WebhookSink {
+    async fn deliver(&mut self, event: &SecurityEvent) -> Result<(), String> {
+        let body = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+
+        if let Some(key) = &self.secret {
+            request = request.header("X-Circle-Signature", webhook_signature(key, &body));
+        }
+
+        let response = request.body(body).send().await.map_err(|e| e.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("webhook responded with {}", response.status()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    fn event(risk_level: i32) -> SecurityEvent {
+        SecurityEvent {
+            id: Uuid::parse_str("6f1c2a44-0d0c-4f55-9a3e-0b7f4b1e2c3d").unwrap(),
+            user_id: Some(Uuid::parse_str("11111111-2222-4333-8444-555555555555").unwrap()),
+            event_type: "failed_login".to_string(),
+            ip_address: Some("203.0.113.7".parse().unwrap()),
+            user_agent: None,
+            details: None,
+            risk_level,
+            created_at: Utc.with_ymd_and_hms(2026, 3, 1, 12, 30, 5).unwrap(),
+        }
+    }
+
+    #[test]
+    fn risk_levels_map_to_syslog_severities() {
+        assert_eq!(syslog_severity(10), 2);
+        assert_eq!(syslog_severity(9), 2);
+        assert_eq!(syslog_severity(8), 3);
+        assert_eq!(syslog_severity(5), 4);
+        assert_eq!(syslog_severity(1), 6);
+        assert_eq!(syslog_severity(-1), 6);
+    }
+
+    #[test]
+    fn rfc5424_messages_carry_priority_header_and_structured_data() {
+        let message = rfc5424_message(&event(9), "circle-1");
+
+        // authpriv (10) * 8 + critical (2)
+        let header = format!(
+            "<82>1 2026-03-01T12:30:05.000Z circle-1 the-circle {} failed_login ",
+            std::process::id()
+        );
+        assert!(message.starts_with(&header), "{}", message);
+        assert!(message.contains(
+            "[circle@32473 eventId=\"6f1c2a44-0d0c-4f55-9a3e-0b7f4b1e2c3d\" risk=\"9\" \
+             userId=\"11111111-2222-4333-8444-555555555555\" ip=\"203.0.113.7\"] {"
+        ));
+        let json: serde_json::Value =
+            serde_json::from_str(&message[message.find("] ").unwrap() + 2..]).unwrap();
+        assert_eq!(json["event_type"], "failed_login");
+    }
+
+    #[test]
+    fn rfc5424_escapes_structured_data_and_cleans_the_msg_id() {
+        let mut event = event(3);
+        event.user_agent = Some(r#"Agent "x" [1] \ end"#.to_string());
+        event.event_type = "bad type\twith spaces".to_string();
+
+        let message = rfc5424_message(&event, "host");
+        assert!(message.contains(r#"userAgent="Agent \"x\" [1\] \\ end"]"#), "{}", message);
+        assert!(message.contains(" badtypewithspaces "));
+
+        event.event_type = " ".to_string();
+        assert!(rfc5424_header(&event, "host").ends_with(" -"));
+    }
+
+    #[test]
+    fn cef_messages_have_header_fields_and_extensions() {
+        let mut event = event(7);
+        event.details = Some(serde_json::json!({ "failed_attempts": 2 }));
+
+        assert_eq!(
+            cef_message(&event),
+            format!(
+                "CEF:0|The Circle|{}|{}|failed_login|failed login|7|rt=1772368205000 \
+                 externalId=6f1c2a44-0d0c-4f55-9a3e-0b7f4b1e2c3d \
+                 suid=11111111-2222-4333-8444-555555555555 src=203.0.113.7 \
+                 cs1Label=details cs1={{\"failed_attempts\":2}}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[test]
+    fn cef_escapes_headers_and_extensions_and_labels_ipv6() {
+        let mut event = event(12);
+        event.event_type = "odd|type\\".to_string();
+        event.ip_address = Some("2001:db8::1".parse().unwrap());
+        event.user_agent = Some("a=b\\c\r\nd".to_string());
+
+        let message = cef_message(&event);
+        assert!(message.contains(r"|odd\|type\\|odd\|type\\|10|"), "{}", message);
+        assert!(message.contains(" c6a2=2001:db8::1 c6a2Label=sourceIPv6Address "));
+        assert!(message.contains(r" requestClientApplication=a\=b\\c\r\nd"));
+        assert!(!message.contains(" src="));
+    }
+}
@@ -0,0 +1,980 @@
+use crate::models::User;
+use crate::utils::{begin_scoped, hex_encode, DbScope};
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+// v1: shared data key (read only); v2: the member's own key
+const CIPHERTEXT_PREFIX: &str = "enc:v1:";
+const USER_CIPHERTEXT_PREFIX: &str = "enc:v2:";
+const KEY_LEN: usize = 32;
+
+const INDEX_KEY_PURPOSE: &str = "blind_index";
+
+// Bound into each ciphertext, with its member's id, so values cannot be swapped between
+// columns or rows
+pub const EMAIL_FIELD: &str = "users.email";
+pub const PENDING_EMAIL_FIELD: &str = "users.pending_email";
+pub const MFA_SECRET_FIELD: &str = "users.mfa_secret";
+pub const DESTRUCTION_KEY_FIELD: &str = "users.destruction_key";
+pub const BIOMETRIC_HASH_FIELD: &str = "users.biometric_hash";
+pub const WEBHOOK_SECRET_FIELD: &str = "notification_preferences.webhook_secret";
+pub const CONVERSATION_TITLE_FIELD: &str = "conversations.title";
+pub const FILE_NAME_FIELD: &str = "files.name";
+pub const FILE_CONTENT_FIELD: &str = "files.content";
+
+#[derive(Debug)]
+pub enum FieldEncryptionError {
+    DatabaseError(sqlx::Error),
+    Keyring(String),
+    UnknownKey(String),
+    Crypto,
+}
+
+impl std::fmt::Display for FieldEncryptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldEncryptionError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            FieldEncryptionError::Keyring(message) => write!(f, "Keyring error: {}", message),
+            FieldEncryptionError::UnknownKey(id) => write!(f, "Unknown key: {}", id),
+            FieldEncryptionError::Crypto => write!(f, "Encryption or decryption failed"),
+        }
+    }
+}
+
+impl std::error::Error for FieldEncryptionError {}
+
+impl From<sqlx::Error> for FieldEncryptionError {
+    fn from(err: sqlx::Error) -> Self {
+        FieldEncryptionError::DatabaseError(err)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyringFile {
+    active_key_id: String,
+    keys: HashMap<String, String>,
+}
+
+/// Local stand-in for a KMS: named master keys in a file, used only to wrap and unwrap data
+/// keys. `wrap`/`unwrap` take the data key id as encryption context, like KMS Encrypt/Decrypt.
+///
+/// Retired master keys stay in the file until `rotate-keys` has re-wrapped every data key.
+pub struct MasterKeyring {
+    path: PathBuf,
+    active_key_id: String,
+    keys: HashMap<String, [u8; KEY_LEN]>,
+}
+
+impl MasterKeyring {
+    /// Loads the keyring, creating it with a fresh master key on first start.
+    pub fn load_or_create(path: &str) -> Result<Self, FieldEncryptionError> {
+        let path = PathBuf::from(path);
+
+        if !path.exists() {
+            let mut keyring = MasterKeyring {
+                path,
+                active_key_id: String::new(),
+                keys: HashMap::new(),
+            };
+            let key_id = keyring.add_key()?;
+            tracing::warn!(
+                "Created master keyring {} with key {}; back it up, encrypted fields are unreadable without it",
+                keyring.path.display(),
+                key_id
+            );
+            return Ok(keyring);
+        }
+
+        let raw = std::fs::read_to_string(&path)
+            .map_err(|e| FieldEncryptionError::Keyring(format!("{}: {}", path.display(), e)))?;
+        let file: KeyringFile = serde_json::from_str(&raw)
+            .map_err(|e| FieldEncryptionError::Keyring(format!("{}: {}", path.display(), e)))?;
+
+        let mut keys = HashMap::new();
+        for (id, encoded) in file.keys {
+            let bytes = BASE64_ENGINE
+                .decode(encoded.trim())
+                .ok()
+                .and_then(|bytes| <[u8; KEY_LEN]>::try_from(bytes).ok())
+                .ok_or_else(|| {
+                    FieldEncryptionError::Keyring(format!("master key {} is not 32 base64 bytes", id))
+                })?;
+            keys.insert(id, bytes);
+        }
+
+        if !keys.contains_key(&file.active_key_id) {
+            return Err(FieldEncryptionError::UnknownKey(file.active_key_id));
+        }
+
+        Ok(MasterKeyring {
+            path,
+            active_key_id: file.active_key_id,
+            keys,
+        })
+    }
+
+    pub fn active_key_id(&self) -> &str {
+        &self.active_key_id
+    }
+
+    /// Generates a master key, makes it the active one and saves the keyring.
+    pub fn add_key(&mut self) -> Result<String, FieldEncryptionError> {
+        let key_id = format!("mk-{}", Uuid::new_v4().simple());
+        self.keys.insert(key_id.clone(), random_key()?);
+        self.active_key_id = key_id.clone();
+        self.save()?;
+        Ok(key_id)
+    }
+
+    fn save(&self) -> Result<(), FieldEncryptionError> {
+        let file = KeyringFile {
+            active_key_id: self.active_key_id.clone(),
+            keys: self
+                .keys
+                .iter()
+                .map(|(id, key)| (id.clone(), BASE64_ENGINE.encode(key)))
+                .collect(),
+        };
+        let json = serde_json::to_string_pretty(&file)
+            .map_err(|e| FieldEncryptionError::Keyring(e.to_string()))?;
+
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| FieldEncryptionError::Keyring(format!("{}: {}", dir.display(), e)))?;
+        }
+        write_private(&self.path, json.as_bytes())
+            .map_err(|e| FieldEncryptionError::Keyring(format!("{}: {}", self.path.display(), e)))
+    }
+
+    fn wrap(&self, data_key_id: Uuid, data_key: &[u8]) -> Result<(String, Vec<u8>), FieldEncryptionError> {
+        let master = aead_key(&self.keys[&self.active_key_id])?;
+        let wrapped = seal(&master, data_key_id.as_bytes(), data_key)?;
+        Ok((self.active_key_id.clone(), wrapped))
+    }
+
+    fn unwrap(
+        &self,
+        master_key_id: &str,
+        data_key_id: Uuid,
+        wrapped: &[u8],
+    ) -> Result<Vec<u8>, FieldEncryptionError> {
+        let master_key = self
+            .keys
+            .get(master_key_id)
+            .ok_or_else(|| FieldEncryptionError::UnknownKey(master_key_id.to_string()))?;
+        open(&aead_key(master_key)?, data_key_id.as_bytes(), wrapped)
+    }
+}
+
+/// Encrypts and decrypts sensitive user columns and computes the email blind index.
+///
+/// Each member's fields are encrypted under their own key, held in a key store outside the
+/// database (`UserKeyStore`). Destroying a member deletes that key, which leaves every copy of
+/// their ciphertext, in WAL, replicas and backups, unreadable without rewriting any of them.
+#[derive(Clone)]
+pub struct FieldCipher {
+    inner: Arc<CipherKeys>,
+}
+
+struct CipherKeys {
+    keyring: MasterKeyring,
+    user_keys: UserKeyStore,
+    // Shared data keys from before per-member keys; only used to read old values
+    legacy_keys: HashMap<Uuid, LessSafeKey>,
+    index_key: hmac::Key,
+}
+
+impl std::fmt::Debug for FieldCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FieldCipher")
+            .field("master_key_id", &self.inner.keyring.active_key_id)
+            .field("user_key_dir", &self.inner.user_keys.dir)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FieldCipher {
+    /// Unwraps the blind index key and any legacy data keys, creating the index key if needed.
+    pub async fn load(
+        db: &PgPool,
+        keyring: MasterKeyring,
+        user_key_dir: &str,
+    ) -> Result<Self, FieldEncryptionError> {
+        ensure_active_key(db, &keyring, INDEX_KEY_PURPOSE).await?;
+
+        let rows = sqlx::query!("SELECT id, purpose, wrapped_key, master_key_id, active FROM data_keys")
+            .fetch_all(db)
+            .await?;
+
+        let mut index_key = None;
+        let mut legacy_keys = HashMap::new();
+
+        for row in rows {
+            let key = keyring.unwrap(&row.master_key_id, row.id, &row.wrapped_key)?;
+            match row.purpose.as_str() {
+                INDEX_KEY_PURPOSE => {
+                    if row.active {
+                        index_key = Some(hmac::Key::new(hmac::HMAC_SHA256, &key));
+                    }
+                }
+                _ => {
+                    legacy_keys.insert(row.id, aead_key(&key)?);
+                }
+            }
+        }
+
+        Ok(FieldCipher {
+            inner: Arc::new(CipherKeys {
+                keyring,
+                user_keys: UserKeyStore::new(user_key_dir)?,
+                legacy_keys,
+                index_key: index_key
+                    .ok_or_else(|| FieldEncryptionError::UnknownKey(INDEX_KEY_PURPOSE.to_string()))?,
+            }),
+        })
+    }
+
+    /// Generates the member's key; call before writing any of their encrypted fields.
+    pub fn create_user_key(&self, user_id: Uuid) -> Result<(), FieldEncryptionError> {
+        self.inner.user_keys.create(&self.inner.keyring, user_id)
+    }
+
+    /// Crypto-shreds a member: deletes their key from the key store and from memory. Takes the
+    /// key store lock on `conn`, so call it inside the transaction that removes the member.
+    /// Returns whether a key existed.
+    pub async fn shred_user_key(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Uuid,
+    ) -> Result<bool, FieldEncryptionError> {
+        sqlx::query!("SELECT pg_advisory_xact_lock($1)", USER_KEY_STORE_LOCK)
+            .fetch_one(conn)
+            .await?;
+
+        self.inner.user_keys.shred(user_id)
+    }
+
+    pub fn encrypt(&self, user_id: Uuid, field: &str, plaintext: &str) -> Result<String, FieldEncryptionError> {
+        let key = self.inner.user_keys.get(&self.inner.keyring, user_id)?;
+        let sealed = seal(&key, &field_aad(field, user_id), plaintext.as_bytes())?;
+
+        Ok(format!(
+            "{}{}:{}",
+            USER_CIPHERTEXT_PREFIX,
+            user_id,
+            BASE64_ENGINE.encode(sealed)
+        ))
+    }
+
+    pub fn encrypt_opt(
+        &self,
+        user_id: Uuid,
+        field: &str,
+        plaintext: Option<&str>,
+    ) -> Result<Option<String>, FieldEncryptionError> {
+        plaintext
+            .map(|value| self.encrypt(user_id, field, value))
+            .transpose()
+    }
+
+    /// Decrypts `user_id`'s value of `field`; a value sealed for another member or column is
+    /// rejected. Values written before encryption was enabled are returned unchanged. Values
+    /// under a shared data key are only read by `encrypt_existing`, which moves them all on
+    /// start, since they were sealed before the member's id was bound in.
+    pub fn decrypt(&self, user_id: Uuid, field: &str, value: &str) -> Result<String, FieldEncryptionError> {
+        if value.starts_with(CIPHERTEXT_PREFIX) {
+            return Err(FieldEncryptionError::Crypto);
+        }
+        self.decrypt_existing(user_id, field, value)
+    }
+
+    pub fn decrypt_opt(
+        &self,
+        user_id: Uuid,
+        field: &str,
+        value: Option<String>,
+    ) -> Result<Option<String>, FieldEncryptionError> {
+        value.map(|value| self.decrypt(user_id, field, &value)).transpose()
+    }
+
+    // Also reads values under a legacy shared data key, for `encrypt_existing` only
+    fn decrypt_existing(&self, user_id: Uuid, field: &str, value: &str) -> Result<String, FieldEncryptionError> {
+        let (rest, per_user) = if let Some(rest) = value.strip_prefix(USER_CIPHERTEXT_PREFIX) {
+            (rest, true)
+        } else if let Some(rest) = value.strip_prefix(CIPHERTEXT_PREFIX) {
+            (rest, false)
+        } else {
+            return Ok(value.to_string());
+        };
+
+        let (key_id, encoded) = rest.split_once(':').ok_or(FieldEncryptionError::Crypto)?;
+        let key_id = Uuid::parse_str(key_id).map_err(|_| FieldEncryptionError::Crypto)?;
+        let sealed = BASE64_ENGINE
+            .decode(encoded)
+            .map_err(|_| FieldEncryptionError::Crypto)?;
+
+        let plaintext = if per_user {
+            if key_id != user_id {
+                return Err(FieldEncryptionError::Crypto);
+            }
+            let key = self.inner.user_keys.get(&self.inner.keyring, key_id)?;
+            // Values sealed before the id was bound in are still tied to the member by their key
+            open(&key, &field_aad(field, user_id), &sealed)
+                .or_else(|_| open(&key, field.as_bytes(), &sealed))?
+        } else {
+            let key = self
+                .inner
+                .legacy_keys
+                .get(&key_id)
+                .ok_or_else(|| FieldEncryptionError::UnknownKey(key_id.to_string()))?;
+            open(key, field.as_bytes(), &sealed)?
+        };
+
+        String::from_utf8(plaintext).map_err(|_| FieldEncryptionError::Crypto)
+    }
+
+    fn decrypt_existing_opt(
+        &self,
+        user_id: Uuid,
+        field: &str,
+        value: Option<String>,
+    ) -> Result<Option<String>, FieldEncryptionError> {
+        value
+            .map(|value| self.decrypt_existing(user_id, field, &value))
+            .transpose()
+    }
+
+    /// True when the value can only be read with its member's key.
+    pub fn is_shreddable(value: &str) -> bool {
+        value.starts_with(USER_CIPHERTEXT_PREFIX)
+    }
+
+    /// Keyed hash of the normalized address, used for lookups and uniqueness.
+    pub fn email_index(&self, email: &str) -> String {
+        let normalized = email.trim().to_lowercase();
+        hex_encode(hmac::sign(&self.inner.index_key, normalized.as_bytes()).as_ref())
+    }
+
+    /// Decrypts the sensitive columns of a row read with `SELECT * FROM users`.
+    pub fn open_user(&self, mut user: User) -> Result<User, FieldEncryptionError> {
+        user.email = self.decrypt(user.id, EMAIL_FIELD, &user.email)?;
+        user.pending_email = self.decrypt_opt(user.id, PENDING_EMAIL_FIELD, user.pending_email)?;
+        user.mfa_secret = self.decrypt_opt(user.id, MFA_SECRET_FIELD, user.mfa_secret)?;
+        user.destruction_key = self.decrypt_opt(user.id, DESTRUCTION_KEY_FIELD, user.destruction_key)?;
+        user.biometric_hash = self.decrypt_opt(user.id, BIOMETRIC_HASH_FIELD, user.biometric_hash)?;
+        Ok(user)
+    }
+
+    /// Moves rows written in plaintext or under a shared data key onto their member's own
+    /// key, indexes their email and encrypts plaintext webhook secrets; returns how many rows
+    /// changed.
+    pub async fn encrypt_existing(&self, db: &PgPool) -> Result<u64, FieldEncryptionError> {
+        let mut tx = begin_scoped(db, DbScope::System).await?;
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, email, pending_email, mfa_secret, destruction_key, biometric_hash
+            FROM users WHERE email NOT LIKE 'enc:v2:%'
+            "#
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut encrypted = 0;
+        for row in rows {
+            let email = self.decrypt_existing(row.id, EMAIL_FIELD, &row.email)?;
+            let pending_email = self.decrypt_existing_opt(row.id, PENDING_EMAIL_FIELD, row.pending_email)?;
+            let mfa_secret = self.decrypt_existing_opt(row.id, MFA_SECRET_FIELD, row.mfa_secret)?;
+            let destruction_key =
+                self.decrypt_existing_opt(row.id, DESTRUCTION_KEY_FIELD, row.destruction_key)?;
+            let biometric_hash =
+                self.decrypt_existing_opt(row.id, BIOMETRIC_HASH_FIELD, row.biometric_hash)?;
+
+            if !self.inner.user_keys.exists(row.id) {
+                self.create_user_key(row.id)?;
+            }
+
+            sqlx::query!(
+                r#"
+                UPDATE users
+                SET email = $2, email_index = $3, pending_email = $4, mfa_secret = $5,
+                    destruction_key = $6, biometric_hash = $7
+                WHERE id = $1
+                "#,
+                row.id,
+                self.encrypt(row.id, EMAIL_FIELD, &email)?,
+                self.email_index(&email),
+                self.encrypt_opt(row.id, PENDING_EMAIL_FIELD, pending_email.as_deref())?,
+                self.encrypt_opt(row.id, MFA_SECRET_FIELD, mfa_secret.as_deref())?,
+                self.encrypt_opt(row.id, DESTRUCTION_KEY_FIELD, destruction_key.as_deref())?,
+                self.encrypt_opt(row.id, BIOMETRIC_HASH_FIELD, biometric_hash.as_deref())?
+            )
+            .execute(&mut *tx)
+            .await?;
+            encrypted += 1;
+        }
+
+        let secrets = sqlx::query!(
+            r#"
+            SELECT user_id, webhook_secret AS "webhook_secret!"
+            FROM notification_preferences
+            WHERE webhook_secret IS NOT NULL AND webhook_secret NOT LIKE 'enc:v2:%'
+            "#
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for row in secrets {
+            let secret = self.encrypt(row.user_id, WEBHOOK_SECRET_FIELD, &row.webhook_secret)?;
+            sqlx::query!(
+                "UPDATE notification_preferences SET webhook_secret = $2 WHERE user_id = $1",
+                row.user_id,
+                secret
+            )
+            .execute(&mut *tx)
+            .await?;
+            encrypted += 1;
+        }
+        tx.commit().await?;
+
+        Ok(encrypted)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct UserKeyFile {
+    master_key_id: String,
+    wrapped_key: String,
+}
+
+/// Per-member keys, one file each, wrapped by the master keyring. Kept out of the database on
+/// purpose: a database backup must never contain the keys that decrypt it.
+struct UserKeyStore {
+    dir: PathBuf,
+    cache: RwLock<HashMap<Uuid, Arc<LessSafeKey>>>,
+}
+
+impl UserKeyStore {
+    fn new(dir: &str) -> Result<Self, FieldEncryptionError> {
+        let dir = PathBuf::from(dir);
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| FieldEncryptionError::Keyring(format!("{}: {}", dir.display(), e)))?;
+
+        Ok(UserKeyStore {
+            dir,
+            cache: RwLock::new(HashMap::new()),
+        })
+    }
+
+    fn path(&self, user_id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.key", user_id))
+    }
+
+    fn exists(&self, user_id: Uuid) -> bool {
+        self.path(user_id).exists()
+    }
+
+    fn create(&self, keyring: &MasterKeyring, user_id: Uuid) -> Result<(), FieldEncryptionError> {
+        let key = random_key()?;
+        write_user_key(&self.path(user_id), keyring, user_id, &key)?;
+        self.cache
+            .write()
+            .unwrap()
+            .insert(user_id, Arc::new(aead_key(&key)?));
+        Ok(())
+    }
+
+    fn get(&self, keyring: &MasterKeyring, user_id: Uuid) -> Result<Arc<LessSafeKey>, FieldEncryptionError> {
+        if let Some(key) = self.cache.read().unwrap().get(&user_id) {
+            return Ok(key.clone());
+        }
+
+        let key = Arc::new(aead_key(&read_user_key(&self.path(user_id), keyring, user_id)?)?);
+        self.cache.write().unwrap().insert(user_id, key.clone());
+        Ok(key)
+    }
+
+    fn shred(&self, user_id: Uuid) -> Result<bool, FieldEncryptionError> {
+        self.cache.write().unwrap().remove(&user_id);
+
+        let path = self.path(user_id);
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => {
+                return Err(FieldEncryptionError::Keyring(format!("{}: {}", path.display(), e)))
+            }
+        }
+
+        // Make the unlink durable before anyone is told the data is gone
+        if let Ok(dir) = std::fs::File::open(&self.dir) {
+            let _ = dir.sync_all();
+        }
+
+        Ok(true)
+    }
+}
+
+fn read_user_key(path: &Path, keyring: &MasterKeyring, user_id: Uuid) -> Result<Vec<u8>, FieldEncryptionError> {
+    let raw = match std::fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(FieldEncryptionError::UnknownKey(user_id.to_string()))
+        }
+        Err(e) => return Err(FieldEncryptionError::Keyring(format!("{}: {}", path.display(), e))),
+    };
+    let file: UserKeyFile = serde_json::from_str(&raw)
+        .map_err(|e| FieldEncryptionError::Keyring(format!("{}: {}", path.display(), e)))?;
+    let wrapped = BASE64_ENGINE
+        .decode(file.wrapped_key)
+        .map_err(|_| FieldEncryptionError::Crypto)?;
+
+    keyring.unwrap(&file.master_key_id, user_id, &wrapped)
+}
+
+fn write_user_key(
+    path: &Path,
+    keyring: &MasterKeyring,
+    user_id: Uuid,
+    key: &[u8],
+) -> Result<(), FieldEncryptionError> {
+    let (master_key_id, wrapped) = keyring.wrap(user_id, key)?;
+    let json = serde_json::to_string(&UserKeyFile {
+        master_key_id,
+        wrapped_key: BASE64_ENGINE.encode(wrapped),
+    })
+    .map_err(|e| FieldEncryptionError::Keyring(e.to_string()))?;
+
+    write_private(path, json.as_bytes())
+        .map_err(|e| FieldEncryptionError::Keyring(format!("{}: {}", path.display(), e)))
+}
+
+/// Advisory lock held while member keys are rewritten or shredded, so a rotation can never
+/// write back a key that was shredded underneath it.
+pub const USER_KEY_STORE_LOCK: i64 = 0x6b65_7973;
+
+/// Re-wraps every data key and member key under the keyring's active master key, optionally
+/// generating a new master key first. Field ciphertexts do not change. Returns how many keys
+/// were re-wrapped.
+pub async fn rotate_master_key(
+    db: &PgPool,
+    keyring: &mut MasterKeyring,
+    user_key_dir: &str,
+    new_master_key: bool,
+) -> Result<u64, FieldEncryptionError> {
+    if new_master_key {
+        let key_id = keyring.add_key()?;
+        tracing::info!("Generated master key {}", key_id);
+    }
+
+    let mut tx = begin_scoped(db, DbScope::System).await?;
+    sqlx::query!("SELECT pg_advisory_xact_lock($1)", USER_KEY_STORE_LOCK)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    let rows = sqlx::query!(
+        "SELECT id, wrapped_key, master_key_id FROM data_keys WHERE master_key_id <> $1 FOR UPDATE",
+        keyring.active_key_id()
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut rotated = 0;
+    for row in rows {
+        let data_key = keyring.unwrap(&row.master_key_id, row.id, &row.wrapped_key)?;
+        let (master_key_id, wrapped_key) = keyring.wrap(row.id, &data_key)?;
+
+        sqlx::query!(
+            r#"
+            UPDATE data_keys SET wrapped_key = $2, master_key_id = $3, rotated_at = NOW()
+            WHERE id = $1
+            "#,
+            row.id,
+            wrapped_key,
+            master_key_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        rotated += 1;
+    }
+
+    let entries = std::fs::read_dir(user_key_dir)
+        .map_err(|e| FieldEncryptionError::Keyring(format!("{}: {}", user_key_dir, e)))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(user_id) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".key"))
+            .and_then(|id| Uuid::parse_str(id).ok())
+        else {
+            continue;
+        };
+
+        let raw = std::fs::read_to_string(&path)
+            .map_err(|e| FieldEncryptionError::Keyring(format!("{}: {}", path.display(), e)))?;
+        let file: UserKeyFile = serde_json::from_str(&raw)
+            .map_err(|e| FieldEncryptionError::Keyring(format!("{}: {}", path.display(), e)))?;
+        if file.master_key_id == keyring.active_key_id() {
+            continue;
+        }
+
+        let user_key = read_user_key(&path, keyring, user_id)?;
+        write_user_key(&path, keyring, user_id, &user_key)?;
+        rotated += 1;
+    }
+
+    tx.commit().await?;
+
+    Ok(rotated)
+}
+
+async fn ensure_active_key(
+    db: &PgPool,
+    keyring: &MasterKeyring,
+    purpose: &str,
+) -> Result<(), FieldEncryptionError> {
+    let exists = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM data_keys WHERE purpose = $1 AND active)",
+        purpose
+    )
+    .fetch_one(db)
+    .await?;
+
+    if exists.unwrap_or(false) {
+        return Ok(());
+    }
+
+    let id = Uuid::new_v4();
+    let (master_key_id, wrapped_key) = keyring.wrap(id, &random_key()?)?;
+
+    // Another instance starting at the same time may win; its key is used instead
+    sqlx::query!(
+        r#"
+        INSERT INTO data_keys (id, purpose, wrapped_key, master_key_id)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (purpose) WHERE active DO NOTHING
+        "#,
+        id,
+        purpose,
+        wrapped_key,
+        master_key_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+fn random_key() -> Result<[u8; KEY_LEN], FieldEncryptionError> {
+    let mut key = [0u8; KEY_LEN];
+    SystemRandom::new()
+        .fill(&mut key)
+        .map_err(|_| FieldEncryptionError::Crypto)?;
+    Ok(key)
+}
+
+fn aead_key(bytes: &[u8]) -> Result<LessSafeKey, FieldEncryptionError> {
+    let key = UnboundKey::new(&AES_256_GCM, bytes).map_err(|_| FieldEncryptionError::Crypto)?;
+    Ok(LessSafeKey::new(key))
+}
+
+/// Additional data for a member's field: the column name and the member's id.
+fn field_aad(field: &str, user_id: Uuid) -> Vec<u8> {
+    let mut aad = field.as_bytes().to_vec();
+    aad.push(0);
+    aad.extend_from_slice(user_id.as_bytes());
+    aad
+}
+
+/// AES-256-GCM with a random nonce; output is nonce || ciphertext || tag.
+fn seal(key: &LessSafeKey, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, FieldEncryptionError> {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| FieldEncryptionError::Crypto)?;
+
+    let mut in_out = plaintext.to_vec();
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut in_out)
+        .map_err(|_| FieldEncryptionError::Crypto)?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&in_out);
+    Ok(sealed)
+}
+
+fn open(key: &LessSafeKey, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, FieldEncryptionError> {
+    if sealed.len() < NONCE_LEN {
+        return Err(FieldEncryptionError::Crypto);
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| FieldEncryptionError::Crypto)?;
+    let mut in_out = ciphertext.to_vec();
+    let plaintext = key
+        .open_in_place(nonce, Aad::from(aad), &mut in_out)
+        .map_err(|_| FieldEncryptionError::Crypto)?;
+
+    Ok(plaintext.to_vec())
+}
+
+// Written beside the keyring and renamed over it, so a crash never leaves a torn keyring
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let staging = path.with_extension("tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(&staging)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&staging, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch key store under the system temp dir, removed when dropped.
+    struct TempKeys {
+        dir: PathBuf,
+    }
+
+    impl TempKeys {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("circle-keys-{}", Uuid::new_v4().simple()));
+            std::fs::create_dir_all(&dir).unwrap();
+            TempKeys { dir }
+        }
+
+        // A fresh cipher over the same files, as after a restart
+        fn cipher(&self) -> FieldCipher {
+            self.cipher_with(HashMap::new())
+        }
+
+        fn cipher_with(&self, legacy_keys: HashMap<Uuid, LessSafeKey>) -> FieldCipher {
+            let keyring = MasterKeyring::load_or_create(self.dir.join("keyring.json").to_str().unwrap()).unwrap();
+            FieldCipher {
+                inner: Arc::new(CipherKeys {
+                    keyring,
+                    user_keys: UserKeyStore::new(self.dir.join("users").to_str().unwrap()).unwrap(),
+                    legacy_keys,
+                    index_key: hmac::Key::new(hmac::HMAC_SHA256, &random_key().unwrap()),
+                }),
+            }
+        }
+    }
+
+    impl Drop for TempKeys {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    // A cipher with one member whose key exists
+    fn member(keys: &TempKeys) -> (FieldCipher, Uuid) {
+        let cipher = keys.cipher();
+        let user_id = Uuid::new_v4();
+        cipher.create_user_key(user_id).unwrap();
+        (cipher, user_id)
+    }
+
+    #[test]
+    fn fields_round_trip_under_the_members_key() {
+        let keys = TempKeys::new();
+        let (cipher, user_id) = member(&keys);
+
+        let sealed = cipher.encrypt(user_id, EMAIL_FIELD, "member@example.com").unwrap();
+        assert!(sealed.starts_with(&format!("{}{}:", USER_CIPHERTEXT_PREFIX, user_id)));
+        assert!(!sealed.contains("member@example.com"));
+        assert_eq!(cipher.decrypt(user_id, EMAIL_FIELD, &sealed).unwrap(), "member@example.com");
+
+        // Nonces are random, so equal values do not give equal ciphertexts
+        assert_ne!(cipher.encrypt(user_id, EMAIL_FIELD, "member@example.com").unwrap(), sealed);
+        assert_eq!(
+            cipher.decrypt_opt(user_id, EMAIL_FIELD, Some(sealed)).unwrap().as_deref(),
+            Some("member@example.com")
+        );
+        assert_eq!(cipher.decrypt_opt(user_id, EMAIL_FIELD, None).unwrap(), None);
+    }
+
+    #[test]
+    fn another_members_key_cannot_decrypt() {
+        let keys = TempKeys::new();
+        let (cipher, owner) = member(&keys);
+        let other = Uuid::new_v4();
+        cipher.create_user_key(other).unwrap();
+        let sealed = cipher.encrypt(owner, MFA_SECRET_FIELD, "JBSWY3DPEHPK3PXP").unwrap();
+
+        // Copied into another member's row as is
+        assert!(cipher.decrypt(other, MFA_SECRET_FIELD, &sealed).is_err());
+
+        // Or relabelled as theirs, so their key is used
+        let relabelled = sealed.replace(&owner.to_string(), &other.to_string());
+        assert!(cipher.decrypt(other, MFA_SECRET_FIELD, &relabelled).is_err());
+    }
+
+    #[test]
+    fn values_cannot_move_between_fields() {
+        let keys = TempKeys::new();
+        let (cipher, user_id) = member(&keys);
+        let sealed = cipher.encrypt(user_id, PENDING_EMAIL_FIELD, "new@example.com").unwrap();
+
+        assert!(cipher.decrypt(user_id, EMAIL_FIELD, &sealed).is_err());
+        assert_eq!(
+            cipher.decrypt(user_id, PENDING_EMAIL_FIELD, &sealed).unwrap(),
+            "new@example.com"
+        );
+    }
+
+    #[test]
+    fn tampered_ciphertexts_are_rejected() {
+        let keys = TempKeys::new();
+        let (cipher, user_id) = member(&keys);
+        let sealed = cipher.encrypt(user_id, EMAIL_FIELD, "member@example.com").unwrap();
+        let (prefix, encoded) = sealed.rsplit_once(':').unwrap();
+
+        let mut bytes = BASE64_ENGINE.decode(encoded).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let flipped = format!("{}:{}", prefix, BASE64_ENGINE.encode(&bytes));
+        assert!(cipher.decrypt(user_id, EMAIL_FIELD, &flipped).is_err());
+
+        let truncated = format!("{}:{}", prefix, BASE64_ENGINE.encode(&bytes[..NONCE_LEN - 1]));
+        assert!(cipher.decrypt(user_id, EMAIL_FIELD, &truncated).is_err());
+        assert!(cipher.decrypt(user_id, EMAIL_FIELD, &format!("{}:not base64!", prefix)).is_err());
+        assert!(cipher.decrypt(user_id, EMAIL_FIELD, "enc:v2:not-a-uuid:AAAA").is_err());
+    }
+
+    #[test]
+    fn values_sealed_before_the_member_id_was_bound_still_open() {
+        let keys = TempKeys::new();
+        let (cipher, user_id) = member(&keys);
+        let key = cipher.inner.user_keys.get(&cipher.inner.keyring, user_id).unwrap();
+        let sealed = format!(
+            "{}{}:{}",
+            USER_CIPHERTEXT_PREFIX,
+            user_id,
+            BASE64_ENGINE.encode(seal(&key, EMAIL_FIELD.as_bytes(), b"member@example.com").unwrap())
+        );
+
+        assert_eq!(cipher.decrypt(user_id, EMAIL_FIELD, &sealed).unwrap(), "member@example.com");
+        assert!(cipher.decrypt(user_id, PENDING_EMAIL_FIELD, &sealed).is_err());
+    }
+
+    #[test]
+    fn shared_key_values_are_only_read_to_move_them_to_the_members_key() {
+        let keys = TempKeys::new();
+        let legacy_id = Uuid::new_v4();
+        let legacy_key = random_key().unwrap();
+        let cipher = keys.cipher_with(HashMap::from([(legacy_id, aead_key(&legacy_key).unwrap())]));
+        let user_id = Uuid::new_v4();
+        let v1 = format!(
+            "{}{}:{}",
+            CIPHERTEXT_PREFIX,
+            legacy_id,
+            BASE64_ENGINE.encode(
+                seal(&aead_key(&legacy_key).unwrap(), EMAIL_FIELD.as_bytes(), b"member@example.com").unwrap()
+            )
+        );
+
+        // Not bound to a member, so it could have been copied from anyone's row
+        assert!(cipher.decrypt(user_id, EMAIL_FIELD, &v1).is_err());
+
+        // As `encrypt_existing` moves it
+        let email = cipher.decrypt_existing(user_id, EMAIL_FIELD, &v1).unwrap();
+        cipher.create_user_key(user_id).unwrap();
+        let v2 = cipher.encrypt(user_id, EMAIL_FIELD, &email).unwrap();
+        assert!(FieldCipher::is_shreddable(&v2));
+        assert_eq!(cipher.decrypt(user_id, EMAIL_FIELD, &v2).unwrap(), "member@example.com");
+        assert!(cipher.decrypt_existing(user_id, MFA_SECRET_FIELD, &v1).is_err());
+    }
+
+    #[test]
+    fn plaintext_passes_through() {
+        let keys = TempKeys::new();
+        let (cipher, user_id) = member(&keys);
+
+        assert_eq!(
+            cipher.decrypt(user_id, EMAIL_FIELD, "member@example.com").unwrap(),
+            "member@example.com"
+        );
+        assert!(!FieldCipher::is_shreddable("member@example.com"));
+    }
+
+    #[test]
+    fn email_index_ignores_case_and_surrounding_space() {
+        let keys = TempKeys::new();
+        let cipher = keys.cipher();
+
+        assert_eq!(
+            cipher.email_index(" Member@Example.com "),
+            cipher.email_index("member@example.com")
+        );
+        assert_ne!(
+            cipher.email_index("member@example.com"),
+            cipher.email_index("other@example.com")
+        );
+    }
+
+    #[test]
+    fn shredded_members_cannot_be_decrypted() {
+        let keys = TempKeys::new();
+        let cipher = keys.cipher();
+        let user_id = Uuid::new_v4();
+        cipher.create_user_key(user_id).unwrap();
+
+        let email = cipher.encrypt(user_id, EMAIL_FIELD, "member@example.com").unwrap();
+        assert!(FieldCipher::is_shreddable(&email));
+        assert_eq!(cipher.decrypt(user_id, EMAIL_FIELD, &email).unwrap(), "member@example.com");
+
+        assert!(cipher.inner.user_keys.shred(user_id).unwrap());
+        assert!(!cipher.inner.user_keys.path(user_id).exists());
+        assert!(matches!(
+            cipher.decrypt(user_id, EMAIL_FIELD, &email),
+            Err(FieldEncryptionError::UnknownKey(_))
+        ));
+        assert!(cipher.encrypt(user_id, EMAIL_FIELD, "member@example.com").is_err());
+    }
+
+    #[test]
+    fn removing_the_key_file_shreds_for_every_instance() {
+        let keys = TempKeys::new();
+        let cipher = keys.cipher();
+        let user_id = Uuid::new_v4();
+        cipher.create_user_key(user_id).unwrap();
+        let title = cipher.encrypt(user_id, CONVERSATION_TITLE_FIELD, "Plans").unwrap();
+
+        // Another instance that never cached the key, with the master keyring intact
+        let restarted = keys.cipher();
+        assert_eq!(restarted.decrypt(user_id, CONVERSATION_TITLE_FIELD, &title).unwrap(), "Plans");
+
+        std::fs::remove_file(cipher.inner.user_keys.path(user_id)).unwrap();
+        assert!(keys.cipher().decrypt(user_id, CONVERSATION_TITLE_FIELD, &title).is_err());
+    }
+
+    #[test]
+    fn shredding_one_member_leaves_the_others_readable() {
+        let keys = TempKeys::new();
+        let cipher = keys.cipher();
+        let (shredded, kept) = (Uuid::new_v4(), Uuid::new_v4());
+        cipher.create_user_key(shredded).unwrap();
+        cipher.create_user_key(kept).unwrap();
+        let value = cipher.encrypt(kept, MFA_SECRET_FIELD, "JBSWY3DPEHPK3PXP").unwrap();
+
+        assert!(cipher.inner.user_keys.shred(shredded).unwrap());
+        assert!(!cipher.inner.user_keys.shred(shredded).unwrap());
+        assert_eq!(keys.cipher().decrypt(kept, MFA_SECRET_FIELD, &value).unwrap(), "JBSWY3DPEHPK3PXP");
+    }
+}
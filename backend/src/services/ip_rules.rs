@@ -0,0 +1,540 @@
+use crate::config::{IpListAction, IpListConfig};
+use crate::models::{CreateIpRuleRequest, IpRule};
+use crate::utils::{begin_scoped, DbScope};
+use axum::http::HeaderMap;
+use ipnetwork::IpNetwork;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+const AUTO_DENY_DAYS: i32 = 30;
+
+// Picks up expired rules, edits from other instances and changed list files
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Network allow/deny rules, evaluated in memory on every request.
+///
+/// Platform-wide rules come from the `ip_rules` table and from reputation list files such as the
+/// Tor exit list. An `allow` rule exempts a network from every deny and elevated-risk rule; `deny`
+/// rejects the request; `elevated_risk` raises the risk level of security events from that
+/// network. Organization rules are allowlists: once an organization has one, all of its members
+/// may only sign in and use their sessions from the listed networks.
+///
+/// Requests are judged by the client address `client_ip` resolves, which only believes
+/// `X-Forwarded-For` from the configured trusted proxies.
+#[derive(Debug, Clone)]
+pub struct IpRuleService {
+    db: PgPool,
+    lists: Arc<Vec<IpListConfig>>,
+    trusted_proxies: Arc<Vec<IpNetwork>>,
+    rules: Arc<RwLock<IpRuleSet>>,
+    // Last good contents of each list file, kept if a later read fails
+    list_cache: Arc<Mutex<HashMap<String, Arc<Vec<IpNetwork>>>>>,
+    blocked: Arc<AtomicU64>,
+}
+
+#[derive(Debug)]
+pub enum IpRuleError {
+    DatabaseError(sqlx::Error),
+    InvalidNetwork,
+    InvalidAction,
+    OrganizationRuleMustAllow,
+    RuleNotFound,
+}
+
+impl std::fmt::Display for IpRuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IpRuleError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            IpRuleError::InvalidNetwork => write!(f, "network must be an IP address or CIDR block"),
+            IpRuleError::InvalidAction => write!(f, "action must be one of allow, deny, elevated_risk"),
+            IpRuleError::OrganizationRuleMustAllow => {
+                write!(f, "Organization rules can only be allow rules")
+            }
+            IpRuleError::RuleNotFound => write!(f, "IP rule not found"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for IpRuleError {
+    fn from(err: sqlx::Error) -> Self {
+        IpRuleError::DatabaseError(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpVerdict {
+    /// Matched a platform allow rule.
+    Trusted,
+    /// Matched a deny rule or list.
+    Blocked,
+    /// No allow or deny rule matched; elevated-risk rules may still add to the risk level.
+    Neutral { risk_adjustment: i32 },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IpListSummary {
+    pub name: String,
+    pub path: String,
+    pub entries: usize,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RuleAction {
+    Allow,
+    Deny,
+    ElevatedRisk,
+}
+
+impl RuleAction {
+    fn parse(action: &str) -> Option<Self> {
+        match action {
+            "allow" => Some(RuleAction::Allow),
+            "deny" => Some(RuleAction::Deny),
+            "elevated_risk" => Some(RuleAction::ElevatedRisk),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CompiledRule {
+    network: IpNetwork,
+    action: RuleAction,
+    risk_adjustment: i32,
+}
+
+#[derive(Debug, Default)]
+struct IpRuleSet {
+    // Single addresses (most list entries) are looked up directly
+    hosts: HashMap<IpAddr, Vec<CompiledRule>>,
+    networks: Vec<CompiledRule>,
+    organizations: HashMap<Uuid, Vec<IpNetwork>>,
+    lists: Vec<IpListSummary>,
+}
+
+impl IpRuleSet {
+    fn insert(&mut self, rule: CompiledRule) {
+        if rule.network.prefix() == max_prefix(rule.network) {
+            self.hosts.entry(rule.network.ip()).or_default().push(rule);
+        } else {
+            self.networks.push(rule);
+        }
+    }
+
+    fn evaluate(&self, ip: IpAddr) -> IpVerdict {
+        let matches = self
+            .hosts
+            .get(&ip)
+            .into_iter()
+            .flatten()
+            .chain(self.networks.iter().filter(|rule| rule.network.contains(ip)));
+
+        let mut denied = false;
+        let mut risk_adjustment = 0;
+        for rule in matches {
+            match rule.action {
+                RuleAction::Allow => return IpVerdict::Trusted,
+                RuleAction::Deny => denied = true,
+                RuleAction::ElevatedRisk => risk_adjustment = risk_adjustment.max(rule.risk_adjustment),
+            }
+        }
+
+        if denied {
+            IpVerdict::Blocked
+        } else {
+            IpVerdict::Neutral { risk_adjustment }
+        }
+    }
+}
+
+impl IpRuleService {
+    /// Loads the rules and starts the periodic refresh.
+    pub async fn start(db: PgPool, lists: Vec<IpListConfig>, trusted_proxies: Vec<IpNetwork>) -> Self {
+        let service = Self {
+            db,
+            lists: Arc::new(lists),
+            trusted_proxies: Arc::new(trusted_proxies),
+            rules: Arc::new(RwLock::new(IpRuleSet::default())),
+            list_cache: Arc::new(Mutex::new(HashMap::new())),
+            blocked: Arc::new(AtomicU64::new(0)),
+        };
+
+        if let Err(e) = service.reload().await {
+            tracing::error!("Failed to load IP rules: {}", e);
+        }
+
+        let refresher = service.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = refresher.reload().await {
+                    tracing::error!("Failed to refresh IP rules: {}", e);
+                }
+            }
+        });
+
+        service
+    }
+
+    /// Rebuilds the in-memory rule set; the previous set stays in force if this fails.
+    pub async fn reload(&self) -> Result<(), IpRuleError> {
+        let rules = sqlx::query_as!(
+            IpRule,
+            "SELECT * FROM ip_rules WHERE expires_at IS NULL OR expires_at > NOW()"
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut set = IpRuleSet::default();
+        for rule in rules {
+            match (rule.organization_id, RuleAction::parse(&rule.action)) {
+                (Some(organization_id), _) => set
+                    .organizations
+                    .entry(organization_id)
+                    .or_default()
+                    .push(rule.network),
+                (None, Some(action)) => set.insert(CompiledRule {
+                    network: rule.network,
+                    action,
+                    risk_adjustment: rule.risk_adjustment,
+                }),
+                (None, None) => tracing::warn!("Ignoring IP rule {} with unknown action", rule.id),
+            }
+        }
+
+        for list in self.lists.iter() {
+            let action = match list.action {
+                IpListAction::Deny => RuleAction::Deny,
+                IpListAction::ElevatedRisk => RuleAction::ElevatedRisk,
+            };
+            let (networks, error) = self.load_list(list).await;
+            for network in networks.iter() {
+                set.insert(CompiledRule {
+                    network: *network,
+                    action,
+                    risk_adjustment: list.risk_adjustment,
+                });
+            }
+            set.lists.push(IpListSummary {
+                name: list.name.clone(),
+                path: list.path.clone(),
+                entries: networks.len(),
+                error,
+            });
+        }
+
+        *self.rules.write().unwrap() = set;
+        Ok(())
+    }
+
+    async fn load_list(&self, list: &IpListConfig) -> (Arc<Vec<IpNetwork>>, Option<String>) {
+        match tokio::fs::read_to_string(&list.path).await {
+            Ok(contents) => {
+                let networks: Vec<IpNetwork> = contents
+                    .lines()
+                    .map(|line| line.split('#').next().unwrap_or_default().trim())
+                    .filter(|line| !line.is_empty())
+                    .filter_map(|line| match line.parse() {
+                        Ok(network) => Some(network),
+                        Err(_) => {
+                            tracing::warn!("Skipping invalid entry {:?} in IP list {}", line, list.name);
+                            None
+                        }
+                    })
+                    .collect();
+                let networks = Arc::new(networks);
+                self.list_cache
+                    .lock()
+                    .unwrap()
+                    .insert(list.name.clone(), networks.clone());
+                (networks, None)
+            }
+            Err(e) => {
+                tracing::error!("Failed to read IP list {} from {}: {}", list.name, list.path, e);
+                let previous = self.list_cache.lock().unwrap().get(&list.name).cloned();
+                (previous.unwrap_or_default(), Some(e.to_string()))
+            }
+        }
+    }
+
+    /// The address of the client behind `peer`, the connection's remote address.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let forwarded_for: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        resolve_client_ip(peer, &forwarded_for.join(","), &self.trusted_proxies)
+    }
+
+    pub fn evaluate(&self, ip: IpAddr) -> IpVerdict {
+        self.rules.read().unwrap().evaluate(canonical(ip))
+    }
+
+    /// Added to the base risk level of security events from this address.
+    pub fn risk_adjustment(&self, ip: IpAddr) -> i32 {
+        match self.evaluate(ip) {
+            IpVerdict::Neutral { risk_adjustment } => risk_adjustment,
+            IpVerdict::Trusted | IpVerdict::Blocked => 0,
+        }
+    }
+
+    /// Whether the member may use the platform from this address under their organization's allowlist.
+    pub async fn member_network_allowed(&self, user_id: Uuid, ip: IpAddr) -> Result<bool, sqlx::Error> {
+        if self.rules.read().unwrap().organizations.is_empty() {
+            return Ok(true);
+        }
+
+        // Every member of the organization is bound, whatever their own plan
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+        let organization_id = sqlx::query_scalar!(
+            "SELECT organization_id FROM users WHERE id = $1",
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .flatten();
+        tx.commit().await?;
+
+        let Some(organization_id) = organization_id else {
+            return Ok(true);
+        };
+
+        let ip = canonical(ip);
+        Ok(self
+            .rules
+            .read()
+            .unwrap()
+            .organizations
+            .get(&organization_id)
+            .is_none_or(|networks| networks.iter().any(|network| network.contains(ip))))
+    }
+
+    pub fn record_block(&self) {
+        self.blocked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn blocked_total(&self) -> u64 {
+        self.blocked.load(Ordering::Relaxed)
+    }
+
+    pub fn list_summaries(&self) -> Vec<IpListSummary> {
+        self.rules.read().unwrap().lists.clone()
+    }
+
+    pub async fn list_rules(&self, organization_id: Option<Uuid>) -> Result<Vec<IpRule>, IpRuleError> {
+        let rules = sqlx::query_as!(
+            IpRule,
+            r#"
+            SELECT * FROM ip_rules
+            WHERE $1::uuid IS NULL OR organization_id = $1
+            ORDER BY created_at DESC
+            "#,
+            organization_id
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rules)
+    }
+
+    pub async fn create_rule(
+        &self,
+        request: CreateIpRuleRequest,
+        created_by: Uuid,
+    ) -> Result<IpRule, IpRuleError> {
+        let network: IpNetwork = request
+            .network
+            .trim()
+            .parse()
+            .map_err(|_| IpRuleError::InvalidNetwork)?;
+        // CIDR columns reject host bits, so 10.0.0.7/24 is stored as 10.0.0.0/24
+        let network = IpNetwork::new(network.network(), network.prefix())
+            .map_err(|_| IpRuleError::InvalidNetwork)?;
+
+        let action = RuleAction::parse(&request.action).ok_or(IpRuleError::InvalidAction)?;
+        if request.organization_id.is_some() && action != RuleAction::Allow {
+            return Err(IpRuleError::OrganizationRuleMustAllow);
+        }
+        let risk_adjustment = match action {
+            RuleAction::ElevatedRisk => request.risk_adjustment.unwrap_or(3),
+            RuleAction::Allow | RuleAction::Deny => 0,
+        };
+
+        let rule = sqlx::query_as!(
+            IpRule,
+            r#"
+            INSERT INTO ip_rules (network, action, risk_adjustment, organization_id, description, expires_at, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+            "#,
+            network,
+            request.action,
+            risk_adjustment,
+            request.organization_id,
+            request.description,
+            request.expires_at,
+            created_by
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        self.reload().await?;
+
+        Ok(rule)
+    }
+
+    /// Denies a single address for `AUTO_DENY_DAYS`, e.g. after it touched a canary.
+    pub async fn deny_source(&self, ip: IpAddr, reason: &str) -> Result<(), IpRuleError> {
+        let ip = canonical(ip);
+        if self.evaluate(ip) == IpVerdict::Trusted {
+            tracing::warn!("Not auto-denying allowlisted address {} ({})", ip, reason);
+            return Ok(());
+        }
+        if exempt_from_auto_deny(ip, &self.trusted_proxies) {
+            tracing::warn!("Not auto-denying proxy or internal address {} ({})", ip, reason);
+            return Ok(());
+        }
+
+        sqlx::query!(
+            r#"
+            INSERT INTO ip_rules (network, action, description, expires_at)
+            VALUES ($1, 'deny', $2, NOW() + make_interval(days => $3))
+            "#,
+            IpNetwork::from(ip),
+            reason,
+            AUTO_DENY_DAYS
+        )
+        .execute(&self.db)
+        .await?;
+
+        tracing::warn!("Auto-denied {}: {}", ip, reason);
+        self.reload().await
+    }
+
+    pub async fn delete_rule(&self, rule_id: Uuid) -> Result<IpRule, IpRuleError> {
+        let rule = sqlx::query_as!(IpRule, "DELETE FROM ip_rules WHERE id = $1 RETURNING *", rule_id)
+            .fetch_optional(&self.db)
+            .await?
+            .ok_or(IpRuleError::RuleNotFound)?;
+
+        self.reload().await?;
+
+        Ok(rule)
+    }
+}
+
+fn max_prefix(network: IpNetwork) -> u8 {
+    match network {
+        IpNetwork::V4(_) => 32,
+        IpNetwork::V6(_) => 128,
+    }
+}
+
+// Dual-stack listeners report IPv4 clients as ::ffff:a.b.c.d
+/// Walks `X-Forwarded-For` back from `peer` for as long as each hop is a trusted proxy, so a
+/// client cannot pick its own address by sending the header itself.
+fn resolve_client_ip(peer: IpAddr, forwarded_for: &str, trusted_proxies: &[IpNetwork]) -> IpAddr {
+    let is_proxy = |ip: IpAddr| trusted_proxies.iter().any(|proxy| proxy.contains(ip));
+
+    let mut client = canonical(peer);
+    for hop in forwarded_for.rsplit(',') {
+        if !is_proxy(client) {
+            break;
+        }
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) => client = canonical(ip),
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+/// Denying a proxy would shut out everyone behind it, and loopback, private and link-local
+/// addresses are our own infrastructure (or a misconfigured proxy) rather than an attacker.
+fn exempt_from_auto_deny(ip: IpAddr, trusted_proxies: &[IpNetwork]) -> bool {
+    let internal = match ip {
+        IpAddr::V4(v4) => v4.is_loopback() || v4.is_private() || v4.is_link_local() || v4.is_unspecified(),
+        IpAddr::V6(v6) => {
+            v6.is_loopback() || v6.is_unique_local() || v6.is_unicast_link_local() || v6.is_unspecified()
+        }
+    };
+    internal || trusted_proxies.iter().any(|proxy| proxy.contains(ip))
+}
+
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn proxies() -> Vec<IpNetwork> {
+        vec!["10.0.0.0/24".parse().unwrap(), "203.0.113.7/32".parse().unwrap()]
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        assert_eq!(
+            resolve_client_ip(ip("198.51.100.4"), "192.0.2.1", &proxies()),
+            ip("198.51.100.4")
+        );
+        assert_eq!(resolve_client_ip(ip("10.0.0.5"), "192.0.2.1", &[]), ip("10.0.0.5"));
+    }
+
+    #[test]
+    fn forwarded_for_is_followed_through_trusted_proxies_only() {
+        // The client claims 192.0.2.1, but the first untrusted hop is what our proxies saw
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.5"), "192.0.2.1, 198.51.100.9, 203.0.113.7", &proxies()),
+            ip("198.51.100.9")
+        );
+        assert_eq!(resolve_client_ip(ip("10.0.0.5"), "198.51.100.9", &proxies()), ip("198.51.100.9"));
+        assert_eq!(
+            resolve_client_ip(ip("10.0.0.5"), "::ffff:198.51.100.9", &proxies()),
+            ip("198.51.100.9")
+        );
+    }
+
+    #[test]
+    fn malformed_or_missing_forwarded_for_leaves_the_proxy() {
+        assert_eq!(resolve_client_ip(ip("10.0.0.5"), "", &proxies()), ip("10.0.0.5"));
+        assert_eq!(resolve_client_ip(ip("10.0.0.5"), "unknown", &proxies()), ip("10.0.0.5"));
+    }
+
+    #[test]
+    fn proxies_and_internal_addresses_are_never_auto_denied() {
+        for address in [
+            "203.0.113.7",
+            "10.0.0.5",
+            "127.0.0.1",
+            "192.168.1.20",
+            "172.16.4.4",
+            "169.254.0.1",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert!(exempt_from_auto_deny(ip(address), &proxies()), "{}", address);
+        }
+        assert!(!exempt_from_auto_deny(ip("198.51.100.9"), &proxies()));
+        assert!(!exempt_from_auto_deny(ip("2001:db8::1"), &proxies()));
+    }
+}
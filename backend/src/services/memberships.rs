@@ -0,0 +1,296 @@
+use crate::models::{CreateMembershipRequest, Membership, MembershipVersion, UpdateMembershipRequest};
+use rust_decimal::Decimal;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+/// Tier every new member starts on; it cannot be retired.
+pub const DEFAULT_TIER: &str = "basic";
+
+/// The membership catalog (migrations/012_membership_catalog.sql).
+///
+/// Changing a tier's prices, features or limits publishes a new version and stops offering the
+/// old one; subscriptions stay on the version they signed up for. Name, description and ordering
+/// are presentation only and are edited in place.
+#[derive(Debug, Clone)]
+pub struct MembershipService {
+    db: PgPool,
+}
+
+#[derive(Debug)]
+pub enum MembershipError {
+    DatabaseError(sqlx::Error),
+    TierExists,
+    TierNotFound,
+    DefaultTierRequired,
+    Invalid(&'static str),
+}
+
+impl std::fmt::Display for MembershipError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MembershipError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            MembershipError::TierExists => write!(f, "Membership tier already exists"),
+            MembershipError::TierNotFound => write!(f, "Membership tier not found"),
+            MembershipError::DefaultTierRequired => {
+                write!(f, "The {} tier cannot be retired", DEFAULT_TIER)
+            }
+            MembershipError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl From<sqlx::Error> for MembershipError {
+    fn from(err: sqlx::Error) -> Self {
+        MembershipError::DatabaseError(err)
+    }
+}
+
+impl MembershipService {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+
+    /// Tiers open to new subscribers, in display order.
+    pub async fn list_offered(&self) -> Result<Vec<Membership>, sqlx::Error> {
+        sqlx::query_as!(
+            Membership,
+            "SELECT * FROM memberships WHERE is_active ORDER BY sort_order, tier"
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    /// The catalog version the member is on: their live subscription's, or the offered default tier.
+    pub async fn current_for(&self, user_id: Uuid) -> Result<Membership, sqlx::Error> {
+        sqlx::query_as!(
+            Membership,
+            "SELECT * FROM memberships WHERE id = current_membership_id($1)",
+            user_id
+        )
+        .fetch_one(&self.db)
+        .await
+    }
+
+    /// Every version, including superseded and retired ones, with their subscriber counts.
+    pub async fn list_versions(&self) -> Result<Vec<MembershipVersion>, MembershipError> {
+        let memberships = sqlx::query_as!(
+            Membership,
+            "SELECT * FROM memberships ORDER BY sort_order, tier, version DESC"
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        let counts = sqlx::query!(
+            r#"
+            SELECT membership_id, COUNT(*) AS "subscribers!"
+            FROM subscriptions
+            GROUP BY membership_id
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(memberships
+            .into_iter()
+            .map(|membership| {
+                let subscribers = counts
+                    .iter()
+                    .find(|count| count.membership_id == membership.id)
+                    .map_or(0, |count| count.subscribers);
+                MembershipVersion {
+                    membership,
+                    subscribers,
+                }
+            })
+            .collect())
+    }
+
+    pub async fn create(&self, request: CreateMembershipRequest) -> Result<Membership, MembershipError> {
+        validate_tier_code(&request.tier)?;
+        validate_terms(request.price_monthly, request.price_yearly, &request.features)?;
+
+        // Codes of retired tiers stay taken, so old subscriptions never change meaning
+        let membership = sqlx::query_as!(
+            Membership,
+            r#"
+            INSERT INTO memberships
+                (tier, version, name, description, price_monthly, price_yearly, features,
+                 max_file_size_mb, max_storage_gb, max_conversations, sort_order)
+            VALUES ($1, 1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            RETURNING *
+            "#,
+            request.tier,
+            request.name,
+            request.description.unwrap_or_default(),
+            request.price_monthly,
+            request.price_yearly,
+            request.features,
+            request.max_file_size_mb,
+            request.max_storage_gb,
+            request.max_conversations,
+            request.sort_order.unwrap_or(0)
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(ref db_err) if db_err.is_unique_violation() => {
+                MembershipError::TierExists
+            }
+            _ => MembershipError::DatabaseError(e),
+        })?;
+
+        Ok(membership)
+    }
+
+    /// Returns the offered version afterwards, which is new if the terms changed.
+    pub async fn update(
+        &self,
+        tier: &str,
+        request: UpdateMembershipRequest,
+    ) -> Result<Membership, MembershipError> {
+        let mut tx = self.db.begin().await?;
+        let current = offered_version(&mut tx, tier).await?;
+
+        let price_monthly = request.price_monthly.or(current.price_monthly);
+        let price_yearly = request.price_yearly.or(current.price_yearly);
+        let features = request.features.unwrap_or_else(|| current.features.clone());
+        let max_file_size_mb = request.max_file_size_mb.unwrap_or(current.max_file_size_mb);
+        let max_storage_gb = request.max_storage_gb.unwrap_or(current.max_storage_gb);
+        let max_conversations = request.max_conversations.unwrap_or(current.max_conversations);
+        validate_terms(price_monthly, price_yearly, &features)?;
+
+        let name = request.name.unwrap_or_else(|| current.name.clone());
+        let description = request.description.unwrap_or_else(|| current.description.clone());
+        let sort_order = request.sort_order.unwrap_or(current.sort_order);
+
+        let terms_changed = price_monthly != current.price_monthly
+            || price_yearly != current.price_yearly
+            || features != current.features
+            || max_file_size_mb != current.max_file_size_mb
+            || max_storage_gb != current.max_storage_gb
+            || max_conversations != current.max_conversations;
+
+        let membership = if terms_changed {
+            sqlx::query!(
+                "UPDATE memberships SET is_active = false, retired_at = NOW(), updated_at = NOW() WHERE id = $1",
+                current.id
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query_as!(
+                Membership,
+                r#"
+                INSERT INTO memberships
+                    (tier, version, name, description, price_monthly, price_yearly, features,
+                     max_file_size_mb, max_storage_gb, max_conversations, sort_order)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                RETURNING *
+                "#,
+                current.tier,
+                current.version + 1,
+                name,
+                description,
+                price_monthly,
+                price_yearly,
+                features,
+                max_file_size_mb,
+                max_storage_gb,
+                max_conversations,
+                sort_order
+            )
+            .fetch_one(&mut *tx)
+            .await?
+        } else {
+            sqlx::query_as!(
+                Membership,
+                r#"
+                UPDATE memberships SET name = $2, description = $3, sort_order = $4, updated_at = NOW()
+                WHERE id = $1
+                RETURNING *
+                "#,
+                current.id,
+                name,
+                description,
+                sort_order
+            )
+            .fetch_one(&mut *tx)
+            .await?
+        };
+
+        tx.commit().await?;
+        Ok(membership)
+    }
+
+    /// Stops offering the tier; current subscribers keep it.
+    pub async fn retire(&self, tier: &str) -> Result<Membership, MembershipError> {
+        if tier == DEFAULT_TIER {
+            return Err(MembershipError::DefaultTierRequired);
+        }
+
+        let mut tx = self.db.begin().await?;
+        let current = offered_version(&mut tx, tier).await?;
+        let membership = sqlx::query_as!(
+            Membership,
+            r#"
+            UPDATE memberships SET is_active = false, retired_at = NOW(), updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#,
+            current.id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(membership)
+    }
+}
+
+/// The version currently offered, locked so concurrent edits publish one version at a time.
+async fn offered_version(conn: &mut PgConnection, tier: &str) -> Result<Membership, MembershipError> {
+    sqlx::query_as!(
+        Membership,
+        "SELECT * FROM memberships WHERE tier = $1 AND is_active FOR UPDATE",
+        tier
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(MembershipError::TierNotFound)
+}
+
+fn validate_tier_code(tier: &str) -> Result<(), MembershipError> {
+    let valid = tier
+        .bytes()
+        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-');
+    if tier.is_empty() || !valid {
+        return Err(MembershipError::Invalid(
+            "tier must be lowercase letters, digits, '-' or '_'",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_terms(
+    price_monthly: Option<Decimal>,
+    price_yearly: Option<Decimal>,
+    features: &serde_json::Value,
+) -> Result<(), MembershipError> {
+    if [price_monthly, price_yearly]
+        .into_iter()
+        .flatten()
+        .any(|price| price.is_sign_negative())
+    {
+        return Err(MembershipError::Invalid("prices cannot be negative"));
+    }
+
+    let features_valid = features
+        .as_object()
+        .is_some_and(|features| features.values().all(|enabled| enabled.is_boolean()));
+    if !features_valid {
+        return Err(MembershipError::Invalid(
+            "features must be an object of feature names to true/false",
+        ));
+    }
+    Ok(())
+}
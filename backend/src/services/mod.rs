@@ -1,5 +1,43 @@
+pub mod access_codes;
 pub mod auth;
+pub mod billing;
+pub mod content;
+pub mod deception;
+pub mod entitlements;
+pub mod event_recorder;
+pub mod event_sinks;
+pub mod field_encryption;
+pub mod ip_rules;
+pub mod mailer;
+pub mod memberships;
+pub mod notifications;
+pub mod organizations;
+pub mod password_policy;
+pub mod promotions;
+pub mod rbac;
+pub mod retention;
 pub mod security;
+pub mod stripe;
+pub mod subscription_scheduler;
 
+pub use access_codes::*;
 pub use auth::*;
-pub use security::*;
\ No newline at end of file
+pub use billing::*;
+pub use content::*;
+pub use deception::*;
+pub use entitlements::*;
+pub use event_recorder::*;
+pub use event_sinks::*;
+pub use field_encryption::*;
+pub use ip_rules::*;
+pub use mailer::*;
+pub use memberships::*;
+pub use notifications::*;
+pub use organizations::*;
+pub use password_policy::*;
+pub use promotions::*;
+pub use rbac::*;
+pub use retention::*;
+pub use security::*;
+pub use stripe::*;
+pub use subscription_scheduler::*;
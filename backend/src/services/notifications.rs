@@ -0,0 +1,478 @@
+use crate::models::{
+    NotificationPreferences, SecurityAlert, SecurityEvent, SecurityEventType,
+    UpdateNotificationPreferencesRequest,
+};
+use crate::services::{FieldCipher, FieldEncryptionError, MailerService, ADMIN_ROLE, EMAIL_FIELD, WEBHOOK_SECRET_FIELD};
+use crate::utils::{begin_scoped, hex_encode, webhook_signature, DbScope};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+const ALERT_CHANNEL_CAPACITY: usize = 32;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+// Events at this level also alert every administrator
+const CRITICAL_RISK_LEVEL: i32 = 10;
+
+/// Tells members about security events on their own account.
+///
+/// Every event with a `user_id` is checked against that member's preferences; events at or above
+/// their `min_risk_level` go out over each enabled channel: email, a push to every live alert
+/// socket, and a signed webhook. Critical events are also sent to every administrator through
+/// their own channels. Delivery runs in the background and never blocks the caller.
+#[derive(Debug, Clone)]
+pub struct NotificationService {
+    db: PgPool,
+    mailer: MailerService,
+    cipher: FieldCipher,
+    hub: AlertHub,
+    rng: SystemRandom,
+}
+
+#[derive(Debug)]
+pub enum NotificationError {
+    DatabaseError(sqlx::Error),
+    InvalidWebhookUrl(String),
+    EncryptionError(FieldEncryptionError),
+}
+
+impl std::fmt::Display for NotificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            NotificationError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            NotificationError::InvalidWebhookUrl(reason) => write!(f, "Invalid webhook URL: {}", reason),
+            NotificationError::EncryptionError(e) => write!(f, "Field encryption error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for NotificationError {
+    fn from(err: sqlx::Error) -> Self {
+        NotificationError::DatabaseError(err)
+    }
+}
+
+impl From<FieldEncryptionError> for NotificationError {
+    fn from(err: FieldEncryptionError) -> Self {
+        NotificationError::EncryptionError(err)
+    }
+}
+
+/// Live alert subscriptions: one broadcast channel per member with an open alert socket.
+#[derive(Debug, Clone, Default)]
+struct AlertHub {
+    channels: Arc<Mutex<HashMap<Uuid, broadcast::Sender<SecurityAlert>>>>,
+}
+
+impl AlertHub {
+    fn subscribe(&self, user_id: Uuid) -> broadcast::Receiver<SecurityAlert> {
+        let mut channels = self.channels.lock().unwrap();
+        channels
+            .entry(user_id)
+            .or_insert_with(|| broadcast::channel(ALERT_CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    fn publish(&self, user_id: Uuid, alert: SecurityAlert) {
+        let channels = self.channels.lock().unwrap();
+        if let Some(sender) = channels.get(&user_id) {
+            let _ = sender.send(alert);
+        }
+    }
+
+    /// Drops the member's channel once their last socket has closed.
+    fn release(&self, user_id: Uuid) {
+        let mut channels = self.channels.lock().unwrap();
+        if channels
+            .get(&user_id)
+            .is_some_and(|sender| sender.receiver_count() == 0)
+        {
+            channels.remove(&user_id);
+        }
+    }
+}
+
+impl NotificationService {
+    pub fn new(db: PgPool, mailer: MailerService, cipher: FieldCipher) -> Self {
+        Self {
+            db,
+            mailer,
+            cipher,
+            hub: AlertHub::default(),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    /// Queues alerts for the event's member, if any, and for administrators if it is critical.
+    pub fn notify(&self, event: &SecurityEvent) {
+        let critical = event.risk_level >= CRITICAL_RISK_LEVEL;
+        if event.user_id.is_none() && !critical {
+            return;
+        }
+
+        let service = self.clone();
+        let event = event.clone();
+        tokio::spawn(async move {
+            if let Some(user_id) = event.user_id {
+                if let Err(e) = service.deliver(user_id, &event).await {
+                    tracing::error!("Failed to send security alert for event {}: {}", event.id, e);
+                }
+            }
+            if critical {
+                if let Err(e) = service.alert_administrators(&event).await {
+                    tracing::error!("Failed to alert administrators of event {}: {}", event.id, e);
+                }
+            }
+        });
+    }
+
+    pub fn subscribe(&self, user_id: Uuid) -> broadcast::Receiver<SecurityAlert> {
+        self.hub.subscribe(user_id)
+    }
+
+    pub fn unsubscribe(&self, user_id: Uuid, receiver: broadcast::Receiver<SecurityAlert>) {
+        drop(receiver);
+        self.hub.release(user_id);
+    }
+
+    pub async fn get_preferences(&self, user_id: Uuid) -> Result<NotificationPreferences, NotificationError> {
+        let preferences = sqlx::query_as!(
+            NotificationPreferences,
+            "SELECT * FROM notification_preferences WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(preferences.unwrap_or_else(|| NotificationPreferences::defaults(user_id)))
+    }
+
+    /// Saves the member's preferences. When a new webhook URL is set, a fresh signing secret is
+    /// generated, stored under the member's key and returned; it is not shown again.
+    pub async fn update_preferences(
+        &self,
+        user_id: Uuid,
+        request: UpdateNotificationPreferencesRequest,
+    ) -> Result<(NotificationPreferences, Option<String>), NotificationError> {
+        let current = self.get_preferences(user_id).await?;
+
+        let mut webhook_url = current.webhook_url.clone();
+        let mut webhook_secret = current.webhook_secret.clone();
+        let mut new_secret = None;
+        match request.webhook_url.as_deref().map(str::trim) {
+            Some("") => {
+                webhook_url = None;
+                webhook_secret = None;
+            }
+            Some(url) if Some(url) != current.webhook_url.as_deref() => {
+                check_webhook_url(url).await?;
+                let secret = self.generate_webhook_secret();
+                webhook_url = Some(url.to_string());
+                webhook_secret = Some(self.cipher.encrypt(user_id, WEBHOOK_SECRET_FIELD, &secret)?);
+                new_secret = Some(secret);
+            }
+            _ => {}
+        }
+
+        let preferences = sqlx::query_as!(
+            NotificationPreferences,
+            r#"
+            INSERT INTO notification_preferences
+                (user_id, min_risk_level, email_enabled, push_enabled, webhook_url, webhook_secret, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, NOW())
+            ON CONFLICT (user_id) DO UPDATE SET
+                min_risk_level = EXCLUDED.min_risk_level,
+                email_enabled = EXCLUDED.email_enabled,
+                push_enabled = EXCLUDED.push_enabled,
+                webhook_url = EXCLUDED.webhook_url,
+                webhook_secret = EXCLUDED.webhook_secret,
+                updated_at = NOW()
+            RETURNING *
+            "#,
+            user_id,
+            request.min_risk_level.unwrap_or(current.min_risk_level),
+            request.email_enabled.unwrap_or(current.email_enabled),
+            request.push_enabled.unwrap_or(current.push_enabled),
+            webhook_url,
+            webhook_secret
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok((preferences, new_secret))
+    }
+
+    async fn alert_administrators(&self, event: &SecurityEvent) -> Result<(), NotificationError> {
+        let mut tx = begin_scoped(&self.db, DbScope::System).await?;
+        let admins = sqlx::query_scalar!(
+            r#"
+            SELECT u.id FROM users u
+            JOIN user_roles ur ON ur.user_id = u.id
+            JOIN roles r ON r.id = ur.role_id
+            WHERE r.name = $1 AND u.is_active = true AND NOT u.is_canary
+            "#,
+            ADMIN_ROLE
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        // An administrator whose own account is affected was already alerted as its member
+        for admin_id in admins.into_iter().filter(|id| event.user_id != Some(*id)) {
+            if let Err(e) = self.deliver(admin_id, event).await {
+                tracing::error!("Failed to alert administrator {}: {}", admin_id, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn deliver(&self, recipient: Uuid, event: &SecurityEvent) -> Result<(), NotificationError> {
+        // Canary accounts have nobody behind them
+        let mut tx = begin_scoped(&self.db, DbScope::Member(recipient)).await?;
+        let email = sqlx::query_scalar!(
+            "SELECT email FROM users WHERE id = $1 AND NOT is_canary",
+            recipient
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        let Some(email) = email else {
+            return Ok(());
+        };
+        let email = match self.cipher.decrypt(recipient, EMAIL_FIELD, &email) {
+            Ok(email) => email,
+            // Crypto-shredded by a destruction still committing; there is nobody left to tell
+            Err(FieldEncryptionError::UnknownKey(_)) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let preferences = self.get_preferences(recipient).await?;
+        if event.risk_level < preferences.min_risk_level {
+            return Ok(());
+        }
+
+        let own_account = event.user_id == Some(recipient);
+        let alert = security_alert(event, own_account);
+
+        if preferences.push_enabled {
+            self.hub.publish(recipient, alert.clone());
+        }
+
+        if preferences.email_enabled {
+            self.mailer
+                .send(
+                    &email,
+                    &format!("Security alert: {}", alert.title),
+                    &format!(
+                        "{}\n\nTime: {}\nIP address: {}\n\n{}",
+                        alert.message,
+                        alert.created_at.to_rfc3339(),
+                        alert.ip_address.as_deref().unwrap_or("unknown"),
+                        if own_account {
+                            "If this wasn't you, change your password now."
+                        } else {
+                            "Review the security event log for details."
+                        },
+                    ),
+                )
+                .await;
+        }
+
+        if let (Some(url), Some(secret)) = (&preferences.webhook_url, &preferences.webhook_secret) {
+            let secret = self.cipher.decrypt(recipient, WEBHOOK_SECRET_FIELD, secret)?;
+            if let Err(e) = send_webhook(url, &secret, &alert).await {
+                tracing::warn!("Security alert webhook for user {} failed: {}", recipient, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn generate_webhook_secret(&self) -> String {
+        let mut bytes = [0u8; 32];
+        self.rng.fill(&mut bytes).unwrap();
+        hex_encode(&bytes)
+    }
+}
+
+fn security_alert(event: &SecurityEvent, own_account: bool) -> SecurityAlert {
+    let (title, message) = if own_account {
+        member_alert_text(event)
+    } else {
+        administrator_alert_text(event)
+    };
+
+    SecurityAlert {
+        event_id: event.id,
+        event_type: event.event_type.clone(),
+        risk_level: event.risk_level,
+        title,
+        message,
+        ip_address: event.ip_address.map(|ip| ip.to_string()),
+        created_at: event.created_at,
+    }
+}
+
+fn member_alert_text(event: &SecurityEvent) -> (String, String) {
+    match event.kind() {
+        Some(SecurityEventType::NewDeviceLogin { .. }) => (
+            "New device signed in".to_string(),
+            "Your account was signed in from a device that has not been used with it before."
+                .to_string(),
+        ),
+        Some(SecurityEventType::AccountLocked { failed_attempts }) => (
+            "Account locked".to_string(),
+            format!(
+                "Your account was locked for 15 minutes after {} failed login attempts.",
+                failed_attempts
+            ),
+        ),
+        Some(SecurityEventType::DestructionPending {
+            failed_attempts,
+            threshold,
+        }) => (
+            "Account destruction imminent".to_string(),
+            format!(
+                "There have been {} failed login attempts. At {} your account and all of its data will be permanently destroyed.",
+                failed_attempts, threshold
+            ),
+        ),
+        _ => (
+            event_title(event),
+            format!(
+                "A security event with risk level {} was recorded on your account.",
+                event.risk_level
+            ),
+        ),
+    }
+}
+
+fn administrator_alert_text(event: &SecurityEvent) -> (String, String) {
+    let account = event
+        .user_id
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    match event.kind() {
+        Some(SecurityEventType::CanaryLoginAttempt) => (
+            "Canary account triggered".to_string(),
+            format!(
+                "A sign-in was attempted against canary account {}. The source address has been denied.",
+                account
+            ),
+        ),
+        Some(SecurityEventType::HoneytokenUsed { label, .. }) => (
+            "Honeytoken used".to_string(),
+            format!(
+                "Honeytoken \"{}\" was presented. The source address has been denied.",
+                label
+            ),
+        ),
+        _ => (
+            event_title(event),
+            format!(
+                "Critical security event (risk level {}) on account {}.",
+                event.risk_level, account
+            ),
+        ),
+    }
+}
+
+fn event_title(event: &SecurityEvent) -> String {
+    let mut title = event.event_type.replace('_', " ");
+    if let Some(first) = title.get_mut(0..1) {
+        first.make_ascii_uppercase();
+    }
+    title
+}
+
+async fn send_webhook(url: &str, secret: &str, alert: &SecurityAlert) -> Result<(), String> {
+    // Re-checked on every delivery and pinned for the request, so the name cannot be re-pointed
+    // at an internal address after it was saved
+    let (url, addr) = check_webhook_url(url).await.map_err(|e| e.to_string())?;
+    let host = url.host_str().unwrap_or_default().to_string();
+
+    let client = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve(&host, addr)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let body = serde_json::to_vec(alert).map_err(|e| e.to_string())?;
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Circle-Signature", webhook_signature(&key, &body))
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("webhook responded with {}", response.status()))
+    }
+}
+
+/// Member webhooks must be HTTPS and resolve only to public addresses.
+async fn check_webhook_url(url: &str) -> Result<(reqwest::Url, SocketAddr), NotificationError> {
+    let invalid = |reason: &str| NotificationError::InvalidWebhookUrl(reason.to_string());
+
+    let parsed = reqwest::Url::parse(url).map_err(|_| invalid("not a valid URL"))?;
+    if parsed.scheme() != "https" {
+        return Err(invalid("must use https"));
+    }
+    let host = parsed.host_str().ok_or_else(|| invalid("missing host"))?;
+    let port = parsed.port_or_known_default().unwrap_or(443);
+
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| invalid("host does not resolve"))?
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(invalid("host does not resolve"));
+    }
+    if !addrs.iter().all(|addr| is_public_address(addr.ip())) {
+        return Err(invalid("must not point at a private or local address"));
+    }
+
+    let addr = addrs[0];
+    Ok((parsed, addr))
+}
+
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64; // 100.64.0.0/10
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_address(IpAddr::V4(mapped)),
+            None => {
+                let segment = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || (segment & 0xfe00) == 0xfc00 // unique local
+                    || (segment & 0xffc0) == 0xfe80) // link local
+            }
+        },
+    }
+}
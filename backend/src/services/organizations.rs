@@ -0,0 +1,103 @@
+use crate::models::Organization;
+use crate::utils::{begin_scoped, DbScope};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const UNIQUE_VIOLATION: &str = "23505";
+
+#[derive(Debug, Clone)]
+pub struct OrganizationService {
+    db: PgPool,
+}
+
+#[derive(Debug)]
+pub enum OrganizationError {
+    DatabaseError(sqlx::Error),
+    AlreadyExists,
+    OrganizationNotFound,
+    UserNotFound,
+}
+
+impl std::fmt::Display for OrganizationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OrganizationError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            OrganizationError::AlreadyExists => write!(f, "Organization already exists"),
+            OrganizationError::OrganizationNotFound => write!(f, "Organization not found"),
+            OrganizationError::UserNotFound => write!(f, "User not found"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for OrganizationError {
+    fn from(err: sqlx::Error) -> Self {
+        OrganizationError::DatabaseError(err)
+    }
+}
+
+impl OrganizationService {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+
+    pub async fn list(&self) -> Result<Vec<Organization>, OrganizationError> {
+        let organizations = sqlx::query_as!(Organization, "SELECT * FROM organizations ORDER BY name")
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(organizations)
+    }
+
+    pub async fn create(&self, name: &str) -> Result<Organization, OrganizationError> {
+        sqlx::query_as!(
+            Organization,
+            "INSERT INTO organizations (name) VALUES ($1) RETURNING *",
+            name.trim()
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db) if db.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+                OrganizationError::AlreadyExists
+            }
+            _ => OrganizationError::DatabaseError(e),
+        })
+    }
+
+    /// Moves a member into an organization, or out of any with `None`, on behalf of the
+    /// administrator `admin_id`.
+    pub async fn assign_member(
+        &self,
+        admin_id: Uuid,
+        user_id: Uuid,
+        organization_id: Option<Uuid>,
+    ) -> Result<(), OrganizationError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Admin(admin_id)).await?;
+        if let Some(organization_id) = organization_id {
+            let exists = sqlx::query_scalar!(
+                "SELECT EXISTS(SELECT 1 FROM organizations WHERE id = $1)",
+                organization_id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            if !exists.unwrap_or(false) {
+                return Err(OrganizationError::OrganizationNotFound);
+            }
+        }
+
+        let result = sqlx::query!(
+            "UPDATE users SET organization_id = $2, updated_at = NOW() WHERE id = $1",
+            user_id,
+            organization_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(OrganizationError::UserNotFound);
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+use crate::utils::hex_encode;
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+const MIN_LENGTH: usize = 8;
+const MAX_LENGTH: usize = 128;
+
+// Checked even when no breach corpus is configured
+const COMMON_PASSWORDS: &[&str] = &[
+    "password", "password1", "password123", "passw0rd", "12345678", "123456789", "1234567890",
+    "qwerty123", "qwertyuiop", "iloveyou", "letmein1", "welcome1", "admin123", "abc12345",
+    "football", "baseball", "sunshine", "princess", "trustno1", "whatever",
+];
+
+/// Local password policy: length, estimated entropy, email reuse and a breached-password corpus.
+///
+/// The corpus is a directory in k-anonymity layout: one file per 5-character uppercase SHA-1
+/// prefix (`21BD1.txt`), each line holding `SUFFIX:COUNT`. Only the matching prefix file is read,
+/// so the full corpus never has to fit in memory and no network calls are made.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    min_entropy_bits: f64,
+    breached_corpus_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum PasswordViolation {
+    TooShort { min_length: usize },
+    TooLong { max_length: usize },
+    TooWeak { entropy_bits: u32, required_bits: u32 },
+    ContainsEmail,
+    Breached { occurrences: u64 },
+}
+
+impl PasswordViolation {
+    pub fn message(&self) -> String {
+        match self {
+            PasswordViolation::TooShort { min_length } => {
+                format!("Password must be at least {} characters", min_length)
+            }
+            PasswordViolation::TooLong { max_length } => {
+                format!("Password must be at most {} characters", max_length)
+            }
+            PasswordViolation::TooWeak { .. } => {
+                "Password is too predictable; use a longer passphrase or more varied characters"
+                    .to_string()
+            }
+            PasswordViolation::ContainsEmail => {
+                "Password must not contain your email address".to_string()
+            }
+            PasswordViolation::Breached { .. } => {
+                "Password appears in a known data breach; choose a different one".to_string()
+            }
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn new(min_entropy_bits: f64, breached_corpus_dir: Option<String>) -> Self {
+        let breached_corpus_dir = breached_corpus_dir.map(PathBuf::from);
+
+        match &breached_corpus_dir {
+            Some(dir) if !dir.is_dir() => {
+                tracing::warn!("Breached password corpus {:?} is not a directory", dir)
+            }
+            None => tracing::warn!("No breached password corpus configured; using built-in list only"),
+            _ => {}
+        }
+
+        Self {
+            min_entropy_bits,
+            breached_corpus_dir,
+        }
+    }
+
+    /// Returns every rule the password breaks, or an empty list if it is acceptable.
+    pub async fn check(&self, password: &str, email: &str) -> Vec<PasswordViolation> {
+        let mut violations = Vec::new();
+        let length = password.chars().count();
+
+        if length < MIN_LENGTH {
+            violations.push(PasswordViolation::TooShort { min_length: MIN_LENGTH });
+        }
+        if length > MAX_LENGTH {
+            violations.push(PasswordViolation::TooLong { max_length: MAX_LENGTH });
+            return violations;
+        }
+
+        let entropy = estimate_entropy(password);
+        if entropy < self.min_entropy_bits {
+            violations.push(PasswordViolation::TooWeak {
+                entropy_bits: entropy as u32,
+                required_bits: self.min_entropy_bits as u32,
+            });
+        }
+
+        if contains_email_local_part(password, email) {
+            violations.push(PasswordViolation::ContainsEmail);
+        }
+
+        if let Some(occurrences) = self.breach_count(password).await {
+            violations.push(PasswordViolation::Breached { occurrences });
+        }
+
+        violations
+    }
+
+    async fn breach_count(&self, password: &str) -> Option<u64> {
+        if COMMON_PASSWORDS.contains(&password.to_lowercase().as_str()) {
+            return Some(1);
+        }
+
+        let dir = self.breached_corpus_dir.as_ref()?;
+        let hash = hex_encode(digest(&SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes()).as_ref())
+            .to_uppercase();
+        let (prefix, suffix) = hash.split_at(5);
+
+        let contents = match tokio::fs::read_to_string(dir.join(format!("{}.txt", prefix))).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                tracing::error!("Failed to read breached password corpus: {:?}", e);
+                return None;
+            }
+        };
+
+        contents.lines().find_map(|line| {
+            let (candidate, count) = line.trim().split_once(':')?;
+            if candidate.eq_ignore_ascii_case(suffix) {
+                Some(count.parse().unwrap_or(1))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// Character-pool estimate that does not credit repeated characters or straight runs like `abc`/`321`.
+fn estimate_entropy(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+
+    let mut pool = 0u32;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        pool += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        pool += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        pool += 10;
+    }
+    if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') {
+        pool += 33;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        pool += 100;
+    }
+    if pool == 0 {
+        return 0.0;
+    }
+
+    let mut effective_length = 0usize;
+    let mut seen = HashSet::new();
+    for (i, c) in chars.iter().enumerate() {
+        let predictable = i > 0 && {
+            let step = *c as i64 - chars[i - 1] as i64;
+            step.abs() <= 1
+        };
+        // Characters already used elsewhere still add something, just less
+        if !predictable && seen.insert(*c) {
+            effective_length += 2;
+        } else if !predictable {
+            effective_length += 1;
+        }
+    }
+
+    (effective_length as f64 / 2.0) * (pool as f64).log2()
+}
+
+fn contains_email_local_part(password: &str, email: &str) -> bool {
+    let local_part = email.split('@').next().unwrap_or_default().to_lowercase();
+    local_part.chars().count() >= 3 && password.to_lowercase().contains(&local_part)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMAIL: &str = "jordan.lee@example.com";
+
+    fn codes(violations: &[PasswordViolation]) -> Vec<String> {
+        violations
+            .iter()
+            .map(|v| serde_json::to_value(v).unwrap()["code"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn entropy_rewards_variety_and_ignores_runs_and_repeats() {
+        assert!(estimate_entropy("Staple battery horse 9") >= 40.0);
+        assert!(estimate_entropy("aaaaaaaaaaaaaaaa") < 40.0);
+        assert!(estimate_entropy("abcdefghijklmnop") < 40.0);
+        assert!(estimate_entropy("9876543210") < estimate_entropy("9371846250"));
+        assert_eq!(estimate_entropy(""), 0.0);
+    }
+
+    #[tokio::test]
+    async fn passwords_below_the_entropy_threshold_are_too_weak() {
+        let policy = PasswordPolicy::new(40.0, None);
+
+        assert!(policy.check("Staple battery horse 9", EMAIL).await.is_empty());
+
+        let violations = policy.check("abcdefgh", EMAIL).await;
+        assert_eq!(codes(&violations), vec!["too_weak"]);
+        assert!(matches!(
+            violations[0],
+            PasswordViolation::TooWeak { required_bits: 40, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn length_is_bounded_both_ways() {
+        let policy = PasswordPolicy::new(0.0, None);
+
+        assert_eq!(codes(&policy.check("Xq7!", EMAIL).await), vec!["too_short"]);
+        assert_eq!(codes(&policy.check(&"Xq7!".repeat(33), EMAIL).await), vec!["too_long"]);
+        assert!(policy.check(&"Xq7!".repeat(32), EMAIL).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn common_passwords_are_breached_without_a_corpus() {
+        let policy = PasswordPolicy::new(0.0, None);
+
+        assert_eq!(codes(&policy.check("Password123", EMAIL).await), vec!["breached"]);
+        assert!(policy.check("Tr0ub4dor&3 horse", EMAIL).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_corpus_is_read_by_hash_prefix() {
+        let dir = std::env::temp_dir().join(format!("circle-breaches-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // SHA-1 of "Tr0ub4dor&3 horse" is BC5F5D22C7ECFFF4D2C371A2FEFC7A200ECEF85A
+        std::fs::write(
+            dir.join("BC5F5.txt"),
+            "0000000000000000000000000000000000A:3\nd22c7ecfff4d2c371a2fefc7a200ecef85a:42\n",
+        )
+        .unwrap();
+        let policy = PasswordPolicy::new(0.0, Some(dir.to_string_lossy().into_owned()));
+
+        let violations = policy.check("Tr0ub4dor&3 horse", EMAIL).await;
+        assert!(matches!(
+            violations.as_slice(),
+            [PasswordViolation::Breached { occurrences: 42 }]
+        ));
+        // No file for their prefixes
+        assert!(policy.check("Staple battery horse 9", EMAIL).await.is_empty());
+        assert!(policy.check("Tr0ub4dor&3 horses", EMAIL).await.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn passwords_may_not_contain_the_email_local_part() {
+        let policy = PasswordPolicy::new(0.0, None);
+
+        assert_eq!(
+            codes(&policy.check("My Jordan.Lee pass 9", EMAIL).await),
+            vec!["contains_email"]
+        );
+        assert!(policy.check("My Jordan Lee pass 9", EMAIL).await.is_empty());
+        // Local parts too short to be meaningful are not matched
+        assert!(!contains_email_local_part("xyz-box-77", "xy@example.com"));
+        assert!(contains_email_local_part("xyz-box-77", "xyz@example.com"));
+    }
+}
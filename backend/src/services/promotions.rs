@@ -0,0 +1,407 @@
+use crate::models::{
+    CreatePromotionRequest, Promotion, PromotionDuration, PromotionKind, StartTrialRequest, Trial,
+};
+use crate::config::BillingConfig;
+use crate::services::{MembershipService, StripeClient, StripeError, DEFAULT_TIER};
+use crate::utils::{begin_scoped, DbScope};
+use chrono::Utc;
+use rust_decimal::Decimal;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+/// Promotion codes: percentage or fixed discounts applied at checkout and on upgrades (see
+/// `BillingService`), and no-card trials.
+///
+/// A trial is a `trialing` subscription with no Stripe ids whose period ends after
+/// `trial_days`; `SubscriptionScheduler` ends it and the member moves back to basic. Trials
+/// are for members who have never had a subscription.
+#[derive(Debug, Clone)]
+pub struct PromotionService {
+    db: PgPool,
+    memberships: MembershipService,
+    stripe: StripeClient,
+    currency: String,
+}
+
+#[derive(Debug)]
+pub enum PromotionError {
+    DatabaseError(sqlx::Error),
+    Provider(StripeError),
+    CodeExists,
+    NotFound,
+    Invalid(&'static str),
+    /// Unknown, not started, ended, used up or already used by the member
+    InvalidCode,
+    /// Valid, but not for this tier or purpose
+    NotApplicable,
+    TierNotOffered,
+    TrialUnavailable,
+}
+
+impl std::fmt::Display for PromotionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PromotionError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            PromotionError::Provider(e) => write!(f, "{}", e),
+            PromotionError::CodeExists => write!(f, "Promotion code already exists"),
+            PromotionError::NotFound => write!(f, "Promotion not found"),
+            PromotionError::Invalid(reason) => write!(f, "{}", reason),
+            PromotionError::InvalidCode => write!(f, "Promotion code is invalid or has expired"),
+            PromotionError::NotApplicable => write!(f, "Promotion code does not apply"),
+            PromotionError::TierNotOffered => write!(f, "Membership tier is not offered"),
+            PromotionError::TrialUnavailable => write!(f, "Member is not eligible for a trial"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for PromotionError {
+    fn from(err: sqlx::Error) -> Self {
+        PromotionError::DatabaseError(err)
+    }
+}
+
+impl From<StripeError> for PromotionError {
+    fn from(err: StripeError) -> Self {
+        PromotionError::Provider(err)
+    }
+}
+
+impl PromotionService {
+    /// Fixed discounts are in the billing currency.
+    pub fn new(db: PgPool, config: &BillingConfig, stripe_secret_key: Option<String>) -> Self {
+        Self {
+            memberships: MembershipService::new(db.clone()),
+            stripe: StripeClient::new(&config.stripe_api_base, stripe_secret_key),
+            db,
+            currency: config.currency.clone(),
+        }
+    }
+
+    pub async fn create(
+        &self,
+        created_by: Uuid,
+        request: CreatePromotionRequest,
+    ) -> Result<Promotion, PromotionError> {
+        let code = normalize_code(&request.code);
+        if !code
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            return Err(PromotionError::Invalid(
+                "code must be letters, digits, '-' or '_'",
+            ));
+        }
+        validate_terms(&request)?;
+
+        let mut tiers = request.tiers.clone();
+        tiers.sort();
+        tiers.dedup();
+        if tiers.iter().any(|tier| tier == DEFAULT_TIER) {
+            return Err(PromotionError::Invalid("Promotions are for paid tiers"));
+        }
+        let known = sqlx::query_scalar!(
+            r#"SELECT COUNT(DISTINCT tier) AS "count!" FROM memberships WHERE tier = ANY($1)"#,
+            &tiers[..]
+        )
+        .fetch_one(&self.db)
+        .await?;
+        if known != tiers.len() as i64 {
+            return Err(PromotionError::Invalid(
+                "tiers must be membership tier codes",
+            ));
+        }
+
+        let duration = match request.kind {
+            PromotionKind::Trial => None,
+            _ => Some(request.duration.unwrap_or(PromotionDuration::Once)),
+        };
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO promotions
+                (code, description, kind, percent_off, amount_off, currency, duration,
+                 duration_months, trial_days, tiers, max_redemptions, starts_at, ends_at,
+                 created_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, COALESCE($12, NOW()), $13, $14)
+            RETURNING id
+            "#,
+            code,
+            request.description,
+            request.kind.as_str(),
+            request.percent_off,
+            request.amount_off,
+            (request.kind == PromotionKind::Fixed).then(|| self.currency.clone()),
+            duration.map(|duration| duration.as_str()),
+            request.duration_months,
+            request.trial_days,
+            &tiers[..],
+            request.max_redemptions,
+            request.starts_at,
+            request.ends_at,
+            created_by
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(ref db_err) if db_err.is_unique_violation() => {
+                PromotionError::CodeExists
+            }
+            other => PromotionError::DatabaseError(other),
+        })?;
+
+        let mut conn = self.db.acquire().await?;
+        promotion(&mut conn, id).await
+    }
+
+    pub async fn list(&self) -> Result<Vec<Promotion>, PromotionError> {
+        let mut conn = self.db.acquire().await?;
+        Ok(promotions(&mut conn, None).await?)
+    }
+
+    /// Stops the promotion from being used from now on, here and through its Stripe coupon,
+    /// which is deleted. Discounts already given keep running. Ending a promotion again retries
+    /// a coupon deletion that failed.
+    pub async fn end(&self, promotion_id: Uuid) -> Result<Promotion, PromotionError> {
+        let coupon_id = sqlx::query_scalar!(
+            r#"
+            UPDATE promotions SET ends_at = LEAST(ends_at, NOW())
+            WHERE id = $1
+            RETURNING stripe_coupon_id
+            "#,
+            promotion_id
+        )
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(PromotionError::NotFound)?;
+
+        if let Some(coupon_id) = coupon_id {
+            self.stripe.delete_coupon(&coupon_id).await?;
+        }
+
+        let mut conn = self.db.acquire().await?;
+        promotion(&mut conn, promotion_id).await
+    }
+
+    /// The promotion `code` names, if the member can use it on `tier` now. The promotion stays
+    /// locked until `conn`'s transaction ends, so redemptions cannot overrun `max_redemptions`.
+    pub async fn redeemable(
+        &self,
+        conn: &mut PgConnection,
+        code: &str,
+        user_id: Uuid,
+        tier: &str,
+    ) -> Result<Promotion, PromotionError> {
+        let id = sqlx::query_scalar!(
+            "SELECT id FROM promotions WHERE code = $1 FOR UPDATE",
+            normalize_code(code)
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(PromotionError::InvalidCode)?;
+        let promotion = promotion(conn, id).await?;
+
+        let now = Utc::now();
+        let running = promotion.starts_at <= now && promotion.ends_at.is_none_or(|end| end > now);
+        let available = promotion
+            .max_redemptions
+            .is_none_or(|max| promotion.redemptions < i64::from(max));
+        let used = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM promotion_redemptions WHERE promotion_id = $1 AND user_id = $2
+            ) AS "exists!"
+            "#,
+            promotion.id,
+            user_id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        if !running || !available || used {
+            return Err(PromotionError::InvalidCode);
+        }
+        if tier == DEFAULT_TIER || !promotion.applies_to(tier) {
+            return Err(PromotionError::NotApplicable);
+        }
+
+        Ok(promotion)
+    }
+
+    /// Records that the member used the promotion, in `conn`'s transaction.
+    pub async fn redeem(
+        &self,
+        conn: &mut PgConnection,
+        promotion_id: Uuid,
+        user_id: Uuid,
+        subscription_id: Option<Uuid>,
+    ) -> Result<(), PromotionError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO promotion_redemptions (promotion_id, user_id, subscription_id)
+            VALUES ($1, $2, $3)
+            "#,
+            promotion_id,
+            user_id,
+            subscription_id
+        )
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Starts a no-card trial of `request.tier` with a trial promotion code.
+    pub async fn start_trial(
+        &self,
+        user_id: Uuid,
+        request: StartTrialRequest,
+    ) -> Result<Trial, PromotionError> {
+        let membership = self
+            .memberships
+            .list_offered()
+            .await?
+            .into_iter()
+            .find(|membership| membership.tier == request.tier)
+            .ok_or(PromotionError::TierNotOffered)?;
+
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+        // Serializes the member's trial requests, so two codes cannot both start one
+        sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let subscribed_before = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM subscriptions WHERE user_id = $1) AS "exists!""#,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if subscribed_before {
+            return Err(PromotionError::TrialUnavailable);
+        }
+
+        let promotion = self
+            .redeemable(&mut tx, &request.code, user_id, &membership.tier)
+            .await?;
+        let Some(trial_days) = promotion.trial_days.filter(|_| promotion.is_trial()) else {
+            return Err(PromotionError::NotApplicable);
+        };
+
+        let subscription = sqlx::query!(
+            r#"
+            INSERT INTO subscriptions
+                (user_id, membership_id, status, current_period_start, current_period_end)
+            VALUES ($1, $2, 'trialing', NOW(), NOW() + make_interval(days => $3))
+            RETURNING id, current_period_end AS "current_period_end!"
+            "#,
+            user_id,
+            membership.id,
+            trial_days
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        self.redeem(&mut tx, promotion.id, user_id, Some(subscription.id))
+            .await?;
+        tx.commit().await?;
+
+        Ok(Trial {
+            subscription_id: subscription.id,
+            tier: membership.tier,
+            trial_ends_at: subscription.current_period_end,
+        })
+    }
+}
+
+async fn promotion(conn: &mut PgConnection, id: Uuid) -> Result<Promotion, PromotionError> {
+    promotions(conn, Some(id))
+        .await?
+        .pop()
+        .ok_or(PromotionError::NotFound)
+}
+
+/// Every promotion, newest first; just `id` if given.
+async fn promotions(
+    conn: &mut PgConnection,
+    id: Option<Uuid>,
+) -> Result<Vec<Promotion>, sqlx::Error> {
+    sqlx::query_as!(
+        Promotion,
+        r#"
+        SELECT p.id, p.code, p.description, p.kind, p.percent_off, p.amount_off, p.currency,
+               p.duration, p.duration_months, p.trial_days, p.tiers, p.max_redemptions,
+               p.starts_at, p.ends_at, p.created_by, p.created_at,
+               (SELECT COUNT(*) FROM promotion_redemptions r WHERE r.promotion_id = p.id)
+                   AS "redemptions!"
+        FROM promotions p
+        WHERE ($1::uuid IS NULL OR p.id = $1)
+        ORDER BY p.created_at DESC
+        "#,
+        id
+    )
+    .fetch_all(&mut *conn)
+    .await
+}
+
+fn normalize_code(code: &str) -> String {
+    code.trim().to_ascii_uppercase()
+}
+
+fn validate_terms(request: &CreatePromotionRequest) -> Result<(), PromotionError> {
+    let discount_terms = request.duration.is_some() || request.duration_months.is_some();
+    match request.kind {
+        PromotionKind::Percent => {
+            if !request
+                .percent_off
+                .is_some_and(|percent| percent > Decimal::ZERO && percent <= Decimal::ONE_HUNDRED)
+            {
+                return Err(PromotionError::Invalid(
+                    "percent promotions need percent_off between 0 and 100",
+                ));
+            }
+            if request.amount_off.is_some() || request.trial_days.is_some() {
+                return Err(PromotionError::Invalid(
+                    "percent promotions take only percent_off",
+                ));
+            }
+        }
+        PromotionKind::Fixed => {
+            if !request
+                .amount_off
+                .is_some_and(|amount| amount > Decimal::ZERO && amount.scale() <= 2)
+            {
+                return Err(PromotionError::Invalid(
+                    "fixed promotions need a positive amount_off with at most 2 decimals",
+                ));
+            }
+            if request.percent_off.is_some() || request.trial_days.is_some() {
+                return Err(PromotionError::Invalid(
+                    "fixed promotions take only amount_off",
+                ));
+            }
+        }
+        PromotionKind::Trial => {
+            if request.trial_days.is_none() {
+                return Err(PromotionError::Invalid("trial promotions need trial_days"));
+            }
+            if request.percent_off.is_some() || request.amount_off.is_some() || discount_terms {
+                return Err(PromotionError::Invalid(
+                    "trial promotions take only trial_days",
+                ));
+            }
+        }
+    }
+
+    let repeating = request.duration == Some(PromotionDuration::Repeating);
+    if repeating != request.duration_months.is_some() {
+        return Err(PromotionError::Invalid(
+            "duration_months is required for, and only for, repeating discounts",
+        ));
+    }
+
+    let starts_at = request.starts_at.unwrap_or_else(Utc::now);
+    if request
+        .ends_at
+        .is_some_and(|end| end <= starts_at || end <= Utc::now())
+    {
+        return Err(PromotionError::Invalid(
+            "ends_at must be in the future and after starts_at",
+        ));
+    }
+    Ok(())
+}
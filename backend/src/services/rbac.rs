@@ -0,0 +1,186 @@
+use crate::models::Role;
+use crate::utils::{begin_scoped, DbScope};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Holders of this role are alerted about every risk-10 event.
+pub const ADMIN_ROLE: &str = "admin";
+
+const MANAGE_ROLES: &str = "roles:manage";
+
+/// Roles and permissions (migrations/011_rbac.sql). Members hold roles through `user_roles`;
+/// each role grants a set of permissions such as `ip_rules:manage`.
+#[derive(Debug, Clone)]
+pub struct RbacService {
+    db: PgPool,
+}
+
+#[derive(Debug)]
+pub enum RbacError {
+    DatabaseError(sqlx::Error),
+    UnknownRole(String),
+    UserNotFound,
+    LastRoleManager,
+}
+
+impl std::fmt::Display for RbacError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RbacError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            RbacError::UnknownRole(role) => write!(f, "Unknown role: {}", role),
+            RbacError::UserNotFound => write!(f, "User not found"),
+            RbacError::LastRoleManager => {
+                write!(f, "At least one active member must keep the roles:manage permission")
+            }
+        }
+    }
+}
+
+impl From<sqlx::Error> for RbacError {
+    fn from(err: sqlx::Error) -> Self {
+        RbacError::DatabaseError(err)
+    }
+}
+
+impl RbacService {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+
+    /// Names of the roles the member holds, for the `roles` claim.
+    pub async fn roles_for(&self, user_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT r.name FROM user_roles ur
+            JOIN roles r ON r.id = ur.role_id
+            WHERE ur.user_id = $1
+            ORDER BY r.name
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db)
+        .await
+    }
+
+    /// Checked against the database on every request, so revoking a role takes effect at once.
+    pub async fn has_permission(&self, user_id: Uuid, permission: &str) -> Result<bool, sqlx::Error> {
+        let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+        let granted = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM users u
+                JOIN user_roles ur ON ur.user_id = u.id
+                JOIN role_permissions rp ON rp.role_id = ur.role_id
+                WHERE u.id = $1 AND u.is_active = true AND NOT u.is_canary
+                  AND rp.permission = $2
+            )
+            "#,
+            user_id,
+            permission
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(granted.unwrap_or(false))
+    }
+
+    pub async fn list_roles(&self) -> Result<Vec<Role>, RbacError> {
+        let roles = sqlx::query_as!(
+            Role,
+            r#"
+            SELECT r.id, r.name, r.description, r.created_at,
+                   COALESCE(
+                       array_agg(rp.permission ORDER BY rp.permission)
+                           FILTER (WHERE rp.permission IS NOT NULL),
+                       '{}'
+                   ) AS "permissions!"
+            FROM roles r
+            LEFT JOIN role_permissions rp ON rp.role_id = r.id
+            GROUP BY r.id
+            ORDER BY r.name
+            "#
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(roles)
+    }
+
+    /// Replaces the member's roles, returning the names now held.
+    pub async fn set_user_roles(
+        &self,
+        user_id: Uuid,
+        roles: &[String],
+        granted_by: Uuid,
+    ) -> Result<Vec<String>, RbacError> {
+        let mut tx = begin_scoped(&self.db, DbScope::Admin(granted_by)).await?;
+
+        // Serializes role changes, so two admins cannot each remove the other's last grant
+        sqlx::query!("LOCK TABLE user_roles IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await?;
+
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM users WHERE id = $1 AND NOT is_canary)",
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if !exists.unwrap_or(false) {
+            return Err(RbacError::UserNotFound);
+        }
+
+        let found = sqlx::query!("SELECT id, name FROM roles WHERE name = ANY($1)", roles)
+            .fetch_all(&mut *tx)
+            .await?;
+        if let Some(unknown) = roles
+            .iter()
+            .find(|role| !found.iter().any(|row| &row.name == *role))
+        {
+            return Err(RbacError::UnknownRole(unknown.clone()));
+        }
+        let role_ids: Vec<Uuid> = found.iter().map(|row| row.id).collect();
+
+        sqlx::query!(
+            "DELETE FROM user_roles WHERE user_id = $1 AND role_id <> ALL($2)",
+            user_id,
+            &role_ids
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO user_roles (user_id, role_id, granted_by)
+            SELECT $1, role_id, $3 FROM UNNEST($2::uuid[]) AS role_id
+            ON CONFLICT DO NOTHING
+            "#,
+            user_id,
+            &role_ids,
+            granted_by
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let managers = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(DISTINCT u.id) AS "count!" FROM users u
+            JOIN user_roles ur ON ur.user_id = u.id
+            JOIN role_permissions rp ON rp.role_id = ur.role_id
+            WHERE u.is_active = true AND rp.permission = $1
+            "#,
+            MANAGE_ROLES
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if managers == 0 {
+            return Err(RbacError::LastRoleManager);
+        }
+
+        tx.commit().await?;
+
+        let mut names: Vec<String> = found.into_iter().map(|row| row.name).collect();
+        names.sort();
+        Ok(names)
+    }
+}
@@ -0,0 +1,395 @@
+use crate::config::RetentionConfig;
+use crate::models::{PurgeRun, RetentionReport, SecurityEventType, TableRetention};
+use crate::services::SecurityService;
+use crate::utils::{begin_scoped, set_scope, DbScope};
+use chrono::{Duration as ChronoDuration, Utc};
+use sqlx::{Connection, PgConnection, PgPool};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Rows per statement, so a large backlog never holds long locks
+const BATCH_SIZE: i64 = 5000;
+
+// Session-level advisory lock: only one instance purges at a time
+const PURGE_LOCK: i64 = 0x7075_7267;
+
+/// Enforces the retention windows for `security_events` and `user_sessions`.
+///
+/// Once a row is older than `ip_truncation_days` its IP address is cut to the /24 (IPv4) or /48
+/// (IPv6) network and its user agent is dropped. Security events are deleted after
+/// `security_events_days`; sessions are deleted once they have been expired for
+/// `user_sessions_days`.
+#[derive(Debug, Clone)]
+pub struct RetentionService {
+    db: PgPool,
+    policy: RetentionConfig,
+    security_service: SecurityService,
+    last_run: Arc<Mutex<Option<PurgeRun>>>,
+}
+
+#[derive(Debug)]
+pub enum RetentionError {
+    DatabaseError(sqlx::Error),
+    PurgeInProgress,
+}
+
+impl std::fmt::Display for RetentionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RetentionError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            RetentionError::PurgeInProgress => write!(f, "A purge is already running"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for RetentionError {
+    fn from(err: sqlx::Error) -> Self {
+        RetentionError::DatabaseError(err)
+    }
+}
+
+impl RetentionService {
+    pub fn start(db: PgPool, policy: RetentionConfig, security_service: SecurityService) -> Self {
+        let service = Self {
+            db,
+            policy,
+            security_service,
+            last_run: Arc::new(Mutex::new(None)),
+        };
+
+        let purger = service.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PURGE_INTERVAL);
+            loop {
+                interval.tick().await;
+                match purger.purge(DbScope::System).await {
+                    Ok(_) | Err(RetentionError::PurgeInProgress) => {}
+                    Err(e) => tracing::error!("Retention purge failed: {}", e),
+                }
+            }
+        });
+
+        service
+    }
+
+    pub fn policy(&self) -> RetentionConfig {
+        self.policy
+    }
+
+    /// Minimizes and deletes everything past its window, acting as `scope`: `System` for the
+    /// scheduled run, or the administrator who asked for one.
+    pub async fn purge(&self, scope: DbScope) -> Result<PurgeRun, RetentionError> {
+        let mut conn = self.db.acquire().await?;
+        let locked = sqlx::query_scalar!("SELECT pg_try_advisory_lock($1)", PURGE_LOCK)
+            .fetch_one(&mut *conn)
+            .await?
+            .unwrap_or(false);
+        if !locked {
+            return Err(RetentionError::PurgeInProgress);
+        }
+
+        let result = self.purge_locked(&mut conn, scope).await;
+
+        if let Err(e) = sqlx::query_scalar!("SELECT pg_advisory_unlock($1)", PURGE_LOCK)
+            .fetch_one(&mut *conn)
+            .await
+        {
+            // Dropping the connection from the pool releases the lock with its session
+            tracing::error!("Failed to release retention purge lock: {}", e);
+            conn.detach();
+        }
+
+        let run = result?;
+        if run.security_events_deleted + run.user_sessions_deleted + run.rows_minimized > 0 {
+            tracing::info!(
+                "Retention purge deleted {} security events and {} sessions, minimized {} rows",
+                run.security_events_deleted,
+                run.user_sessions_deleted,
+                run.rows_minimized
+            );
+            self.security_service
+                .log_security_event(
+                    None,
+                    SecurityEventType::RetentionPurged {
+                        events_deleted: run.security_events_deleted,
+                        sessions_deleted: run.user_sessions_deleted,
+                        rows_minimized: run.rows_minimized,
+                    },
+                    None,
+                    None,
+                )
+                .await;
+        }
+        *self.last_run.lock().unwrap() = Some(run.clone());
+
+        Ok(run)
+    }
+
+    /// Each batch commits on its own, so row locks are only held per batch.
+    async fn purge_locked(
+        &self,
+        conn: &mut PgConnection,
+        scope: DbScope,
+    ) -> Result<PurgeRun, RetentionError> {
+        let started_at = Utc::now();
+        let truncate_before = started_at - ChronoDuration::days(self.policy.ip_truncation_days);
+        let events_before = started_at - ChronoDuration::days(self.policy.security_events_days);
+        let sessions_before = started_at - ChronoDuration::days(self.policy.user_sessions_days);
+
+        let mut security_events_deleted = 0;
+        loop {
+            let mut tx = conn.begin().await?;
+            set_scope(&mut tx, scope).await?;
+            let deleted = sqlx::query!(
+                r#"
+                DELETE FROM security_events WHERE id IN (
+                    SELECT id FROM security_events WHERE created_at < $1 LIMIT $2
+                )
+                "#,
+                events_before,
+                BATCH_SIZE
+            )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            tx.commit().await?;
+            security_events_deleted += deleted;
+            if deleted < BATCH_SIZE as u64 {
+                break;
+            }
+        }
+
+        let mut user_sessions_deleted = 0;
+        loop {
+            let mut tx = conn.begin().await?;
+            set_scope(&mut tx, scope).await?;
+            let deleted = sqlx::query!(
+                r#"
+                DELETE FROM user_sessions WHERE id IN (
+                    SELECT id FROM user_sessions
+                    WHERE COALESCE(refresh_expires_at, expires_at) < $1
+                    LIMIT $2
+                )
+                "#,
+                sessions_before,
+                BATCH_SIZE
+            )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            tx.commit().await?;
+            user_sessions_deleted += deleted;
+            if deleted < BATCH_SIZE as u64 {
+                break;
+            }
+        }
+
+        let mut rows_minimized = 0;
+        loop {
+            let mut tx = conn.begin().await?;
+            set_scope(&mut tx, scope).await?;
+            let minimized = sqlx::query!(
+                r#"
+                UPDATE security_events SET ip_address = minimize_ip(ip_address), user_agent = NULL
+                WHERE id IN (
+                    SELECT id FROM security_events
+                    WHERE created_at < $1 AND needs_minimizing(ip_address, user_agent)
+                    LIMIT $2
+                )
+                "#,
+                truncate_before,
+                BATCH_SIZE
+            )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            tx.commit().await?;
+            rows_minimized += minimized;
+            if minimized < BATCH_SIZE as u64 {
+                break;
+            }
+        }
+        loop {
+            let mut tx = conn.begin().await?;
+            set_scope(&mut tx, scope).await?;
+            let minimized = sqlx::query!(
+                r#"
+                UPDATE user_sessions SET ip_address = minimize_ip(ip_address), user_agent = NULL
+                WHERE id IN (
+                    SELECT id FROM user_sessions
+                    WHERE created_at < $1 AND needs_minimizing(ip_address, user_agent)
+                    LIMIT $2
+                )
+                "#,
+                truncate_before,
+                BATCH_SIZE
+            )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            tx.commit().await?;
+            rows_minimized += minimized;
+            if minimized < BATCH_SIZE as u64 {
+                break;
+            }
+        }
+
+        Ok(PurgeRun {
+            started_at,
+            finished_at: Utc::now(),
+            security_events_deleted,
+            user_sessions_deleted,
+            rows_minimized,
+        })
+    }
+
+    /// What each table currently retains, measured against the configured windows.
+    pub async fn report(&self, admin_id: Uuid) -> Result<RetentionReport, RetentionError> {
+        let now = Utc::now();
+        let truncate_before = now - ChronoDuration::days(self.policy.ip_truncation_days);
+        let events_before = now - ChronoDuration::days(self.policy.security_events_days);
+        let sessions_before = now - ChronoDuration::days(self.policy.user_sessions_days);
+
+        let mut tx = begin_scoped(&self.db, DbScope::Admin(admin_id)).await?;
+        let events = sqlx::query!(
+            r#"
+            SELECT
+                COUNT(*) AS "rows!",
+                MIN(created_at) AS oldest_record,
+                COUNT(*) FILTER (WHERE needs_minimizing(ip_address, user_agent)) AS "rows_with_full_ip!",
+                COUNT(*) FILTER (
+                    WHERE created_at < $1 AND needs_minimizing(ip_address, user_agent)
+                ) AS "rows_due_for_minimizing!",
+                COUNT(*) FILTER (WHERE created_at < $2) AS "rows_due_for_deletion!"
+            FROM security_events
+            "#,
+            truncate_before,
+            events_before
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let sessions = sqlx::query!(
+            r#"
+            SELECT
+                COUNT(*) AS "rows!",
+                MIN(created_at) AS oldest_record,
+                COUNT(*) FILTER (WHERE needs_minimizing(ip_address, user_agent)) AS "rows_with_full_ip!",
+                COUNT(*) FILTER (
+                    WHERE created_at < $1 AND needs_minimizing(ip_address, user_agent)
+                ) AS "rows_due_for_minimizing!",
+                COUNT(*) FILTER (
+                    WHERE COALESCE(refresh_expires_at, expires_at) < $2
+                ) AS "rows_due_for_deletion!"
+            FROM user_sessions
+            "#,
+            truncate_before,
+            sessions_before
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(RetentionReport {
+            tables: vec![
+                TableRetention {
+                    table: "security_events",
+                    retention_days: self.policy.security_events_days,
+                    ip_truncation_days: self.policy.ip_truncation_days,
+                    rows: events.rows,
+                    oldest_record: events.oldest_record,
+                    rows_with_full_ip: events.rows_with_full_ip,
+                    rows_due_for_minimizing: events.rows_due_for_minimizing,
+                    rows_due_for_deletion: events.rows_due_for_deletion,
+                },
+                TableRetention {
+                    table: "user_sessions",
+                    retention_days: self.policy.user_sessions_days,
+                    ip_truncation_days: self.policy.ip_truncation_days,
+                    rows: sessions.rows,
+                    oldest_record: sessions.oldest_record,
+                    rows_with_full_ip: sessions.rows_with_full_ip,
+                    rows_due_for_minimizing: sessions.rows_due_for_minimizing,
+                    rows_due_for_deletion: sessions.rows_due_for_deletion,
+                },
+            ],
+            last_run: self.last_run.lock().unwrap().clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ipnetwork::IpNetwork;
+    use sqlx::PgPool;
+
+    // Minimization runs in SQL (`minimize_ip` and `needs_minimizing` in 010_retention.sql)
+    async fn database() -> PgPool {
+        dotenvy::dotenv().ok();
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL");
+        PgPool::connect(&url).await.expect("connect to the backend's database")
+    }
+
+    async fn minimize(db: &PgPool, ip: &str) -> String {
+        let ip: IpNetwork = ip.parse().unwrap();
+        let minimized: IpNetwork = sqlx::query_scalar("SELECT minimize_ip($1)")
+            .bind(ip)
+            .fetch_one(db)
+            .await
+            .unwrap();
+        minimized.to_string()
+    }
+
+    async fn needs_minimizing(db: &PgPool, ip: Option<&str>, user_agent: Option<&str>) -> bool {
+        let ip: Option<IpNetwork> = ip.map(|ip| ip.parse().unwrap());
+        sqlx::query_scalar("SELECT needs_minimizing($1, $2)")
+            .bind(ip)
+            .bind(user_agent)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the backend's database"]
+    async fn ipv4_addresses_are_cut_to_their_slash_24() {
+        let db = database().await;
+
+        assert_eq!(minimize(&db, "203.0.113.77").await, "203.0.113.0/32");
+        assert_eq!(minimize(&db, "10.1.2.255").await, "10.1.2.0/32");
+        assert_eq!(minimize(&db, "203.0.113.0").await, "203.0.113.0/32");
+
+        assert!(needs_minimizing(&db, Some("203.0.113.77"), None).await);
+        assert!(!needs_minimizing(&db, Some("203.0.113.0"), None).await);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the backend's database"]
+    async fn ipv6_addresses_are_cut_to_their_slash_48() {
+        let db = database().await;
+
+        assert_eq!(
+            minimize(&db, "2001:db8:abcd:1234:5678::1").await,
+            "2001:db8:abcd::/128"
+        );
+        assert_eq!(minimize(&db, "2001:db8:abcd:ffff::").await, "2001:db8:abcd::/128");
+        // IPv4-mapped addresses are IPv6 to Postgres, so keep only the /48 as well
+        assert_eq!(minimize(&db, "::ffff:203.0.113.77").await, "::/128");
+
+        assert!(needs_minimizing(&db, Some("2001:db8:abcd:1234::1"), None).await);
+        assert!(!needs_minimizing(&db, Some("2001:db8:abcd::"), None).await);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the backend's database"]
+    async fn user_agents_are_always_dropped() {
+        let db = database().await;
+
+        assert!(needs_minimizing(&db, Some("203.0.113.0"), Some("Mozilla/5.0")).await);
+        assert!(needs_minimizing(&db, None, Some("Mozilla/5.0")).await);
+        assert!(!needs_minimizing(&db, None, None).await);
+    }
+}
@@ -1,7 +1,13 @@
-use crate::models::{SecurityEvent, DestructionLog};
+use crate::models::{DestructionLog, SecurityEvent, SecurityEventType};
+use crate::services::{
+    EventForwarder, EventRecorder, FieldCipher, FieldEncryptionError, IpRuleService,
+    NotificationService, RecorderMetricsSnapshot, StripeClient,
+};
+use crate::utils::{begin_scoped, DbScope};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as CURSOR_ENGINE;
+use base64::Engine;
 use chrono::{DateTime, Utc};
-use serde_json::Value;
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder};
 use std::net::IpAddr;
 use ipnetwork::IpNetwork;
 use uuid::Uuid;
@@ -9,12 +15,45 @@ use uuid::Uuid;
 #[derive(Debug, Clone)]
 pub struct SecurityService {
     db: PgPool,
+    forwarder: EventForwarder,
+    recorder: EventRecorder,
+    notifier: NotificationService,
+    ip_rules: IpRuleService,
+    cipher: FieldCipher,
+    stripe: StripeClient,
 }
 
 #[derive(Debug)]
 pub enum SecurityError {
     DatabaseError(sqlx::Error),
-    DestructionFailed,
+    InvalidCursor,
+}
+
+impl std::fmt::Display for SecurityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SecurityError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            SecurityError::InvalidCursor => write!(f, "Invalid cursor"),
+        }
+    }
+}
+
+/// Filters for reading back the audit trail; every field narrows the result.
+#[derive(Debug, Default)]
+pub struct SecurityEventFilter {
+    pub user_id: Option<Uuid>,
+    pub event_type: Option<String>,
+    pub min_risk: Option<i32>,
+    pub max_risk: Option<i32>,
+    pub ip_network: Option<IpNetwork>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+pub struct SecurityEventPage {
+    pub events: Vec<SecurityEvent>,
+    pub next_cursor: Option<String>,
 }
 
 impl From<sqlx::Error> for SecurityError {
@@ -24,60 +63,303 @@ impl From<sqlx::Error> for SecurityError {
 }
 
 impl SecurityService {
-    pub fn new(db: PgPool) -> Self {
-        Self { db }
+    pub fn new(
+        db: PgPool,
+        forwarder: EventForwarder,
+        recorder: EventRecorder,
+        notifier: NotificationService,
+        ip_rules: IpRuleService,
+        cipher: FieldCipher,
+        stripe: StripeClient,
+    ) -> Self {
+        Self {
+            db,
+            forwarder,
+            recorder,
+            notifier,
+            ip_rules,
+            cipher,
+            stripe,
+        }
     }
 
     pub async fn log_security_event(
         &self,
         user_id: Option<Uuid>,
-        event_type: String,
+        event_type: SecurityEventType,
         ip_address: Option<IpAddr>,
         user_agent: Option<String>,
-        details: Option<Value>,
     ) {
         let risk_level = self.calculate_risk_level(&event_type, &ip_address);
-        
-        let _ = sqlx::query!(
-            r#"
-            INSERT INTO security_events (user_id, event_type, ip_address, user_agent, details, risk_level)
-            VALUES ($1, $2, $3, $4, $5, $6)
-            "#,
+        let event = SecurityEvent {
+            id: Uuid::new_v4(),
             user_id,
-            event_type,
-            ip_address.map(|ip| IpNetwork::from(ip)),
+            event_type: event_type.as_str().to_string(),
+            ip_address,
             user_agent,
-            details,
-            risk_level
-        )
-        .execute(&self.db)
-        .await;
+            details: event_type.details(),
+            risk_level,
+            created_at: Utc::now(),
+        };
 
         // Log to tracing for immediate visibility
         match risk_level {
-            1..=3 => tracing::info!("Security event: {} for user {:?}", event_type, user_id),
-            4..=6 => tracing::warn!("Medium risk security event: {} for user {:?}", event_type, user_id),
-            7..=10 => tracing::error!("High risk security event: {} for user {:?}", event_type, user_id),
+            1..=3 => tracing::info!("Security event: {} for user {:?}", event.event_type, user_id),
+            4..=6 => tracing::warn!("Medium risk security event: {} for user {:?}", event.event_type, user_id),
+            7..=10 => tracing::error!("High risk security event: {} for user {:?}", event.event_type, user_id),
             _ => {},
         }
+
+        // Hand off to SIEM sinks, member alerts and the durable writer without waiting on any
+        self.forwarder.forward(event.clone());
+        self.notifier.notify(&event);
+        self.recorder.record(event).await;
+    }
+
+    /// Records a deception trigger (canary account, honeytoken) and denies the source address.
+    pub async fn trip_wire(
+        &self,
+        user_id: Option<Uuid>,
+        event_type: SecurityEventType,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) {
+        let reason = format!("Auto-denied after {}", event_type.as_str());
+        self.log_security_event(user_id, event_type, ip_address, user_agent)
+            .await;
+
+        // In the background so the response takes as long as a real one
+        if let Some(ip) = ip_address {
+            let ip_rules = self.ip_rules.clone();
+            tokio::spawn(async move {
+                if let Err(e) = ip_rules.deny_source(ip, &reason).await {
+                    tracing::error!("Failed to auto-deny {}: {}", ip, e);
+                }
+            });
+        }
+    }
+
+    /// Waits until every event logged so far has been stored or journaled.
+    pub async fn flush_events(&self) {
+        self.recorder.flush().await;
+    }
+
+    pub fn event_metrics(&self) -> RecorderMetricsSnapshot {
+        self.recorder.metrics()
+    }
+
+    /// Newest first, optionally for one (destroyed) member; page with `before`.
+    pub async fn destruction_logs(
+        &self,
+        user_id: Option<Uuid>,
+        before: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<DestructionLog>, SecurityError> {
+        let logs = sqlx::query_as!(
+            DestructionLog,
+            r#"
+            SELECT id, user_id, trigger_type,
+                   COALESCE(data_types_destroyed, '{}') AS "data_types_destroyed!",
+                   COALESCE(execution_time, NOW()) AS "execution_time!",
+                   COALESCE(success, false) AS "success!",
+                   COALESCE(forensic_residue_level, 0) AS "forensic_residue_level!",
+                   details
+            FROM destruction_logs
+            WHERE ($1::uuid IS NULL OR user_id = $1)
+              AND ($2::timestamptz IS NULL OR execution_time < $2)
+            ORDER BY execution_time DESC
+            LIMIT $3
+            "#,
+            user_id,
+            before,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(logs)
     }
 
+    /// Newest-first keyset pagination; the cursor is opaque to clients. Runs on the caller's
+    /// scoped transaction so row level security limits members to their own events.
+    pub async fn query_events(
+        &self,
+        conn: &mut PgConnection,
+        filter: &SecurityEventFilter,
+        cursor: Option<&str>,
+        limit: i64,
+    ) -> Result<SecurityEventPage, SecurityError> {
+        let cursor = cursor.map(decode_cursor).transpose()?;
+
+        let mut query = QueryBuilder::<Postgres>::new(
+            r#"
+            SELECT id, user_id, event_type, ip_address, user_agent, details,
+                   COALESCE(risk_level, 1) AS risk_level,
+                   COALESCE(created_at, NOW()) AS created_at
+            FROM security_events
+            WHERE TRUE
+            "#,
+        );
+
+        if let Some(user_id) = filter.user_id {
+            query.push(" AND user_id = ").push_bind(user_id);
+        }
+        if let Some(event_type) = &filter.event_type {
+            query.push(" AND event_type = ").push_bind(event_type.clone());
+        }
+        if let Some(min_risk) = filter.min_risk {
+            query.push(" AND risk_level >= ").push_bind(min_risk);
+        }
+        if let Some(max_risk) = filter.max_risk {
+            query.push(" AND risk_level <= ").push_bind(max_risk);
+        }
+        if let Some(ip_network) = filter.ip_network {
+            query.push(" AND ip_address <<= ").push_bind(ip_network);
+        }
+        if let Some(from) = filter.from {
+            query.push(" AND created_at >= ").push_bind(from);
+        }
+        if let Some(to) = filter.to {
+            query.push(" AND created_at < ").push_bind(to);
+        }
+        if let Some((created_at, id)) = cursor {
+            query
+                .push(" AND (created_at, id) < (")
+                .push_bind(created_at)
+                .push(", ")
+                .push_bind(id)
+                .push(")");
+        }
+
+        // Fetch one extra row to know whether another page exists
+        query
+            .push(" ORDER BY created_at DESC, id DESC LIMIT ")
+            .push_bind(limit + 1);
+
+        let mut events = query
+            .build_query_as::<SecurityEvent>()
+            .fetch_all(conn)
+            .await?;
+
+        let next_cursor = if events.len() as i64 > limit {
+            events.truncate(limit as usize);
+            events.last().map(|event| encode_cursor(event.created_at, event.id))
+        } else {
+            None
+        };
+
+        Ok(SecurityEventPage { events, next_cursor })
+    }
+
+    /// Crypto-shreds the member, then removes their rows. Deleted rows live on in WAL, replicas
+    /// and backups; once the member's key is gone the encrypted fields in those copies cannot be
+    /// decrypted either, but anything stored in plain text stays readable there.
+    ///
+    /// Stripe subscriptions are cancelled first, or Stripe would go on charging an account that
+    /// no longer exists. Destruction does not wait on Stripe: any it could not cancel are listed
+    /// in the destruction log for an administrator to cancel by hand.
     pub async fn trigger_destruction(&self, user_id: Uuid, trigger_type: String) -> Result<(), SecurityError> {
-        // Begin transaction for atomic destruction
-        let mut tx = self.db.begin().await?;
+        let stripe_cancellation_failed = self.cancel_stripe_subscriptions(user_id).await?;
+
+        // Begin transaction for atomic destruction; set off by failed sign-ins, not by the member
+        let mut tx = begin_scoped(&self.db, DbScope::System).await?;
+
+        // Fields not yet under the member's own key would survive shredding in old copies
+        let fields = sqlx::query!(
+            r#"
+            SELECT u.email, u.pending_email, u.mfa_secret, u.destruction_key, u.biometric_hash,
+                np.webhook_secret AS "webhook_secret?"
+            FROM users u
+            LEFT JOIN notification_preferences np ON np.user_id = u.id
+            WHERE u.id = $1 FOR UPDATE OF u
+            "#,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let member_found = fields.is_some();
+        let unshreddable_fields = fields.map_or(0, |row| {
+            [
+                Some(row.email),
+                row.pending_email,
+                row.mfa_secret,
+                row.destruction_key,
+                row.biometric_hash,
+                row.webhook_secret,
+            ]
+            .iter()
+            .flatten()
+            .filter(|value| !FieldCipher::is_shreddable(value))
+            .count()
+        });
+
+        // Kept in plain text, so old copies of these rows stay readable whatever happens to the key
+        let plaintext = sqlx::query!(
+            r#"
+            SELECT
+                EXISTS (SELECT 1 FROM user_sessions WHERE user_id = $1) AS "sessions!",
+                EXISTS (SELECT 1 FROM security_events WHERE user_id = $1) AS "security_events!",
+                EXISTS (SELECT 1 FROM billing_customers WHERE user_id = $1)
+                    OR EXISTS (
+                        SELECT 1 FROM subscriptions
+                        WHERE user_id = $1 AND stripe_subscription_id IS NOT NULL
+                    ) AS "billing_references!"
+            "#,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let plaintext_residue: Vec<&str> = [
+            ("password_hash", member_found),
+            ("sessions", plaintext.sessions),
+            ("security_events", plaintext.security_events),
+            ("billing_references", plaintext.billing_references),
+        ]
+        .into_iter()
+        .filter_map(|(data, present)| present.then_some(data))
+        .collect();
+
+        // Shred first: if anything below fails, the data is already unreadable
+        let key_shredded = match self.cipher.shred_user_key(&mut tx, user_id).await {
+            Ok(_) => true,
+            Err(FieldEncryptionError::DatabaseError(e)) => return Err(e.into()),
+            Err(e) => {
+                tracing::error!("Failed to shred key for user {}: {}", user_id, e);
+                false
+            }
+        };
+
+        let forensic_residue_level = if !key_shredded {
+            DestructionLog::RESIDUE_RECOVERABLE
+        } else if unshreddable_fields > 0 || !plaintext_residue.is_empty() {
+            DestructionLog::RESIDUE_PARTIAL
+        } else {
+            DestructionLog::RESIDUE_NONE
+        };
+
+        let mut data_types = vec!["user_data".to_string(), "sessions".to_string(), "files".to_string()];
+        if key_shredded {
+            data_types.push("encryption_key".to_string());
+        }
 
         // Log destruction event
-        let data_types = vec!["user_data".to_string(), "sessions".to_string(), "files".to_string()];
-        
         sqlx::query!(
             r#"
-            INSERT INTO destruction_logs (user_id, trigger_type, data_types_destroyed, success)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO destruction_logs
+                (user_id, trigger_type, data_types_destroyed, success, forensic_residue_level, details)
+            VALUES ($1, $2, $3, $4, $5, $6)
             "#,
             user_id,
             trigger_type,
             &data_types,
-            true
+            key_shredded,
+            forensic_residue_level,
+            serde_json::json!({
+                "key_shredded": key_shredded,
+                "unshreddable_fields": unshreddable_fields,
+                "plaintext_residue": plaintext_residue,
+                "stripe_cancellation_failed": stripe_cancellation_failed
+            })
         )
         .execute(&mut *tx)
         .await?;
@@ -105,22 +387,79 @@ impl SecurityService {
         // Commit transaction
         tx.commit().await?;
 
-        tracing::warn!("User {} destroyed due to trigger: {}", user_id, trigger_type);
+        tracing::warn!(
+            "User {} destroyed due to trigger: {} (forensic residue level {})",
+            user_id,
+            trigger_type,
+            forensic_residue_level
+        );
+        // The member's own events are gone; the recorder stores this one unlinked from them
+        self.log_security_event(
+            Some(user_id),
+            SecurityEventType::DestructionTriggered { trigger_type },
+            None,
+            None,
+        )
+        .await;
 
         Ok(())
     }
 
-    fn calculate_risk_level(&self, event_type: &str, _ip_address: &Option<IpAddr>) -> i32 {
-        match event_type {
-            "login_failed" => 3,
-            "login_success" => 1,
-            "user_registered" => 2,
-            "password_reset" => 4,
-            "destruction_triggered" => 10,
-            "suspicious_activity" => 7,
-            "multiple_failed_logins" => 6,
-            "account_locked" => 5,
-            _ => 1,
+    /// Cancels the member's Stripe subscriptions that have not ended; returns the ids Stripe
+    /// could not be reached or refused to cancel.
+    async fn cancel_stripe_subscriptions(&self, user_id: Uuid) -> Result<Vec<String>, SecurityError> {
+        let mut tx = begin_scoped(&self.db, DbScope::System).await?;
+        let subscriptions = sqlx::query_scalar!(
+            r#"
+            SELECT stripe_subscription_id AS "stripe_subscription_id!"
+            FROM subscriptions
+            WHERE user_id = $1 AND stripe_subscription_id IS NOT NULL
+              AND status NOT IN ('canceled', 'incomplete_expired')
+            "#,
+            user_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        let mut failed = Vec::new();
+        for stripe_subscription_id in subscriptions {
+            if let Err(e) = self.stripe.cancel_subscription(&stripe_subscription_id).await {
+                tracing::error!(
+                    "Failed to cancel Stripe subscription {} of destroyed user {}: {}",
+                    stripe_subscription_id,
+                    user_id,
+                    e
+                );
+                failed.push(stripe_subscription_id);
+            }
         }
+
+        Ok(failed)
+    }
+
+    fn calculate_risk_level(&self, event_type: &SecurityEventType, ip_address: &Option<IpAddr>) -> i32 {
+        let adjustment = ip_address.map_or(0, |ip| self.ip_rules.risk_adjustment(ip));
+        (event_type.base_risk_level() + adjustment).min(10)
     }
-}
\ No newline at end of file
+}
+
+fn encode_cursor(created_at: DateTime<Utc>, id: Uuid) -> String {
+    CURSOR_ENGINE.encode(format!("{}|{}", created_at.to_rfc3339(), id))
+}
+
+fn decode_cursor(cursor: &str) -> Result<(DateTime<Utc>, Uuid), SecurityError> {
+    let raw = CURSOR_ENGINE
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or(SecurityError::InvalidCursor)?;
+    let (created_at, id) = raw.split_once('|').ok_or(SecurityError::InvalidCursor)?;
+
+    let created_at = DateTime::parse_from_rfc3339(created_at)
+        .map_err(|_| SecurityError::InvalidCursor)?
+        .with_timezone(&Utc);
+    let id = Uuid::parse_str(id).map_err(|_| SecurityError::InvalidCursor)?;
+
+    Ok((created_at, id))
+}
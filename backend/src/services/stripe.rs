@@ -0,0 +1,458 @@
+use crate::utils::hex_decode;
+use ring::hmac;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+const STRIPE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Minimal client for the parts of the Stripe API billing uses.
+///
+/// Requests are form-encoded as Stripe expects. The API base comes from `STRIPE_API_BASE` so
+/// tests can run against a local mock.
+#[derive(Debug, Clone)]
+pub struct StripeClient {
+    http: reqwest::Client,
+    api_base: String,
+    secret_key: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum StripeError {
+    NotConfigured,
+    Request(reqwest::Error),
+    Api { status: u16, message: String },
+    UnexpectedResponse(&'static str),
+}
+
+impl std::fmt::Display for StripeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StripeError::NotConfigured => write!(f, "STRIPE_SECRET_KEY is not set"),
+            StripeError::Request(e) => write!(f, "Stripe request failed: {}", e),
+            StripeError::Api { status, message } => {
+                write!(f, "Stripe responded with {}: {}", status, message)
+            }
+            StripeError::UnexpectedResponse(what) => write!(f, "Unexpected Stripe response: {}", what),
+        }
+    }
+}
+
+impl From<reqwest::Error> for StripeError {
+    fn from(err: reqwest::Error) -> Self {
+        StripeError::Request(err)
+    }
+}
+
+/// A newly created Checkout or portal session.
+#[derive(Debug, Deserialize)]
+pub struct StripeSession {
+    pub id: String,
+    pub url: String,
+}
+
+/// A webhook event; `data.object` is parsed according to `event_type`.
+#[derive(Debug, Deserialize)]
+pub struct StripeEvent {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub created: i64,
+    pub data: StripeEventData,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StripeEventData {
+    pub object: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StripeCheckoutSession {
+    pub id: String,
+    pub customer: Option<String>,
+    pub subscription: Option<String>,
+    pub payment_status: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StripeSubscription {
+    pub id: String,
+    pub customer: Option<String>,
+    pub status: String,
+    pub current_period_start: Option<i64>,
+    pub current_period_end: Option<i64>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    pub items: Option<StripeList<StripeSubscriptionItem>>,
+}
+
+/// One priced line of a subscription; ours have exactly one.
+#[derive(Debug, Deserialize)]
+pub struct StripeSubscriptionItem {
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StripeInvoice {
+    pub subscription: Option<String>,
+    pub lines: Option<StripeList<StripeInvoiceLine>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StripeList<T> {
+    pub data: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StripeInvoiceLine {
+    pub period: Option<StripePeriod>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StripePeriod {
+    pub start: i64,
+    pub end: i64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WebhookSignatureError {
+    Malformed,
+    Expired,
+    Mismatch,
+}
+
+/// Checks a `Stripe-Signature` header (`t=<unix time>,v1=<hex HMAC-SHA256 of "t.payload">`,
+/// possibly with several `v1` entries while a secret is rolled) and rejects signatures older or
+/// newer than `tolerance_seconds`, so captured deliveries cannot be replayed later.
+pub fn verify_webhook_signature(
+    secret: &str,
+    header: &str,
+    payload: &[u8],
+    now: i64,
+    tolerance_seconds: i64,
+) -> Result<(), WebhookSignatureError> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => timestamp = value.parse::<i64>().ok(),
+            Some(("v1", value)) => signatures.extend(hex_decode(value)),
+            _ => {}
+        }
+    }
+    let timestamp = timestamp.ok_or(WebhookSignatureError::Malformed)?;
+    if signatures.is_empty() {
+        return Err(WebhookSignatureError::Malformed);
+    }
+    if (now - timestamp).abs() > tolerance_seconds {
+        return Err(WebhookSignatureError::Expired);
+    }
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let mut signed = timestamp.to_string().into_bytes();
+    signed.push(b'.');
+    signed.extend_from_slice(payload);
+
+    signatures
+        .iter()
+        .any(|signature| hmac::verify(&key, &signed, signature).is_ok())
+        .then_some(())
+        .ok_or(WebhookSignatureError::Mismatch)
+}
+
+#[derive(Debug, Deserialize)]
+struct StripeObject {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct StripeErrorBody {
+    error: StripeErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+struct StripeErrorDetail {
+    message: Option<String>,
+}
+
+impl StripeClient {
+    pub fn new(api_base: &str, secret_key: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(STRIPE_TIMEOUT)
+                .build()
+                .expect("HTTP client"),
+            api_base: api_base.to_string(),
+            secret_key: secret_key.filter(|key| !key.is_empty()),
+        }
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.secret_key.is_some()
+    }
+
+    /// A customer carrying nothing but the member's id; repeated calls for the same member within
+    /// Stripe's idempotency window return the same customer.
+    pub async fn create_customer(&self, user_id: Uuid) -> Result<String, StripeError> {
+        let form = [("metadata[user_id]".to_string(), user_id.to_string())];
+        let customer: StripeObject = self
+            .post("/v1/customers", &form, Some(&format!("customer-{}", user_id)))
+            .await?;
+        Ok(customer.id)
+    }
+
+    /// `params` are Checkout session parameters in Stripe's bracketed form notation.
+    pub async fn create_checkout_session(
+        &self,
+        params: &[(String, String)],
+        idempotency_key: &str,
+    ) -> Result<StripeSession, StripeError> {
+        self.post("/v1/checkout/sessions", params, Some(idempotency_key)).await
+    }
+
+    pub async fn create_portal_session(
+        &self,
+        customer_id: &str,
+        return_url: &str,
+    ) -> Result<StripeSession, StripeError> {
+        let form = [
+            ("customer".to_string(), customer_id.to_string()),
+            ("return_url".to_string(), return_url.to_string()),
+        ];
+        self.post("/v1/billing_portal/sessions", &form, None).await
+    }
+
+    /// A product named after a catalog version, for subscription prices outside Checkout.
+    pub async fn create_product(&self, membership_id: Uuid, name: &str) -> Result<String, StripeError> {
+        let form = [
+            ("name".to_string(), name.to_string()),
+            ("metadata[membership_id]".to_string(), membership_id.to_string()),
+        ];
+        let product: StripeObject = self
+            .post("/v1/products", &form, Some(&format!("product-{}", membership_id)))
+            .await?;
+        Ok(product.id)
+    }
+
+    /// `params` are coupon parameters in Stripe's bracketed form notation.
+    pub async fn create_coupon(
+        &self,
+        promotion_id: Uuid,
+        params: &[(String, String)],
+    ) -> Result<String, StripeError> {
+        let coupon: StripeObject = self
+            .post("/v1/coupons", params, Some(&format!("coupon-{}", promotion_id)))
+            .await?;
+        Ok(coupon.id)
+    }
+
+    /// Stops new redemptions; discounts already applied with the coupon keep running. A coupon
+    /// Stripe no longer has counts as deleted.
+    pub async fn delete_coupon(&self, coupon_id: &str) -> Result<(), StripeError> {
+        let secret_key = self.secret_key.as_deref().ok_or(StripeError::NotConfigured)?;
+
+        let request = self
+            .http
+            .delete(format!("{}/v1/coupons/{}", self.api_base, coupon_id))
+            .bearer_auth(secret_key);
+        match self.send::<StripeObject>(request).await {
+            Ok(_) | Err(StripeError::Api { status: 404, .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn retrieve_subscription(
+        &self,
+        subscription_id: &str,
+    ) -> Result<StripeSubscription, StripeError> {
+        self.get(&format!("/v1/subscriptions/{}", subscription_id)).await
+    }
+
+    /// `params` are subscription update parameters in Stripe's bracketed form notation.
+    pub async fn update_subscription(
+        &self,
+        subscription_id: &str,
+        params: &[(String, String)],
+        idempotency_key: &str,
+    ) -> Result<(), StripeError> {
+        let _: StripeObject = self
+            .post(
+                &format!("/v1/subscriptions/{}", subscription_id),
+                params,
+                Some(idempotency_key),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Cancels at once, without a final invoice. A subscription Stripe no longer has counts as
+    /// cancelled.
+    pub async fn cancel_subscription(&self, subscription_id: &str) -> Result<(), StripeError> {
+        let secret_key = self.secret_key.as_deref().ok_or(StripeError::NotConfigured)?;
+
+        let request = self
+            .http
+            .delete(format!("{}/v1/subscriptions/{}", self.api_base, subscription_id))
+            .bearer_auth(secret_key);
+        match self.send::<StripeObject>(request).await {
+            Ok(_) | Err(StripeError::Api { status: 404, .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<T, StripeError> {
+        let secret_key = self.secret_key.as_deref().ok_or(StripeError::NotConfigured)?;
+
+        self.send(
+            self.http
+                .get(format!("{}{}", self.api_base, path))
+                .bearer_auth(secret_key),
+        )
+        .await
+    }
+
+    async fn post<T: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        form: &[(String, String)],
+        idempotency_key: Option<&str>,
+    ) -> Result<T, StripeError> {
+        let secret_key = self.secret_key.as_deref().ok_or(StripeError::NotConfigured)?;
+
+        let mut request = self
+            .http
+            .post(format!("{}{}", self.api_base, path))
+            .bearer_auth(secret_key)
+            .form(form);
+        if let Some(key) = idempotency_key {
+            request = request.header("Idempotency-Key", key);
+        }
+
+        self.send(request).await
+    }
+
+    async fn send<T: for<'de> Deserialize<'de>>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, StripeError> {
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let message = response
+                .json::<StripeErrorBody>()
+                .await
+                .ok()
+                .and_then(|body| body.error.message)
+                .unwrap_or_else(|| "no error message".to_string());
+            return Err(StripeError::Api {
+                status: status.as_u16(),
+                message,
+            });
+        }
+
+        Ok(response.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::hex_encode;
+
+    const SECRET: &str = "whsec_test";
+    const PAYLOAD: &[u8] = br#"{"id":"evt_1","type":"invoice.paid"}"#;
+    const NOW: i64 = 1_700_000_000;
+    const TOLERANCE: i64 = 300;
+
+    fn sign(secret: &str, timestamp: i64, payload: &[u8]) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let mut signed = format!("{}.", timestamp).into_bytes();
+        signed.extend_from_slice(payload);
+        hex_encode(hmac::sign(&key, &signed).as_ref())
+    }
+
+    fn verify(header: &str) -> Result<(), WebhookSignatureError> {
+        verify_webhook_signature(SECRET, header, PAYLOAD, NOW, TOLERANCE)
+    }
+
+    #[test]
+    fn valid_signatures_are_accepted() {
+        let header = format!("t={},v1={}", NOW, sign(SECRET, NOW, PAYLOAD));
+        assert_eq!(verify(&header), Ok(()));
+
+        // Stripe also sends a v0 test-mode entry, and may add spaces
+        let header = format!("t={}, v1={}, v0=deadbeef", NOW - 10, sign(SECRET, NOW - 10, PAYLOAD));
+        assert_eq!(verify(&header), Ok(()));
+    }
+
+    #[test]
+    fn signatures_over_anything_else_are_a_mismatch() {
+        let other_secret = format!("t={},v1={}", NOW, sign("whsec_other", NOW, PAYLOAD));
+        assert_eq!(verify(&other_secret), Err(WebhookSignatureError::Mismatch));
+
+        let other_payload = format!("t={},v1={}", NOW, sign(SECRET, NOW, b"{}"));
+        assert_eq!(verify(&other_payload), Err(WebhookSignatureError::Mismatch));
+
+        // The timestamp is signed too, so it cannot be moved forward to dodge the tolerance
+        let moved = format!("t={},v1={}", NOW, sign(SECRET, NOW - 3600, PAYLOAD));
+        assert_eq!(verify(&moved), Err(WebhookSignatureError::Mismatch));
+    }
+
+    #[test]
+    fn signatures_outside_the_tolerance_have_expired() {
+        for timestamp in [NOW - TOLERANCE - 1, NOW + TOLERANCE + 1] {
+            let header = format!("t={},v1={}", timestamp, sign(SECRET, timestamp, PAYLOAD));
+            assert_eq!(verify(&header), Err(WebhookSignatureError::Expired));
+        }
+
+        let edge = NOW - TOLERANCE;
+        let header = format!("t={},v1={}", edge, sign(SECRET, edge, PAYLOAD));
+        assert_eq!(verify(&header), Ok(()));
+    }
+
+    #[test]
+    fn headers_without_a_timestamp_or_v1_are_malformed() {
+        let signature = sign(SECRET, NOW, PAYLOAD);
+
+        assert_eq!(verify(&format!("t={}", NOW)), Err(WebhookSignatureError::Malformed));
+        assert_eq!(
+            verify(&format!("t={},v0={}", NOW, signature)),
+            Err(WebhookSignatureError::Malformed)
+        );
+        assert_eq!(verify(&format!("v1={}", signature)), Err(WebhookSignatureError::Malformed));
+        assert_eq!(
+            verify(&format!("t=yesterday,v1={}", signature)),
+            Err(WebhookSignatureError::Malformed)
+        );
+        assert_eq!(verify(""), Err(WebhookSignatureError::Malformed));
+    }
+
+    #[test]
+    fn any_v1_entry_may_match_while_a_secret_is_rolled() {
+        let old = sign("whsec_old", NOW, PAYLOAD);
+        let new = sign(SECRET, NOW, PAYLOAD);
+
+        assert_eq!(verify(&format!("t={},v1={},v1={}", NOW, old, new)), Ok(()));
+        assert_eq!(verify(&format!("t={},v1={},v1={}", NOW, new, old)), Ok(()));
+        assert_eq!(
+            verify(&format!("t={},v1={},v1={}", NOW, old, old)),
+            Err(WebhookSignatureError::Mismatch)
+        );
+    }
+
+    #[test]
+    fn non_hex_entries_are_ignored() {
+        let signature = sign(SECRET, NOW, PAYLOAD);
+
+        assert_eq!(
+            verify(&format!("t={},v1=zz{}", NOW, &signature[2..])),
+            Err(WebhookSignatureError::Malformed)
+        );
+        assert_eq!(
+            verify(&format!("t={},v1={}", NOW, &signature[1..])),
+            Err(WebhookSignatureError::Malformed)
+        );
+        assert_eq!(verify(&format!("t={},v1=not-hex,v1={}", NOW, signature)), Ok(()));
+    }
+}
@@ -0,0 +1,406 @@
+use crate::config::BillingConfig;
+use crate::models::{SecurityEventType, SubscriptionStatus};
+use crate::services::{
+    BillingError, BillingService, FieldCipher, MailerService, SecurityService, EMAIL_FIELD,
+};
+use crate::utils::{begin_scoped, set_scope, DbScope};
+use chrono::{DateTime, Utc};
+use sqlx::{Connection, PgConnection, PgPool};
+use std::time::Duration;
+use uuid::Uuid;
+
+// Subscriptions per statement, so a large backlog never holds long locks
+const BATCH_SIZE: i64 = 500;
+
+// Session-level advisory lock: only one instance runs the schedule at a time
+const SCHEDULER_LOCK: i64 = 0x7375_6273;
+
+/// Time-driven subscription changes: payment reminders while a `past_due` subscription is in
+/// its grace period, moving the member to basic once grace runs out, ending prepaid
+/// memberships and no-card trials whose time has run out, and downgrades scheduled for the end
+/// of a billing period.
+///
+/// Moving a member to a lower tier never deletes what they have stored. Content over the new
+/// tier's limits stays readable but cannot be added to (see `EntitlementService::check_limit`).
+#[derive(Debug, Clone)]
+pub struct SubscriptionScheduler {
+    db: PgPool,
+    config: BillingConfig,
+    billing_service: BillingService,
+    mailer: MailerService,
+    cipher: FieldCipher,
+    security_service: SecurityService,
+}
+
+#[derive(Debug)]
+pub enum SchedulerError {
+    DatabaseError(sqlx::Error),
+    Billing(BillingError),
+    RunInProgress,
+}
+
+impl std::fmt::Display for SchedulerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SchedulerError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            SchedulerError::Billing(e) => write!(f, "{}", e),
+            SchedulerError::RunInProgress => write!(f, "A scheduler run is already in progress"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for SchedulerError {
+    fn from(err: sqlx::Error) -> Self {
+        SchedulerError::DatabaseError(err)
+    }
+}
+
+impl From<BillingError> for SchedulerError {
+    fn from(err: BillingError) -> Self {
+        SchedulerError::Billing(err)
+    }
+}
+
+/// What one run did.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedulerRun {
+    pub reminders_sent: u64,
+    pub grace_expired: u64,
+    pub prepaid_ended: u64,
+    pub plan_changes_applied: u64,
+}
+
+impl SubscriptionScheduler {
+    pub fn start(
+        db: PgPool,
+        config: BillingConfig,
+        billing_service: BillingService,
+        mailer: MailerService,
+        cipher: FieldCipher,
+        security_service: SecurityService,
+    ) -> Self {
+        let scheduler = Self {
+            db,
+            config,
+            billing_service,
+            mailer,
+            cipher,
+            security_service,
+        };
+
+        let runner = scheduler.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(runner.run_interval());
+            loop {
+                interval.tick().await;
+                match runner.run().await {
+                    Ok(_) | Err(SchedulerError::RunInProgress) => {}
+                    Err(e) => tracing::error!("Subscription scheduler run failed: {}", e),
+                }
+            }
+        });
+
+        scheduler
+    }
+
+    fn run_interval(&self) -> Duration {
+        Duration::from_secs(self.config.scheduler_interval_seconds)
+    }
+
+    pub async fn run(&self) -> Result<SchedulerRun, SchedulerError> {
+        let mut conn = self.db.acquire().await?;
+        let locked = sqlx::query_scalar!("SELECT pg_try_advisory_lock($1)", SCHEDULER_LOCK)
+            .fetch_one(&mut *conn)
+            .await?
+            .unwrap_or(false);
+        if !locked {
+            return Err(SchedulerError::RunInProgress);
+        }
+
+        let result = self.run_locked(&mut conn).await;
+
+        if let Err(e) = sqlx::query_scalar!("SELECT pg_advisory_unlock($1)", SCHEDULER_LOCK)
+            .fetch_one(&mut *conn)
+            .await
+        {
+            // Dropping the connection from the pool releases the lock with its session
+            tracing::error!("Failed to release subscription scheduler lock: {}", e);
+            conn.detach();
+        }
+
+        let run = result?;
+        if run.reminders_sent + run.grace_expired + run.prepaid_ended + run.plan_changes_applied > 0
+        {
+            tracing::info!(
+                "Subscription scheduler sent {} reminders, ended {} grace periods and {} prepaid memberships or trials, applied {} plan changes",
+                run.reminders_sent,
+                run.grace_expired,
+                run.prepaid_ended,
+                run.plan_changes_applied
+            );
+        }
+        Ok(run)
+    }
+
+    async fn run_locked(&self, conn: &mut PgConnection) -> Result<SchedulerRun, SchedulerError> {
+        // Expire first so nobody is reminded about a grace period that already ended
+        let grace_expired = self.expire_grace_periods(conn).await?;
+        let reminders_sent = self.send_payment_reminders(conn).await?;
+        let prepaid_ended = self.end_prepaid_periods(conn).await?;
+        // Downgrades go to Stripe within the last run before their period ends, so the renewal
+        // is billed at the new price
+        let lead = chrono::Duration::seconds(self.run_interval().as_secs() as i64);
+        let plan_changes_applied = self
+            .billing_service
+            .apply_due_plan_changes(Utc::now() + lead)
+            .await?;
+
+        Ok(SchedulerRun {
+            reminders_sent,
+            grace_expired,
+            prepaid_ended,
+            plan_changes_applied,
+        })
+    }
+
+    /// `past_due` subscriptions whose grace period is over become `unpaid`, moving their
+    /// members to basic. A later successful payment makes them `active` again.
+    async fn expire_grace_periods(&self, conn: &mut PgConnection) -> Result<u64, SchedulerError> {
+        let mut expired = 0;
+        loop {
+            // The outer status check is re-evaluated if a webhook changed the row meanwhile
+            let batch = sqlx::query!(
+                r#"
+                UPDATE subscriptions s SET status = $1, updated_at = NOW()
+                FROM memberships m
+                WHERE m.id = s.membership_id AND s.status = 'past_due' AND s.id IN (
+                    SELECT id FROM subscriptions
+                    WHERE status = 'past_due' AND grace_ends_at <= NOW()
+                    LIMIT $2
+                )
+                RETURNING s.id, s.user_id, m.tier, m.name
+                "#,
+                SubscriptionStatus::Unpaid.as_str(),
+                BATCH_SIZE
+            )
+            .fetch_all(&mut *conn)
+            .await?;
+            let count = batch.len();
+
+            for subscription in batch {
+                self.security_service
+                    .log_security_event(
+                        Some(subscription.user_id),
+                        SecurityEventType::SubscriptionChanged {
+                            subscription_id: subscription.id,
+                            tier: subscription.tier,
+                            status: SubscriptionStatus::Unpaid.as_str().to_string(),
+                        },
+                        None,
+                        None,
+                    )
+                    .await;
+
+                self.email_member(
+                    subscription.user_id,
+                    &format!("Your {} membership has ended", subscription.name),
+                    &format!(
+                        "We still haven't been able to take payment for your {} membership, so \
+                         your account has moved to Basic.\n\nNothing has been deleted. Everything \
+                         you have stored stays readable; anything over Basic's limits is \
+                         read-only until you upgrade again.\n\nUpdate your payment details to \
+                         restore {}: {}",
+                        subscription.name, subscription.name, self.config.return_url
+                    ),
+                )
+                .await;
+            }
+
+            expired += count as u64;
+            if count < BATCH_SIZE as usize {
+                break;
+            }
+        }
+
+        Ok(expired)
+    }
+
+    /// Subscriptions without Stripe ids (prepaid access codes and no-card trials) end with their
+    /// period; there is no renewal to wait for. `current_membership_id` already stops counting
+    /// them at that point.
+    async fn end_prepaid_periods(&self, conn: &mut PgConnection) -> Result<u64, SchedulerError> {
+        let mut ended = 0;
+        loop {
+            // Re-checked like grace expiry, in case a code extended the period meanwhile
+            let batch = sqlx::query!(
+                r#"
+                WITH due AS (
+                    SELECT id, status FROM subscriptions
+                    WHERE stripe_subscription_id IS NULL AND status IN ('active', 'trialing')
+                      AND current_period_end <= NOW()
+                    LIMIT $2
+                )
+                UPDATE subscriptions s SET status = $1, updated_at = NOW()
+                FROM memberships m, due
+                WHERE due.id = s.id AND m.id = s.membership_id
+                  AND s.status IN ('active', 'trialing') AND s.current_period_end <= NOW()
+                RETURNING s.id, s.user_id, m.tier, m.name, due.status AS was
+                "#,
+                SubscriptionStatus::Canceled.as_str(),
+                BATCH_SIZE
+            )
+            .fetch_all(&mut *conn)
+            .await?;
+            let count = batch.len();
+
+            for subscription in batch {
+                self.security_service
+                    .log_security_event(
+                        Some(subscription.user_id),
+                        SecurityEventType::SubscriptionChanged {
+                            subscription_id: subscription.id,
+                            tier: subscription.tier,
+                            status: SubscriptionStatus::Canceled.as_str().to_string(),
+                        },
+                        None,
+                        None,
+                    )
+                    .await;
+
+                let (subject, ended, restore) = if subscription.was == "trialing" {
+                    ("trial", "trial", "Subscribe to keep")
+                } else {
+                    ("membership", "prepaid membership", "Redeem another code or subscribe to restore")
+                };
+                self.email_member(
+                    subscription.user_id,
+                    &format!("Your {} {} has ended", subscription.name, subject),
+                    &format!(
+                        "Your {} {} has run out, so your account has moved to Basic.\n\nNothing \
+                         has been deleted. Everything you have stored stays readable; anything \
+                         over Basic's limits is read-only until you upgrade again.\n\n{} {}: {}",
+                        subscription.name,
+                        ended,
+                        restore,
+                        subscription.name,
+                        self.config.return_url
+                    ),
+                )
+                .await;
+            }
+
+            ended += count as u64;
+            if count < BATCH_SIZE as usize {
+                break;
+            }
+        }
+
+        Ok(ended)
+    }
+
+    /// Reminds members in a grace period every `reminder_interval_days`, starting right away.
+    async fn send_payment_reminders(&self, conn: &mut PgConnection) -> Result<u64, SchedulerError> {
+        let mut tx = conn.begin().await?;
+        set_scope(&mut tx, DbScope::System).await?;
+        let due = sqlx::query!(
+            r#"
+            SELECT s.id, s.user_id, s.grace_ends_at AS "grace_ends_at!", m.name
+            FROM subscriptions s
+            JOIN memberships m ON m.id = s.membership_id
+            JOIN users u ON u.id = s.user_id
+            WHERE s.status = 'past_due' AND s.grace_ends_at > NOW() AND NOT u.is_canary
+              AND (s.last_reminder_at IS NULL
+                   OR s.last_reminder_at <= NOW() - make_interval(days => $1))
+            ORDER BY s.grace_ends_at
+            LIMIT $2
+            "#,
+            self.config.reminder_interval_days,
+            BATCH_SIZE
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        let mut sent = 0;
+        for subscription in due {
+            let sent_to = self
+                .email_member(
+                    subscription.user_id,
+                    &format!("Payment failed for your {} membership", subscription.name),
+                    &payment_reminder(
+                        &subscription.name,
+                        subscription.grace_ends_at,
+                        &self.config.return_url,
+                    ),
+                )
+                .await;
+            if !sent_to {
+                continue;
+            }
+
+            sqlx::query!(
+                r#"
+                UPDATE subscriptions
+                SET reminders_sent = reminders_sent + 1, last_reminder_at = NOW()
+                WHERE id = $1
+                "#,
+                subscription.id
+            )
+            .execute(&mut *conn)
+            .await?;
+            sent += 1;
+        }
+
+        Ok(sent)
+    }
+
+    /// Returns false if the member could not be emailed; canaries never are.
+    async fn email_member(&self, user_id: Uuid, subject: &str, body: &str) -> bool {
+        let lookup = async {
+            let mut tx = begin_scoped(&self.db, DbScope::Member(user_id)).await?;
+            let email = sqlx::query_scalar!(
+                "SELECT email FROM users WHERE id = $1 AND NOT is_canary",
+                user_id
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            Ok::<_, sqlx::Error>(email)
+        };
+        let email = match lookup.await {
+            Ok(Some(email)) => email,
+            Ok(None) => return false,
+            Err(e) => {
+                tracing::error!(
+                    "Failed to look up member {} for billing email: {}",
+                    user_id,
+                    e
+                );
+                return false;
+            }
+        };
+
+        match self.cipher.decrypt(user_id, EMAIL_FIELD, &email) {
+            Ok(email) => {
+                self.mailer.send(&email, subject, body).await;
+                true
+            }
+            Err(e) => {
+                tracing::error!("Failed to decrypt email of member {}: {}", user_id, e);
+                false
+            }
+        }
+    }
+}
+
+fn payment_reminder(membership: &str, grace_ends_at: DateTime<Utc>, billing_url: &str) -> String {
+    format!(
+        "Your last payment for {} didn't go through.\n\nUpdate your payment details by {} to \
+         keep your membership: {}\n\nAfter that your account moves to Basic. Nothing you have \
+         stored will be deleted, but anything over Basic's limits becomes read-only until you \
+         upgrade again.",
+        membership,
+        grace_ends_at.format("%Y-%m-%d %H:%M UTC"),
+        billing_url
+    )
+}
@@ -0,0 +1,199 @@
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::{Executor, PgConnection, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Who a request-scoped transaction acts for. Row level security policies
+/// (migrations/007_row_level_security.sql) read this back from `app.role` and `app.user_id`.
+#[derive(Debug, Clone, Copy)]
+pub enum DbScope {
+    /// Only the member's own rows are visible.
+    Member(Uuid),
+    /// Administrator operations; every row is visible.
+    Admin(Uuid),
+    /// Background jobs, Stripe webhooks and the flows that run before anyone is authenticated
+    /// (registration, login, password reset, token refresh); every row is visible.
+    System,
+}
+
+impl DbScope {
+    fn role(&self) -> &'static str {
+        match self {
+            DbScope::Member(_) => "member",
+            DbScope::Admin(_) => "admin",
+            DbScope::System => "system",
+        }
+    }
+
+    fn user_id(&self) -> String {
+        match self {
+            DbScope::Member(user_id) | DbScope::Admin(user_id) => user_id.to_string(),
+            DbScope::System => String::new(),
+        }
+    }
+}
+
+/// Connection pool for the backend. Sessions carry no privilege, so row level security hides
+/// every protected row until `begin_scoped` says who a transaction acts for.
+pub async fn connect(database_url: &str, max_connections: u32) -> Result<PgPool, sqlx::Error> {
+    PgPoolOptions::new()
+        .max_connections(max_connections)
+        .after_connect(|conn, _meta| {
+            Box::pin(async move {
+                conn.execute("SET app.role = 'anonymous'").await?;
+                Ok(())
+            })
+        })
+        .connect(database_url)
+        .await
+}
+
+/// Begins a transaction scoped to `scope`. The settings are transaction-local, so they are
+/// gone once it commits or rolls back and the connection goes back to the pool.
+pub async fn begin_scoped(
+    db: &PgPool,
+    scope: DbScope,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    set_scope(&mut tx, scope).await?;
+    Ok(tx)
+}
+
+/// Scopes the transaction already open on `conn`, for callers that keep one connection across
+/// several transactions.
+pub async fn set_scope(conn: &mut PgConnection, scope: DbScope) -> Result<(), sqlx::Error> {
+    // set_config(.., true) is SET LOCAL, but takes bind parameters
+    sqlx::query!(
+        r#"
+        SELECT set_config('app.role', $1, true) AS role,
+               set_config('app.user_id', $2, true) AS user_id
+        "#,
+        scope.role(),
+        scope.user_id()
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn database() -> PgPool {
+        dotenvy::dotenv().ok();
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL");
+        connect(&url, 2).await.expect("connect to the backend's database")
+    }
+
+    // A member with a session, a security event and a conversation
+    async fn seed_member(db: &PgPool) -> Uuid {
+        let mut tx = begin_scoped(db, DbScope::System).await.unwrap();
+        let user_id: Uuid = sqlx::query_scalar(
+            "INSERT INTO users (email, password_hash) VALUES ($1, 'x') RETURNING id",
+        )
+        .bind(format!("rls-{}@example.com", Uuid::new_v4()))
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO user_sessions (user_id, session_token, expires_at)
+            VALUES ($1, $2, NOW() + INTERVAL '1 hour')
+            "#,
+        )
+        .bind(user_id)
+        .bind(Uuid::new_v4().to_string())
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO security_events (user_id, event_type, risk_level) VALUES ($1, 'login_success', 1)",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO conversations (user_id, title) VALUES ($1, 'plans')")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        user_id
+    }
+
+    // Rows of `owner` in each protected table that `scope` can see
+    async fn visible(db: &PgPool, scope: Option<DbScope>, owner: Uuid) -> Vec<i64> {
+        let mut tx = match scope {
+            Some(scope) => begin_scoped(db, scope).await.unwrap(),
+            None => db.begin().await.unwrap(),
+        };
+        let mut counts = Vec::new();
+        for query in [
+            "SELECT COUNT(*) FROM users WHERE id = $1",
+            "SELECT COUNT(*) FROM user_sessions WHERE user_id = $1",
+            "SELECT COUNT(*) FROM security_events WHERE user_id = $1",
+            "SELECT COUNT(*) FROM conversations WHERE user_id = $1",
+        ] {
+            counts.push(
+                sqlx::query_scalar(query)
+                    .bind(owner)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .unwrap(),
+            );
+        }
+        tx.commit().await.unwrap();
+        counts
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the backend's database"]
+    async fn members_cannot_see_or_change_each_others_rows() {
+        let db = database().await;
+        let (alice, bob) = (seed_member(&db).await, seed_member(&db).await);
+
+        assert_eq!(visible(&db, Some(DbScope::Member(alice)), alice).await, vec![1, 1, 1, 1]);
+        assert_eq!(visible(&db, Some(DbScope::Member(alice)), bob).await, vec![0, 0, 0, 0]);
+        // The pool's own sessions are anonymous, and a committed scope does not linger
+        assert_eq!(visible(&db, None, alice).await, vec![0, 0, 0, 0]);
+        assert_eq!(visible(&db, Some(DbScope::System), bob).await, vec![1, 1, 1, 1]);
+
+        let mut tx = begin_scoped(&db, DbScope::Member(alice)).await.unwrap();
+        let updated = sqlx::query("UPDATE users SET mfa_enabled = true WHERE id = $1")
+            .bind(bob)
+            .execute(&mut *tx)
+            .await
+            .unwrap()
+            .rows_affected();
+        assert_eq!(updated, 0);
+        let deleted = sqlx::query("DELETE FROM conversations WHERE user_id = $1")
+            .bind(bob)
+            .execute(&mut *tx)
+            .await
+            .unwrap()
+            .rows_affected();
+        assert_eq!(deleted, 0);
+        // Nor write rows in another member's name
+        let inserted = sqlx::query("INSERT INTO conversations (user_id, title) VALUES ($1, 'forged')")
+            .bind(bob)
+            .execute(&mut *tx)
+            .await;
+        assert!(inserted.is_err());
+        tx.rollback().await.unwrap();
+
+        let mut tx = begin_scoped(&db, DbScope::System).await.unwrap();
+        sqlx::query("DELETE FROM security_events WHERE user_id = ANY($1)")
+            .bind(vec![alice, bob])
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM users WHERE id = ANY($1)")
+            .bind(vec![alice, bob])
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+    }
+}
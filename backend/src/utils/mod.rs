@@ -1,27 +1,70 @@
-use crate::config::Config;
-use crate::services::{AuthService, SecurityService};
+pub mod db;
+pub mod totp;
+
+use crate::services::{
+    AccessCodeService, AuthService, BillingService, ContentService, DeceptionService, EntitlementService, IpRuleService, MembershipService, NotificationService,
+    OrganizationService, PromotionService, RbacService, RetentionService, SecurityService,
+};
+use axum::http::{header::USER_AGENT, HeaderMap};
+use ring::digest::{digest, SHA256};
+use ring::hmac;
 use sqlx::PgPool;
 
+pub use db::{begin_scoped, set_scope, DbScope};
+pub use totp::verify_totp;
+
 #[derive(Clone)]
 pub struct AppState {
     pub db: PgPool,
-    pub config: Config,
     pub auth_service: AuthService,
     pub security_service: SecurityService,
+    pub notification_service: NotificationService,
+    pub ip_rules: IpRuleService,
+    pub organization_service: OrganizationService,
+    pub deception_service: DeceptionService,
+    pub retention_service: RetentionService,
+    pub rbac_service: RbacService,
+    pub membership_service: MembershipService,
+    pub entitlement_service: EntitlementService,
+    pub billing_service: BillingService,
+    pub access_code_service: AccessCodeService,
+    pub promotion_service: PromotionService,
+    pub content_service: ContentService,
+}
+
+pub fn user_agent(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
 }
 
-impl AppState {
-    pub fn new(
-        db: PgPool,
-        config: Config,
-        auth_service: AuthService,
-        security_service: SecurityService,
-    ) -> Self {
-        Self {
-            db,
-            config,
-            auth_service,
-            security_service,
-        }
+/// `None` unless `value` is an even number of hex digits.
+pub fn hex_decode(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
     }
-}
\ No newline at end of file
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex_encode(digest(&SHA256, bytes).as_ref())
+}
+
+/// `X-Circle-Signature` value for an outbound webhook: HMAC-SHA256 over "{timestamp}.{body}".
+pub fn webhook_signature(key: &hmac::Key, body: &[u8]) -> String {
+    let timestamp = chrono::Utc::now().timestamp();
+    let mut signed = timestamp.to_string().into_bytes();
+    signed.push(b'.');
+    signed.extend_from_slice(body);
+    let signature = hmac::sign(key, &signed);
+
+    format!("t={},v1={}", timestamp, hex_encode(signature.as_ref()))
+}
@@ -0,0 +1,1548 @@
+//! Billing flows against a running backend whose Stripe calls go to a mock served by the test.
+//!
+//! Start the backend pointed at the mock's address, then run the ignored tests:
+//!
+//! ```sh
+//! STRIPE_API_BASE=http://127.0.0.1:12111 STRIPE_SECRET_KEY=sk_test_mock \
+//!     STRIPE_WEBHOOK_SECRET=whsec_test BILLING_SCHEDULER_INTERVAL_SECONDS=1 cargo run &
+//! cargo test --test billing -- --ignored --test-threads=1
+//! ```
+//!
+//! `CIRCLE_TEST_URL` overrides the backend (`http://127.0.0.1:8000`), `CIRCLE_TEST_STRIPE_ADDR`
+//! the mock's listen address (`127.0.0.1:12111`) and `CIRCLE_TEST_STRIPE_WEBHOOK_SECRET` the
+//! secret webhooks are signed with (`whsec_test`). Tests that need what no endpoint does, such
+//! as granting a role or letting a period run out, use the backend's `DATABASE_URL`.
+
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    routing::{delete, get, post},
+    Form, Json, Router,
+};
+use reqwest::{Client, StatusCode};
+use ring::hmac;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+const PASSWORD: &str = "Staple battery horse 9";
+
+fn base_url() -> String {
+    std::env::var("CIRCLE_TEST_URL").unwrap_or_else(|_| "http://127.0.0.1:8000".to_string())
+}
+
+/// One request the backend made to Stripe.
+#[derive(Debug, Clone)]
+struct StripeCall {
+    method: &'static str,
+    path: String,
+    authorization: String,
+    idempotency_key: Option<String>,
+    form: Vec<(String, String)>,
+}
+
+impl StripeCall {
+    fn param(&self, key: &str) -> Option<&str> {
+        self.form
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+type Calls = Arc<Mutex<Vec<StripeCall>>>;
+
+async fn mock_stripe() -> Calls {
+    let calls = Calls::default();
+    let addr = std::env::var("CIRCLE_TEST_STRIPE_ADDR")
+        .unwrap_or_else(|_| "127.0.0.1:12111".to_string());
+
+    let app = Router::new()
+        .route("/v1/:resource", post(stripe_create))
+        .route("/v1/:resource/:kind", post(stripe_create_nested))
+        .route(
+            "/v1/subscriptions/:id",
+            get(stripe_subscription)
+                .post(stripe_update_subscription)
+                .delete(stripe_cancel_subscription),
+        )
+        .route("/v1/coupons/:id", delete(stripe_delete_coupon))
+        .with_state(calls.clone());
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .expect("bind mock Stripe address");
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    calls
+}
+
+async fn stripe_create(
+    State(calls): State<Calls>,
+    Path(resource): Path<String>,
+    headers: HeaderMap,
+    Form(form): Form<Vec<(String, String)>>,
+) -> Json<Value> {
+    record(&calls, "POST", format!("/v1/{}", resource), &headers, form)
+}
+
+async fn stripe_create_nested(
+    State(calls): State<Calls>,
+    Path((resource, kind)): Path<(String, String)>,
+    headers: HeaderMap,
+    Form(form): Form<Vec<(String, String)>>,
+) -> Json<Value> {
+    record(&calls, "POST", format!("/v1/{}/{}", resource, kind), &headers, form)
+}
+
+async fn stripe_subscription(Path(id): Path<String>) -> Json<Value> {
+    Json(json!({
+        "id": id,
+        "status": "active",
+        "items": { "data": [{ "id": format!("si_{}", id) }] }
+    }))
+}
+
+async fn stripe_update_subscription(
+    State(calls): State<Calls>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Form(form): Form<Vec<(String, String)>>,
+) -> Json<Value> {
+    record(&calls, "POST", format!("/v1/subscriptions/{}", id), &headers, form)
+}
+
+async fn stripe_cancel_subscription(
+    State(calls): State<Calls>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Json<Value> {
+    record(&calls, "DELETE", format!("/v1/subscriptions/{}", id), &headers, Vec::new())
+}
+
+async fn stripe_delete_coupon(
+    State(calls): State<Calls>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Json<Value> {
+    record(&calls, "DELETE", format!("/v1/coupons/{}", id), &headers, Vec::new())
+}
+
+fn record(
+    calls: &Calls,
+    method: &'static str,
+    path: String,
+    headers: &HeaderMap,
+    form: Vec<(String, String)>,
+) -> Json<Value> {
+    let id = Uuid::new_v4().simple().to_string();
+    let body = match path.as_str() {
+        "/v1/customers" => json!({ "id": format!("cus_{}", id) }),
+        "/v1/products" => json!({ "id": format!("prod_{}", id) }),
+        "/v1/coupons" => json!({ "id": format!("coupon_{}", id) }),
+        "/v1/checkout/sessions" => json!({
+            "id": format!("cs_test_{}", id),
+            "url": format!("https://checkout.stripe.test/{}", id)
+        }),
+        _ => json!({
+            "id": format!("bps_{}", id),
+            "url": format!("https://billing.stripe.test/{}", id)
+        }),
+    };
+
+    calls.lock().unwrap().push(StripeCall {
+        method,
+        path,
+        authorization: headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string(),
+        idempotency_key: headers
+            .get("idempotency-key")
+            .and_then(|value| value.to_str().ok())
+            .map(String::from),
+        form,
+    });
+    Json(body)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Delivers a webhook event signed the way Stripe signs it, at `signed_at` (Unix time).
+async fn deliver(client: &Client, event: &Value, signed_at: i64) -> (StatusCode, Value) {
+    let secret = std::env::var("CIRCLE_TEST_STRIPE_WEBHOOK_SECRET")
+        .unwrap_or_else(|_| "whsec_test".to_string());
+    let payload = event.to_string();
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let signature = hmac::sign(&key, format!("{}.{}", signed_at, payload).as_bytes());
+
+    let response = client
+        .post(format!("{}/api/billing/webhook", base_url()))
+        .header("stripe-signature", format!("t={},v1={}", signed_at, hex(signature.as_ref())))
+        .header("content-type", "application/json")
+        .body(payload)
+        .send()
+        .await
+        .expect("webhook request");
+    let status = response.status();
+    (status, response.json().await.unwrap_or(Value::Null))
+}
+
+fn event(event_type: &str, created: i64, object: Value) -> Value {
+    json!({
+        "id": format!("evt_{}", Uuid::new_v4().simple()),
+        "type": event_type,
+        "created": created,
+        "data": { "object": object }
+    })
+}
+
+async fn tier(client: &Client, token: &str) -> String {
+    let body: Value = client
+        .get(format!("{}/api/account/entitlements", base_url()))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("entitlements request")
+        .json()
+        .await
+        .expect("entitlements body");
+    body["entitlements"]["tier"].as_str().expect("tier").to_string()
+}
+
+async fn billing_status(client: &Client, token: &str) -> Value {
+    client
+        .get(format!("{}/api/billing", base_url()))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("billing request")
+        .json()
+        .await
+        .expect("billing body")
+}
+
+/// Registers a member and returns their login response.
+async fn new_member(client: &Client) -> Value {
+    let email = format!("billing-{}@example.com", Uuid::new_v4());
+    let response = client
+        .post(format!("{}/api/auth/register", base_url()))
+        .json(&json!({ "email": email, "password": PASSWORD }))
+        .send()
+        .await
+        .expect("register request");
+    assert_eq!(response.status(), StatusCode::OK, "registration failed");
+
+    client
+        .post(format!("{}/api/auth/login/complete", base_url()))
+        .json(&json!({ "email": email, "password": PASSWORD }))
+        .send()
+        .await
+        .expect("login request")
+        .json()
+        .await
+        .expect("login body")
+}
+
+async fn member_token(client: &Client) -> String {
+    new_member(client).await["access_token"]
+        .as_str()
+        .expect("access token")
+        .to_string()
+}
+
+async fn database() -> PgPool {
+    dotenvy::dotenv().ok();
+    let url = std::env::var("DATABASE_URL").expect("DATABASE_URL");
+    PgPool::connect(&url).await.expect("connect to the backend's database")
+}
+
+/// A new member made an administrator in SQL, as the README describes, returning a step-up
+/// token so actions that need one are allowed too.
+async fn admin_token(client: &Client, db: &PgPool) -> String {
+    let login = new_member(client).await;
+    let user_id: Uuid = login["user"]["id"]
+        .as_str()
+        .and_then(|id| id.parse().ok())
+        .expect("user id");
+    sqlx::query("INSERT INTO user_roles (user_id, role_id) SELECT $1, id FROM roles WHERE name = 'admin'")
+        .bind(user_id)
+        .execute(db)
+        .await
+        .expect("grant admin role");
+
+    let token = login["access_token"].as_str().expect("access token");
+    let (status, body) =
+        post_json(client, token, "/api/auth/step-up", json!({ "password": PASSWORD })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["access_token"].as_str().expect("step-up token").to_string()
+}
+
+/// Moves the end of a subscription's period into the past, as if its time had run out.
+async fn run_out(db: &PgPool, subscription_id: &str) {
+    let subscription_id: Uuid = subscription_id.parse().expect("subscription id");
+    sqlx::query("UPDATE subscriptions SET current_period_end = NOW() - INTERVAL '1 second' WHERE id = $1")
+        .bind(subscription_id)
+        .execute(db)
+        .await
+        .expect("end subscription period");
+}
+
+/// Waits for `SubscriptionScheduler` to move the member's subscription to `status`.
+async fn scheduled_status(client: &Client, token: &str, status: &str) {
+    for _ in 0..60 {
+        if billing_status(client, token).await["status"] == status {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    panic!(
+        "the scheduler never made the subscription {}; is the backend running with a short \
+         BILLING_SCHEDULER_INTERVAL_SECONDS?",
+        status
+    );
+}
+
+async fn post_json(client: &Client, token: &str, path: &str, body: Value) -> (StatusCode, Value) {
+    let response = client
+        .post(format!("{}{}", base_url(), path))
+        .bearer_auth(token)
+        .json(&body)
+        .send()
+        .await
+        .expect("request");
+    let status = response.status();
+    (status, response.json().await.unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend configured with the mock Stripe address"]
+async fn checkout_is_priced_from_the_catalog_and_reuses_the_customer() {
+    let calls = mock_stripe().await;
+    let client = Client::new();
+    let token = member_token(&client).await;
+
+    let (status, body) = post_json(
+        &client,
+        &token,
+        "/api/billing/checkout",
+        json!({ "tier": "standard", "interval": "monthly" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["checkout_url"].as_str().unwrap().starts_with("https://checkout.stripe.test/"));
+    let first_session = body["session_id"].clone();
+
+    // One open checkout at a time, until it completes or expires
+    let yearly = json!({ "tier": "premium", "interval": "yearly" });
+    let (status, body) = post_json(&client, &token, "/api/billing/checkout", yearly.clone()).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+    assert!(body["expires_at"].is_string());
+
+    let now = chrono::Utc::now().timestamp();
+    let expired = event("checkout.session.expired", now, json!({ "id": first_session }));
+    assert_eq!(deliver(&client, &expired, now).await.0, StatusCode::OK);
+
+    let (status, body) = post_json(&client, &token, "/api/billing/checkout", yearly).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let calls = calls.lock().unwrap().clone();
+    let customers: Vec<_> = calls.iter().filter(|c| c.path == "/v1/customers").collect();
+    assert_eq!(customers.len(), 1, "customer created once per member");
+    assert!(customers[0].authorization.starts_with("Bearer sk_"));
+    assert!(customers[0].param("email").is_none(), "no personal data sent to Stripe");
+
+    let sessions: Vec<_> = calls
+        .iter()
+        .filter(|c| c.path == "/v1/checkout/sessions")
+        .collect();
+    assert_eq!(sessions.len(), 2);
+    let monthly = sessions[0];
+    assert_eq!(monthly.param("mode"), Some("subscription"));
+    let expires_at: i64 = monthly.param("expires_at").unwrap().parse().unwrap();
+    assert!(expires_at > now && expires_at <= now + 1800);
+    assert_eq!(monthly.param("customer"), sessions[1].param("customer"));
+    assert_eq!(monthly.param("line_items[0][price_data][unit_amount]"), Some("999"));
+    assert_eq!(
+        monthly.param("line_items[0][price_data][recurring][interval]"),
+        Some("month")
+    );
+    assert!(monthly.param("subscription_data[metadata][membership_id]").is_some());
+    let yearly = sessions[1];
+    assert_eq!(yearly.param("line_items[0][price_data][unit_amount]"), Some("19999"));
+    assert_eq!(
+        yearly.param("line_items[0][price_data][recurring][interval]"),
+        Some("year")
+    );
+
+    let (status, body) = post_json(&client, &token, "/api/billing/portal", json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["portal_url"].as_str().unwrap().starts_with("https://billing.stripe.test/"));
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend configured with the mock Stripe address"]
+async fn concurrent_checkouts_open_one_session() {
+    let calls = mock_stripe().await;
+    let client = Client::new();
+    let token = member_token(&client).await;
+
+    let requests: Vec<_> = (0..6)
+        .map(|_| {
+            let (client, token) = (client.clone(), token.clone());
+            tokio::spawn(async move {
+                post_json(
+                    &client,
+                    &token,
+                    "/api/billing/checkout",
+                    json!({ "tier": "standard", "interval": "monthly" }),
+                )
+                .await
+                .0
+            })
+        })
+        .collect();
+    let mut statuses = Vec::new();
+    for request in requests {
+        statuses.push(request.await.expect("checkout request"));
+    }
+
+    assert_eq!(statuses.iter().filter(|s| **s == StatusCode::OK).count(), 1, "{:?}", statuses);
+    assert_eq!(
+        statuses.iter().filter(|s| **s == StatusCode::CONFLICT).count(),
+        statuses.len() - 1
+    );
+    let sessions: Vec<_> = calls
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|c| c.path == "/v1/checkout/sessions")
+        .cloned()
+        .collect();
+    assert_eq!(sessions.len(), 1);
+    assert!(sessions[0]
+        .idempotency_key
+        .as_deref()
+        .is_some_and(|key| key.starts_with("checkout-")));
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend"]
+async fn free_and_unknown_tiers_cannot_be_bought() {
+    let client = Client::new();
+    let token = member_token(&client).await;
+
+    for body in [
+        json!({ "tier": "basic", "interval": "monthly" }),
+        json!({ "tier": "platinum", "interval": "monthly" }),
+    ] {
+        let (status, _) = post_json(&client, &token, "/api/billing/checkout", body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    // Nothing was bought, so there is no customer to open a portal for
+    let (status, _) = post_json(&client, &token, "/api/billing/portal", json!({})).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend configured with the mock Stripe address and webhook secret"]
+async fn webhooks_drive_the_subscription_and_tier() {
+    let _calls = mock_stripe().await;
+    let client = Client::new();
+    let token = member_token(&client).await;
+    let now = chrono::Utc::now().timestamp();
+
+    let (status, checkout) = post_json(
+        &client,
+        &token,
+        "/api/billing/checkout",
+        json!({ "tier": "standard", "interval": "monthly" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", checkout);
+    let subscription_id = format!("sub_{}", Uuid::new_v4().simple());
+
+    let completed = event(
+        "checkout.session.completed",
+        now - 30,
+        json!({
+            "id": checkout["session_id"],
+            "customer": "cus_webhook",
+            "subscription": subscription_id,
+            "payment_status": "paid"
+        }),
+    );
+    let (status, body) = deliver(&client, &completed, now).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["duplicate"], false);
+    assert_eq!(tier(&client, &token).await, "standard");
+
+    // Redelivery is acknowledged but not applied again
+    let (status, body) = deliver(&client, &completed, now).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["duplicate"], true);
+
+    // Bad signatures and replays outside the tolerance are refused
+    let forged = event("customer.subscription.deleted", now, json!({ "id": subscription_id, "status": "canceled" }));
+    let response = client
+        .post(format!("{}/api/billing/webhook", base_url()))
+        .header("stripe-signature", format!("t={},v1={}", now, "00".repeat(32)))
+        .body(forged.to_string())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let (status, _) = deliver(&client, &forged, now - 3600).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(tier(&client, &token).await, "standard");
+
+    // A failed payment keeps the tier while past due
+    let failed = event(
+        "invoice.payment_failed",
+        now - 20,
+        json!({ "subscription": subscription_id }),
+    );
+    assert_eq!(deliver(&client, &failed, now).await.0, StatusCode::OK);
+    assert_eq!(tier(&client, &token).await, "standard");
+
+    // An older event arriving late does not undo newer state
+    let stale = event(
+        "customer.subscription.updated",
+        now - 25,
+        json!({ "id": subscription_id, "status": "active", "current_period_start": now - 60, "current_period_end": now + 2_592_000 }),
+    );
+    assert_eq!(deliver(&client, &stale, now).await.0, StatusCode::OK);
+
+    let deleted = event(
+        "customer.subscription.deleted",
+        now - 10,
+        json!({ "id": subscription_id, "status": "canceled" }),
+    );
+    assert_eq!(deliver(&client, &deleted, now).await.0, StatusCode::OK);
+    assert_eq!(tier(&client, &token).await, "basic");
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend configured with the mock Stripe address"]
+async fn failed_payments_get_one_grace_period() {
+    let _calls = mock_stripe().await;
+    let client = Client::new();
+    let token = member_token(&client).await;
+    let now = chrono::Utc::now().timestamp();
+
+    let (status, checkout) = post_json(
+        &client,
+        &token,
+        "/api/billing/checkout",
+        json!({ "tier": "standard", "interval": "monthly" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", checkout);
+    let subscription_id = format!("sub_{}", Uuid::new_v4().simple());
+    let completed = event(
+        "checkout.session.completed",
+        now - 60,
+        json!({
+            "id": checkout["session_id"],
+            "customer": "cus_grace",
+            "subscription": subscription_id,
+            "payment_status": "paid"
+        }),
+    );
+    assert_eq!(deliver(&client, &completed, now).await.0, StatusCode::OK);
+    assert_eq!(billing_status(&client, &token).await["status"], "active");
+
+    // Grace starts at the first failure and later retries do not extend it
+    let failed = event("invoice.payment_failed", now - 50, json!({ "subscription": subscription_id }));
+    assert_eq!(deliver(&client, &failed, now).await.0, StatusCode::OK);
+    let billing = billing_status(&client, &token).await;
+    assert_eq!(billing["status"], "past_due");
+    assert_eq!(billing["tier"], "standard");
+    let grace_ends_at = billing["grace_ends_at"].clone();
+    assert!(grace_ends_at.is_string(), "{}", billing);
+
+    let retried = event("invoice.payment_failed", now - 40, json!({ "subscription": subscription_id }));
+    assert_eq!(deliver(&client, &retried, now).await.0, StatusCode::OK);
+    assert_eq!(billing_status(&client, &token).await["grace_ends_at"], grace_ends_at);
+
+    // Paying ends the grace period
+    let paid = event("invoice.paid", now - 30, json!({ "subscription": subscription_id }));
+    assert_eq!(deliver(&client, &paid, now).await.0, StatusCode::OK);
+    let billing = billing_status(&client, &token).await;
+    assert_eq!(billing["status"], "active");
+    assert!(billing["grace_ends_at"].is_null(), "{}", billing);
+
+    // A cancelled subscription stays cancelled
+    let deleted = event(
+        "customer.subscription.deleted",
+        now - 20,
+        json!({ "id": subscription_id, "status": "canceled" }),
+    );
+    assert_eq!(deliver(&client, &deleted, now).await.0, StatusCode::OK);
+    let revived = event(
+        "customer.subscription.updated",
+        now - 10,
+        json!({ "id": subscription_id, "status": "active" }),
+    );
+    assert_eq!(deliver(&client, &revived, now).await.0, StatusCode::OK);
+    assert_eq!(billing_status(&client, &token).await["status"], "canceled");
+    assert_eq!(tier(&client, &token).await, "basic");
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend configured with the mock Stripe address"]
+async fn upgrades_are_prorated_and_downgrades_wait_for_period_end() {
+    let calls = mock_stripe().await;
+    let client = Client::new();
+    let token = member_token(&client).await;
+    let now = chrono::Utc::now().timestamp();
+
+    let (status, checkout) = post_json(
+        &client,
+        &token,
+        "/api/billing/checkout",
+        json!({ "tier": "standard", "interval": "monthly" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", checkout);
+    let subscription_id = format!("sub_{}", Uuid::new_v4().simple());
+    let completed = event(
+        "checkout.session.completed",
+        now - 20,
+        json!({
+            "id": checkout["session_id"],
+            "customer": "cus_plans",
+            "subscription": subscription_id,
+            "payment_status": "paid"
+        }),
+    );
+    assert_eq!(deliver(&client, &completed, now).await.0, StatusCode::OK);
+
+    // Nothing to prorate against until Stripe reports the period
+    let upgrade = json!({ "tier": "premium", "interval": "monthly" });
+    let (status, _) = post_json(&client, &token, "/api/billing/plan/preview", upgrade.clone()).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // Halfway through a 30-day period
+    let period_end = now + 15 * 86_400;
+    let updated = event(
+        "customer.subscription.updated",
+        now - 10,
+        json!({
+            "id": subscription_id,
+            "status": "active",
+            "current_period_start": now - 15 * 86_400,
+            "current_period_end": period_end
+        }),
+    );
+    assert_eq!(deliver(&client, &updated, now).await.0, StatusCode::OK);
+
+    let (status, preview) = post_json(&client, &token, "/api/billing/plan/preview", upgrade.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{}", preview);
+    assert_eq!(preview["kind"], "upgrade");
+    assert_eq!(preview["from"]["tier"], "standard");
+    // Half of 19.99 less half of 9.99
+    let amount_due: f64 = preview["amount_due"].as_str().unwrap().parse().unwrap();
+    assert!((4.98..=5.02).contains(&amount_due), "{}", preview);
+
+    let (status, change) = post_json(&client, &token, "/api/billing/plan", upgrade).await;
+    assert_eq!(status, StatusCode::OK, "{}", change);
+    assert_eq!(change["status"], "applied");
+    assert_eq!(tier(&client, &token).await, "premium");
+    let update = calls
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|call| call.path == format!("/v1/subscriptions/{}", subscription_id))
+        .cloned()
+        .expect("subscription update");
+    assert_eq!(update.param("items[0][id]"), Some(format!("si_{}", subscription_id).as_str()));
+    assert_eq!(update.param("items[0][price_data][unit_amount]"), Some("1999"));
+    assert_eq!(update.param("proration_behavior"), Some("always_invoice"));
+    assert!(update.param("items[0][price_data][product]").unwrap().starts_with("prod_"));
+
+    // Downgrades keep the tier until the period ends
+    let (status, change) = post_json(
+        &client,
+        &token,
+        "/api/billing/plan",
+        json!({ "tier": "standard", "interval": "monthly" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", change);
+    assert_eq!(change["kind"], "downgrade");
+    assert_eq!(change["status"], "scheduled");
+    assert_eq!(
+        chrono::DateTime::parse_from_rfc3339(change["effective_at"].as_str().unwrap())
+            .unwrap()
+            .timestamp(),
+        period_end
+    );
+    assert_eq!(tier(&client, &token).await, "premium");
+    assert_eq!(billing_status(&client, &token).await["scheduled_change"]["id"], change["id"]);
+
+    let response = client
+        .delete(format!("{}/api/billing/plan/scheduled", base_url()))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let history: Value = client
+        .get(format!("{}/api/billing/plan/history", base_url()))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let statuses: Vec<&str> = history["plan_changes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|change| change["status"].as_str().unwrap())
+        .collect();
+    assert_eq!(statuses, ["cancelled", "applied"]);
+
+    // Free tiers are left through the portal
+    let (status, _) = post_json(
+        &client,
+        &token,
+        "/api/billing/plan/preview",
+        json!({ "tier": "basic", "interval": "monthly" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend"]
+async fn unknown_access_codes_are_rejected() {
+    let client = Client::new();
+    let token = member_token(&client).await;
+
+    // Malformed and merely unknown codes get the same answer as used or revoked ones
+    for code in ["not-a-code", "AAAAA-BBBBB-CCCCC-DDDDD"] {
+        let (status, body) =
+            post_json(&client, &token, "/api/billing/redeem", json!({ "code": code })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "Invalid or expired access code");
+    }
+    assert_eq!(tier(&client, &token).await, "basic");
+
+    let (status, _) = post_json(&client, &token, "/api/billing/redeem", json!({ "code": "" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+/// Issues a batch of prepaid codes, returning the batch id and the codes.
+async fn issue_codes(client: &Client, token: &str, request: Value) -> (String, Vec<String>) {
+    let (status, body) = post_json(client, token, "/api/admin/access-codes", request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let codes = body["codes"]
+        .as_array()
+        .expect("codes")
+        .iter()
+        .map(|code| code.as_str().expect("code").to_string())
+        .collect();
+    (body["batch"]["id"].as_str().expect("batch id").to_string(), codes)
+}
+
+async fn redeem(client: &Client, token: &str, code: &str) -> (StatusCode, Value) {
+    post_json(client, token, "/api/billing/redeem", json!({ "code": code })).await
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend with a short scheduler interval and its database"]
+async fn prepaid_codes_start_and_extend_a_membership_until_it_runs_out() {
+    let client = Client::new();
+    let db = database().await;
+    let admin = admin_token(&client, &db).await;
+    let token = member_token(&client).await;
+
+    let (_, codes) = issue_codes(
+        &client,
+        &admin,
+        json!({ "tier": "premium", "months": 1, "quantity": 2, "note": "billing test" }),
+    )
+    .await;
+    assert_eq!(codes.len(), 2);
+
+    // Typed by hand: lower case, no separators
+    let (status, body) = redeem(&client, &token, &codes[0].to_lowercase().replace('-', "")).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let first = body["redemption"].clone();
+    assert_eq!(first["tier"], "premium");
+    assert_eq!(tier(&client, &token).await, "premium");
+    assert_eq!(billing_status(&client, &token).await["status"], "active");
+
+    // A second code for the same plan extends the same subscription by its months
+    let (status, body) = redeem(&client, &token, &codes[1]).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let second = body["redemption"].clone();
+    assert_eq!(second["subscription_id"], first["subscription_id"]);
+    let end = |redemption: &Value| {
+        redemption["current_period_end"]
+            .as_str()
+            .and_then(|end| end.parse::<chrono::DateTime<chrono::Utc>>().ok())
+            .expect("current_period_end")
+    };
+    assert!(end(&second) - end(&first) >= chrono::Duration::days(28));
+
+    // Codes for another plan don't mix with it
+    let (_, other) =
+        issue_codes(&client, &admin, json!({ "tier": "standard", "months": 1, "quantity": 1 })).await;
+    let (status, _) = redeem(&client, &token, &other[0]).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(tier(&client, &token).await, "premium");
+
+    run_out(&db, first["subscription_id"].as_str().expect("subscription id")).await;
+    scheduled_status(&client, &token, "canceled").await;
+    assert_eq!(tier(&client, &token).await, "basic");
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend and its database"]
+async fn used_revoked_and_expired_codes_are_rejected() {
+    let client = Client::new();
+    let db = database().await;
+    let admin = admin_token(&client, &db).await;
+    let token = member_token(&client).await;
+    let other = member_token(&client).await;
+
+    let (batch_id, codes) =
+        issue_codes(&client, &admin, json!({ "tier": "standard", "months": 1, "quantity": 2 })).await;
+    let (status, body) = redeem(&client, &token, &codes[0]).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    // Single use, whoever tries again
+    let (status, body) = redeem(&client, &other, &codes[0]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "Invalid or expired access code");
+
+    // Revoking a batch leaves redeemed codes alone and stops the rest
+    let response = client
+        .delete(format!("{}/api/admin/access-codes/{}", base_url(), batch_id))
+        .bearer_auth(&admin)
+        .send()
+        .await
+        .expect("revoke request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let revoked: Value = response.json().await.expect("revoke body");
+    assert_eq!(revoked["revoked"], 1);
+    assert_eq!(revoked["batch"]["redeemed"], 1);
+
+    let (status, _) = redeem(&client, &other, &codes[1]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(2);
+    let (_, expiring) = issue_codes(
+        &client,
+        &admin,
+        json!({ "tier": "standard", "months": 1, "quantity": 1, "expires_at": expires_at }),
+    )
+    .await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    let (status, _) = redeem(&client, &other, &expiring[0]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    assert_eq!(tier(&client, &token).await, "standard");
+    assert_eq!(tier(&client, &other).await, "basic");
+}
+
+/// Creates a promotion with a fresh code from `terms`, returning it.
+async fn create_promotion(client: &Client, token: &str, mut terms: Value) -> Value {
+    terms["code"] = json!(format!("TEST-{}", &Uuid::new_v4().simple().to_string()[..12]));
+    let (status, body) = post_json(client, token, "/api/admin/promotions", terms).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["promotion"].clone()
+}
+
+/// Completes the Checkout session as Stripe would and reports a 30-day period half used,
+/// returning the Stripe subscription id.
+async fn complete_checkout(client: &Client, checkout: &Value) -> String {
+    let now = chrono::Utc::now().timestamp();
+    let subscription_id = format!("sub_{}", Uuid::new_v4().simple());
+    let completed = event(
+        "checkout.session.completed",
+        now - 20,
+        json!({
+            "id": checkout["session_id"],
+            "customer": "cus_promotions",
+            "subscription": subscription_id,
+            "payment_status": "paid"
+        }),
+    );
+    assert_eq!(deliver(client, &completed, now).await.0, StatusCode::OK);
+
+    let updated = event(
+        "customer.subscription.updated",
+        now - 10,
+        json!({
+            "id": subscription_id,
+            "status": "active",
+            "current_period_start": now - 15 * 86_400,
+            "current_period_end": now + 15 * 86_400
+        }),
+    );
+    assert_eq!(deliver(client, &updated, now).await.0, StatusCode::OK);
+    subscription_id
+}
+
+fn last_call(calls: &Calls, method: &str, path: &str) -> Option<StripeCall> {
+    calls
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|call| call.method == method && call.path == path)
+        .cloned()
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend configured with the mock Stripe address and its database"]
+async fn discount_promotions_reach_checkout_and_the_plan_preview() {
+    let calls = mock_stripe().await;
+    let client = Client::new();
+    let db = database().await;
+    let admin = admin_token(&client, &db).await;
+    let token = member_token(&client).await;
+
+    let percent = create_promotion(
+        &client,
+        &admin,
+        json!({ "kind": "percent", "percent_off": "20", "duration": "repeating", "duration_months": 3 }),
+    )
+    .await;
+    let (status, checkout) = post_json(
+        &client,
+        &token,
+        "/api/billing/checkout",
+        json!({ "tier": "standard", "interval": "monthly", "promotion_code": percent["code"] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", checkout);
+
+    let coupon = last_call(&calls, "POST", "/v1/coupons").expect("coupon");
+    assert_eq!(coupon.param("metadata[promotion_id]"), percent["id"].as_str());
+    assert_eq!(coupon.param("percent_off"), Some("20.00"));
+    assert_eq!(coupon.param("duration"), Some("repeating"));
+    assert_eq!(coupon.param("duration_in_months"), Some("3"));
+    let session = last_call(&calls, "POST", "/v1/checkout/sessions").expect("checkout session");
+    assert!(session.param("discounts[0][coupon]").unwrap().starts_with("coupon_"));
+    complete_checkout(&client, &checkout).await;
+
+    // Half of 19.99 less half of 9.99, less 2.50
+    let fixed = create_promotion(
+        &client,
+        &admin,
+        json!({ "kind": "fixed", "amount_off": "2.50", "tiers": ["premium"] }),
+    )
+    .await;
+    let (status, preview) = post_json(
+        &client,
+        &token,
+        "/api/billing/plan/preview",
+        json!({ "tier": "premium", "interval": "monthly", "promotion_code": fixed["code"] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", preview);
+    assert_eq!(preview["discount"], "2.50");
+    let amount_due: f64 = preview["amount_due"].as_str().unwrap().parse().unwrap();
+    assert!((2.48..=2.52).contains(&amount_due), "{}", preview);
+
+    // Each member uses a promotion once
+    let (status, body) = post_json(
+        &client,
+        &token,
+        "/api/billing/plan/preview",
+        json!({ "tier": "premium", "interval": "monthly", "promotion_code": percent["code"] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "Promotion code is invalid or has expired");
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend configured with the mock Stripe address and its database"]
+async fn promotions_stop_when_used_up_or_ended() {
+    let calls = mock_stripe().await;
+    let client = Client::new();
+    let db = database().await;
+    let admin = admin_token(&client, &db).await;
+
+    let promotion = create_promotion(
+        &client,
+        &admin,
+        json!({ "kind": "percent", "percent_off": "10", "max_redemptions": 1 }),
+    )
+    .await;
+    let checkout_with_code = json!({
+        "tier": "standard",
+        "interval": "monthly",
+        "promotion_code": promotion["code"]
+    });
+
+    let first = member_token(&client).await;
+    let (status, checkout) =
+        post_json(&client, &first, "/api/billing/checkout", checkout_with_code.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{}", checkout);
+    let coupon = last_call(&calls, "POST", "/v1/checkout/sessions")
+        .and_then(|session| session.param("discounts[0][coupon]").map(String::from))
+        .expect("coupon");
+    complete_checkout(&client, &checkout).await;
+
+    let second = member_token(&client).await;
+    let (status, body) =
+        post_json(&client, &second, "/api/billing/checkout", checkout_with_code.clone()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "Promotion code is invalid or has expired");
+
+    // Ending one deletes its coupon at Stripe too
+    let unlimited = create_promotion(&client, &admin, json!({ "kind": "percent", "percent_off": "10" })).await;
+    let (status, checkout) = post_json(
+        &client,
+        &second,
+        "/api/billing/checkout",
+        json!({ "tier": "standard", "interval": "monthly", "promotion_code": unlimited["code"] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", checkout);
+    let unlimited_coupon = last_call(&calls, "POST", "/v1/checkout/sessions")
+        .and_then(|session| session.param("discounts[0][coupon]").map(String::from))
+        .expect("coupon");
+    assert_ne!(unlimited_coupon, coupon);
+
+    let response = client
+        .delete(format!("{}/api/admin/promotions/{}", base_url(), unlimited["id"].as_str().unwrap()))
+        .bearer_auth(&admin)
+        .send()
+        .await
+        .expect("end promotion request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let deleted = last_call(&calls, "DELETE", &format!("/v1/coupons/{}", unlimited_coupon))
+        .expect("coupon deletion");
+    assert!(deleted.authorization.starts_with("Bearer "));
+
+    let third = member_token(&client).await;
+    let (status, _) = post_json(
+        &client,
+        &third,
+        "/api/billing/checkout",
+        json!({ "tier": "standard", "interval": "monthly", "promotion_code": unlimited["code"] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend with the mock Stripe address, a short scheduler interval and its database"]
+async fn no_card_trials_convert_at_trial_end_or_run_out() {
+    let calls = mock_stripe().await;
+    let client = Client::new();
+    let db = database().await;
+    let admin = admin_token(&client, &db).await;
+    let promotion = create_promotion(
+        &client,
+        &admin,
+        json!({ "kind": "trial", "trial_days": 14, "tiers": ["premium"] }),
+    )
+    .await;
+    let trial = json!({ "tier": "premium", "code": promotion["code"] });
+
+    let converting = member_token(&client).await;
+    let (status, body) = post_json(&client, &converting, "/api/billing/trial", trial.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(tier(&client, &converting).await, "premium");
+    assert_eq!(billing_status(&client, &converting).await["status"], "trialing");
+    let trial_ends_at = body["trial"]["trial_ends_at"]
+        .as_str()
+        .and_then(|end| end.parse::<chrono::DateTime<chrono::Utc>>().ok())
+        .expect("trial_ends_at");
+
+    // Subscribing to the same tier bills from the end of the trial
+    let (status, checkout) = post_json(
+        &client,
+        &converting,
+        "/api/billing/checkout",
+        json!({ "tier": "premium", "interval": "monthly" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", checkout);
+    let session = last_call(&calls, "POST", "/v1/checkout/sessions").expect("checkout session");
+    assert_eq!(
+        session.param("subscription_data[trial_end]"),
+        Some(trial_ends_at.timestamp().to_string().as_str())
+    );
+    complete_checkout(&client, &checkout).await;
+    assert_eq!(billing_status(&client, &converting).await["status"], "active");
+    assert_eq!(tier(&client, &converting).await, "premium");
+
+    let lapsing = member_token(&client).await;
+    let (status, body) = post_json(&client, &lapsing, "/api/billing/trial", trial).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    run_out(&db, body["trial"]["subscription_id"].as_str().expect("subscription id")).await;
+    scheduled_status(&client, &lapsing, "canceled").await;
+    assert_eq!(tier(&client, &lapsing).await, "basic");
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend configured with the mock Stripe address"]
+async fn unknown_promotion_codes_are_rejected() {
+    let calls = mock_stripe().await;
+    let client = Client::new();
+    let token = member_token(&client).await;
+
+    let (status, body) = post_json(
+        &client,
+        &token,
+        "/api/billing/checkout",
+        json!({ "tier": "premium", "interval": "monthly", "promotion_code": "NO-SUCH-CODE" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "Promotion code is invalid or has expired");
+
+    let (status, _) = post_json(
+        &client,
+        &token,
+        "/api/billing/trial",
+        json!({ "tier": "premium", "code": "NO-SUCH-CODE" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(tier(&client, &token).await, "basic");
+
+    // Rejected before anything reached Stripe
+    assert!(calls
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|call| call.path != "/v1/checkout/sessions"));
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend configured with the mock Stripe address and webhook secret"]
+async fn replaced_subscriptions_are_cancelled_at_stripe() {
+    let calls = mock_stripe().await;
+    let client = Client::new();
+    let token = member_token(&client).await;
+    let now = chrono::Utc::now().timestamp();
+
+    let (status, checkout) = post_json(
+        &client,
+        &token,
+        "/api/billing/checkout",
+        json!({ "tier": "standard", "interval": "monthly" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", checkout);
+    let first = format!("sub_{}", Uuid::new_v4().simple());
+    let completed = event(
+        "checkout.session.completed",
+        now - 30,
+        json!({
+            "id": checkout["session_id"],
+            "customer": "cus_replaced",
+            "subscription": first,
+            "payment_status": "paid"
+        }),
+    );
+    assert_eq!(deliver(&client, &completed, now).await.0, StatusCode::OK);
+
+    // A second subscription for the same member, e.g. created in the Stripe dashboard
+    let session = calls
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|call| call.path == "/v1/checkout/sessions")
+        .cloned()
+        .expect("checkout session call");
+    let second = format!("sub_{}", Uuid::new_v4().simple());
+    let created = event(
+        "customer.subscription.created",
+        now - 20,
+        json!({
+            "id": second,
+            "customer": "cus_replaced",
+            "status": "active",
+            "metadata": {
+                "user_id": session.param("client_reference_id"),
+                "membership_id": session.param("subscription_data[metadata][membership_id]"),
+                "interval": "monthly"
+            }
+        }),
+    );
+    let (status, body) = deliver(&client, &created, now).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(tier(&client, &token).await, "standard");
+
+    let cancelled: Vec<_> = calls
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|call| call.method == "DELETE")
+        .map(|call| call.path.clone())
+        .collect();
+    assert_eq!(cancelled, vec![format!("/v1/subscriptions/{}", first)]);
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend configured with the mock Stripe address and webhook secret"]
+async fn destroyed_members_subscriptions_are_cancelled_at_stripe() {
+    let calls = mock_stripe().await;
+    let client = Client::new();
+    let db = database().await;
+    let login = new_member(&client).await;
+    let token = login["access_token"].as_str().expect("access token");
+    let email = login["user"]["email"].as_str().expect("email");
+    let user_id: Uuid = login["user"]["id"]
+        .as_str()
+        .and_then(|id| id.parse().ok())
+        .expect("user id");
+    let now = chrono::Utc::now().timestamp();
+
+    let (status, checkout) = post_json(
+        &client,
+        token,
+        "/api/billing/checkout",
+        json!({ "tier": "standard", "interval": "monthly" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", checkout);
+    let subscription = format!("sub_{}", Uuid::new_v4().simple());
+    let completed = event(
+        "checkout.session.completed",
+        now - 30,
+        json!({
+            "id": checkout["session_id"],
+            "customer": "cus_destroyed",
+            "subscription": subscription,
+            "payment_status": "paid"
+        }),
+    );
+    assert_eq!(deliver(&client, &completed, now).await.0, StatusCode::OK);
+    assert_eq!(tier(&client, token).await, "standard");
+
+    // One wrong password short of the destruction threshold, past the lockout
+    let mut tx = db.begin().await.expect("begin");
+    sqlx::query("SELECT set_config('app.role', 'system', true)")
+        .execute(&mut *tx)
+        .await
+        .expect("system scope");
+    sqlx::query("UPDATE users SET failed_login_attempts = 4, account_locked_until = NULL WHERE id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .expect("set failed attempts");
+    tx.commit().await.expect("commit");
+
+    let response = client
+        .post(format!("{}/api/auth/login/complete", base_url()))
+        .json(&json!({ "email": email, "password": "wrong password" }))
+        .send()
+        .await
+        .expect("login request");
+    assert_eq!(response.status(), StatusCode::GONE);
+
+    let cancelled: Vec<_> = calls
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|call| call.method == "DELETE")
+        .map(|call| call.path.clone())
+        .collect();
+    assert_eq!(cancelled, vec![format!("/v1/subscriptions/{}", subscription)]);
+
+    let details: Value = sqlx::query_scalar("SELECT details FROM destruction_logs WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(&db)
+        .await
+        .expect("destruction log");
+    assert_eq!(details["stripe_cancellation_failed"], json!([]));
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend configured with the mock Stripe address and webhook secret"]
+async fn plan_changes_are_reconciled_from_subscription_metadata() {
+    let calls = mock_stripe().await;
+    let client = Client::new();
+    let token = member_token(&client).await;
+    let now = chrono::Utc::now().timestamp();
+
+    let (status, checkout) = post_json(
+        &client,
+        &token,
+        "/api/billing/checkout",
+        json!({ "tier": "standard", "interval": "monthly" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", checkout);
+    let subscription_id = format!("sub_{}", Uuid::new_v4().simple());
+    let completed = event(
+        "checkout.session.completed",
+        now - 30,
+        json!({
+            "id": checkout["session_id"],
+            "customer": "cus_metadata",
+            "subscription": subscription_id,
+            "payment_status": "paid"
+        }),
+    );
+    assert_eq!(deliver(&client, &completed, now).await.0, StatusCode::OK);
+    assert_eq!(tier(&client, &token).await, "standard");
+
+    let session = calls
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|call| call.path == "/v1/checkout/sessions")
+        .cloned()
+        .expect("checkout session call");
+    let catalog: Value = client
+        .get(format!("{}/api/memberships", base_url()))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let premium = catalog["memberships"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|membership| membership["tier"] == "premium")
+        .expect("premium tier")["id"]
+        .clone();
+
+    // Stripe took the new plan but the change was never recorded here; only the status is unchanged
+    let updated = event(
+        "customer.subscription.updated",
+        now - 20,
+        json!({
+            "id": subscription_id,
+            "customer": "cus_metadata",
+            "status": "active",
+            "metadata": {
+                "user_id": session.param("client_reference_id"),
+                "membership_id": premium,
+                "interval": "yearly"
+            }
+        }),
+    );
+    let (status, body) = deliver(&client, &updated, now).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(tier(&client, &token).await, "premium");
+
+    // Metadata naming someone else is not trusted to move this member's plan
+    let foreign = event(
+        "customer.subscription.updated",
+        now - 10,
+        json!({
+            "id": subscription_id,
+            "customer": "cus_metadata",
+            "status": "active",
+            "metadata": {
+                "user_id": Uuid::new_v4(),
+                "membership_id": session.param("subscription_data[metadata][membership_id]"),
+                "interval": "monthly"
+            }
+        }),
+    );
+    assert_eq!(deliver(&client, &foreign, now).await.0, StatusCode::OK);
+    assert_eq!(tier(&client, &token).await, "premium");
+}
+
+async fn upload(client: &Client, token: &str, name: &str, content: &[u8]) -> (StatusCode, Value) {
+    let response = client
+        .post(format!("{}/api/files", base_url()))
+        .query(&[("name", name)])
+        .bearer_auth(token)
+        .header("content-type", "text/plain")
+        .body(content.to_vec())
+        .send()
+        .await
+        .expect("upload request");
+    let status = response.status();
+    (status, response.json().await.unwrap_or(Value::Null))
+}
+
+async fn list(client: &Client, token: &str, path: &str) -> Value {
+    client
+        .get(format!("{}{}", base_url(), path))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("list request")
+        .json()
+        .await
+        .expect("list body")
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend and its database"]
+async fn plans_gate_content_writes_by_feature_and_limit() {
+    let client = Client::new();
+    let db = database().await;
+    let token = member_token(&client).await;
+    assert_eq!(tier(&client, &token).await, "basic");
+
+    // Basic has no file sharing
+    let (status, body) = upload(&client, &token, "notes.txt", b"hello").await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    assert_eq!(body["feature"], "file_sharing");
+    assert_eq!(body["tier"], "basic");
+    assert!(body["upgrade_to"].as_array().expect("upgrade_to").contains(&json!("standard")));
+
+    // ... but has messaging, for up to five conversations
+    for n in 0..5 {
+        let (status, body) =
+            post_json(&client, &token, "/api/conversations", json!({ "title": format!("Chat {}", n) })).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+    let (status, body) =
+        post_json(&client, &token, "/api/conversations", json!({ "title": "One too many" })).await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED, "{}", body);
+    assert_eq!(body["limit"], "max_conversations");
+    assert_eq!(body["max"], 5);
+    assert_eq!(body["current"], 5);
+    assert_eq!(body["read_only"], false);
+
+    let conversations = list(&client, &token, "/api/conversations").await;
+    let conversations = conversations["conversations"].as_array().expect("conversations");
+    assert_eq!(conversations.len(), 5);
+    assert_eq!(conversations[0]["title"], "Chat 0");
+
+    // Titles are stored under the member's key
+    let mut tx = db.begin().await.expect("begin");
+    sqlx::query("SELECT set_config('app.role', 'system', true)")
+        .execute(&mut *tx)
+        .await
+        .expect("system scope");
+    let stored: String = sqlx::query_scalar("SELECT title FROM conversations WHERE id = $1")
+        .bind(conversations[0]["id"].as_str().expect("id").parse::<Uuid>().expect("uuid"))
+        .fetch_one(&mut *tx)
+        .await
+        .expect("read title");
+    tx.commit().await.expect("commit");
+    assert!(stored.starts_with("enc:v2:"), "{}", stored);
+
+    // Deleting makes room again
+    let response = client
+        .delete(format!(
+            "{}/api/conversations/{}",
+            base_url(),
+            conversations[0]["id"].as_str().expect("id")
+        ))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("delete request");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let (status, body) =
+        post_json(&client, &token, "/api/conversations", json!({ "title": "Chat 5" })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    // The catalog only takes features routes can check
+    let admin = admin_token(&client, &db).await;
+    let (status, body) = post_json(
+        &client,
+        &admin,
+        "/api/admin/memberships",
+        json!({
+            "tier": format!("test-{}", &Uuid::new_v4().simple().to_string()[..8]),
+            "name": "Misspelt",
+            "features": { "file_sharng": true },
+            "max_file_size_mb": 5,
+            "max_storage_gb": 1,
+            "max_conversations": 5
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["unknown"], json!(["file_sharng"]));
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend with a short scheduler interval and its database"]
+async fn downgrades_keep_content_over_the_new_limits_read_only() {
+    let client = Client::new();
+    let db = database().await;
+    let admin = admin_token(&client, &db).await;
+    let token = member_token(&client).await;
+
+    let (_, codes) =
+        issue_codes(&client, &admin, json!({ "tier": "standard", "months": 1, "quantity": 1 })).await;
+    let (status, body) = redeem(&client, &token, &codes[0]).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let subscription_id = body["redemption"]["subscription_id"]
+        .as_str()
+        .expect("subscription id")
+        .to_string();
+    assert_eq!(tier(&client, &token).await, "standard");
+
+    // More than Basic's five conversations, and a file Basic could not have shared
+    for n in 0..7 {
+        let (status, body) =
+            post_json(&client, &token, "/api/conversations", json!({ "title": format!("Chat {}", n) })).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+    let (status, body) = upload(&client, &token, "plans.txt", b"kept after downgrade").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let file_id = body["file"]["id"].as_str().expect("file id").to_string();
+
+    run_out(&db, &subscription_id).await;
+    scheduled_status(&client, &token, "canceled").await;
+    assert_eq!(tier(&client, &token).await, "basic");
+
+    // Nothing was deleted and all of it can still be read
+    let conversations = list(&client, &token, "/api/conversations").await;
+    let conversations = conversations["conversations"].as_array().expect("conversations").clone();
+    assert_eq!(conversations.len(), 7);
+    let files = list(&client, &token, "/api/files").await;
+    assert_eq!(files["files"].as_array().expect("files").len(), 1);
+    let response = client
+        .get(format!("{}/api/files/{}", base_url(), file_id))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("download request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.bytes().await.expect("file").as_ref(), b"kept after downgrade");
+
+    // ... but none of it can grow
+    let (status, body) =
+        post_json(&client, &token, "/api/conversations", json!({ "title": "Chat 7" })).await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED, "{}", body);
+    assert_eq!(body["current"], 7);
+    assert_eq!(body["max"], 5);
+    assert_eq!(body["read_only"], true);
+    let (status, body) = upload(&client, &token, "more.txt", b"refused").await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    assert_eq!(body["feature"], "file_sharing");
+
+    // Back under the limit, the member can add again
+    for conversation in &conversations[..3] {
+        let response = client
+            .delete(format!(
+                "{}/api/conversations/{}",
+                base_url(),
+                conversation["id"].as_str().expect("id")
+            ))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .expect("delete request");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+    let (status, body) =
+        post_json(&client, &token, "/api/conversations", json!({ "title": "Chat 7" })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
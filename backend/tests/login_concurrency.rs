@@ -0,0 +1,210 @@
+//! Hammers the login and re-authentication endpoints of a running backend with parallel attempts.
+//!
+//! These need the server and its database, so they are ignored by default:
+//!
+//! ```sh
+//! cargo run &
+//! cargo test --test login_concurrency -- --ignored
+//! ```
+//!
+//! `CIRCLE_TEST_URL` overrides the default `http://127.0.0.1:8000`.
+
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+const PASSWORD: &str = "Staple battery horse 9";
+const LOCKOUT_THRESHOLD: usize = 3;
+
+fn base_url() -> String {
+    std::env::var("CIRCLE_TEST_URL").unwrap_or_else(|_| "http://127.0.0.1:8000".to_string())
+}
+
+async fn register(client: &Client) -> String {
+    let email = format!("race-{}@example.com", Uuid::new_v4());
+    let response = client
+        .post(format!("{}/api/auth/register", base_url()))
+        .json(&json!({ "email": email, "password": PASSWORD }))
+        .send()
+        .await
+        .expect("register request");
+    assert_eq!(response.status(), StatusCode::OK, "registration failed");
+
+    email
+}
+
+/// Sends one login per password at once and returns each response's status and body.
+async fn login_burst(client: &Client, email: &str, passwords: &[&str]) -> Vec<(StatusCode, Value)> {
+    let requests = passwords.iter().map(|password| {
+        let request = client
+            .post(format!("{}/api/auth/login/complete", base_url()))
+            .json(&json!({ "email": email, "password": password }))
+            .send();
+        async move {
+            let response = request.await.expect("login request");
+            let status = response.status();
+            (status, response.json::<Value>().await.unwrap_or(Value::Null))
+        }
+    });
+
+    let handles: Vec<_> = requests.map(tokio::spawn).collect();
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        results.push(handle.await.expect("login task"));
+    }
+    results
+}
+
+async fn login(client: &Client, email: &str) -> String {
+    let results = login_burst(client, email, &[PASSWORD]).await;
+    assert_eq!(results[0].0, StatusCode::OK, "login failed: {:?}", results[0].1);
+    results[0].1["access_token"].as_str().unwrap().to_string()
+}
+
+/// Sends one authenticated request per body at once and returns each response's status.
+async fn authenticated_burst(
+    client: &Client,
+    token: &str,
+    path: &str,
+    bodies: Vec<Value>,
+) -> Vec<StatusCode> {
+    let handles: Vec<_> = bodies
+        .into_iter()
+        .map(|body| {
+            let request = client
+                .post(format!("{}{}", base_url(), path))
+                .bearer_auth(token)
+                .json(&body)
+                .send();
+            tokio::spawn(async move { request.await.expect("request").status() })
+        })
+        .collect();
+    let mut statuses = Vec::with_capacity(handles.len());
+    for handle in handles {
+        statuses.push(handle.await.expect("request task"));
+    }
+    statuses
+}
+
+fn count(results: &[(StatusCode, Value)], status: StatusCode) -> usize {
+    results.iter().filter(|(s, _)| *s == status).count()
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend"]
+async fn parallel_wrong_passwords_stop_at_the_lockout() {
+    let client = Client::new();
+    let email = register(&client).await;
+
+    let results = login_burst(&client, &email, &["wrong password"; 16]).await;
+
+    // Exactly the attempts up to the threshold are counted; the rest see the lock
+    assert_eq!(count(&results, StatusCode::UNAUTHORIZED), LOCKOUT_THRESHOLD);
+    assert_eq!(
+        count(&results, StatusCode::LOCKED),
+        results.len() - LOCKOUT_THRESHOLD
+    );
+    assert_eq!(count(&results, StatusCode::GONE), 0, "destruction triggered");
+
+    // The right password no longer gets in either
+    let results = login_burst(&client, &email, &[PASSWORD; 8]).await;
+    assert_eq!(count(&results, StatusCode::LOCKED), results.len());
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend"]
+async fn correct_password_never_slips_past_a_concurrent_lockout() {
+    let client = Client::new();
+    let email = register(&client).await;
+
+    let mut passwords = vec!["wrong password"; 12];
+    passwords.extend([PASSWORD; 4]);
+    let results = login_burst(&client, &email, &passwords).await;
+
+    // Each success resets the counter, so at most the threshold is counted after the last one
+    let failures = count(&results, StatusCode::UNAUTHORIZED);
+    let successes = count(&results, StatusCode::OK);
+    assert!(failures <= LOCKOUT_THRESHOLD * (successes + 1));
+    assert_eq!(
+        failures + successes + count(&results, StatusCode::LOCKED),
+        results.len(),
+        "unexpected status: {:?}",
+        results
+    );
+
+    // Once any attempt saw the lock, every success came before it and it still holds
+    let locked = count(&results, StatusCode::LOCKED) > 0;
+    let after = login_burst(&client, &email, &[PASSWORD]).await;
+    let expected = if locked {
+        StatusCode::LOCKED
+    } else {
+        StatusCode::OK
+    };
+    assert_eq!(after[0].0, expected);
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend"]
+async fn parallel_correct_logins_each_get_a_session() {
+    let client = Client::new();
+    let email = register(&client).await;
+
+    let results = login_burst(&client, &email, &[PASSWORD; 8]).await;
+
+    assert_eq!(count(&results, StatusCode::OK), results.len());
+    let mut refresh_tokens: Vec<_> = results
+        .iter()
+        .map(|(_, body)| body["refresh_token"].as_str().unwrap().to_string())
+        .collect();
+    refresh_tokens.sort();
+    refresh_tokens.dedup();
+    assert_eq!(refresh_tokens.len(), results.len());
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend"]
+async fn wrong_current_passwords_lock_the_account() {
+    let client = Client::new();
+    let email = register(&client).await;
+    let token = login(&client, &email).await;
+
+    let bodies = (0..8)
+        .map(|_| json!({ "current_password": "wrong password", "new_password": "Anvil lantern otter 4" }))
+        .collect();
+    let statuses = authenticated_burst(&client, &token, "/api/account/password", bodies).await;
+
+    let failures = statuses.iter().filter(|s| **s == StatusCode::UNAUTHORIZED).count();
+    let locked = statuses.iter().filter(|s| **s == StatusCode::LOCKED).count();
+    assert_eq!(failures, LOCKOUT_THRESHOLD, "statuses: {:?}", statuses);
+    assert_eq!(locked, statuses.len() - LOCKOUT_THRESHOLD);
+
+    // The lock holds for logins too, even with the right password
+    let results = login_burst(&client, &email, &[PASSWORD]).await;
+    assert_eq!(results[0].0, StatusCode::LOCKED);
+}
+
+#[tokio::test]
+#[ignore = "needs a running backend"]
+async fn repeated_wrong_step_ups_lock_the_account() {
+    let client = Client::new();
+    let email = register(&client).await;
+    let token = login(&client, &email).await;
+
+    let bodies = (0..8).map(|_| json!({ "password": "wrong password" })).collect();
+    let statuses = authenticated_burst(&client, &token, "/api/auth/step-up", bodies).await;
+
+    let failures = statuses.iter().filter(|s| **s == StatusCode::UNAUTHORIZED).count();
+    let locked = statuses.iter().filter(|s| **s == StatusCode::LOCKED).count();
+    assert_eq!(failures, LOCKOUT_THRESHOLD, "statuses: {:?}", statuses);
+    assert_eq!(locked, statuses.len() - LOCKOUT_THRESHOLD);
+
+    // Not even the right password steps up while the lock holds
+    let statuses = authenticated_burst(
+        &client,
+        &token,
+        "/api/auth/step-up",
+        vec![json!({ "password": PASSWORD })],
+    )
+    .await;
+    assert_eq!(statuses[0], StatusCode::LOCKED);
+}